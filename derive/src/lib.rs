@@ -0,0 +1,3005 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use std::collections::{BTreeMap, HashMap};
+use syn::{
+    ext::IdentExt, parse_macro_input, Attribute, Data, DeriveInput, Expr, ExprLit, Fields,
+    GenericArgument, Ident, Lit, LitBool, LitChar, LitStr, Meta, MetaList, PathArguments, Type,
+    TypePath, Visibility,
+};
+
+#[allow(dead_code)]
+trait Parse {
+    fn parse_env(&mut self, vars: HashMap<String, String>);
+    fn parse_args(&mut self, args: Vec<String>) -> Vec<String>;
+}
+
+fn is_bool(ty: &Type) -> bool {
+    if let Type::Path(TypePath { path, .. }) = ty {
+        if let Some(segment) = path.segments.first() {
+            if let PathArguments::None = segment.arguments {
+                return segment.ident == "bool";
+            }
+        }
+    }
+    false
+}
+
+fn is_map(ty: &Type) -> bool {
+    if let Type::Path(TypePath { path, .. }) = ty {
+        if let Some(segment) = path.segments.last() {
+            return segment.ident == "HashMap" || segment.ident == "BTreeMap";
+        }
+    }
+    false
+}
+
+fn is_vec(ty: &Type) -> syn::Result<bool> {
+    if let Type::Path(TypePath { path, .. }) = ty {
+        if let Some(segment) = path.segments.last() {
+            if segment.ident == "Vec" {
+                if let PathArguments::AngleBracketed(args) = &segment.arguments {
+                    for arg in &args.args {
+                        if let GenericArgument::Type(inner_ty) = arg {
+                            if is_bool(inner_ty) {
+                                return Err(syn::Error::new_spanned(
+                                    ty,
+                                    "cliconf::Parse does not support Vec<bool>",
+                                ));
+                            }
+                            return Ok(true);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// A smart pointer around a field's actual type.
+enum Pointer {
+    Box,
+    Arc,
+    Rc,
+}
+
+impl Pointer {
+    /// Wraps a value of the inner type.
+    fn wrap(&self, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        match self {
+            Pointer::Box => quote! { ::std::boxed::Box::new(#value) },
+            Pointer::Arc => quote! { ::std::sync::Arc::new(#value) },
+            Pointer::Rc => quote! { ::std::rc::Rc::new(#value) },
+        }
+    }
+
+    /// Borrows the inner value mutably. Shared pointers clone it first if
+    /// it's shared.
+    fn get_mut(&self, place: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        match self {
+            Pointer::Box => quote! { &mut *#place },
+            Pointer::Arc => quote! { ::std::sync::Arc::make_mut(&mut #place) },
+            Pointer::Rc => quote! { ::std::rc::Rc::make_mut(&mut #place) },
+        }
+    }
+}
+
+/// Splits `Box<T>`, `Arc<T>` and `Rc<T>` into the pointer and `T`.
+fn pointer(ty: &Type) -> Option<(Pointer, &Type)> {
+    let Type::Path(TypePath { path, .. }) = ty else {
+        return None;
+    };
+    let segment = path.segments.last()?;
+    let pointer = match segment.ident.to_string().as_str() {
+        "Box" => Pointer::Box,
+        "Arc" => Pointer::Arc,
+        "Rc" => Pointer::Rc,
+        _ => return None,
+    };
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(inner) => Some((pointer, inner)),
+        _ => None,
+    }
+}
+
+fn is_cow(ty: &Type) -> bool {
+    if let Type::Path(TypePath { path, .. }) = ty {
+        if let Some(segment) = path.segments.last() {
+            return segment.ident == "Cow";
+        }
+    }
+    false
+}
+
+/// Whether a field holds text that could be anything, like a path or a name,
+/// as opposed to a number or one of a fixed set of values.
+fn is_free_form(ty: &Type) -> bool {
+    let ty = vec_item(ty).unwrap_or(ty);
+    let Type::Path(TypePath { path, .. }) = ty else {
+        return false;
+    };
+    path.segments.last().is_some_and(|segment| {
+        [
+            "String",
+            "str",
+            "Cow",
+            "SmolStr",
+            "CompactString",
+            "PathBuf",
+            "OsString",
+        ]
+        .iter()
+        .any(|name| segment.ident == name)
+    })
+}
+
+/// Whether a field holds file system paths, for completion to suggest files.
+fn is_path(ty: &Type) -> bool {
+    let ty = vec_item(ty).unwrap_or(ty);
+    let Type::Path(TypePath { path, .. }) = ty else {
+        return false;
+    };
+    path.segments
+        .last()
+        .is_some_and(|segment| segment.ident == "PathBuf" || segment.ident == "Path")
+}
+
+/// Whether a field holds `std::time::Duration`s, which have no `FromStr` or
+/// `Display`, so they're read and written as e.g. `1h30m`.
+fn is_duration(ty: &Type) -> bool {
+    let ty = vec_item(ty).unwrap_or(ty);
+    let Type::Path(TypePath { path, .. }) = ty else {
+        return false;
+    };
+    path.segments
+        .last()
+        .is_some_and(|segment| segment.ident == "Duration")
+}
+
+/// The `cliconf::meta::FlagKind` for a field, or for each item of a `Vec`.
+fn flag_kind(ty: &Type) -> proc_macro2::TokenStream {
+    let name = match ty {
+        Type::Path(TypePath { path, .. }) => path.segments.last().map(|s| s.ident.to_string()),
+        _ => None,
+    }
+    .unwrap_or_else(|| quote! { #ty }.to_string());
+    match name.as_str() {
+        "bool" => quote! { ::cliconf::meta::FlagKind::Bool },
+        "i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "u8" | "u16" | "u32" | "u64" | "u128"
+        | "usize" => quote! { ::cliconf::meta::FlagKind::Integer },
+        "f32" | "f64" => quote! { ::cliconf::meta::FlagKind::Float },
+        "String" | "str" | "Cow" | "SmolStr" | "CompactString" | "OsString" | "char" => {
+            quote! { ::cliconf::meta::FlagKind::String }
+        }
+        "PathBuf" | "Path" => quote! { ::cliconf::meta::FlagKind::Path },
+        _ => quote! {
+            ::cliconf::meta::FlagKind::Other(::std::borrow::ToOwned::to_owned(#name))
+        },
+    }
+}
+
+/// The `T` in `Vec<T>`.
+fn vec_item(ty: &Type) -> Option<&Type> {
+    let Type::Path(TypePath { path, .. }) = ty else {
+        return None;
+    };
+    let segment = path.segments.last()?;
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(item) if segment.ident == "Vec" => Some(item),
+        _ => None,
+    }
+}
+
+/// The `T` in `Option<T>`.
+fn option_item(ty: &Type) -> Option<&Type> {
+    let Type::Path(TypePath { path, .. }) = ty else {
+        return None;
+    };
+    let segment = path.segments.last()?;
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(item) if segment.ident == "Option" => Some(item),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct CliconfAttrs {
+    shorthand: Option<LitChar>,
+    delimiter: Option<String>,
+    env_prefix_map: Option<String>,
+    immutable: bool,
+    unit: Option<String>,
+    parse_with: Option<syn::Path>,
+    completer: Option<syn::Path>,
+    expand: bool,
+    auto: Option<String>,
+    description: Option<String>,
+    env: Option<String>,
+    rename: Option<LitStr>,
+    aliases: Vec<LitStr>,
+    /// Set by `from_command`, with the attribute's command if it has one.
+    from_command: Option<Option<String>>,
+    command_timeout_ms: Option<u64>,
+    gsettings: Option<LitStr>,
+    defaults: Option<LitStr>,
+    secret: bool,
+    telemetry: bool,
+    flatten: bool,
+    count: bool,
+    default: Option<Expr>,
+    required: bool,
+    deprecated: Option<LitStr>,
+    sources: bool,
+}
+
+/// Parses a duration like `500ms`, `5s` or `1m` into milliseconds.
+fn millis(s: &LitStr) -> syn::Result<u64> {
+    let value = s.value();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let scale = match unit {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(number) if scale > 0 => Ok(number * scale),
+        _ => Err(syn::Error::new_spanned(
+            s,
+            "expected a duration like \"500ms\", \"5s\" or \"1m\"",
+        )),
+    }
+}
+
+/// Parses positional arguments like `SRC [DEST]...`: optional ones in
+/// brackets, and `...` after the last if it takes any number.
+fn positionals(s: &LitStr) -> syn::Result<Vec<(String, bool, bool)>> {
+    let error = |message: &str| syn::Error::new_spanned(s, message);
+    let value = s.value();
+    let words: Vec<&str> = value.split_whitespace().collect();
+    let mut result = vec![];
+    for (i, word) in words.iter().enumerate() {
+        let (word, variadic) = match word.strip_suffix("...") {
+            Some(word) => (word, true),
+            None => (*word, false),
+        };
+        if variadic && i != words.len() - 1 {
+            return Err(error(
+                "only the last positional argument can take any number",
+            ));
+        }
+        let (name, required) = match word.strip_prefix('[').and_then(|w| w.strip_suffix(']')) {
+            Some(name) => (name, false),
+            None => (word, true),
+        };
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(error(
+                "expected positional arguments like \"SRC [DEST]...\"",
+            ));
+        }
+        if required && result.iter().any(|(_, required, _)| !required) {
+            return Err(error(
+                "required positional arguments must come before optional ones",
+            ));
+        }
+        result.push((name.to_string(), required, variadic));
+    }
+    Ok(result)
+}
+
+/// Checks that `s` can be used as a long flag name.
+fn flag_name(s: LitStr) -> syn::Result<LitStr> {
+    let name = s.value();
+    if name.is_empty()
+        || name.starts_with('-')
+        || name.contains(|c: char| c == '=' || c.is_whitespace())
+    {
+        return Err(syn::Error::new_spanned(
+            s,
+            "expected a flag name without leading dashes, `=` or spaces",
+        ));
+    }
+    Ok(s)
+}
+
+/// Every `#[name(...)]` attribute, so a field's keys can be split across
+/// several of them, e.g. to repeat `alias`
+fn get_meta_lists<'a>(attrs: &'a [Attribute], name: &'a str) -> impl Iterator<Item = &'a MetaList> {
+    attrs.iter().filter_map(move |attr| match &attr.meta {
+        Meta::List(meta_list) if meta_list.path.is_ident(name) => Some(meta_list),
+        _ => None,
+    })
+}
+
+fn get_cliconf_attrs(attrs: &[Attribute]) -> syn::Result<CliconfAttrs> {
+    let mut result = CliconfAttrs::default();
+    for meta_list in get_meta_lists(attrs, "cliconf") {
+        meta_list.parse_nested_meta(|meta| {
+            if meta.path.is_ident("shorthand") {
+                let value = meta.value()?;
+                let c: LitChar = value.parse()?;
+                result.shorthand = Some(c);
+            } else if meta.path.is_ident("delimiter") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.delimiter = Some(s.value());
+            } else if meta.path.is_ident("env_prefix_map") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.env_prefix_map = Some(s.value());
+            } else if meta.path.is_ident("description") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.description = Some(s.value());
+            } else if meta.path.is_ident("rename") {
+                let value = meta.value()?;
+                result.rename = Some(flag_name(value.parse()?)?);
+            } else if meta.path.is_ident("alias") {
+                let value = meta.value()?;
+                result.aliases.push(flag_name(value.parse()?)?);
+            } else if meta.path.is_ident("from_command") {
+                result.from_command = Some(if meta.input.peek(syn::Token![=]) {
+                    let value = meta.value()?;
+                    let s: LitStr = value.parse()?;
+                    Some(s.value())
+                } else {
+                    None
+                });
+            } else if meta.path.is_ident("command_timeout") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.command_timeout_ms = Some(millis(&s)?);
+            } else if meta.path.is_ident("gsettings") {
+                let value = meta.value()?;
+                result.gsettings = Some(value.parse()?);
+            } else if meta.path.is_ident("defaults") {
+                let value = meta.value()?;
+                result.defaults = Some(value.parse()?);
+            } else if meta.path.is_ident("env") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.env = Some(s.value());
+            } else if meta.path.is_ident("auto") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.auto = Some(s.value());
+            } else if meta.path.is_ident("unit") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.unit = Some(s.value());
+            } else if meta.path.is_ident("parse_with") {
+                let value = meta.value()?;
+                result.parse_with = Some(value.parse()?);
+            } else if meta.path.is_ident("completer") {
+                let value = meta.value()?;
+                result.completer = Some(value.parse()?);
+            } else if meta.path.is_ident("expand") {
+                result.expand = true;
+            } else if meta.path.is_ident("immutable") {
+                result.immutable = true;
+            } else if meta.path.is_ident("secret") {
+                result.secret = true;
+            } else if meta.path.is_ident("default") {
+                let value = meta.value()?;
+                result.default = Some(value.parse()?);
+            } else if meta.path.is_ident("telemetry") {
+                result.telemetry = true;
+            } else if meta.path.is_ident("flatten") {
+                result.flatten = true;
+            } else if meta.path.is_ident("count") {
+                result.count = true;
+            } else if meta.path.is_ident("required") {
+                result.required = true;
+            } else if meta.path.is_ident("deprecated") {
+                let value = meta.value()?;
+                result.deprecated = Some(value.parse()?);
+            } else if meta.path.is_ident("sources") {
+                result.sources = true;
+            } else {
+                return Err(meta.error("unknown cliconf attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(result)
+}
+
+/// Joins `///` comments into a description. Lines within a paragraph are
+/// joined with spaces, and paragraphs are separated by a blank line.
+fn get_doc(attrs: &[Attribute]) -> Option<String> {
+    let mut paragraphs: Vec<String> = vec![String::new()];
+    for attr in attrs {
+        let Meta::NameValue(meta) = &attr.meta else {
+            continue;
+        };
+        let Expr::Lit(ExprLit {
+            lit: Lit::Str(s), ..
+        }) = &meta.value
+        else {
+            continue;
+        };
+        if !meta.path.is_ident("doc") {
+            continue;
+        }
+        let line = s.value();
+        let line = line.trim();
+        let paragraph = paragraphs.last_mut().unwrap();
+        if line.is_empty() {
+            if !paragraph.is_empty() {
+                paragraphs.push(String::new());
+            }
+        } else {
+            if !paragraph.is_empty() {
+                paragraph.push(' ');
+            }
+            paragraph.push_str(line);
+        }
+    }
+    let doc = paragraphs.join("\n\n");
+    let doc = doc.trim_end();
+    (!doc.is_empty()).then(|| doc.to_string())
+}
+
+/// How generated code looks up flag names.
+#[derive(Clone, Copy, PartialEq)]
+enum Matcher {
+    /// A single `match` over every name.
+    Match,
+    /// A `match` on the name's length, then on the names of that length, so
+    /// each lookup only compares against a handful of candidates.
+    Bucketed,
+    /// A hash map built on first use, so lookups take the same time however
+    /// many flags there are.
+    Hashed,
+}
+
+#[derive(Default)]
+struct StructAttrs {
+    matcher: Option<Matcher>,
+    env_case_insensitive: Option<bool>,
+    env_prefix: Option<String>,
+    unset_sentinel: Option<String>,
+    unset_flag: Option<String>,
+    set_flag: Option<String>,
+    print_config: Option<LitStr>,
+    coerce: bool,
+    /// `ArgSyntax` fields to override, with the `SpecialArg` for each.
+    arg_syntax: Vec<(Ident, Ident)>,
+    to_args: bool,
+    dump: bool,
+    /// Each positional argument's name, whether it's required, and whether
+    /// it's variadic.
+    positionals: Option<Vec<(String, bool, bool)>>,
+    /// The embedded defaults file, and the `cliconf::Format` variant for it.
+    embedded_defaults: Option<(LitStr, Ident)>,
+    bin_name: Option<String>,
+    version: Option<String>,
+    about: Option<String>,
+    author: Option<String>,
+    vis: Option<Visibility>,
+    renamed_methods: HashMap<String, Ident>,
+}
+
+/// The inherent methods that `rename_methods(...)` can rename.
+const METHODS: &[&str] = &[
+    "parse_env",
+    "parse_process_env",
+    "parse_args",
+    "parse_file",
+    "parse_file_with_limits",
+    "parse_str",
+    "parse_bytes",
+    "parse_embedded_defaults",
+    "load",
+    "load_with",
+    "parse_gsettings",
+    "parse_defaults",
+    "apply_arg",
+    "apply_env_var",
+    "freeze",
+    "reload",
+    "resolve_auto",
+    "flags_metadata",
+    "about",
+    "positionals",
+    "synopsis",
+    "completions",
+    "complete",
+    "markdown",
+    "nixos_module",
+    "dockerfile",
+    "compose_environment",
+    "replay_manifest",
+    "to_args",
+    "usage",
+    "usage_styled",
+    "usage_all",
+    "usage_all_styled",
+    "sanitized_snapshot",
+    "validate",
+    "check",
+    "dump",
+    "sources",
+    "new",
+];
+
+impl StructAttrs {
+    /// The name to generate for one of [`METHODS`].
+    fn method(&self, name: &str) -> Ident {
+        self.renamed_methods
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| Ident::new(name, Span::call_site()))
+    }
+}
+
+fn get_struct_attrs(attrs: &[Attribute]) -> syn::Result<StructAttrs> {
+    let mut result = StructAttrs::default();
+    for meta_list in get_meta_lists(attrs, "cliconf") {
+        meta_list.parse_nested_meta(|meta| {
+            if meta.path.is_ident("matcher") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.matcher = Some(match s.value().as_str() {
+                    "match" => Matcher::Match,
+                    "bucketed" => Matcher::Bucketed,
+                    "hashed" => Matcher::Hashed,
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            s,
+                            "expected \"match\", \"bucketed\" or \"hashed\"",
+                        ))
+                    }
+                });
+            } else if meta.path.is_ident("env_case_insensitive") {
+                let value = meta.value()?;
+                let b: LitBool = value.parse()?;
+                result.env_case_insensitive = Some(b.value());
+            } else if meta.path.is_ident("env_prefix") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.env_prefix = Some(s.value());
+            } else if meta.path.is_ident("unset_sentinel") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.unset_sentinel = Some(s.value());
+            } else if meta.path.is_ident("unset_flag") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.unset_flag = Some(s.value());
+            } else if meta.path.is_ident("set_flag") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.set_flag = Some(s.value());
+            } else if meta.path.is_ident("print_config") {
+                result.print_config = Some(if meta.input.peek(syn::Token![=]) {
+                    let value = meta.value()?;
+                    flag_name(value.parse()?)?
+                } else {
+                    LitStr::new("print-config", Span::call_site())
+                });
+            } else if meta.path.is_ident("coerce") {
+                result.coerce = true;
+            } else if let Some((_, field)) = [
+                ("dash", "dash"),
+                ("double_dash", "double_dash"),
+                ("empty_arg", "empty"),
+            ]
+            .iter()
+            .find(|(attr, _)| meta.path.is_ident(attr))
+            {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                let variant = match s.value().as_str() {
+                    "positional" => "Positional",
+                    "end" => "End",
+                    "keep_and_end" => "KeepAndEnd",
+                    "skip" => "Skip",
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            s,
+                            "expected \"positional\", \"end\", \"keep_and_end\" or \"skip\"",
+                        ))
+                    }
+                };
+                result.arg_syntax.retain(|(other, _)| other != field);
+                result.arg_syntax.push((
+                    Ident::new(field, Span::call_site()),
+                    Ident::new(variant, Span::call_site()),
+                ));
+            } else if meta.path.is_ident("to_args") {
+                result.to_args = true;
+            } else if meta.path.is_ident("dump") {
+                result.dump = true;
+            } else if meta.path.is_ident("positionals") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.positionals = Some(positionals(&s)?);
+            } else if meta.path.is_ident("embedded_defaults") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                let path = s.value();
+                let format = match path.rsplit_once('.').map(|(_, ext)| ext) {
+                    Some("json") => "Json",
+                    Some("toml") => "Toml",
+                    Some("yaml" | "yml") => "Yaml",
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            s,
+                            "expected a .json, .toml, .yaml or .yml file",
+                        ))
+                    }
+                };
+                result.embedded_defaults = Some((s, Ident::new(format, Span::call_site())));
+            } else if meta.path.is_ident("bin_name") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.bin_name = Some(s.value());
+            } else if meta.path.is_ident("version") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.version = Some(s.value());
+            } else if meta.path.is_ident("about") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.about = Some(s.value());
+            } else if meta.path.is_ident("author") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.author = Some(s.value());
+            } else if meta.path.is_ident("vis") {
+                let value = meta.value()?;
+                let s: LitStr = value.parse()?;
+                result.vis = Some(s.parse()?);
+            } else if meta.path.is_ident("rename_methods") {
+                meta.parse_nested_meta(|meta| {
+                    let Some(method) = METHODS.iter().find(|m| meta.path.is_ident(m)) else {
+                        return Err(meta.error(format!("expected one of: {}", METHODS.join(", "))));
+                    };
+                    let value = meta.value()?;
+                    let s: LitStr = value.parse()?;
+                    result
+                        .renamed_methods
+                        .insert(method.to_string(), s.parse()?);
+                    Ok(())
+                })?;
+            } else {
+                return Err(meta.error("unknown cliconf attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(result)
+}
+
+/// The number of flags above which names are looked up in a hash map unless
+/// the struct picks a matcher.
+const HASHED_ABOVE: usize = 64;
+
+/// Generates a lookup of `scrutinee` against string `arms`, evaluating
+/// `fallback` when nothing matches.
+fn string_match(
+    matcher: &Matcher,
+    scrutinee: proc_macro2::TokenStream,
+    arms: &[(String, proc_macro2::TokenStream)],
+    fallback: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    if *matcher == Matcher::Hashed {
+        let names = arms.iter().map(|(name, _)| name);
+        let indexes = 0..arms.len();
+        let arms = arms.iter().enumerate().map(|(i, (_, op))| {
+            quote! { ::std::option::Option::Some(#i) => { #op } }
+        });
+        return quote! {
+            {
+                static __CLICONF_INDEX: ::std::sync::OnceLock<
+                    ::std::collections::HashMap<&'static str, usize>,
+                > = ::std::sync::OnceLock::new();
+                let __cliconf_index = __CLICONF_INDEX.get_or_init(|| {
+                    ::std::iter::FromIterator::from_iter([#((#names, #indexes)),*])
+                });
+                match __cliconf_index.get(#scrutinee).copied() {
+                    #(#arms)*
+                    _ => #fallback
+                }
+            }
+        };
+    }
+    if *matcher == Matcher::Match {
+        let names = arms.iter().map(|(name, _)| name);
+        let ops = arms.iter().map(|(_, op)| op);
+        return quote! {
+            match #scrutinee {
+                #(#names => { #ops })*
+                _ => #fallback
+            }
+        };
+    }
+
+    let mut buckets: BTreeMap<usize, Vec<&(String, proc_macro2::TokenStream)>> = BTreeMap::new();
+    for arm in arms {
+        buckets.entry(arm.0.len()).or_default().push(arm);
+    }
+    let buckets = buckets.into_iter().map(|(len, arms)| {
+        let names = arms.iter().map(|(name, _)| name);
+        let ops = arms.iter().map(|(_, op)| op);
+        quote! {
+            #len => match #scrutinee {
+                #(#names => { #ops })*
+                _ => #fallback
+            }
+        }
+    });
+    quote! {
+        match #scrutinee.len() {
+            #(#buckets,)*
+            _ => #fallback
+        }
+    }
+}
+
+/// Generates `parse_env`, `parse_process_env`, and `parse_args` methods for a struct with named
+/// fields, by implementing `cliconf::engine::Dispatch` for it. They return a
+/// `cliconf::Error` for unknown flags and values that don't parse, and for
+/// help and version requests.
+///
+/// `--help` and `-h` make `parse_args` return `cliconf::Error::HelpRequested`,
+/// `--help-all` `cliconf::Error::HelpAllRequested`, and `--version` and `-V`
+/// `cliconf::Error::VersionRequested`, unless a field uses them itself. With
+/// the `usage` feature, `usage(width)` then gives help listing each field's
+/// flag, described by its doc comment, and `usage_all(width)` the same help
+/// without cutting long defaults short.
+///
+/// With the `json` feature, `parse_file(path)` applies a config file,
+/// `parse_file_with_limits(path, limits)` one read within
+/// `cliconf::Limits`, and `parse_str(data, format)` and
+/// `parse_bytes(data, format)` a config document held in memory, e.g. an
+/// asset bundled with a mobile app. Call them
+/// before the other methods, so the environment and arguments override them.
+/// Or call `load()`, which applies the embedded defaults, the program's
+/// config files, the environment and the arguments in that order, as
+/// `cliconf::Loader` documents, and returns the positional arguments.
+/// Tables in the document prefix their keys' flags, so `port` in a `db`
+/// table sets `db_port`.
+///
+/// Fields can be `bool`, anything that implements `FromStr` (including
+/// `SmolStr` and `CompactString`), `Cow<'static, str>`, `Duration`, a `Vec`
+/// of those, or any of these inside a `Box`, `Arc` or `Rc`. `Cow` fields let
+/// defaults be borrowed literals, and only allocate once a value is parsed.
+/// `Duration` fields read values like `30s` or `1h30m`, and plain numbers as
+/// seconds. Wrap any of them in an `Option` to leave the field `None` unless
+/// the flag is given.
+///
+/// Field attributes, which can be split across several `#[cliconf(...)]`
+/// attributes:
+///
+/// - `#[cliconf(shorthand = 'n')]`: also accept `-n`
+/// - `#[cliconf(rename = "type")]`: use this long flag name instead of the
+///   field's name with `_` turned into `-`. The environment variable follows
+///   the new name.
+/// - `#[cliconf(alias = "colour")]`: also accept `--colour`, e.g. to keep an
+///   old name working after a rename. Repeat it, in the same attribute or
+///   another, for more aliases. Aliases
+///   aren't shown in help.
+/// - `#[cliconf(description = "...")]`: describe the flag in help and docs,
+///   instead of using the field's doc comment
+/// - `#[cliconf(env = "MYAPP_LISTEN")]`: read the field from this environment
+///   variable instead of its uppercased name. The struct's `env_prefix` isn't
+///   added.
+/// - `#[cliconf(count)]`: make an integer field a switch that adds one each
+///   time it's given, so `-vvv` sets `3`. Occurrences add to whatever the
+///   environment or a config file set, and `--verbose=2` still sets a value.
+/// - `#[cliconf(default = "8080")]`: the field's default, parsed like a value
+///   from the command line, or `default = 8080` for an expression of the
+///   field's type. The macro then generates the struct's `Default` impl and a
+///   `new()` that uses it, so don't derive `Default` as well. Fields without
+///   a default get their type's.
+/// - `#[cliconf(delimiter = ",")]`: split `Vec` values read from the environment
+/// - `#[cliconf(env_prefix_map = "MYAPP_LABEL_")]`: fill a `HashMap` or
+///   `BTreeMap` field from every environment variable starting with the prefix,
+///   keyed by the rest of the variable's name in lowercase. Map fields can't be
+///   set from the command line.
+/// - `#[cliconf(auto = "cpus * 2")]`: work out the default when the program
+///   starts, with a resolver from `cliconf::auto::Resolvers`, by calling
+///   `resolve_auto` before parsing anything else
+/// - `#[cliconf(unit = "ms")]`: the unit of a numeric field. Values with
+///   another unit of the same kind are converted, so `2s` sets `2000`.
+///   Help and `print_config` show the unit. `Duration` fields already read
+///   units, so they can't have one.
+/// - `#[cliconf(parse_with = path::to::parse)]`: parse values with a
+///   `fn(&str) -> Result<T, E>` instead of `FromStr`, e.g. for a type from
+///   another crate. For a `Vec`, `Option` or map field, `T` is the item's
+///   type. An `Err` makes the value invalid.
+/// - `#[cliconf(completer = path::to::complete)]`: suggest values for the
+///   field when completing it in a shell, with a `cliconf::complete::Completer`
+/// - `#[cliconf(expand)]`: expand a leading `~` and `$VAR` or `${VAR}` in
+///   values before parsing them, e.g. for a `PathBuf` field set to
+///   `~/.cache/app` in a config file. A variable that isn't set makes the
+///   value invalid. See `cliconf::paths`.
+/// - `#[cliconf(from_command)]`: run values written as `cmd://COMMAND`, e.g.
+///   `cmd://pass show api-token`, and use what the command prints. Other
+///   fields never run anything.
+/// - `#[cliconf(from_command = "pass show")]`: run the command with every
+///   value as its last argument instead, like a git credential helper
+/// - `#[cliconf(command_timeout = "5s")]`: how long `from_command` waits
+///   before failing (10 seconds by default)
+/// - `#[cliconf(gsettings = "org.gnome.system.proxy.http host")]`: with the
+///   `gsettings` feature, generate `parse_gsettings`, which reads the field
+///   from this GSettings schema and key, or from a dconf path like
+///   `/org/gnome/desktop/interface/gtk-theme`. Settings that can't be read
+///   are skipped.
+/// - `#[cliconf(defaults = "com.example.app ShowSidebar")]`: with the `plist`
+///   feature, generate `parse_defaults`, which reads the field from this
+///   macOS `defaults` domain and key, e.g. one a companion GUI app writes.
+///   Keys that aren't set, and every key on other platforms, are skipped.
+/// - `#[cliconf(secret)]`: show the field as `<redacted>` when printing the
+///   configuration with `print_config`
+/// - `#[cliconf(flatten)]`: take the flags of a field whose type also derives
+///   `Parse`, prefixed with the field's name, so `host` in a `db` field is
+///   `--db-host` and `DB_HOST`. `rename` and `env` change the prefixes. The
+///   nested struct's shorthands aren't used, since they'd clash between
+///   fields of the same type. Nor is its `env_prefix`, though variables its
+///   fields name in full with `env` are used as written. Its other struct
+///   attributes are ignored too, except that it needs `to_args`,
+///   `print_config` or `dump` for the outer struct to use them.
+/// - `#[cliconf(telemetry)]`: with the `json` feature, generate
+///   `sanitized_snapshot`, which gives the fields marked like this as a JSON
+///   object, e.g. for crash reports. Secrets and strings can't be marked,
+///   since they're where personal data ends up. Marking a flattened field
+///   includes the nested struct's telemetry fields.
+/// - `#[cliconf(immutable)]`: keep the field's value when the configuration is
+///   reloaded, and report the change as needing a restart
+/// - `#[cliconf(sources)]`: on a field of type `cliconf::Sources`, which isn't
+///   a flag, record where each field's value came from, e.g.
+///   `conf.sources.get("port")`. This also generates `sources()`, listing
+///   every field's source, and adds them to `print_config`'s output.
+/// - `#[cliconf(required)]`: make `validate()` fail with
+///   `cliconf::Error::MissingRequired` unless some source set the field. The
+///   struct needs a `sources` field to tell.
+/// - `#[cliconf(deprecated = "use --new instead")]`: make `check()` warn
+///   about each source that set the field, naming the environment variable
+///   or config file, since whoever has to migrate may never see the
+///   arguments. The struct needs a `sources` field to tell.
+///
+/// Struct attributes:
+///
+/// - `#[cliconf(matcher = "bucketed")]`: group flag names by length before
+///   comparing them, which keeps lookups fast for structs with many flags
+/// - `#[cliconf(matcher = "hashed")]`: look flag names up in a hash map, so
+///   lookups take constant time however many flags there are. This is the
+///   default for structs with more than 64 flags; use `matcher = "match"` to
+///   compare names one by one instead.
+/// - `#[cliconf(env_prefix = "MYAPP")]`: prepend to every field's environment
+///   variable, e.g. `MYAPP_NAME`. An `_` is added if the prefix doesn't end
+///   with one.
+/// - `#[cliconf(env_case_insensitive = true)]`: match environment variable
+///   names regardless of case in `parse_env` (the default on Windows). An
+///   exact match wins, and otherwise the first matching name in sorted order
+/// - `#[cliconf(unset_sentinel = "@unset")]`: reset a field to its default
+///   with e.g. `--name @unset`, overriding the environment and config files.
+///   The default comes from the struct's `Default` impl.
+/// - `#[cliconf(unset_flag = "unset")]`: reset a field to its default with e.g.
+///   `--unset name`, which also works for `bool` fields
+/// - `#[cliconf(set_flag = "set")]`: set any field by its name with e.g.
+///   `--set name=john`, even one without a dedicated flag
+/// - `#[cliconf(print_config)]`: make `--print-config[=json|toml|env]` end
+///   parsing with `cliconf::Error::PrintConfigRequested`, holding the
+///   effective configuration for the program to print before it exits. Give
+///   it a value to use another flag name, e.g. `print_config = "dump"`.
+///   Every field except maps and `Duration`s then also has to implement
+///   `Display`.
+/// - `#[cliconf(dump)]`: generate `dump`, which gives the effective
+///   configuration as JSON, TOML or `.env` lines, with secrets redacted, e.g.
+///   for a `config show` command. `print_config` implies it. Every field
+///   except maps and `Duration`s then also has to implement `Display`.
+/// - `#[cliconf(coerce)]`: accept config file values of the wrong type where
+///   that's unambiguous, e.g. `verbose = 1` for a `bool` field. Strings and
+///   numbers are read as each other either way.
+/// - `#[cliconf(dash = "...", double_dash = "...", empty_arg = "...")]`: what
+///   `parse_args` does with `-`, `--` and empty arguments: `"positional"`
+///   keeps them as positional arguments, `"end"` drops them and ends the
+///   flags, `"keep_and_end"` keeps them and ends the flags (e.g. to forward
+///   `--` to a child process), and `"skip"` drops them. By default `--` ends
+///   the flags and the others are positional.
+/// - `#[cliconf(embedded_defaults = "defaults.toml")]`: with the `json`
+///   feature, compile this file into the program, relative to the crate's
+///   root, and generate `parse_embedded_defaults` to apply it. Call it before
+///   anything else, so it sits just above the `Default` impl and every other
+///   source overrides it. Resetting a field still goes back to `Default`.
+/// - `#[cliconf(to_args)]`: also generate `to_args`, which turns the fields
+///   back into arguments that `parse_args` reads as the same values. Every
+///   field except maps and `Duration`s then also has to implement `Display`.
+/// - `#[cliconf(bin_name = "...", version = "...", about = "...", author = "...")]`:
+///   override what `about()` takes from the crate's Cargo metadata
+/// - `#[cliconf(positionals = "SRC [DEST]...")]`: name the positional
+///   arguments for `synopsis()` and help, with optional ones in brackets and
+///   `...` after the last if it takes any number. `parse_args` then fails
+///   if there are too few or too many. Without it, help shows `[ARGS]...`
+///   and any number are accepted.
+/// - `#[cliconf(vis = "pub(crate)")]`: the visibility of the generated methods
+///   (`pub` by default)
+/// - `#[cliconf(rename_methods(parse_args = "apply_cli"))]`: rename generated
+///   methods that would clash with your own
+#[proc_macro_derive(Parse, attributes(cliconf))]
+pub fn derive_flags(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let struct_attrs = get_struct_attrs(&input.attrs)?;
+
+    let mut long = vec![];
+    let mut short = vec![];
+    let mut env_vars = vec![];
+    let mut set_switch = vec![];
+    let mut set_value = vec![];
+    let mut clear_array = vec![];
+    let mut gsettings = vec![];
+    let mut defaults = vec![];
+    let mut reset = vec![];
+    let mut env_prefixes = vec![];
+    let mut set_entry = vec![];
+    let mut swap_immutable = vec![];
+    let mut auto_defaults = vec![];
+    let mut metadata = vec![];
+    let mut to_args = vec![];
+    let mut dump_entries = vec![];
+    let mut telemetry_entries = vec![];
+    // Each field's initializer in the generated `Default` impl, if any field
+    // has a default
+    let mut default_inits = vec![];
+    let mut has_defaults = false;
+    let mut swap_field = vec![];
+    // Nested structs, with the prefixes of their flags and variables
+    let mut flattened: Vec<(&Option<Ident>, String, String)> = vec![];
+    // The nested structs' types, in the same order
+    let mut flat_types = vec![];
+    // Environment variables named in full with `env`
+    let mut custom_env_vars = vec![];
+    let field_count;
+    // The field that records where each field's value came from
+    let mut sources_field: Option<&Option<Ident>> = None;
+    // Each field's key and flag name, for recording its source
+    let mut source_names = vec![];
+    // Every field's flag name, except flattened fields
+    let mut flag_names = vec![];
+    // The fields with a completer
+    let mut completers = vec![];
+    // Required fields, by flag name
+    let mut required: Vec<(String, &Option<Ident>)> = vec![];
+    // Deprecated fields, by flag name, with why they're deprecated
+    let mut deprecated: Vec<(String, LitStr)> = vec![];
+    let mut immutable_names = vec![];
+    let mut shorthands: HashMap<char, String> = HashMap::new();
+    let mut env_names: HashMap<String, String> = HashMap::new();
+    let mut long_names: HashMap<String, String> = HashMap::new();
+    if let Data::Struct(data_struct) = &input.data {
+        if let Fields::Named(fields_named) = &data_struct.fields {
+            field_count = fields_named.named.len();
+            for f in &fields_named.named {
+                if !get_cliconf_attrs(&f.attrs)?.sources {
+                    continue;
+                }
+                if sources_field.is_some() {
+                    return Err(syn::Error::new_spanned(
+                        &f.ident,
+                        "only one field can record sources",
+                    ));
+                }
+                sources_field = Some(&f.ident);
+            }
+            for (key, f) in fields_named.named.iter().enumerate() {
+                let field_name = &f.ident;
+                let field_name_string = field_name.as_ref().unwrap().unraw().to_string();
+                // Optional fields stay `None` until they're set, so they tell
+                // an unset flag apart from one set to its default
+                let (is_option, ty) = match option_item(&f.ty) {
+                    Some(item) => (true, item),
+                    None => (false, &f.ty),
+                };
+                // Smart pointers are parsed as what they point to, then
+                // wrapped
+                let (pointer, ty) = match pointer(ty) {
+                    Some((pointer, inner)) => (Some(pointer), inner),
+                    None => (None, ty),
+                };
+                let wrap = |value| {
+                    let value = match &pointer {
+                        Some(pointer) => pointer.wrap(value),
+                        None => value,
+                    };
+                    if is_option {
+                        quote! { ::std::option::Option::Some(#value) }
+                    } else {
+                        value
+                    }
+                };
+                let place = if is_option {
+                    quote! {
+                        *self.#field_name.get_or_insert_with(::std::default::Default::default)
+                    }
+                } else {
+                    quote! { self.#field_name }
+                };
+                let field_mut = match &pointer {
+                    Some(pointer) => pointer.get_mut(place),
+                    None => quote! { &mut #place },
+                };
+                let field_is_vec = is_vec(ty)?;
+
+                let cliconf_attrs = get_cliconf_attrs(&f.attrs)?;
+                if cliconf_attrs.sources {
+                    default_inits.push(quote! { #field_name: ::std::default::Default::default() });
+                    continue;
+                }
+                let arg_name = match &cliconf_attrs.rename {
+                    Some(rename) => rename.value(),
+                    None => field_name_string.replace("_", "-"),
+                };
+                let flag = format!("--{arg_name}");
+                if let Some(other) = long_names.insert(arg_name.clone(), field_name_string.clone())
+                {
+                    let message = format!("flag {flag} is already used by `{other}`");
+                    return Err(match &cliconf_attrs.rename {
+                        Some(rename) => syn::Error::new_spanned(rename, message),
+                        None => syn::Error::new_spanned(field_name, message),
+                    });
+                }
+                // Variables are named after the flag, so a renamed field's
+                // variable matches its flag. A custom name is used as is,
+                // without the struct's prefix.
+                let var_stem = arg_name.replace('-', "_").to_uppercase();
+                let var_name = match (&cliconf_attrs.env, &struct_attrs.env_prefix) {
+                    (Some(env), _) => {
+                        if !cliconf_attrs.flatten {
+                            custom_env_vars.push(env.clone());
+                        }
+                        env.clone()
+                    }
+                    (None, Some(prefix)) if !prefix.is_empty() && !prefix.ends_with('_') => {
+                        format!("{prefix}_{var_stem}")
+                    }
+                    (None, prefix) => format!("{}{var_stem}", prefix.as_deref().unwrap_or("")),
+                };
+
+                swap_field.push(quote! {
+                    #key => ::std::mem::swap(&mut self.#field_name, &mut __cliconf_other.#field_name),
+                });
+
+                // Nested structs and maps take their default as an
+                // expression of the field's type
+                let default_expr = match &cliconf_attrs.default {
+                    Some(expr) => {
+                        has_defaults = true;
+                        quote! { #expr }
+                    }
+                    None => quote! { ::std::default::Default::default() },
+                };
+                if cliconf_attrs.flatten || is_map(&f.ty) {
+                    default_inits.push(quote! { #field_name: #default_expr });
+                }
+
+                if cliconf_attrs.flatten {
+                    if is_option || pointer.is_some() || field_is_vec || is_map(ty) {
+                        return Err(syn::Error::new_spanned(
+                            &f.ty,
+                            "flatten requires a struct that also derives cliconf::Parse",
+                        ));
+                    }
+                    if cliconf_attrs.required {
+                        return Err(syn::Error::new_spanned(
+                            field_name,
+                            "a flattened field can't be required; mark the nested struct's fields instead",
+                        ));
+                    }
+                    if let Some(message) = &cliconf_attrs.deprecated {
+                        return Err(syn::Error::new_spanned(
+                            message,
+                            "a flattened field can't be deprecated; mark the nested struct's fields instead",
+                        ));
+                    }
+                    let prefix = format!("{arg_name}-");
+                    let var_prefix = format!("{var_name}_");
+                    swap_immutable.push(quote! {
+                        let mut __cliconf_nested = ::std::vec::Vec::new();
+                        ::cliconf::engine::Dispatch::swap_immutable(
+                            &mut self.#field_name,
+                            &mut __cliconf_other.#field_name,
+                            &mut __cliconf_nested,
+                        );
+                        for __cliconf_name in __cliconf_nested {
+                            __cliconf_restart.push(::std::format!("{}{}", #prefix, __cliconf_name));
+                        }
+                    });
+                    metadata.push(quote! {
+                        for __cliconf_meta in <#ty>::flags_metadata() {
+                            __cliconf_metadata.push(::cliconf::meta::FlagMeta {
+                                name: ::std::format!("{}{}", #prefix, __cliconf_meta.name),
+                                shorthand: ::std::option::Option::None,
+                                description: __cliconf_meta.description,
+                                required: __cliconf_meta.required,
+                                value_hint: __cliconf_meta.value_hint,
+                                kind: __cliconf_meta.kind,
+                                is_array: __cliconf_meta.is_array,
+                                default: __cliconf_meta.default,
+                                unit: __cliconf_meta.unit,
+                                secret: __cliconf_meta.secret,
+                                env_var: __cliconf_meta
+                                    .env_var
+                                    .map(|__cliconf_var| ::cliconf::engine::nested_env_var::<#ty>(#var_prefix, &__cliconf_var)),
+                            });
+                        }
+                    });
+                    to_args.push(quote! {
+                        for __cliconf_arg in self.#field_name.to_args() {
+                            let __cliconf_arg = __cliconf_arg.strip_prefix("--").unwrap_or(&__cliconf_arg);
+                            __cliconf_args.push(::std::format!("--{}{}", #prefix, __cliconf_arg));
+                        }
+                    });
+                    let nested_entries = |method: Ident| {
+                        quote! {
+                            for __cliconf_entry in ::cliconf::engine::Dispatch::#method(&self.#field_name) {
+                                __cliconf_entries.push(::cliconf::dump::Entry {
+                                    name: ::std::format!("{}{}", #prefix, __cliconf_entry.name),
+                                    env_var: __cliconf_entry
+                                        .env_var
+                                        .map(|__cliconf_var| ::cliconf::engine::nested_env_var::<#ty>(#var_prefix, &__cliconf_var)),
+                                    ..__cliconf_entry
+                                });
+                            }
+                        }
+                    };
+                    dump_entries.push(nested_entries(Ident::new(
+                        "dump_entries",
+                        Span::call_site(),
+                    )));
+                    // The nested struct's telemetry fields are only included
+                    // if the field itself is marked
+                    if cliconf_attrs.telemetry {
+                        telemetry_entries.push(nested_entries(Ident::new(
+                            "telemetry_entries",
+                            Span::call_site(),
+                        )));
+                    }
+                    flattened.push((field_name, prefix, var_prefix));
+                    flat_types.push(ty);
+                    continue;
+                }
+
+                source_names.push(quote! { #key => ::std::option::Option::Some(#arg_name), });
+                flag_names.push(arg_name.clone());
+                if let Some(completer) = &cliconf_attrs.completer {
+                    completers.push(quote! {
+                        #key => ::std::option::Option::Some(#completer),
+                    });
+                }
+                if cliconf_attrs.required {
+                    required.push((arg_name.clone(), field_name));
+                }
+                if let Some(message) = cliconf_attrs.deprecated.clone() {
+                    deprecated.push((arg_name.clone(), message));
+                }
+                if cliconf_attrs.immutable {
+                    immutable_names.push(arg_name.clone());
+                }
+
+                if cliconf_attrs.immutable {
+                    swap_immutable.push(quote! {
+                        if self.#field_name != __cliconf_other.#field_name {
+                            __cliconf_restart.push(::std::borrow::ToOwned::to_owned(#arg_name));
+                        }
+                        ::std::mem::swap(&mut self.#field_name, &mut __cliconf_other.#field_name);
+                    });
+                }
+
+                if is_map(&f.ty) {
+                    let Some(prefix) = cliconf_attrs.env_prefix_map else {
+                        return Err(syn::Error::new_spanned(
+                            &f.ty,
+                            "map fields can only be set with #[cliconf(env_prefix_map = \"...\")]",
+                        ));
+                    };
+                    let parse_entry = match &cliconf_attrs.parse_with {
+                        Some(parse_with) => quote! { #parse_with(__cliconf_value) },
+                        None => quote! { __cliconf_value.parse() },
+                    };
+                    env_prefixes.push(quote! {
+                        ::cliconf::engine::EnvPrefix {
+                            key: #key,
+                            prefix: ::std::borrow::ToOwned::to_owned(#prefix),
+                        }
+                    });
+                    set_entry.push(quote! {
+                        #key => {
+                            let __cliconf_entry = __cliconf_name.parse().map_err(|_| {
+                                ::cliconf::Error::InvalidValue {
+                                    flag: ::std::borrow::ToOwned::to_owned(#flag),
+                                    value: ::std::borrow::ToOwned::to_owned(__cliconf_name),
+                                    source: ::std::option::Option::None,
+                                }
+                            })?;
+                            let __cliconf_parsed = #parse_entry.map_err(|_| {
+                                ::cliconf::Error::InvalidValue {
+                                    flag: ::std::borrow::ToOwned::to_owned(#flag),
+                                    value: ::std::borrow::ToOwned::to_owned(__cliconf_value),
+                                    source: ::std::option::Option::None,
+                                }
+                            })?;
+                            if __cliconf_first {
+                                self.#field_name.clear();
+                            }
+                            self.#field_name.insert(__cliconf_entry, __cliconf_parsed);
+                            ::std::result::Result::Ok(())
+                        }
+                    });
+                    continue;
+                }
+                if cliconf_attrs.env_prefix_map.is_some() {
+                    return Err(syn::Error::new_spanned(
+                        &f.ty,
+                        "env_prefix_map requires a HashMap or BTreeMap field",
+                    ));
+                }
+
+                if cliconf_attrs.count && (is_bool(ty) || field_is_vec) {
+                    return Err(syn::Error::new_spanned(
+                        &f.ty,
+                        "count requires an integer field",
+                    ));
+                }
+                let arity = if is_bool(ty) || cliconf_attrs.count {
+                    quote! { ::cliconf::engine::Arity::Switch }
+                } else {
+                    quote! { ::cliconf::engine::Arity::Value }
+                };
+                let lookup = quote! { ::std::option::Option::Some((#key, #arity)) };
+
+                if let Some(resolver) = &cliconf_attrs.auto {
+                    auto_defaults.push(quote! {
+                        ::cliconf::engine::AutoDefault {
+                            key: #key,
+                            flag: ::std::borrow::ToOwned::to_owned(#flag),
+                            resolver: ::std::borrow::ToOwned::to_owned(#resolver),
+                        }
+                    });
+                }
+
+                let description = match cliconf_attrs.description.or_else(|| get_doc(&f.attrs)) {
+                    Some(description) => quote! {
+                        ::std::option::Option::Some(::std::borrow::ToOwned::to_owned(#description))
+                    },
+                    None => quote! { ::std::option::Option::None },
+                };
+                let shorthand = match &cliconf_attrs.shorthand {
+                    Some(lit) => quote! { ::std::option::Option::Some(#lit) },
+                    None => quote! { ::std::option::Option::None },
+                };
+                let required_flag = cliconf_attrs.required;
+                // The names a FlagEnum takes, or None for any other type
+                let item_ty = vec_item(ty).unwrap_or(ty);
+                let choices = quote! {
+                    {
+                        use ::cliconf::flag_enum::{IsFlagEnum as _, NotFlagEnum as _};
+                        (&::cliconf::flag_enum::Probe::<#item_ty>(::std::marker::PhantomData))
+                            .choices()
+                    }
+                };
+                let value_hint = if is_bool(ty) || cliconf_attrs.count {
+                    quote! { ::std::option::Option::None }
+                } else if is_path(ty) {
+                    quote! { ::std::option::Option::Some(::cliconf::meta::ValueHint::Path) }
+                } else {
+                    quote! {
+                        ::std::option::Option::Some(::cliconf::flag_enum::value_hint(#choices))
+                    }
+                };
+                let kind = flag_kind(vec_item(ty).unwrap_or(ty));
+                let default_text = match &cliconf_attrs.default {
+                    Some(Expr::Lit(ExprLit { lit, .. })) => match lit {
+                        Lit::Str(s) => Some(s.value()),
+                        Lit::Int(n) => Some(n.base10_digits().to_owned()),
+                        Lit::Float(n) => Some(n.base10_digits().to_owned()),
+                        Lit::Bool(b) => Some(b.value.to_string()),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let default_text = match default_text {
+                    Some(text) => quote! {
+                        ::std::option::Option::Some(::std::borrow::ToOwned::to_owned(#text))
+                    },
+                    None => quote! { ::std::option::Option::None },
+                };
+                let unit_text = match &cliconf_attrs.unit {
+                    Some(unit) => quote! {
+                        ::std::option::Option::Some(::std::borrow::ToOwned::to_owned(#unit))
+                    },
+                    None => quote! { ::std::option::Option::None },
+                };
+                let secret_flag = cliconf_attrs.secret;
+                metadata.push(quote! {
+                    __cliconf_metadata.push(::cliconf::meta::FlagMeta {
+                        name: ::std::borrow::ToOwned::to_owned(#arg_name),
+                        shorthand: #shorthand,
+                        description: #description,
+                        env_var: ::std::option::Option::Some(
+                            ::std::borrow::ToOwned::to_owned(#var_name),
+                        ),
+                        required: #required_flag,
+                        value_hint: #value_hint,
+                        kind: #kind,
+                        is_array: #field_is_vec,
+                        default: #default_text,
+                        unit: #unit_text,
+                        secret: #secret_flag,
+                    });
+                });
+
+                long.push((arg_name.clone(), lookup.clone()));
+                for alias in &cliconf_attrs.aliases {
+                    let name = alias.value();
+                    if let Some(other) = long_names.insert(name.clone(), field_name_string.clone())
+                    {
+                        return Err(syn::Error::new_spanned(
+                            alias,
+                            format!("flag --{name} is already used by `{other}`"),
+                        ));
+                    }
+                    long.push((name, lookup.clone()));
+                }
+
+                if let Some(lit) = cliconf_attrs.shorthand {
+                    let c = lit.value();
+                    if let Some(other) = shorthands.insert(c, field_name_string.clone()) {
+                        return Err(syn::Error::new_spanned(
+                            lit,
+                            format!("shorthand '{c}' is already used by `{other}`"),
+                        ));
+                    }
+                    short.push(quote! { #c => #lookup, });
+                }
+
+                if let Some(other) = env_names.insert(var_name.clone(), field_name_string.clone()) {
+                    return Err(syn::Error::new_spanned(
+                        field_name,
+                        format!("environment variable {var_name} is already used by `{other}`"),
+                    ));
+                }
+                let delimiter = match cliconf_attrs.delimiter {
+                    Some(delimiter) => {
+                        quote! { ::std::option::Option::Some(::std::borrow::ToOwned::to_owned(#delimiter)) }
+                    }
+                    None => quote! { ::std::option::Option::None },
+                };
+                env_vars.push(quote! {
+                    ::cliconf::engine::EnvVar {
+                        key: #key,
+                        var: ::std::borrow::ToOwned::to_owned(#var_name),
+                        delimiter: #delimiter,
+                        is_array: #field_is_vec,
+                    }
+                });
+
+                if is_bool(ty) {
+                    let value = wrap(quote! { true });
+                    set_switch.push(quote! {
+                        #key => {
+                            self.#field_name = #value;
+                            ::std::result::Result::Ok(())
+                        }
+                    });
+                } else if cliconf_attrs.count {
+                    set_switch.push(quote! {
+                        #key => {
+                            let __cliconf_count = #field_mut;
+                            *__cliconf_count = __cliconf_count.saturating_add(1);
+                            ::std::result::Result::Ok(())
+                        }
+                    });
+                }
+
+                let invalid = quote! {
+                    ::cliconf::flag_enum::invalid_value(#flag, __cliconf_value, #choices)
+                };
+                if let (Some(parse_with), Some(_)) =
+                    (&cliconf_attrs.parse_with, &cliconf_attrs.unit)
+                {
+                    return Err(syn::Error::new_spanned(
+                        parse_with,
+                        "parse_with can't be combined with unit",
+                    ));
+                }
+                if is_duration(item_ty) && cliconf_attrs.unit.is_some() {
+                    return Err(syn::Error::new_spanned(
+                        ty,
+                        "Duration fields read their own units, e.g. `30s`, so they can't have a unit",
+                    ));
+                }
+                let parse_value = match (&cliconf_attrs.parse_with, &cliconf_attrs.unit) {
+                    (Some(parse_with), _) => quote! {
+                        #parse_with(__cliconf_value).map_err(|_| #invalid)?
+                    },
+                    // Cow<str> has no FromStr, and can't fail anyway
+                    _ if is_cow(item_ty) => quote! {
+                        ::std::borrow::Cow::Owned(::std::borrow::ToOwned::to_owned(__cliconf_value))
+                    },
+                    _ if is_duration(item_ty) => quote! {
+                        ::cliconf::units::parse_duration(__cliconf_value).ok_or_else(|| #invalid)?
+                    },
+                    (None, Some(unit)) => quote! {
+                        ::cliconf::units::convert(__cliconf_value, #unit)
+                            .and_then(|__cliconf_converted| __cliconf_converted.parse().ok())
+                            .ok_or_else(|| #invalid)?
+                    },
+                    (None, None) => quote! {
+                        __cliconf_value.parse().map_err(|_| #invalid)?
+                    },
+                };
+                let parse_value = match cliconf_attrs.expand {
+                    true => quote! {
+                        {
+                            let __cliconf_expanded = ::cliconf::paths::expand(__cliconf_value)
+                                .ok_or_else(|| #invalid)?;
+                            let __cliconf_value: &str = &__cliconf_expanded;
+                            #parse_value
+                        }
+                    },
+                    false => parse_value,
+                };
+                // A string default is parsed like a value from the command
+                // line, and anything else is an expression of the flag's type
+                let default_init = match &cliconf_attrs.default {
+                    Some(Expr::Lit(ExprLit {
+                        lit: Lit::Str(s), ..
+                    })) => {
+                        let parsed = if field_is_vec {
+                            wrap(quote! { ::std::vec![__cliconf_parsed] })
+                        } else {
+                            wrap(quote! { __cliconf_parsed })
+                        };
+                        let message = format!("invalid default for `{field_name_string}`: {{}}");
+                        quote! {
+                            {
+                                let __cliconf_value: &str = #s;
+                                let __cliconf_parse = || -> ::std::result::Result<_, ::cliconf::Error> {
+                                    let __cliconf_parsed = #parse_value;
+                                    ::std::result::Result::Ok(__cliconf_parsed)
+                                };
+                                let __cliconf_parsed = __cliconf_parse()
+                                    .unwrap_or_else(|__cliconf_err| ::std::panic!(#message, __cliconf_err));
+                                #parsed
+                            }
+                        }
+                    }
+                    Some(expr) if field_is_vec => quote! { #expr },
+                    Some(expr) => wrap(quote! { #expr }),
+                    None => default_expr,
+                };
+                default_inits.push(quote! { #field_name: #default_init });
+
+                let set_value_op = if field_is_vec {
+                    quote! {
+                        let __cliconf_parsed = #parse_value;
+                        let __cliconf_vec = #field_mut;
+                        if __cliconf_first {
+                            __cliconf_vec.clear();
+                        }
+                        __cliconf_vec.push(__cliconf_parsed);
+                    }
+                } else {
+                    let value = wrap(quote! { __cliconf_parsed });
+                    quote! {
+                        let __cliconf_parsed = #parse_value;
+                        self.#field_name = #value;
+                    }
+                };
+                set_value.push(match &cliconf_attrs.from_command {
+                    Some(command) => {
+                        let command = match command {
+                            Some(command) => quote! {
+                                ::std::option::Option::Some(::std::borrow::ToOwned::to_owned(#command))
+                            },
+                            None => quote! { ::std::option::Option::None },
+                        };
+                        let timeout = match cliconf_attrs.command_timeout_ms {
+                            Some(ms) => quote! { ::std::time::Duration::from_millis(#ms) },
+                            None => quote! { ::cliconf::exec::DEFAULT_TIMEOUT },
+                        };
+                        quote! {
+                            #key => ::cliconf::exec::FromCommand {
+                                command: #command,
+                                timeout: #timeout,
+                            }
+                            .apply(#flag, __cliconf_value, |__cliconf_value| {
+                                #set_value_op
+                                ::std::result::Result::Ok(())
+                            }),
+                        }
+                    }
+                    None => quote! {
+                        #key => {
+                            #set_value_op
+                            ::std::result::Result::Ok(())
+                        }
+                    },
+                });
+                if let Some(setting) = &cliconf_attrs.gsettings {
+                    gsettings.push(quote! { (#key, #setting) });
+                }
+                if let Some(setting) = &cliconf_attrs.defaults {
+                    defaults.push(quote! { (#key, #setting) });
+                }
+                if field_is_vec {
+                    clear_array.push(quote! {
+                        #key => {
+                            let __cliconf_vec = #field_mut;
+                            __cliconf_vec.clear();
+                            true
+                        }
+                    });
+                }
+                reset.push(quote! {
+                    #key => {
+                        self.#field_name =
+                            <Self as ::std::default::Default>::default().#field_name;
+                        ::std::result::Result::Ok(())
+                    }
+                });
+
+                // Values always follow an `=`, so ones that start with `-`
+                // aren't mistaken for flags
+                let display = |value: proc_macro2::TokenStream| match is_duration(ty) {
+                    true => quote! { ::cliconf::units::format_duration(#value) },
+                    false => value,
+                };
+                let item = display(quote! { __cliconf_item });
+                let value = display(quote! { __cliconf_value });
+                let push = if is_bool(ty) {
+                    quote! {
+                        if *__cliconf_value {
+                            __cliconf_args.push(::std::borrow::ToOwned::to_owned(#flag));
+                        } else {
+                            __cliconf_args.push(::std::format!("{}=false", #flag));
+                        }
+                    }
+                } else if field_is_vec {
+                    quote! {
+                        for __cliconf_item in __cliconf_value.iter() {
+                            __cliconf_args.push(::std::format!("{}={}", #flag, #item));
+                        }
+                    }
+                } else {
+                    quote! {
+                        __cliconf_args.push(::std::format!("{}={}", #flag, #value));
+                    }
+                };
+                let deref = match &pointer {
+                    Some(_) => quote! { let __cliconf_value = &**__cliconf_value; },
+                    None => quote! {},
+                };
+                to_args.push(if is_option {
+                    quote! {
+                        if let ::std::option::Option::Some(__cliconf_value) = &self.#field_name {
+                            #deref
+                            #push
+                        }
+                    }
+                } else {
+                    quote! {
+                        let __cliconf_value = &self.#field_name;
+                        #deref
+                        #push
+                    }
+                });
+
+                // Unset optional fields are left out, having no value to show
+                let to_string = match is_duration(ty) {
+                    true => quote! { ::cliconf::units::format_duration },
+                    false => quote! { ::std::string::ToString::to_string },
+                };
+                let values = if field_is_vec {
+                    quote! {
+                        ::std::iter::Iterator::collect(::std::iter::Iterator::map(
+                            __cliconf_value.iter(),
+                            #to_string,
+                        ))
+                    }
+                } else {
+                    quote! { ::std::vec![#to_string(__cliconf_value)] }
+                };
+                let secret = cliconf_attrs.secret;
+                let source = match sources_field {
+                    Some(sources_field) => quote! {
+                        ::std::option::Option::Some(::std::clone::Clone::clone(
+                            ::cliconf::Sources::get(&self.#sources_field, #arg_name),
+                        ))
+                    },
+                    None => quote! { ::std::option::Option::None },
+                };
+                let entry = quote! {
+                    __cliconf_entries.push(::cliconf::dump::Entry {
+                        name: ::std::borrow::ToOwned::to_owned(#arg_name),
+                        env_var: ::std::option::Option::Some(
+                            ::std::borrow::ToOwned::to_owned(#var_name),
+                        ),
+                        values: #values,
+                        is_array: #field_is_vec,
+                        secret: #secret,
+                        source: #source,
+                        unit: #unit_text,
+                    });
+                };
+                let entry = if is_option {
+                    quote! {
+                        if let ::std::option::Option::Some(__cliconf_value) = &self.#field_name {
+                            #deref
+                            #entry
+                        }
+                    }
+                } else {
+                    quote! {
+                        let __cliconf_value = &self.#field_name;
+                        #deref
+                        #entry
+                    }
+                };
+                if cliconf_attrs.telemetry {
+                    if secret || is_free_form(ty) {
+                        return Err(syn::Error::new_spanned(
+                            field_name,
+                            "telemetry fields can't be secrets or strings, which may hold personal data",
+                        ));
+                    }
+                    telemetry_entries.push(entry.clone());
+                }
+                dump_entries.push(entry);
+            }
+        } else {
+            return Err(syn::Error::new_spanned(
+                &data_struct.fields,
+                "cliconf::Parse can only be derived for structs with named fields",
+            ));
+        }
+    } else {
+        return Err(syn::Error::new_spanned(
+            name,
+            "cliconf::Parse can only be derived for structs",
+        ));
+    };
+
+    // A field's own --help or -h flag takes precedence
+    let mut help_args = vec![];
+    if !long.iter().any(|(name, _)| name == "help") {
+        help_args.push("--help");
+    }
+    if !shorthands.contains_key(&'h') {
+        help_args.push("-h");
+    }
+    let is_help_arg = (!help_args.is_empty()).then(|| {
+        quote! {
+            fn is_help_arg(&self, __cliconf_arg: &str) -> bool {
+                #(__cliconf_arg == #help_args)||*
+            }
+        }
+    });
+
+    // Likewise a field's own --help-all flag
+    let is_help_all_arg = (!long.iter().any(|(name, _)| name == "help-all")).then(|| {
+        quote! {
+            fn is_help_all_arg(&self, __cliconf_arg: &str) -> bool {
+                __cliconf_arg == "--help-all"
+            }
+        }
+    });
+
+    // Likewise a field's own --version or -V flag
+    let mut version_args = vec![];
+    if !long.iter().any(|(name, _)| name == "version") {
+        version_args.push("--version");
+    }
+    if !shorthands.contains_key(&'V') {
+        version_args.push("-V");
+    }
+    let is_version_arg = (!version_args.is_empty()).then(|| {
+        quote! {
+            fn is_version_arg(&self, __cliconf_arg: &str) -> bool {
+                #(__cliconf_arg == #version_args)||*
+            }
+        }
+    });
+
+    // Comparing names one by one only falls behind hashing them somewhere
+    // between 10 and 100 flags; see benches/parse.rs
+    let matcher = struct_attrs
+        .matcher
+        .unwrap_or(if long.len() > HASHED_ABOVE {
+            Matcher::Hashed
+        } else {
+            Matcher::Match
+        });
+    let long = string_match(
+        &matcher,
+        quote! { __cliconf_name },
+        &long,
+        quote! { ::std::option::Option::None },
+    );
+
+    // A flattened struct's keys come after the outer struct's own,
+    // interleaved so that each one's keys can be told apart without knowing
+    // how many it has
+    let flat_count = flattened.len();
+    // Constant factors are left out, so they don't trip clippy
+    let outer_key = |i: usize, inner: proc_macro2::TokenStream| {
+        let start = field_count + i;
+        if flat_count == 1 {
+            quote! { #start + #inner }
+        } else {
+            quote! { #start + #inner * #flat_count }
+        }
+    };
+    let flatten_arm = |op: &dyn Fn(&Option<Ident>, &str) -> proc_macro2::TokenStream| {
+        if let [(field, prefix, _)] = flattened.as_slice() {
+            let op = op(field, prefix);
+            return Some(quote! {
+                __cliconf_flat if __cliconf_flat >= #field_count => {
+                    let __cliconf_inner_key = __cliconf_flat - #field_count;
+                    #op
+                }
+            });
+        }
+        (!flattened.is_empty()).then(|| {
+            let arms = flattened.iter().enumerate().map(|(i, (field, prefix, _))| {
+                let op = op(field, prefix);
+                quote! { #i => { #op } }
+            });
+            quote! {
+                __cliconf_flat if __cliconf_flat >= #field_count => {
+                    let __cliconf_inner_key = (__cliconf_flat - #field_count) / #flat_count;
+                    match (__cliconf_flat - #field_count) % #flat_count {
+                        #(#arms)*
+                        _ => ::std::unreachable!(),
+                    }
+                }
+            }
+        })
+    };
+    let long = if flattened.is_empty() {
+        long
+    } else {
+        let lookups = flattened.iter().enumerate().map(|(i, (field, prefix, _))| {
+            let key = outer_key(i, quote! { __cliconf_key });
+            quote! {
+                if let ::std::option::Option::Some(__cliconf_rest) = __cliconf_name.strip_prefix(#prefix) {
+                    if let ::std::option::Option::Some((__cliconf_key, __cliconf_arity)) =
+                        ::cliconf::engine::Dispatch::long(&self.#field, __cliconf_rest)
+                    {
+                        return ::std::option::Option::Some((#key, __cliconf_arity));
+                    }
+                }
+            }
+        });
+        quote! {
+            let __cliconf_found: ::std::option::Option<(usize, ::cliconf::engine::Arity)> = #long;
+            if __cliconf_found.is_some() {
+                return __cliconf_found;
+            }
+            #(#lookups)*
+            ::std::option::Option::None
+        }
+    };
+    // Errors from a flattened struct name its flags with the prefix
+    let prefix_error = |op: proc_macro2::TokenStream, prefix: &str| {
+        quote! {
+            #op.map_err(|__cliconf_err| ::cliconf::engine::prefix_error(__cliconf_err, #prefix))
+        }
+    };
+    let flat_set_switch = flatten_arm(&|field, prefix| {
+        prefix_error(
+            quote! {
+                ::cliconf::engine::Dispatch::set_switch(
+                    &mut self.#field,
+                    &__cliconf_inner_key,
+                    __cliconf_source,
+                )
+            },
+            prefix,
+        )
+    });
+    let flat_set_value = flatten_arm(&|field, prefix| {
+        prefix_error(
+            quote! {
+                ::cliconf::engine::Dispatch::set_value(
+                    &mut self.#field,
+                    &__cliconf_inner_key,
+                    __cliconf_value,
+                    __cliconf_first,
+                    __cliconf_source,
+                )
+            },
+            prefix,
+        )
+    });
+    let flat_set_entry = flatten_arm(&|field, prefix| {
+        prefix_error(
+            quote! {
+                ::cliconf::engine::Dispatch::set_entry(
+                    &mut self.#field,
+                    &__cliconf_inner_key,
+                    __cliconf_name,
+                    __cliconf_value,
+                    __cliconf_first,
+                    __cliconf_source,
+                )
+            },
+            prefix,
+        )
+    });
+    let flat_clear_array = flatten_arm(&|field, _| {
+        quote! {
+            ::cliconf::engine::Dispatch::clear_array(&mut self.#field, &__cliconf_inner_key, __cliconf_source)
+        }
+    });
+    let flat_reset = flatten_arm(&|field, _| {
+        quote! {
+            let mut __cliconf_default = <Self as ::std::default::Default>::default().#field;
+            ::cliconf::engine::Dispatch::swap_field(
+                &mut self.#field,
+                &__cliconf_inner_key,
+                &mut __cliconf_default,
+            );
+            ::std::result::Result::Ok(())
+        }
+    });
+    let flat_swap_field = flatten_arm(&|field, _| {
+        quote! {
+            ::cliconf::engine::Dispatch::swap_field(
+                &mut self.#field,
+                &__cliconf_inner_key,
+                &mut __cliconf_other.#field,
+            )
+        }
+    });
+    let source = if flattened.is_empty() && sources_field.is_none() {
+        quote! { _ }
+    } else {
+        quote! { __cliconf_source }
+    };
+    // With a sources field, wraps a method's body to record the source of
+    // the field it sets when `is_ok` holds for its result
+    let tracked = |body: proc_macro2::TokenStream, is_ok: proc_macro2::TokenStream| {
+        let Some(sources_field) = sources_field else {
+            return body;
+        };
+        let deprecated_names = deprecated.iter().map(|(name, _)| name);
+        let record_deprecated = (!deprecated.is_empty()).then(|| {
+            quote! {
+                if #(__cliconf_flag == #deprecated_names)||* {
+                    ::cliconf::Sources::record_deprecated(&mut self.#sources_field, __cliconf_flag, __cliconf_source);
+                }
+            }
+        });
+        quote! {
+            let __cliconf_result = { #body };
+            if #is_ok {
+                let __cliconf_flag = match *__cliconf_key {
+                    #(#source_names)*
+                    _ => ::std::option::Option::None,
+                };
+                if let ::std::option::Option::Some(__cliconf_flag) = __cliconf_flag {
+                    ::cliconf::Sources::record(&mut self.#sources_field, __cliconf_flag, __cliconf_source);
+                    #record_deprecated
+                }
+            }
+            __cliconf_result
+        }
+    };
+    let is_ok = quote! { ::std::result::Result::is_ok(&__cliconf_result) };
+    let flat_env_vars = flattened
+        .iter()
+        .zip(&flat_types)
+        .enumerate()
+        .map(|(i, ((field, _, var_prefix), ty))| {
+            let key = outer_key(i, quote! { __cliconf_var.key });
+            quote! {
+                for __cliconf_var in ::cliconf::engine::Dispatch::env_vars(&self.#field) {
+                    __cliconf_env_vars.push(::cliconf::engine::EnvVar {
+                        key: #key,
+                        var: ::cliconf::engine::nested_env_var::<#ty>(#var_prefix, &__cliconf_var.var),
+                        delimiter: __cliconf_var.delimiter,
+                        is_array: __cliconf_var.is_array,
+                    });
+                }
+            }
+        });
+    let flat_env_prefixes = flattened
+        .iter()
+        .enumerate()
+        .map(|(i, (field, _, var_prefix))| {
+            let key = outer_key(i, quote! { __cliconf_prefix.key });
+            quote! {
+                for __cliconf_prefix in ::cliconf::engine::Dispatch::env_prefixes(&self.#field) {
+                    __cliconf_prefixes.push(::cliconf::engine::EnvPrefix {
+                        key: #key,
+                        prefix: ::std::format!("{}{}", #var_prefix, __cliconf_prefix.prefix),
+                    });
+                }
+            }
+        });
+    let flat_auto_defaults = flattened.iter().enumerate().map(|(i, (field, prefix, _))| {
+        let key = outer_key(i, quote! { __cliconf_auto.key });
+        quote! {
+            for __cliconf_auto in ::cliconf::engine::Dispatch::auto_defaults(&self.#field) {
+                __cliconf_autos.push(::cliconf::engine::AutoDefault {
+                    key: #key,
+                    flag: ::std::format!(
+                        "--{}{}",
+                        #prefix,
+                        __cliconf_auto.flag.trim_start_matches('-'),
+                    ),
+                    resolver: __cliconf_auto.resolver,
+                });
+            }
+        }
+    });
+
+    // A struct flattening this one puts its own prefix before the stems of
+    // the variables named after flags, including those of nested structs
+    let env_stem = {
+        let own_prefix = match struct_attrs.env_prefix.as_deref() {
+            Some(prefix) if !prefix.is_empty() && !prefix.ends_with('_') => format!("{prefix}_"),
+            prefix => prefix.unwrap_or("").to_string(),
+        };
+        let custom = (!custom_env_vars.is_empty()).then(|| {
+            quote! { ::std::matches!(__cliconf_var, #(#custom_env_vars)|*) }
+        });
+        let nested_custom = flat_types.iter().map(|ty| {
+            quote! { ::std::option::Option::is_none(&<#ty as ::cliconf::engine::Dispatch>::env_stem(__cliconf_var)) }
+        });
+        let checks: Vec<_> = custom.into_iter().chain(nested_custom).collect();
+        let check = (!checks.is_empty()).then(|| {
+            quote! {
+                if #(#checks)||* {
+                    return ::std::option::Option::None;
+                }
+            }
+        });
+        (check.is_some() || !own_prefix.is_empty()).then(|| {
+            quote! {
+                fn env_stem(__cliconf_var: &str) -> ::std::option::Option<&str> {
+                    #check
+                    ::std::option::Option::Some(
+                        __cliconf_var.strip_prefix(#own_prefix).unwrap_or(__cliconf_var),
+                    )
+                }
+            }
+        })
+    };
+
+    let arg_syntax = (!struct_attrs.arg_syntax.is_empty()).then(|| {
+        let (fields, variants): (Vec<_>, Vec<_>) = struct_attrs.arg_syntax.iter().cloned().unzip();
+        quote! {
+            fn arg_syntax(&self) -> ::cliconf::engine::ArgSyntax {
+                ::cliconf::engine::ArgSyntax {
+                    #(#fields: ::cliconf::engine::SpecialArg::#variants,)*
+                    ..::std::default::Default::default()
+                }
+            }
+        }
+    });
+
+    let env_case_insensitive = struct_attrs.env_case_insensitive.map(|b| {
+        quote! {
+            fn env_case_insensitive(&self) -> bool {
+                #b
+            }
+        }
+    });
+
+    let coerce = struct_attrs.coerce.then(|| {
+        quote! {
+            fn coerce(&self) -> bool {
+                true
+            }
+        }
+    });
+
+    let set_entry_body = tracked(
+        quote! {
+            match *__cliconf_key {
+                #(#set_entry)*
+                #flat_set_entry
+                _ => ::std::unreachable!(),
+            }
+        },
+        is_ok.clone(),
+    );
+
+    let env_prefix_methods = if env_prefixes.is_empty() && flattened.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            fn env_prefixes(&self) -> ::std::vec::Vec<::cliconf::engine::EnvPrefix<usize>> {
+                #[allow(unused_mut)]
+                let mut __cliconf_prefixes = ::std::vec![#(#env_prefixes),*];
+                #(#flat_env_prefixes)*
+                __cliconf_prefixes
+            }
+
+            fn set_entry(
+                &mut self,
+                __cliconf_key: &usize,
+                __cliconf_name: &str,
+                __cliconf_value: &str,
+                __cliconf_first: bool,
+                #source: &::cliconf::Source,
+            ) -> ::std::result::Result<(), ::cliconf::Error> {
+                #set_entry_body
+            }
+        }
+    };
+
+    let unset_methods =
+        if struct_attrs.unset_sentinel.is_none() && struct_attrs.unset_flag.is_none() {
+            quote! {}
+        } else {
+            let option = |value: &Option<String>| match value {
+                Some(value) => quote! { ::std::option::Option::Some(#value) },
+                None => quote! { ::std::option::Option::None },
+            };
+            let unset_sentinel = option(&struct_attrs.unset_sentinel);
+            let reset_source = match sources_field {
+                Some(_) => quote! { __cliconf_source },
+                None => quote! { _ },
+            };
+            let reset_body = tracked(
+                quote! {
+                    match *__cliconf_key {
+                        #(#reset)*
+                        #flat_reset
+                        _ => ::std::unreachable!(),
+                    }
+                },
+                is_ok.clone(),
+            );
+            let unset_flag = option(&struct_attrs.unset_flag);
+            quote! {
+                fn unset_sentinel(&self) -> ::std::option::Option<&str> {
+                    #unset_sentinel
+                }
+
+                fn unset_flag(&self) -> ::std::option::Option<&str> {
+                    #unset_flag
+                }
+
+                fn reset(
+                    &mut self,
+                    __cliconf_key: &usize,
+                    #reset_source: &::cliconf::Source,
+                ) -> ::std::result::Result<(), ::cliconf::Error> {
+                    #reset_body
+                }
+            }
+        };
+
+    let auto_defaults_method = (!auto_defaults.is_empty() || !flattened.is_empty()).then(|| {
+        quote! {
+            fn auto_defaults(&self) -> ::std::vec::Vec<::cliconf::engine::AutoDefault<usize>> {
+                #[allow(unused_mut)]
+                let mut __cliconf_autos = ::std::vec![#(#auto_defaults),*];
+                #(#flat_auto_defaults)*
+                __cliconf_autos
+            }
+        }
+    });
+
+    let set_flag = struct_attrs.set_flag.as_ref().map(|set_flag| {
+        quote! {
+            fn set_flag(&self) -> ::std::option::Option<&str> {
+                ::std::option::Option::Some(#set_flag)
+            }
+        }
+    });
+
+    let print_config_methods = match &struct_attrs.print_config {
+        Some(print_config) => {
+            if let Some(other) = long_names.get(&print_config.value()) {
+                return Err(syn::Error::new_spanned(
+                    print_config,
+                    format!(
+                        "flag --{} is already used by `{other}`",
+                        print_config.value()
+                    ),
+                ));
+            }
+            quote! {
+                fn print_config_flag(&self) -> ::std::option::Option<&str> {
+                    ::std::option::Option::Some(#print_config)
+                }
+            }
+        }
+        None => quote! {},
+    };
+    let dumps = struct_attrs.print_config.is_some() || struct_attrs.dump;
+    let dump_entries_method = dumps.then(|| {
+        quote! {
+            fn dump_entries(&self) -> ::std::vec::Vec<::cliconf::dump::Entry> {
+                let mut __cliconf_entries = ::std::vec::Vec::new();
+                #(#dump_entries)*
+                __cliconf_entries
+            }
+        }
+    });
+
+    let clear_array_body = tracked(
+        quote! {
+            match *__cliconf_key {
+                #(#clear_array)*
+                #flat_clear_array
+                _ => false,
+            }
+        },
+        quote! { __cliconf_result },
+    );
+    let set_switch_body = quote! {
+        match *__cliconf_key {
+            #(#set_switch)*
+            #flat_set_switch
+            _ => ::std::unreachable!(),
+        }
+    };
+    // Without any switches there's nothing to record, and recording after a
+    // match that always panics wouldn't compile without warnings
+    let set_switch_body = match set_switch.is_empty() && flattened.is_empty() {
+        true => set_switch_body,
+        false => tracked(set_switch_body, is_ok.clone()),
+    };
+    let set_value_body = tracked(
+        quote! {
+            match *__cliconf_key {
+                #(#set_value)*
+                #flat_set_value
+                _ => ::std::unreachable!(),
+            }
+        },
+        is_ok,
+    );
+    // Swapping a field also swaps where its value came from
+    let swap_sources = sources_field.map(|sources_field| {
+        quote! {
+            let __cliconf_flag = match *__cliconf_key {
+                #(#source_names)*
+                _ => ::std::option::Option::None,
+            };
+            if let ::std::option::Option::Some(__cliconf_flag) = __cliconf_flag {
+                ::cliconf::Sources::swap(
+                    &mut self.#sources_field,
+                    &mut __cliconf_other.#sources_field,
+                    __cliconf_flag,
+                );
+            }
+        }
+    });
+    let flat_completer = flatten_arm(&|field, _| {
+        quote! {
+            ::cliconf::engine::Dispatch::completer(&self.#field, &__cliconf_inner_key)
+        }
+    });
+    let completer = (!completers.is_empty() || !flattened.is_empty()).then(|| {
+        quote! {
+            fn completer(
+                &self,
+                __cliconf_key: &usize,
+            ) -> ::std::option::Option<::cliconf::complete::Completer> {
+                match *__cliconf_key {
+                    #flat_completer
+                    #(#completers)*
+                    _ => ::std::option::Option::None,
+                }
+            }
+        }
+    });
+    let flat_flag_name = flatten_arm(&|field, prefix| {
+        quote! {
+            ::std::option::Option::map(
+                ::cliconf::engine::Dispatch::flag_name(&self.#field, &__cliconf_inner_key),
+                |__cliconf_name| ::std::format!("{}{}", #prefix, __cliconf_name),
+            )
+        }
+    });
+    let flat_source = flatten_arm(&|field, _| {
+        quote! {
+            ::cliconf::engine::Dispatch::source(&self.#field, &__cliconf_inner_key)
+        }
+    });
+    let source_method = (sources_field.is_some() || !flattened.is_empty()).then(|| {
+        let own = match sources_field {
+            Some(sources_field) => quote! {
+                let __cliconf_flag: ::std::option::Option<&str> = match __cliconf_own {
+                    #(#source_names)*
+                    _ => ::std::option::Option::None,
+                };
+                ::std::option::Option::map(__cliconf_flag, |__cliconf_flag| {
+                    ::std::clone::Clone::clone(::cliconf::Sources::get(&self.#sources_field, __cliconf_flag))
+                })
+            },
+            None => quote! {
+                let _ = __cliconf_own;
+                ::std::option::Option::None
+            },
+        };
+        quote! {
+            fn source(&self, __cliconf_key: &usize) -> ::std::option::Option<::cliconf::Source> {
+                match *__cliconf_key {
+                    #flat_source
+                    __cliconf_own => { #own }
+                }
+            }
+        }
+    });
+    if let Some(sources_field) = sources_field {
+        swap_immutable.push(quote! {
+            #(
+                ::cliconf::Sources::swap(
+                    &mut self.#sources_field,
+                    &mut __cliconf_other.#sources_field,
+                    #immutable_names,
+                );
+            )*
+        });
+    }
+
+    let sources_methods = (sources_field.is_some() || !flattened.is_empty()).then(|| {
+        let own = sources_field.into_iter().flat_map(|sources_field| {
+            flag_names.iter().map(move |name| {
+                quote! {
+                    __cliconf_sources.push((
+                        ::std::borrow::ToOwned::to_owned(#name),
+                        ::std::clone::Clone::clone(::cliconf::Sources::get(&self.#sources_field, #name)),
+                    ));
+                }
+            })
+        });
+        let nested = flattened.iter().map(|(field, prefix, _)| {
+            quote! {
+                for (__cliconf_name, __cliconf_source) in ::cliconf::engine::Dispatch::sources(&self.#field) {
+                    __cliconf_sources.push((::std::format!("{}{}", #prefix, __cliconf_name), __cliconf_source));
+                }
+            }
+        });
+        quote! {
+            fn sources(&self) -> ::std::vec::Vec<(::std::string::String, ::cliconf::Source)> {
+                let mut __cliconf_sources = ::std::vec::Vec::new();
+                #(#own)*
+                #(#nested)*
+                __cliconf_sources
+            }
+        }
+    });
+
+    let missing_required_method = match (sources_field, required.first()) {
+        (None, Some((_, field_name))) => {
+            return Err(syn::Error::new_spanned(
+                field_name,
+                "required fields need a #[cliconf(sources)] field of type cliconf::Sources to record which fields were set",
+            ));
+        }
+        _ if required.is_empty() && flattened.is_empty() => None,
+        _ => {
+            let checks = sources_field.into_iter().flat_map(|sources_field| {
+                required.iter().map(move |(name, _)| {
+                    quote! {
+                        if ::cliconf::Source::is_default(::cliconf::Sources::get(&self.#sources_field, #name)) {
+                            __cliconf_missing.push(::std::borrow::ToOwned::to_owned(#name));
+                        }
+                    }
+                })
+            });
+            let flat_checks = flattened.iter().map(|(field, prefix, _)| {
+                quote! {
+                    for __cliconf_name in ::cliconf::engine::Dispatch::missing_required(&self.#field) {
+                        __cliconf_missing.push(::std::format!("{}{}", #prefix, __cliconf_name));
+                    }
+                }
+            });
+            Some(quote! {
+                fn missing_required(&self) -> ::std::vec::Vec<::std::string::String> {
+                    let mut __cliconf_missing = ::std::vec::Vec::new();
+                    #(#checks)*
+                    #(#flat_checks)*
+                    __cliconf_missing
+                }
+            })
+        }
+    };
+
+    let deprecated_uses_method = match (sources_field, deprecated.first()) {
+        (None, Some((_, message))) => {
+            return Err(syn::Error::new_spanned(
+                message,
+                "deprecated fields need a #[cliconf(sources)] field of type cliconf::Sources to record which sources set them",
+            ));
+        }
+        _ if deprecated.is_empty() && flattened.is_empty() => None,
+        _ => {
+            let own = sources_field.into_iter().flat_map(|sources_field| {
+                deprecated.iter().map(move |(name, message)| {
+                    quote! {
+                        for __cliconf_source in ::cliconf::Sources::deprecated_uses(&self.#sources_field, #name) {
+                            __cliconf_uses.push((
+                                ::std::borrow::ToOwned::to_owned(#name),
+                                #message,
+                                ::std::clone::Clone::clone(__cliconf_source),
+                            ));
+                        }
+                    }
+                })
+            });
+            let nested = flattened.iter().map(|(field, prefix, _)| {
+                quote! {
+                    for (__cliconf_name, __cliconf_message, __cliconf_source) in ::cliconf::engine::Dispatch::deprecated_uses(&self.#field) {
+                        __cliconf_uses.push((::std::format!("{}{}", #prefix, __cliconf_name), __cliconf_message, __cliconf_source));
+                    }
+                }
+            });
+            Some(quote! {
+                fn deprecated_uses(&self) -> ::std::vec::Vec<(::std::string::String, &'static str, ::cliconf::Source)> {
+                    let mut __cliconf_uses = ::std::vec::Vec::new();
+                    #(#own)*
+                    #(#nested)*
+                    __cliconf_uses
+                }
+            })
+        }
+    };
+
+    let telemetry_methods = (!telemetry_entries.is_empty()).then(|| {
+        quote! {
+            fn telemetry_entries(&self) -> ::std::vec::Vec<::cliconf::dump::Entry> {
+                let mut __cliconf_entries = ::std::vec::Vec::new();
+                #(#telemetry_entries)*
+                __cliconf_entries
+            }
+        }
+    });
+
+    let vis = match &struct_attrs.vis {
+        Some(vis) => quote! { #vis },
+        None => quote! { pub },
+    };
+    let parse_env = struct_attrs.method("parse_env");
+    let parse_process_env = struct_attrs.method("parse_process_env");
+    let parse_args = struct_attrs.method("parse_args");
+    let parse_file = struct_attrs.method("parse_file");
+    let parse_file_with_limits = struct_attrs.method("parse_file_with_limits");
+    let parse_str = struct_attrs.method("parse_str");
+    let parse_bytes = struct_attrs.method("parse_bytes");
+    let apply_arg = struct_attrs.method("apply_arg");
+    let apply_env_var = struct_attrs.method("apply_env_var");
+    let freeze = struct_attrs.method("freeze");
+    let validate = struct_attrs.method("validate");
+    let check = struct_attrs.method("check");
+    let sources_method = sources_field.map(|_| {
+        let sources = struct_attrs.method("sources");
+        quote! {
+            /// Where each field's value came from, by flag name, in the
+            /// order the fields are declared. Flattened structs' fields come
+            /// last, if they have a `sources` field too.
+            #vis fn #sources(&self) -> ::std::vec::Vec<(::std::string::String, ::cliconf::Source)> {
+                ::cliconf::engine::Dispatch::sources(self)
+            }
+        }
+    });
+    let reload_method = struct_attrs.method("reload");
+    let resolve_auto = struct_attrs.method("resolve_auto");
+    let flags_metadata = struct_attrs.method("flags_metadata");
+    let about_method = struct_attrs.method("about");
+    let positionals_method = struct_attrs.method("positionals");
+    let synopsis_method = struct_attrs.method("synopsis");
+    let completions_method = struct_attrs.method("completions");
+    let complete_method = struct_attrs.method("complete");
+    let markdown_method = struct_attrs.method("markdown");
+    let nixos_module_method = struct_attrs.method("nixos_module");
+    let dockerfile_method = struct_attrs.method("dockerfile");
+    let compose_environment_method = struct_attrs.method("compose_environment");
+    let positionals = struct_attrs
+        .positionals
+        .clone()
+        .unwrap_or_else(|| vec![("ARGS".into(), false, true)])
+        .into_iter()
+        .map(|(name, required, variadic)| {
+            quote! {
+                ::cliconf::meta::Positional {
+                    name: ::std::borrow::ToOwned::to_owned(#name),
+                    required: #required,
+                    variadic: #variadic,
+                }
+            }
+        });
+    let declared_positionals = struct_attrs.positionals.is_some().then(|| {
+        quote! {
+            fn declared_positionals(
+                &self,
+            ) -> ::std::option::Option<::std::vec::Vec<::cliconf::meta::Positional>> {
+                ::std::option::Option::Some(Self::#positionals_method())
+            }
+        }
+    });
+    let replay_manifest = struct_attrs.method("replay_manifest");
+    let usage = struct_attrs.method("usage");
+    let usage_styled = struct_attrs.method("usage_styled");
+    let usage_all = struct_attrs.method("usage_all");
+    let usage_all_styled = struct_attrs.method("usage_all_styled");
+    let sanitized_snapshot = (!telemetry_entries.is_empty()).then(|| {
+        let sanitized_snapshot = struct_attrs.method("sanitized_snapshot");
+        quote! { ::cliconf::__sanitized_snapshot!(#vis #sanitized_snapshot); }
+    });
+    let parse_gsettings = (!gsettings.is_empty()).then(|| {
+        let parse_gsettings = struct_attrs.method("parse_gsettings");
+        quote! { ::cliconf::__parse_gsettings!(#vis #parse_gsettings, [#(#gsettings),*]); }
+    });
+    let parse_defaults = (!defaults.is_empty()).then(|| {
+        let parse_defaults = struct_attrs.method("parse_defaults");
+        quote! { ::cliconf::__parse_defaults!(#vis #parse_defaults, [#(#defaults),*]); }
+    });
+    let embedded_defaults_method = struct_attrs
+        .embedded_defaults
+        .is_some()
+        .then(|| struct_attrs.method("parse_embedded_defaults"));
+    let parse_embedded_defaults = struct_attrs.embedded_defaults.as_ref().map(|(path, format)| {
+        quote! {
+            ::cliconf::__parse_embedded_defaults!(#vis #embedded_defaults_method, #format, #path);
+        }
+    });
+    let load = struct_attrs.method("load");
+    let load_with = struct_attrs.method("load_with");
+    let to_args_method = struct_attrs.to_args.then(|| {
+        let to_args_name = struct_attrs.method("to_args");
+        quote! {
+            /// Arguments that recreate every field's current value, e.g. to
+            /// forward the configuration to a child process. Parsing them
+            /// into a struct holding the defaults gives the same values, as
+            /// long as empty `Vec` and `None` fields default to empty and
+            /// `None`, which no argument can express.
+            #vis fn #to_args_name(&self) -> ::std::vec::Vec<::std::string::String> {
+                let mut __cliconf_args = ::std::vec::Vec::new();
+                #(#to_args)*
+                __cliconf_args
+            }
+        }
+    });
+    let dump_method = dumps.then(|| {
+        let dump = struct_attrs.method("dump");
+        quote! {
+            /// The effective configuration in `format`, with secrets
+            /// redacted and, with a `sources` field, where each value came
+            /// from, e.g. for a `config show` command.
+            #vis fn #dump(&self, format: ::cliconf::dump::DumpFormat) -> ::std::string::String {
+                ::cliconf::engine::dump(self, format)
+            }
+        }
+    });
+    let override_string = |field: &str, value: &Option<String>| {
+        let field = Ident::new(field, Span::call_site());
+        value.as_ref().map(|value| {
+            quote! { __cliconf_about.#field = ::std::borrow::ToOwned::to_owned(#value); }
+        })
+    };
+    let override_option = |field: &str, value: &Option<String>| {
+        let field = Ident::new(field, Span::call_site());
+        value.as_ref().map(|value| {
+            quote! {
+                __cliconf_about.#field =
+                    ::std::option::Option::Some(::std::borrow::ToOwned::to_owned(#value));
+            }
+        })
+    };
+    let about_overrides = [
+        override_string("name", &struct_attrs.bin_name),
+        override_string("version", &struct_attrs.version),
+        override_option("about", &struct_attrs.about),
+        override_option("authors", &struct_attrs.author),
+    ];
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let default_impl = has_defaults.then(|| {
+        let new_method = struct_attrs.method("new");
+        quote! {
+            impl #impl_generics ::std::default::Default for #name #ty_generics #where_clause {
+                fn default() -> Self {
+                    Self {
+                        #(#default_inits,)*
+                    }
+                }
+            }
+
+            impl #impl_generics #name #ty_generics #where_clause {
+                /// A struct holding every field's default.
+                #vis fn #new_method() -> Self {
+                    <Self as ::std::default::Default>::default()
+                }
+            }
+        }
+    });
+
+    // Everything below is emitted into the user's crate, so paths are absolute
+    // and locals are prefixed to stay clear of the user's own identifiers. The
+    // parsing itself happens in cliconf::engine; we only describe the fields.
+    let expanded = quote! {
+        impl #impl_generics ::cliconf::engine::Dispatch for #name #ty_generics #where_clause {
+            type Key = usize;
+
+            fn long(&self, __cliconf_name: &str) -> ::std::option::Option<(usize, ::cliconf::engine::Arity)> {
+                #long
+            }
+
+            fn short(&self, __cliconf_c: char) -> ::std::option::Option<(usize, ::cliconf::engine::Arity)> {
+                match __cliconf_c {
+                    #(#short)*
+                    _ => ::std::option::Option::None,
+                }
+            }
+
+            fn env_vars(&self) -> ::std::vec::Vec<::cliconf::engine::EnvVar<usize>> {
+                #[allow(unused_mut)]
+                let mut __cliconf_env_vars = ::std::vec![#(#env_vars),*];
+                #(#flat_env_vars)*
+                __cliconf_env_vars
+            }
+
+            #env_stem
+            #env_case_insensitive
+            #coerce
+            #completer
+
+            fn metadata(&self) -> ::std::vec::Vec<::cliconf::meta::FlagMeta> {
+                Self::#flags_metadata()
+            }
+
+            #env_prefix_methods
+
+            #unset_methods
+
+            #set_flag
+
+            #print_config_methods
+
+            #dump_entries_method
+
+            #telemetry_methods
+
+            #auto_defaults_method
+
+            #arg_syntax
+
+            #is_help_arg
+            #is_help_all_arg
+            #is_version_arg
+
+            fn swap_field(&mut self, __cliconf_key: &usize, __cliconf_other: &mut Self) {
+                match *__cliconf_key {
+                    #(#swap_field)*
+                    #flat_swap_field
+                    _ => ::std::unreachable!(),
+                }
+                #swap_sources
+            }
+
+            fn flag_name(&self, __cliconf_key: &usize) -> ::std::option::Option<::std::string::String> {
+                match *__cliconf_key {
+                    #flat_flag_name
+                    __cliconf_own => {
+                        let __cliconf_flag: ::std::option::Option<&str> = match __cliconf_own {
+                            #(#source_names)*
+                            _ => ::std::option::Option::None,
+                        };
+                        ::std::option::Option::map(__cliconf_flag, ::std::borrow::ToOwned::to_owned)
+                    }
+                }
+            }
+
+            #source_method
+
+            fn swap_immutable(
+                &mut self,
+                __cliconf_other: &mut Self,
+                __cliconf_restart: &mut ::std::vec::Vec<::std::string::String>,
+            ) {
+                #(#swap_immutable)*
+            }
+
+            fn clear_array(&mut self, __cliconf_key: &usize, #source: &::cliconf::Source) -> bool {
+                #clear_array_body
+            }
+
+            fn set_switch(
+                &mut self,
+                __cliconf_key: &usize,
+                #source: &::cliconf::Source,
+            ) -> ::std::result::Result<(), ::cliconf::Error> {
+                #set_switch_body
+            }
+
+            fn set_value(
+                &mut self,
+                __cliconf_key: &usize,
+                __cliconf_value: &str,
+                __cliconf_first: bool,
+                #source: &::cliconf::Source,
+            ) -> ::std::result::Result<(), ::cliconf::Error> {
+                #set_value_body
+            }
+
+            #missing_required_method
+
+            #deprecated_uses_method
+
+            #declared_positionals
+
+            #sources_methods
+        }
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            #vis fn #parse_env(
+                &mut self,
+                vars: ::std::collections::HashMap<::std::string::String, ::std::string::String>,
+            ) -> ::std::result::Result<(), ::cliconf::Error> {
+                ::cliconf::engine::parse_env(self, vars)
+            }
+
+            /// Reads only the environment variables that this struct's fields
+            /// use, straight from the process environment.
+            #vis fn #parse_process_env(&mut self) -> ::std::result::Result<(), ::cliconf::Error> {
+                ::cliconf::engine::parse_process_env(self)
+            }
+
+            /// Parses flags from `args`, returning the positional arguments.
+            #vis fn #parse_args(
+                &mut self,
+                args: ::std::vec::Vec<::std::string::String>,
+            ) -> ::std::result::Result<::std::vec::Vec<::std::string::String>, ::cliconf::Error> {
+                ::cliconf::engine::parse_args(self, args)
+            }
+
+            ::cliconf::__parse_file!(
+                #vis #parse_file, #parse_file_with_limits, #parse_str, #parse_bytes
+            );
+
+            #parse_gsettings
+
+            #parse_defaults
+
+            #parse_embedded_defaults
+
+            ::cliconf::__load!(#vis #load, #load_with, #about_method, [#embedded_defaults_method]);
+
+            /// Applies a single flag by its long name, without the leading
+            /// `--`, for hosts with their own tokenizer. Switches take `None`.
+            #vis fn #apply_arg(
+                &mut self,
+                __cliconf_name: &str,
+                __cliconf_value: ::std::option::Option<&str>,
+            ) -> ::std::result::Result<(), ::cliconf::Error> {
+                ::cliconf::engine::apply_arg(self, __cliconf_name, __cliconf_value)
+            }
+
+            /// Applies a single environment variable, returning whether any
+            /// field reads it.
+            #vis fn #apply_env_var(
+                &mut self,
+                __cliconf_var: &str,
+                __cliconf_value: &str,
+            ) -> ::std::result::Result<bool, ::cliconf::Error> {
+                ::cliconf::engine::apply_env_var(self, __cliconf_var, __cliconf_value)
+            }
+
+            /// Works out the defaults of fields with an auto default. With a
+            /// `sources` field, fields that some source already set are left
+            /// alone; without one, they're overwritten, so call it before
+            /// parsing anything else.
+            #vis fn #resolve_auto(
+                &mut self,
+                resolvers: &::cliconf::auto::Resolvers,
+            ) -> ::std::result::Result<(), ::cliconf::Error> {
+                ::cliconf::engine::resolve_auto(self, resolvers)
+            }
+
+            /// Describes each flag, with descriptions taken from the fields'
+            /// doc comments.
+            #vis fn #flags_metadata() -> ::std::vec::Vec<::cliconf::meta::FlagMeta> {
+                let mut __cliconf_metadata = ::std::vec::Vec::new();
+                #(#metadata)*
+                __cliconf_metadata
+            }
+
+            /// The program's name, version, description and authors for help
+            /// and version output, from the crate's Cargo metadata unless
+            /// overridden.
+            #vis fn #about_method() -> ::cliconf::meta::About {
+                #[allow(unused_mut)]
+                let mut __cliconf_about = ::cliconf::about!();
+                #(#about_overrides)*
+                __cliconf_about
+            }
+
+            ::cliconf::__replay_manifest!(#vis #replay_manifest, #about_method);
+
+            /// The positional arguments, as help names them.
+            #vis fn #positionals_method() -> ::std::vec::Vec<::cliconf::meta::Positional> {
+                ::std::vec![#(#positionals),*]
+            }
+
+            /// The line of help that shows how to call the program, e.g.
+            /// `Usage: mytool [OPTIONS] [--] [ARGS]...`.
+            #vis fn #synopsis_method() -> ::std::string::String {
+                ::cliconf::meta::synopsis(
+                    &Self::#about_method().name,
+                    &Self::#flags_metadata(),
+                    &Self::#positionals_method(),
+                )
+            }
+
+            /// A completion script for `shell` that completes the flags and
+            /// their shorthands.
+            #vis fn #completions_method(
+                shell: ::cliconf::completions::Shell,
+            ) -> ::std::string::String {
+                ::cliconf::completions::generate(
+                    shell,
+                    &Self::#about_method().name,
+                    &Self::#flags_metadata(),
+                )
+            }
+
+            /// Answers the completion scripts: the completions if `args`
+            /// start with `__complete`, or `None` if the program should run
+            /// as usual. Print them to stdout and exit.
+            #vis fn #complete_method(
+                &self,
+                args: &[::std::string::String],
+            ) -> ::std::option::Option<::cliconf::complete::Completions> {
+                ::cliconf::complete::intercept(self, args)
+            }
+
+            /// Markdown documentation of every flag, with its default,
+            /// environment variable and description, for a README or docs
+            /// site.
+            #vis fn #markdown_method() -> ::std::string::String {
+                ::cliconf::docs::markdown(&Self::#about_method(), &Self::#flags_metadata())
+            }
+
+            /// A NixOS module with an option for every flag, which writes
+            /// them to the config file `Loader::for_app` reads.
+            #vis fn #nixos_module_method() -> ::std::string::String {
+                ::cliconf::nix::module(&Self::#about_method(), &Self::#flags_metadata())
+            }
+
+            /// `ARG` and `ENV` lines for a Dockerfile, with each field's
+            /// environment variable, default and description.
+            #vis fn #dockerfile_method() -> ::std::string::String {
+                ::cliconf::docker::dockerfile(&Self::#flags_metadata())
+            }
+
+            /// An `environment:` block for a Compose service, with each
+            /// field's environment variable, default and description.
+            #vis fn #compose_environment_method() -> ::std::string::String {
+                ::cliconf::docker::compose_environment(&Self::#flags_metadata())
+            }
+
+            ::cliconf::__usage!(
+                #vis #usage,
+                #usage_styled,
+                #usage_all,
+                #usage_all_styled,
+                #flags_metadata,
+                #about_method,
+                #positionals_method
+            );
+
+            #to_args_method
+
+            #dump_method
+
+            #sanitized_snapshot
+
+            #sources_method
+
+            /// Checks that every required field was set by some source.
+            #vis fn #validate(&self) -> ::std::result::Result<(), ::cliconf::Error> {
+                ::cliconf::engine::validate(self)
+            }
+
+            /// Validates the configuration, then runs the built-in lints,
+            /// e.g. warning about each source that set a deprecated field,
+            /// for the program to report. See `cliconf::engine::check` for
+            /// other lints.
+            #vis fn #check(
+                &self,
+            ) -> ::std::result::Result<::std::vec::Vec<::cliconf::lint::Warning>, ::cliconf::Error> {
+                ::cliconf::engine::check(self, &[])
+            }
+
+            /// Marks the configuration as final, e.g. once the program has
+            /// started.
+            #vis fn #freeze(self) -> ::cliconf::Frozen<Self> {
+                ::cliconf::Frozen::new(self)
+            }
+
+            /// Applies a freshly loaded configuration, except for immutable
+            /// fields, which keep their current values. Returns the flags of
+            /// the immutable fields whose values changed, which need a
+            /// restart to apply. If the result doesn't validate, nothing
+            /// changes and the error says why.
+            #vis fn #reload_method(
+                &mut self,
+                mut __cliconf_new: Self,
+            ) -> ::std::result::Result<::std::vec::Vec<::std::string::String>, ::cliconf::Error> {
+                let mut __cliconf_restart = ::std::vec::Vec::new();
+                ::cliconf::engine::Dispatch::swap_immutable(self, &mut __cliconf_new, &mut __cliconf_restart);
+                if let ::std::result::Result::Err(__cliconf_err) = ::cliconf::engine::validate(&__cliconf_new) {
+                    // Swapping back leaves the current configuration as it was
+                    ::cliconf::engine::Dispatch::swap_immutable(
+                        self,
+                        &mut __cliconf_new,
+                        &mut ::std::vec::Vec::new(),
+                    );
+                    return ::std::result::Result::Err(__cliconf_err);
+                }
+                *self = __cliconf_new;
+                __cliconf_restart.sort();
+                ::std::result::Result::Ok(__cliconf_restart)
+            }
+        }
+    };
+
+    Ok(quote! {
+        #expanded
+
+        #default_impl
+    })
+}
+
+/// Derives `cliconf::FlagEnum`, `FromStr` and `Display` for an enum of unit
+/// variants, so it can be the type of a flag that takes one of them by name.
+/// Each variant is named in kebab case, e.g. `WarnOnce` as `warn-once`, and
+/// names are matched regardless of case.
+///
+/// Variant attributes:
+///
+/// - `#[cliconf(rename = "...")]`: use this name instead
+#[proc_macro_derive(FlagEnum, attributes(cliconf))]
+pub fn derive_flag_enum(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_flag_enum(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_flag_enum(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let Data::Enum(data_enum) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            name,
+            "cliconf::FlagEnum can only be derived for enums",
+        ));
+    };
+    let mut variants = vec![];
+    let mut names: HashMap<String, &Ident> = HashMap::new();
+    for variant in &data_enum.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(syn::Error::new_spanned(
+                variant,
+                "cliconf::FlagEnum variants can't have fields",
+            ));
+        }
+        let mut rename = None;
+        for meta_list in get_meta_lists(&variant.attrs, "cliconf") {
+            meta_list.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    let s: LitStr = meta.value()?.parse()?;
+                    if s.value().is_empty() {
+                        return Err(syn::Error::new_spanned(s, "the name can't be empty"));
+                    }
+                    rename = Some(s.value());
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown cliconf attribute"))
+                }
+            })?;
+        }
+        let variant_name = rename.unwrap_or_else(|| kebab_case(&variant.ident.unraw().to_string()));
+        if let Some(other) = names.insert(variant_name.to_lowercase(), &variant.ident) {
+            return Err(syn::Error::new_spanned(
+                variant,
+                format!("the name {variant_name:?} is already used by `{other}`"),
+            ));
+        }
+        variants.push((&variant.ident, variant_name));
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let variant_names = variants.iter().map(|(_, name)| name);
+    let from_name = variants.iter().map(|(ident, name)| {
+        quote! {
+            if __cliconf_name.eq_ignore_ascii_case(#name) {
+                return ::std::option::Option::Some(Self::#ident);
+            }
+        }
+    });
+    let to_name = variants
+        .iter()
+        .map(|(ident, name)| quote! { Self::#ident => #name, });
+    Ok(quote! {
+        impl #impl_generics ::cliconf::flag_enum::FlagEnum for #name #ty_generics #where_clause {
+            const NAMES: &'static [&'static str] = &[#(#variant_names),*];
+
+            fn from_name(__cliconf_name: &str) -> ::std::option::Option<Self> {
+                #(#from_name)*
+                ::std::option::Option::None
+            }
+
+            fn name(&self) -> &'static str {
+                match *self {
+                    #(#to_name)*
+                }
+            }
+        }
+
+        impl #impl_generics ::std::str::FromStr for #name #ty_generics #where_clause {
+            type Err = ::cliconf::flag_enum::InvalidChoice;
+
+            fn from_str(__cliconf_value: &str) -> ::std::result::Result<Self, Self::Err> {
+                ::cliconf::flag_enum::parse(__cliconf_value)
+            }
+        }
+
+        impl #impl_generics ::std::fmt::Display for #name #ty_generics #where_clause {
+            fn fmt(&self, __cliconf_f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                __cliconf_f.write_str(::cliconf::flag_enum::FlagEnum::name(self))
+            }
+        }
+    })
+}
+
+/// A variant's name in kebab case, e.g. `warn-once` for `WarnOnce`. Runs of
+/// capitals stay together, so `HTTPProxy` is `http-proxy`.
+fn kebab_case(ident: &str) -> String {
+    let chars: Vec<char> = ident.chars().collect();
+    let mut name = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '_' {
+            name.push('-');
+            continue;
+        }
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            if prev != '_' && (prev.is_lowercase() || prev.is_ascii_digit() || next_is_lower) {
+                name.push('-');
+            }
+        }
+        name.extend(c.to_lowercase());
+    }
+    name
+}
@@ -0,0 +1,64 @@
+//! A long-running service that reloads its configuration on request.
+//!
+//! The config file is `$DAEMON_CONFIG`, or `daemon.json` in the current
+//! directory. Each line on stdin is a command: `show` prints the current
+//! configuration and `reload` reads every source again, keeping `port` since
+//! the socket is already bound. Arguments and `DAEMON_*` variables override
+//! the file, on reload too.
+
+use std::{
+    env,
+    io::{self, BufRead},
+    process,
+};
+
+use cliconf::{Loader, Parse};
+
+#[derive(Parse)]
+#[cliconf(env_prefix = "DAEMON", positionals = "")]
+struct Conf {
+    /// The port to listen on. Changing it needs a restart.
+    #[cliconf(immutable, default = 8000)]
+    port: u16,
+
+    /// How many requests to handle at once
+    #[cliconf(default = 4)]
+    workers: u32,
+
+    #[cliconf(default = "info")]
+    log_level: String,
+}
+
+fn load() -> Result<Conf, cliconf::Error> {
+    let path = env::var_os("DAEMON_CONFIG").unwrap_or_else(|| "daemon.json".into());
+    let mut conf = Conf::new();
+    conf.load_with(&Loader::new().user_file(path))?;
+    Ok(conf)
+}
+
+fn main() {
+    let mut conf = load().unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        process::exit(2);
+    });
+
+    for line in io::stdin().lock().lines() {
+        match line.expect("Failed to read stdin").trim() {
+            "show" => println!(
+                "port={} workers={} log-level={}",
+                conf.port, conf.workers, conf.log_level
+            ),
+            // A bad file keeps the old configuration running
+            "reload" => match load().and_then(|new| conf.reload(new)) {
+                Err(err) => eprintln!("error: {err}"),
+                Ok(restart) => {
+                    for flag in restart {
+                        println!("restart needed for {flag}");
+                    }
+                    println!("reloaded");
+                }
+            },
+            command => eprintln!("error: unknown command {command:?}"),
+        }
+    }
+}
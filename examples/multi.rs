@@ -0,0 +1,74 @@
+//! A tool with subcommands, e.g. `multi --verbose build --release` or
+//! `multi run --port 8080 app`. Global flags go before or after the command.
+//!
+//! Exits with 2 for an unknown command or bad arguments.
+
+use std::process;
+
+use cliconf::{
+    command::{Command, Commands, Invocation},
+    meta::Positional,
+    Flag, FlagValue, Flags,
+};
+
+fn commands() -> Commands {
+    let mut build = Flags::new();
+    build.add(Flag::new("release", FlagValue::Bool(false)).description("Build with optimizations"));
+    build.add(
+        Flag::new("jobs", FlagValue::Int64(1))
+            .shorthand('j')
+            .description("How many jobs to run at once"),
+    );
+    build.positionals = Some(vec![]);
+
+    let mut run = Flags::new();
+    run.add(Flag::new("port", FlagValue::Int64(8000)).description("The port to listen on"));
+    run.positionals = Some(vec![Positional::new("APP")]);
+
+    let mut commands = Commands::new();
+    commands
+        .global
+        .add(Flag::new("verbose", FlagValue::Bool(false)).shorthand('v'));
+    commands.global.help_args = vec!["--help".into(), "-h".into()];
+    commands.add(Command::new("build", build).alias("b"));
+    commands.add(Command::new("run", run));
+    commands
+}
+
+fn main() {
+    let mut commands = commands();
+    let matched = match commands.parse_args(std::env::args().skip(1).collect()) {
+        Ok(Invocation::Command(matched)) => matched,
+        Ok(Invocation::External(external)) => unreachable!("no external prefix: {external:?}"),
+        Err(cliconf::Error::HelpRequested) => {
+            println!("Usage: multi [--verbose] <build|run> [OPTIONS]");
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {err}");
+            process::exit(2);
+        }
+    };
+
+    let verbose = commands.global.get("verbose").value == FlagValue::Bool(true);
+    let flags = &commands.get(&matched.name).flags;
+    let value = |name: &str| flags.get(name).value.to_strings().join(",");
+    match matched.name.as_str() {
+        "build" => {
+            let profile = match flags.get("release").value {
+                FlagValue::Bool(true) => "release",
+                _ => "debug",
+            };
+            println!("building {profile} with {} jobs", value("jobs"));
+        }
+        "run" => println!(
+            "running {} on port {}",
+            matched.positionals[0],
+            value("port")
+        ),
+        _ => unreachable!(),
+    }
+    if verbose {
+        println!("done");
+    }
+}
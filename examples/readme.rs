@@ -4,10 +4,10 @@ use cliconf::Parse;
 struct Conf {
     spanish: bool,
 
-    #[cliconf(shorthand = 'n')]
+    #[cliconf(shorthand = 'n', default = "world")]
     name: String,
 
-    #[cliconf(shorthand = 'r')]
+    #[cliconf(shorthand = 'r', default = 1)]
     repeat: i32,
 
     #[cliconf(shorthand = 'N', delimiter = ",")]
@@ -15,14 +15,14 @@ struct Conf {
 }
 
 fn main() {
-    let mut conf = Conf {
-        spanish: false,
-        name: "world".into(),
-        repeat: 1,
-        extra_names: vec![],
-    };
-    conf.parse_env(std::env::vars().collect());
-    conf.parse_args(std::env::args().skip(1).collect());
+    let mut conf = Conf::new();
+    if let Err(err) = conf
+        .parse_process_env()
+        .and_then(|()| conf.parse_args(std::env::args().skip(1).collect()))
+    {
+        eprintln!("error: {err}");
+        std::process::exit(2);
+    }
     let conf = conf;
 
     let (and, hello) = if conf.spanish {
@@ -0,0 +1,59 @@
+//! Runs a command, retrying it if it fails, e.g.
+//! `wrapper --retries 3 -- curl -f https://example.com`.
+//!
+//! `WRAPPER_RETRIES=3` works too, and the argument wins over the variable.
+//! Exits with 2 for bad arguments, and with the command's status otherwise.
+
+use std::process::{self, Command};
+
+use cliconf::Parse;
+
+#[derive(Parse)]
+#[cliconf(env_prefix = "WRAPPER", positionals = "COMMAND [ARGS]...")]
+struct Conf {
+    /// How many more times to run the command if it fails
+    #[cliconf(shorthand = 'r', default = 0)]
+    retries: u32,
+
+    /// Print the command instead of running it
+    #[cliconf(shorthand = 'n')]
+    dry_run: bool,
+}
+
+fn main() {
+    let mut conf = Conf::new();
+    let command = match conf
+        .parse_process_env()
+        .and_then(|()| conf.parse_args(std::env::args().skip(1).collect()))
+    {
+        Ok(command) => command,
+        Err(cliconf::Error::HelpRequested) => {
+            print!("{}", Conf::synopsis());
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {err}");
+            process::exit(2);
+        }
+    };
+    let conf = conf;
+
+    if conf.dry_run {
+        println!("{} (retries: {})", command.join(" "), conf.retries);
+        return;
+    }
+    let mut code = 0;
+    for _ in 0..=conf.retries {
+        code = match Command::new(&command[0]).args(&command[1..]).status() {
+            Ok(status) => status.code().unwrap_or(1),
+            Err(err) => {
+                eprintln!("error: failed to run {}: {err}", command[0]);
+                process::exit(127);
+            }
+        };
+        if code == 0 {
+            break;
+        }
+    }
+    process::exit(code);
+}
@@ -0,0 +1,140 @@
+//! A record of every external input read while loading the configuration,
+//! e.g. to log at startup or attach to a bug report. Files are recorded with
+//! a hash of their contents, so the exact inputs of a run can be checked
+//! later.
+
+use std::{fmt, path::PathBuf, time::SystemTime};
+
+use sha2::{Digest, Sha256};
+
+/// Something read from outside the program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    /// A config file, with the SHA-256 of its contents.
+    File { path: PathBuf, sha256: String },
+    /// A config document passed in as a string.
+    Inline { sha256: String },
+    /// An environment variable. The value of a secret flag isn't recorded.
+    Env { var: String, value: Option<String> },
+    /// A document from a [`crate::provider::Provider`], with the SHA-256 of
+    /// the document as JSON.
+    Provider { name: String, sha256: String },
+    /// Command-line arguments, as given.
+    Args(Vec<String>),
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::File { path, sha256 } => write!(f, "file {} sha256:{sha256}", path.display()),
+            Input::Inline { sha256 } => write!(f, "inline config sha256:{sha256}"),
+            Input::Env {
+                var,
+                value: Some(value),
+            } => write!(f, "env {var}={value:?}"),
+            Input::Env { var, value: None } => write!(f, "env {var}=<secret>"),
+            Input::Provider { name, sha256 } => write!(f, "provider {name} sha256:{sha256}"),
+            Input::Args(args) => write!(f, "args {args:?}"),
+        }
+    }
+}
+
+/// An input and when it was read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub at: SystemTime,
+    pub input: Input,
+}
+
+/// Every input read, in order. Set [`crate::Flags::audit`] to start
+/// recording.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Audit {
+    pub entries: Vec<Entry>,
+}
+
+impl Audit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record(&mut self, input: Input) {
+        self.entries.push(Entry {
+            at: SystemTime::now(),
+            input,
+        });
+    }
+}
+
+/// The lowercase hex SHA-256 of `data`.
+pub(crate) fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{Flag, FlagValue, Flags};
+
+    use super::*;
+
+    #[test]
+    fn test_audit() {
+        let dir = std::env::temp_dir().join(format!("cliconf-test-audit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.json");
+        std::fs::write(&path, r#"{ "name": "file" }"#).unwrap();
+
+        let mut flags = Flags::new();
+        flags.add(Flag::new("name", FlagValue::String("".into())));
+        flags.add(Flag::new("token", FlagValue::String("".into())).secret());
+        flags.parse_args(vec!["--name".into(), "x".into()]).unwrap();
+        assert_eq!(flags.audit, None);
+
+        flags.audit = Some(Audit::new());
+        flags.parse_file(&path).unwrap();
+        flags
+            .parse_env(HashMap::from([
+                ("NAME".into(), "env".into()),
+                ("TOKEN".into(), "hunter2".into()),
+                ("OTHER".into(), "unread".into()),
+            ]))
+            .unwrap();
+        flags
+            .parse_args(vec!["--name".into(), "arg".into()])
+            .unwrap();
+
+        let mut inputs: Vec<String> = flags
+            .audit
+            .unwrap()
+            .entries
+            .iter()
+            .map(|entry| entry.input.to_string())
+            .collect();
+        // Variables are read in no particular order
+        inputs[1..3].sort();
+        assert_eq!(
+            inputs,
+            [
+                format!(
+                    "file {} sha256:{}",
+                    path.display(),
+                    sha256(br#"{ "name": "file" }"#)
+                ),
+                "env NAME=\"env\"".into(),
+                "env TOKEN=<secret>".into(),
+                "args [\"--name\", \"arg\"]".into(),
+            ]
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+//! Defaults that are worked out when the program starts, e.g. a free port or
+//! the number of CPUs, instead of being fixed at compile time.
+//!
+//! An auto default is a resolver's name, optionally followed by arithmetic
+//! with a number, e.g. `cpus * 2` or `memory / 4`.
+
+use std::{collections::HashMap, env, fs, net::TcpListener, thread};
+
+use crate::{Error, Source};
+
+/// Works out a default value, returning a message explaining why it couldn't.
+pub type Resolver = fn() -> Result<String, String>;
+
+/// The resolvers that auto defaults can name.
+#[derive(Debug, Clone)]
+pub struct Resolvers {
+    resolvers: HashMap<String, Resolver>,
+}
+
+impl Resolvers {
+    /// No resolvers at all.
+    pub fn empty() -> Self {
+        Self {
+            resolvers: HashMap::new(),
+        }
+    }
+
+    /// Adds a resolver, replacing any built-in one with the same name.
+    pub fn register(&mut self, name: &str, resolver: Resolver) {
+        self.resolvers.insert(name.into(), resolver);
+    }
+
+    /// Resolves the auto default `expr` for `flag`.
+    pub fn resolve(&self, flag: &str, expr: &str) -> Result<String, Error> {
+        let invalid = |message: String| Error::Invalid {
+            flag: flag.into(),
+            message: format!("couldn't resolve auto default {expr:?}: {message}"),
+            source: Source::Auto(expr.into()),
+        };
+        let (name, op) = match expr.find(['*', '/', '+', '-']) {
+            Some(i) => {
+                let operand = expr[i + 1..].trim();
+                let operand: f64 = operand
+                    .parse()
+                    .map_err(|_| invalid(format!("{operand:?} isn't a number")))?;
+                (expr[..i].trim(), Some((&expr[i..i + 1], operand)))
+            }
+            None => (expr.trim(), None),
+        };
+        let resolver = self
+            .resolvers
+            .get(name)
+            .ok_or_else(|| invalid(format!("no resolver named {name:?}")))?;
+        let value = resolver().map_err(invalid)?;
+        let Some((op, operand)) = op else {
+            return Ok(value);
+        };
+        let base: f64 = value
+            .parse()
+            .map_err(|_| invalid(format!("{name} isn't a number: {value:?}")))?;
+        let result = match op {
+            "*" => base * operand,
+            "/" => base / operand,
+            "+" => base + operand,
+            _ => base - operand,
+        };
+        // Keep integer resolvers integral, so `cpus / 2` still parses as a
+        // count, rounding down
+        if value.parse::<i64>().is_ok() {
+            Ok((result.floor() as i64).to_string())
+        } else {
+            Ok(result.to_string())
+        }
+    }
+}
+
+/// The built-in resolvers:
+///
+/// - `free_port`: a TCP port that's free on localhost right now
+/// - `cpus`: the number of CPUs the program may use
+/// - `temp_dir`: the system's temporary directory
+/// - `memory`: the total memory in bytes (Linux only)
+/// - `hostname`: the machine's host name
+impl Default for Resolvers {
+    fn default() -> Self {
+        let mut resolvers = Self::empty();
+        resolvers.register("free_port", free_port);
+        resolvers.register("cpus", cpus);
+        resolvers.register("temp_dir", temp_dir);
+        resolvers.register("memory", memory);
+        resolvers.register("hostname", hostname);
+        resolvers
+    }
+}
+
+fn free_port() -> Result<String, String> {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).map_err(|e| e.to_string())?;
+    let addr = listener.local_addr().map_err(|e| e.to_string())?;
+    Ok(addr.port().to_string())
+}
+
+fn cpus() -> Result<String, String> {
+    let cpus = thread::available_parallelism().map_err(|e| e.to_string())?;
+    Ok(cpus.to_string())
+}
+
+fn temp_dir() -> Result<String, String> {
+    env::temp_dir()
+        .into_os_string()
+        .into_string()
+        .map_err(|dir| format!("not valid UTF-8: {}", dir.to_string_lossy()))
+}
+
+fn memory() -> Result<String, String> {
+    let meminfo = fs::read_to_string("/proc/meminfo").map_err(|e| e.to_string())?;
+    let kib = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|rest| rest.trim().strip_suffix("kB"))
+        .and_then(|kib| kib.trim().parse::<u64>().ok())
+        .ok_or("no MemTotal in /proc/meminfo")?;
+    Ok((kib * 1024).to_string())
+}
+
+fn hostname() -> Result<String, String> {
+    let var = if cfg!(windows) {
+        "COMPUTERNAME"
+    } else {
+        "HOSTNAME"
+    };
+    if let Some(name) = env::var(var).ok().filter(|name| !name.is_empty()) {
+        return Ok(name);
+    }
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| {
+            let name = fs::read_to_string(path).ok()?;
+            Some(name.trim().to_string()).filter(|name| !name.is_empty())
+        })
+        .ok_or_else(|| "couldn't find the host name".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let mut resolvers = Resolvers::default();
+        let port: u16 = resolvers
+            .resolve("--port", "free_port")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_ne!(port, 0);
+        let cpus: usize = resolvers
+            .resolve("--workers", "cpus")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(cpus >= 1);
+
+        resolvers.register("eight", || Ok("8".into()));
+        resolvers.register("half", || Ok("0.5".into()));
+        assert_eq!(resolvers.resolve("--workers", "eight * 2").unwrap(), "16");
+        assert_eq!(resolvers.resolve("--workers", "eight/3").unwrap(), "2");
+        assert_eq!(resolvers.resolve("--workers", "eight - 1").unwrap(), "7");
+        assert_eq!(resolvers.resolve("--ratio", "half + 1").unwrap(), "1.5");
+        assert!(resolvers.resolve("--workers", "eight * x").is_err());
+
+        resolvers.register("cpus", || Err("cgroup unreadable".into()));
+        let err = resolvers.resolve("--workers", "cpus").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid value for --workers: couldn't resolve auto default \"cpus\": cgroup unreadable (from auto default cpus)"
+        );
+        let err = resolvers.resolve("--workers", "nope").unwrap_err();
+        assert!(matches!(err, Error::Invalid { .. }));
+    }
+}
@@ -0,0 +1,491 @@
+//! Parsing sources into a [`ChangeSet`] instead of straight into the
+//! configuration, so the changes can be inspected, filtered and checked
+//! before any of them are applied, e.g. for a dry run or to reject secrets
+//! given on the command line.
+//!
+//! A [`Recorder`] stands in for the configuration wherever a source takes
+//! one, so every source can be recorded:
+//!
+//! ```
+//! use cliconf::{changes::Recorder, engine, Flag, FlagValue, Flags, Source};
+//!
+//! let mut flags = Flags::new();
+//! flags.add(Flag::new("port", FlagValue::Int64(8080)));
+//! flags.add(Flag::new("token", FlagValue::String("".into())));
+//!
+//! let mut recorder = Recorder::new(&flags);
+//! engine::parse_args(&mut recorder, ["--port", "80", "--token", "hunter2"].map(String::from).into())
+//!     .unwrap();
+//! let mut changes = recorder.finish();
+//! // Nothing is applied yet
+//! assert_eq!(flags.get("port").value, FlagValue::Int64(8080));
+//!
+//! changes.retain(|change| !(change.is("token") && matches!(change.source, Source::Arg(_))));
+//! changes.apply(&mut flags).unwrap();
+//! assert_eq!(flags.get("port").value, FlagValue::Int64(80));
+//! assert_eq!(flags.get("token").value, FlagValue::String("".into()));
+//! ```
+
+use std::slice;
+
+use crate::{
+    complete::Completer,
+    dump,
+    engine::{ArgSyntax, Arity, AutoDefault, Dispatch, EnvPrefix, EnvVar},
+    meta::{FlagMeta, Positional},
+    Error, Source,
+};
+
+/// What a change does to its flag, as the source gave it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    /// Turns on a boolean switch, or counts one more for a counting flag.
+    Switch,
+    /// Sets the flag to `value`, or adds it to an array flag. `first` is true
+    /// for a source's first item, which replaces earlier sources' items.
+    Value { value: String, first: bool },
+    /// Inserts an entry into a map flag.
+    Entry {
+        name: String,
+        value: String,
+        first: bool,
+    },
+    /// Empties an array flag.
+    ClearArray,
+    /// Resets the flag to its default.
+    Reset,
+}
+
+/// One change to one flag, and the source that made it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change<K> {
+    pub key: K,
+    /// The flag's name, without the leading `--`, if the configuration names
+    /// its flags. [`crate::Flags`] and `#[derive(Parse)]` structs do.
+    pub flag: Option<String>,
+    pub op: Op,
+    pub source: Source,
+}
+
+impl<K> Change<K> {
+    /// Whether the change is to the flag named `flag`, e.g. `db-host`.
+    pub fn is(&self, flag: &str) -> bool {
+        self.flag.as_deref() == Some(flag)
+    }
+}
+
+/// Changes recorded from one or more sources, in the order they were read,
+/// so applying them gives the same precedence as parsing the sources
+/// directly would have.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeSet<K> {
+    changes: Vec<Change<K>>,
+}
+
+impl<K> Default for ChangeSet<K> {
+    fn default() -> Self {
+        Self { changes: vec![] }
+    }
+}
+
+impl<K: Clone + PartialEq> ChangeSet<K> {
+    pub fn iter(&self) -> slice::Iter<'_, Change<K>> {
+        self.changes.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Keeps only the changes that `keep` accepts. When a source's first
+    /// item for an array or map flag is dropped, its next kept item takes
+    /// over replacing earlier sources' items.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Change<K>) -> bool) {
+        let mut kept: Vec<Change<K>> = Vec::with_capacity(self.changes.len());
+        // The flag and source of a dropped first item, until the next item
+        let mut dropped_first: Vec<(K, Source)> = vec![];
+        for mut change in self.changes.drain(..) {
+            let pending = dropped_first
+                .iter()
+                .position(|(key, source)| *key == change.key && source.same_as(&change.source));
+            if let Some(i) = pending {
+                match &mut change.op {
+                    Op::Value { first, .. } | Op::Entry { first, .. } if !*first => {
+                        *first = true;
+                    }
+                    _ => {}
+                }
+                dropped_first.remove(i);
+            }
+            if keep(&change) {
+                kept.push(change);
+                continue;
+            }
+            if let Op::Value { first: true, .. } | Op::Entry { first: true, .. } = change.op {
+                dropped_first.push((change.key, change.source));
+            }
+        }
+        self.changes = kept;
+    }
+
+    /// Applies every change to `d`, in order. If one fails, `d` is left as
+    /// it was. Values were already checked while recording, but a value read
+    /// with `from_command` runs its command again.
+    pub fn apply<D: Dispatch<Key = K> + Clone>(&self, d: &mut D) -> Result<(), Error> {
+        self.apply_validated(d, |_| Ok(()))
+    }
+
+    /// Like [`ChangeSet::apply`], but only if the result passes `validate`,
+    /// e.g. [`crate::Flags::validate`] or a derived struct's `validate`, so
+    /// the configuration never ends up half changed or invalid.
+    pub fn apply_validated<D: Dispatch<Key = K> + Clone>(
+        &self,
+        d: &mut D,
+        validate: impl FnOnce(&D) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut candidate = d.clone();
+        for change in &self.changes {
+            let Change {
+                key, op, source, ..
+            } = change;
+            match op {
+                Op::Switch => candidate.set_switch(key, source)?,
+                Op::Value { value, first } => candidate.set_value(key, value, *first, source)?,
+                Op::Entry { name, value, first } => {
+                    candidate.set_entry(key, name, value, *first, source)?
+                }
+                Op::ClearArray => {
+                    candidate.clear_array(key, source);
+                }
+                Op::Reset => candidate.reset(key, source)?,
+            }
+        }
+        validate(&candidate)?;
+        *d = candidate;
+        Ok(())
+    }
+}
+
+impl<'a, K> IntoIterator for &'a ChangeSet<K> {
+    type Item = &'a Change<K>;
+    type IntoIter = slice::Iter<'a, Change<K>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.changes.iter()
+    }
+}
+
+/// Records what sources would change, for any function that parses a
+/// source into a [`Dispatch`], e.g. [`crate::engine::parse_args`] or
+/// [`crate::Loader::load`].
+///
+/// Each value is applied to a copy of the configuration as it's recorded,
+/// so invalid values fail while parsing, like they would without recording,
+/// and later sources see what earlier ones set.
+pub struct Recorder<D: Dispatch> {
+    scratch: D,
+    changes: Vec<Change<D::Key>>,
+}
+
+impl<D: Dispatch + Clone> Recorder<D> {
+    pub fn new(d: &D) -> Self {
+        Self {
+            scratch: d.clone(),
+            changes: vec![],
+        }
+    }
+}
+
+impl<D: Dispatch> Recorder<D> {
+    /// The configuration as it would be with every change recorded so far.
+    pub fn preview(&self) -> &D {
+        &self.scratch
+    }
+
+    pub fn finish(self) -> ChangeSet<D::Key> {
+        ChangeSet {
+            changes: self.changes,
+        }
+    }
+
+    fn record(&mut self, key: &D::Key, op: Op, source: &Source) {
+        self.changes.push(Change {
+            key: key.clone(),
+            flag: self.scratch.flag_name(key),
+            op,
+            source: source.clone(),
+        });
+    }
+}
+
+impl<D: Dispatch> Dispatch for Recorder<D> {
+    type Key = D::Key;
+
+    fn long(&self, name: &str) -> Option<(D::Key, Arity)> {
+        self.scratch.long(name)
+    }
+
+    fn short(&self, c: char) -> Option<(D::Key, Arity)> {
+        self.scratch.short(c)
+    }
+
+    fn env_vars(&self) -> Vec<EnvVar<D::Key>> {
+        self.scratch.env_vars()
+    }
+
+    fn env_prefixes(&self) -> Vec<EnvPrefix<D::Key>> {
+        self.scratch.env_prefixes()
+    }
+
+    fn set_entry(
+        &mut self,
+        key: &D::Key,
+        name: &str,
+        value: &str,
+        first: bool,
+        source: &Source,
+    ) -> Result<(), Error> {
+        self.scratch.set_entry(key, name, value, first, source)?;
+        let op = Op::Entry {
+            name: name.into(),
+            value: value.into(),
+            first,
+        };
+        self.record(key, op, source);
+        Ok(())
+    }
+
+    fn auto_defaults(&self) -> Vec<AutoDefault<D::Key>> {
+        self.scratch.auto_defaults()
+    }
+
+    fn unset_sentinel(&self) -> Option<&str> {
+        self.scratch.unset_sentinel()
+    }
+
+    fn unset_flag(&self) -> Option<&str> {
+        self.scratch.unset_flag()
+    }
+
+    fn set_flag(&self) -> Option<&str> {
+        self.scratch.set_flag()
+    }
+
+    fn arg_syntax(&self) -> ArgSyntax {
+        self.scratch.arg_syntax()
+    }
+
+    fn is_help_arg(&self, arg: &str) -> bool {
+        self.scratch.is_help_arg(arg)
+    }
+
+    fn is_help_all_arg(&self, arg: &str) -> bool {
+        self.scratch.is_help_all_arg(arg)
+    }
+
+    fn is_version_arg(&self, arg: &str) -> bool {
+        self.scratch.is_version_arg(arg)
+    }
+
+    fn print_config_flag(&self) -> Option<&str> {
+        self.scratch.print_config_flag()
+    }
+
+    fn dump_entries(&self) -> Vec<dump::Entry> {
+        self.scratch.dump_entries()
+    }
+
+    fn telemetry_entries(&self) -> Vec<dump::Entry> {
+        self.scratch.telemetry_entries()
+    }
+
+    fn flag_name(&self, key: &D::Key) -> Option<String> {
+        self.scratch.flag_name(key)
+    }
+
+    fn source(&self, key: &D::Key) -> Option<Source> {
+        self.scratch.source(key)
+    }
+
+    fn sources(&self) -> Vec<(String, Source)> {
+        self.scratch.sources()
+    }
+
+    fn missing_required(&self) -> Vec<String> {
+        self.scratch.missing_required()
+    }
+
+    fn deprecated_uses(&self) -> Vec<(String, &'static str, Source)> {
+        self.scratch.deprecated_uses()
+    }
+
+    fn declared_positionals(&self) -> Option<Vec<Positional>> {
+        self.scratch.declared_positionals()
+    }
+
+    fn reset(&mut self, key: &D::Key, source: &Source) -> Result<(), Error> {
+        self.scratch.reset(key, source)?;
+        self.record(key, Op::Reset, source);
+        Ok(())
+    }
+
+    fn clear_array(&mut self, key: &D::Key, source: &Source) -> bool {
+        let cleared = self.scratch.clear_array(key, source);
+        if cleared {
+            self.record(key, Op::ClearArray, source);
+        }
+        cleared
+    }
+
+    fn read_env(&mut self, key: &D::Key, var: &str, value: &str) {
+        self.scratch.read_env(key, var, value);
+    }
+
+    fn env_case_insensitive(&self) -> bool {
+        self.scratch.env_case_insensitive()
+    }
+
+    fn coerce(&self) -> bool {
+        self.scratch.coerce()
+    }
+
+    fn completer(&self, key: &D::Key) -> Option<Completer> {
+        self.scratch.completer(key)
+    }
+
+    fn metadata(&self) -> Vec<FlagMeta> {
+        self.scratch.metadata()
+    }
+
+    fn set_switch(&mut self, key: &D::Key, source: &Source) -> Result<(), Error> {
+        self.scratch.set_switch(key, source)?;
+        self.record(key, Op::Switch, source);
+        Ok(())
+    }
+
+    fn set_value(
+        &mut self,
+        key: &D::Key,
+        value: &str,
+        first: bool,
+        source: &Source,
+    ) -> Result<(), Error> {
+        self.scratch.set_value(key, value, first, source)?;
+        let op = Op::Value {
+            value: value.into(),
+            first,
+        };
+        self.record(key, op, source);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine, Flag, FlagValue, Flags};
+
+    use super::*;
+
+    fn flags() -> Flags {
+        let mut flags = Flags::new();
+        flags.add(Flag::new("port", FlagValue::Int64(8080)));
+        flags.add(Flag::new("tags", FlagValue::StringArray(vec![])).delimiter(","));
+        flags.add(Flag::new("verbose", FlagValue::Bool(false)));
+        flags
+    }
+
+    #[test]
+    fn test_record() {
+        let mut flags = flags();
+        let mut recorder = Recorder::new(&flags);
+        engine::parse_env(
+            &mut recorder,
+            [("TAGS".into(), "a,b".into()), ("PORT".into(), "81".into())].into(),
+        )
+        .unwrap();
+        engine::parse_args(
+            &mut recorder,
+            ["--port", "82", "--verbose"].map(String::from).into(),
+        )
+        .unwrap();
+        assert_eq!(recorder.preview().get("port").value, FlagValue::Int64(82));
+        let err = engine::parse_args(&mut recorder, ["--port", "x"].map(String::from).into())
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { .. }));
+
+        let changes = recorder.finish();
+        let ops: Vec<_> = changes
+            .iter()
+            .map(|change| (change.flag.as_deref().unwrap(), &change.op))
+            .collect();
+        let value = |value: &str, first| Op::Value {
+            value: value.into(),
+            first,
+        };
+        // The environment is read in the order the flags declare it
+        assert!(ops.contains(&("tags", &value("a", true))));
+        assert!(ops.contains(&("tags", &value("b", false))));
+        assert_eq!(
+            ops[ops.len() - 2..],
+            [("port", &value("82", true)), ("verbose", &Op::Switch)]
+        );
+        assert_eq!(flags.get("port").value, FlagValue::Int64(8080));
+
+        changes.apply(&mut flags).unwrap();
+        assert_eq!(flags.get("port").value, FlagValue::Int64(82));
+        assert_eq!(flags.get("port").source, Source::Arg(Some(2)));
+        assert_eq!(
+            flags.get("tags").value,
+            FlagValue::StringArray(vec!["a".into(), "b".into()])
+        );
+        assert_eq!(flags.get("verbose").value, FlagValue::Bool(true));
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut flags = flags();
+        flags.flags.get_mut("tags").unwrap().value = FlagValue::StringArray(vec!["old".into()]);
+        let mut recorder = Recorder::new(&flags);
+        engine::parse_args(
+            &mut recorder,
+            ["--tags", "a", "--tags", "b", "--port", "1"]
+                .map(String::from)
+                .into(),
+        )
+        .unwrap();
+        let mut changes = recorder.finish();
+        changes.retain(|change| {
+            !matches!(&change.op, Op::Value { value, .. } if value == "a") && !change.is("port")
+        });
+        assert_eq!(changes.len(), 1);
+        changes.apply(&mut flags).unwrap();
+        // b still replaces the earlier value rather than adding to it
+        assert_eq!(
+            flags.get("tags").value,
+            FlagValue::StringArray(vec!["b".into()])
+        );
+        assert_eq!(flags.get("port").value, FlagValue::Int64(8080));
+    }
+
+    #[test]
+    fn test_apply_validated() {
+        let mut flags = flags();
+        flags.add(Flag::new("host", FlagValue::String("".into())).required());
+        let mut recorder = Recorder::new(&flags);
+        engine::parse_args(&mut recorder, ["--port", "1"].map(String::from).into()).unwrap();
+        let changes = recorder.finish();
+
+        let err = changes
+            .apply_validated(&mut flags, Flags::validate)
+            .unwrap_err();
+        assert!(matches!(err, Error::MissingRequired(names) if names == ["host"]));
+        assert_eq!(flags.get("port").value, FlagValue::Int64(8080));
+
+        changes.apply(&mut flags).unwrap();
+        assert_eq!(flags.get("port").value, FlagValue::Int64(1));
+    }
+}
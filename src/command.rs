@@ -0,0 +1,851 @@
+//! Subcommands, each with its own flags, e.g. `mytool build --release`.
+
+use std::{
+    env,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    complete::Completer,
+    dump,
+    engine::{self, ArgSyntax, Arity, AutoDefault, Dispatch, EnvPrefix, EnvVar},
+    meta::{FlagMeta, Positional},
+    Error, Flags, Source,
+};
+
+#[derive(Debug)]
+pub struct Command {
+    pub name: String,
+    /// Other names that select this command and are shown in help.
+    pub aliases: Vec<String>,
+    /// Other names that select this command but are never shown, e.g. the
+    /// old name of a renamed command, so that existing scripts keep working.
+    pub hidden_aliases: Vec<String>,
+    pub flags: Flags,
+}
+
+impl Command {
+    pub fn new(name: &str, flags: Flags) -> Self {
+        Self {
+            name: name.into(),
+            aliases: vec![],
+            hidden_aliases: vec![],
+            flags,
+        }
+    }
+
+    pub fn alias(mut self, alias: &str) -> Self {
+        self.aliases.push(alias.into());
+        self
+    }
+
+    pub fn hidden_alias(mut self, alias: &str) -> Self {
+        self.hidden_aliases.push(alias.into());
+        self
+    }
+
+    fn names(&self) -> impl Iterator<Item = &String> {
+        [&self.name]
+            .into_iter()
+            .chain(&self.aliases)
+            .chain(&self.hidden_aliases)
+    }
+}
+
+/// The command that [`Commands::parse_args`] picked, and the positional
+/// arguments left after its flags.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matched {
+    /// The command's name, even if an alias was used.
+    pub name: String,
+    pub positionals: Vec<String>,
+}
+
+/// A plugin program that should run in place of an unknown command, e.g.
+/// `mytool-foo` for `mytool foo`. Running it is up to the caller.
+#[derive(Debug, Clone, PartialEq)]
+pub struct External {
+    pub program: PathBuf,
+    /// The arguments after the command name, untouched.
+    pub args: Vec<String>,
+}
+
+/// What [`Commands::parse_args`] decided should run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Invocation {
+    Command(Matched),
+    External(External),
+}
+
+#[derive(Debug, Default)]
+pub struct Commands {
+    /// Flags that every command accepts, before or after its name, e.g. both
+    /// `mytool --verbose build` and `mytool build --verbose`. A command's own
+    /// flag wins if it has the same name.
+    pub global: Flags,
+    pub commands: Vec<Command>,
+    /// The command that runs when the first argument isn't a command name,
+    /// e.g. for a tool that used to have just one command.
+    pub default: Option<String>,
+    /// Looks for unknown commands on `PATH` as `<prefix>-<command>`, e.g.
+    /// `mytool` for `mytool-foo`, like git and cargo do for plugins.
+    pub external_prefix: Option<String>,
+}
+
+impl Commands {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, command: Command) {
+        for name in command.names() {
+            if let Some(other) = self.find(name) {
+                panic!(
+                    "Commands {} and {} both use the name {name}",
+                    self.commands[other].name, command.name
+                );
+            }
+        }
+        self.commands.push(command);
+    }
+
+    pub fn get(&self, name: &str) -> &Command {
+        self.find(name)
+            .map(|i| &self.commands[i])
+            .unwrap_or_else(|| panic!("Unknown command: {name}"))
+    }
+
+    /// Finds a command by its name or any of its aliases.
+    fn find(&self, name: &str) -> Option<usize> {
+        self.commands
+            .iter()
+            .position(|command| command.names().any(|n| n == name))
+    }
+
+    /// Picks a command by the first argument after any global flags, and
+    /// parses the rest with its flags and the global ones. If that argument
+    /// isn't a command, it may name an external one (see
+    /// [`Commands::external_prefix`]), or else all of the arguments go to the
+    /// default command, if there is one.
+    pub fn parse_args(&mut self, mut args: Vec<String>) -> Result<Invocation, Error> {
+        let at = self.command_position(&args);
+        // Help for the program as a whole, rather than a command's
+        match args.get(at) {
+            Some(arg) if self.global.is_help_arg(arg) => return Err(Error::HelpRequested),
+            Some(arg) if self.global.is_help_all_arg(arg) => return Err(Error::HelpAllRequested),
+            Some(arg) if self.global.is_version_arg(arg) => return Err(Error::VersionRequested),
+            _ => {}
+        }
+        if let Some(program) = self.find_external(args.get(at)) {
+            let rest = args.split_off(at + 1);
+            args.pop();
+            engine::parse_args(&mut self.global, args)?;
+            return Ok(Invocation::External(External {
+                program,
+                args: rest,
+            }));
+        }
+        let (i, removed) = match args.get(at).and_then(|arg| self.find(arg)) {
+            Some(i) => {
+                args.remove(at);
+                (i, Some(at))
+            }
+            None => match &self.default {
+                Some(default) => (
+                    self.find(default)
+                        .unwrap_or_else(|| panic!("Unknown default command: {default}")),
+                    None,
+                ),
+                None => {
+                    return Err(match args.into_iter().nth(at) {
+                        Some(arg) => self.unknown(arg),
+                        None => Error::MissingCommand,
+                    })
+                }
+            },
+        };
+        let command = &mut self.commands[i];
+        let args = engine::expand_aliases(args, &command.flags.aliases)?;
+        let positionals = engine::parse_args_without(
+            &mut Scoped {
+                global: &mut self.global,
+                command: &mut command.flags,
+            },
+            args,
+            removed,
+        )?;
+        Ok(Invocation::Command(Matched {
+            name: command.name.clone(),
+            positionals,
+        }))
+    }
+
+    /// The error for an argument that isn't a command, suggesting the
+    /// closest name if it's near enough to be a typo. Hidden aliases are
+    /// never suggested.
+    fn unknown(&self, name: String) -> Error {
+        let suggestion = self
+            .commands
+            .iter()
+            .flat_map(|command| [&command.name].into_iter().chain(&command.aliases))
+            .map(|candidate| (distance(&name, candidate), candidate))
+            .filter(|(distance, candidate)| *distance <= candidate.chars().count() / 3)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, candidate)| candidate.clone());
+        Error::UnknownCommand {
+            name,
+            suggestion,
+            commands: self.commands.iter().map(|c| c.name.clone()).collect(),
+        }
+    }
+
+    fn find_external(&self, arg: Option<&String>) -> Option<PathBuf> {
+        let prefix = self.external_prefix.as_ref()?;
+        let arg = arg.filter(|arg| !arg.starts_with('-') && self.find(arg).is_none())?;
+        find_program(&format!("{prefix}-{arg}"), &env::var_os("PATH")?)
+    }
+
+    /// Where the command name should be: the first argument that isn't a
+    /// global flag or its value.
+    fn command_position(&self, args: &[String]) -> usize {
+        let mut i = 0;
+        while let Some(arg) = args.get(i) {
+            match engine::pending_value(&self.global, arg) {
+                Some(Some(_)) => i += 2,
+                Some(None) => i += 1,
+                None => break,
+            }
+        }
+        i
+    }
+}
+
+/// The number of single-character insertions, deletions, substitutions and
+/// swaps of neighbouring characters that turn `a` into `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // Three rows of the usual table: the one before last, the last and the
+    // one being filled in
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut last: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (last[j] + 1).min(row[j - 1] + 1).min(last[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        before = std::mem::replace(&mut last, row);
+    }
+    last[b.len()]
+}
+
+/// Finds an executable named `name` in one of the directories of `path`,
+/// which is formatted like the `PATH` environment variable.
+pub fn find_program(name: &str, path: &OsStr) -> Option<PathBuf> {
+    env::split_paths(path).find_map(|dir| {
+        if cfg!(windows) {
+            let exts = env::var("PATHEXT").unwrap_or_else(|_| ".EXE;.CMD;.BAT;.COM".into());
+            exts.split(';')
+                .map(|ext| dir.join(format!("{name}{ext}")))
+                .find(|program| is_executable(program))
+        } else {
+            Some(dir.join(name)).filter(|program| is_executable(program))
+        }
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ScopedKey {
+    Global(String),
+    Command(String),
+}
+
+impl ScopedKey {
+    fn name(&self) -> &str {
+        match self {
+            ScopedKey::Global(name) | ScopedKey::Command(name) => name,
+        }
+    }
+}
+
+/// A command's flags together with the global ones.
+struct Scoped<'a> {
+    global: &'a mut Flags,
+    command: &'a mut Flags,
+}
+
+impl Scoped<'_> {
+    /// The command's items followed by the global ones, leaving out global
+    /// flags that a command flag of the same name hides.
+    fn merge<T>(&self, command: Vec<T>, mut global: Vec<T>, name: impl Fn(&T) -> &str) -> Vec<T> {
+        global.retain(|item| !self.command.flags.contains_key(name(item)));
+        command.into_iter().chain(global).collect()
+    }
+
+    /// Like [`Scoped::merge`], for items listed in order of their names.
+    fn merge_sorted<T>(
+        &self,
+        command: Vec<T>,
+        global: Vec<T>,
+        name: impl Fn(&T) -> &str,
+    ) -> Vec<T> {
+        let mut merged = self.merge(command, global, &name);
+        merged.sort_by(|a, b| name(a).cmp(name(b)));
+        merged
+    }
+}
+
+impl Dispatch for Scoped<'_> {
+    type Key = ScopedKey;
+
+    fn long(&self, name: &str) -> Option<(ScopedKey, Arity)> {
+        match self.command.long(name) {
+            Some((key, arity)) => Some((ScopedKey::Command(key), arity)),
+            None => {
+                let (key, arity) = self.global.long(name)?;
+                Some((ScopedKey::Global(key), arity))
+            }
+        }
+    }
+
+    fn short(&self, c: char) -> Option<(ScopedKey, Arity)> {
+        match self.command.short(c) {
+            Some((key, arity)) => Some((ScopedKey::Command(key), arity)),
+            None => {
+                let (key, arity) = self.global.short(c)?;
+                Some((ScopedKey::Global(key), arity))
+            }
+        }
+    }
+
+    fn env_vars(&self) -> Vec<EnvVar<ScopedKey>> {
+        let scope = |vars: Vec<EnvVar<String>>, scope: fn(String) -> ScopedKey| {
+            vars.into_iter()
+                .map(|var| EnvVar {
+                    key: scope(var.key),
+                    var: var.var,
+                    delimiter: var.delimiter,
+                    is_array: var.is_array,
+                })
+                .collect()
+        };
+        self.merge(
+            scope(self.command.env_vars(), ScopedKey::Command),
+            scope(self.global.env_vars(), ScopedKey::Global),
+            |var| var.key.name(),
+        )
+    }
+
+    fn env_stem(var: &str) -> Option<&str> {
+        Flags::env_stem(var)
+    }
+
+    fn env_prefixes(&self) -> Vec<EnvPrefix<ScopedKey>> {
+        let scope = |prefixes: Vec<EnvPrefix<String>>, scope: fn(String) -> ScopedKey| {
+            prefixes
+                .into_iter()
+                .map(|prefix| EnvPrefix {
+                    key: scope(prefix.key),
+                    prefix: prefix.prefix,
+                })
+                .collect()
+        };
+        self.merge(
+            scope(self.command.env_prefixes(), ScopedKey::Command),
+            scope(self.global.env_prefixes(), ScopedKey::Global),
+            |prefix| prefix.key.name(),
+        )
+    }
+
+    fn set_entry(
+        &mut self,
+        key: &ScopedKey,
+        name: &str,
+        value: &str,
+        first: bool,
+        source: &Source,
+    ) -> Result<(), Error> {
+        match key {
+            ScopedKey::Global(key) => self.global.set_entry(key, name, value, first, source),
+            ScopedKey::Command(key) => self.command.set_entry(key, name, value, first, source),
+        }
+    }
+
+    fn auto_defaults(&self) -> Vec<AutoDefault<ScopedKey>> {
+        let scope = |autos: Vec<AutoDefault<String>>, scope: fn(String) -> ScopedKey| {
+            autos
+                .into_iter()
+                .map(|auto| AutoDefault {
+                    key: scope(auto.key),
+                    flag: auto.flag,
+                    resolver: auto.resolver,
+                })
+                .collect()
+        };
+        self.merge(
+            scope(self.command.auto_defaults(), ScopedKey::Command),
+            scope(self.global.auto_defaults(), ScopedKey::Global),
+            |auto| auto.key.name(),
+        )
+    }
+
+    fn unset_sentinel(&self) -> Option<&str> {
+        self.command
+            .unset_sentinel()
+            .or_else(|| self.global.unset_sentinel())
+    }
+
+    fn unset_flag(&self) -> Option<&str> {
+        self.command
+            .unset_flag()
+            .or_else(|| self.global.unset_flag())
+    }
+
+    fn set_flag(&self) -> Option<&str> {
+        self.command.set_flag().or_else(|| self.global.set_flag())
+    }
+
+    fn arg_syntax(&self) -> ArgSyntax {
+        // A command's own syntax wins, and otherwise the program's applies
+        match self.command.arg_syntax() {
+            syntax if syntax != ArgSyntax::default() => syntax,
+            _ => self.global.arg_syntax(),
+        }
+    }
+
+    fn is_help_arg(&self, arg: &str) -> bool {
+        self.command.is_help_arg(arg) || self.global.is_help_arg(arg)
+    }
+
+    fn is_help_all_arg(&self, arg: &str) -> bool {
+        self.command.is_help_all_arg(arg) || self.global.is_help_all_arg(arg)
+    }
+
+    fn is_version_arg(&self, arg: &str) -> bool {
+        self.command.is_version_arg(arg) || self.global.is_version_arg(arg)
+    }
+
+    fn print_config_flag(&self) -> Option<&str> {
+        self.command
+            .print_config_flag()
+            .or_else(|| self.global.print_config_flag())
+    }
+
+    fn dump_entries(&self) -> Vec<dump::Entry> {
+        self.merge_sorted(
+            self.command.dump_entries(),
+            self.global.dump_entries(),
+            |entry| &entry.name,
+        )
+    }
+
+    fn telemetry_entries(&self) -> Vec<dump::Entry> {
+        self.merge_sorted(
+            self.command.telemetry_entries(),
+            self.global.telemetry_entries(),
+            |entry| &entry.name,
+        )
+    }
+
+    fn flag_name(&self, key: &ScopedKey) -> Option<String> {
+        match key {
+            ScopedKey::Global(key) => self.global.flag_name(key),
+            ScopedKey::Command(key) => self.command.flag_name(key),
+        }
+    }
+
+    fn source(&self, key: &ScopedKey) -> Option<Source> {
+        match key {
+            ScopedKey::Global(key) => self.global.source(key),
+            ScopedKey::Command(key) => self.command.source(key),
+        }
+    }
+
+    fn sources(&self) -> Vec<(String, Source)> {
+        self.merge_sorted(
+            self.command.sources(),
+            self.global.sources(),
+            |(name, _)| name,
+        )
+    }
+
+    fn missing_required(&self) -> Vec<String> {
+        self.merge(
+            self.command.missing_required(),
+            self.global.missing_required(),
+            |name| name,
+        )
+    }
+
+    fn deprecated_uses(&self) -> Vec<(String, &'static str, Source)> {
+        self.merge(
+            self.command.deprecated_uses(),
+            self.global.deprecated_uses(),
+            |(name, _, _)| name,
+        )
+    }
+
+    fn declared_positionals(&self) -> Option<Vec<Positional>> {
+        // Positionals come after the command, so only its own count
+        self.command.declared_positionals()
+    }
+
+    fn reset(&mut self, key: &ScopedKey, source: &Source) -> Result<(), Error> {
+        match key {
+            ScopedKey::Global(key) => self.global.reset(key, source),
+            ScopedKey::Command(key) => self.command.reset(key, source),
+        }
+    }
+
+    fn swap_field(&mut self, key: &ScopedKey, other: &mut Self) {
+        match key {
+            ScopedKey::Global(key) => self.global.swap_field(key, other.global),
+            ScopedKey::Command(key) => self.command.swap_field(key, other.command),
+        }
+    }
+
+    fn swap_immutable(&mut self, other: &mut Self, restart: &mut Vec<String>) {
+        self.global.swap_immutable(other.global, restart);
+        self.command.swap_immutable(other.command, restart);
+    }
+
+    fn clear_array(&mut self, key: &ScopedKey, source: &Source) -> bool {
+        match key {
+            ScopedKey::Global(key) => self.global.clear_array(key, source),
+            ScopedKey::Command(key) => self.command.clear_array(key, source),
+        }
+    }
+
+    fn read_env(&mut self, key: &ScopedKey, var: &str, value: &str) {
+        match key {
+            ScopedKey::Global(key) => self.global.read_env(key, var, value),
+            ScopedKey::Command(key) => self.command.read_env(key, var, value),
+        }
+    }
+
+    fn env_case_insensitive(&self) -> bool {
+        match self.command.env_case_insensitive {
+            Some(_) => self.command.env_case_insensitive(),
+            None => self.global.env_case_insensitive(),
+        }
+    }
+
+    fn coerce(&self) -> bool {
+        self.command.coerce() || self.global.coerce()
+    }
+
+    fn completer(&self, key: &ScopedKey) -> Option<Completer> {
+        match key {
+            ScopedKey::Global(key) => self.global.completer(key),
+            ScopedKey::Command(key) => self.command.completer(key),
+        }
+    }
+
+    fn metadata(&self) -> Vec<FlagMeta> {
+        self.merge_sorted(self.command.metadata(), self.global.metadata(), |flag| {
+            &flag.name
+        })
+    }
+
+    fn set_switch(&mut self, key: &ScopedKey, source: &Source) -> Result<(), Error> {
+        match key {
+            ScopedKey::Global(key) => self.global.set_switch(key, source),
+            ScopedKey::Command(key) => self.command.set_switch(key, source),
+        }
+    }
+
+    fn set_value(
+        &mut self,
+        key: &ScopedKey,
+        value: &str,
+        first: bool,
+        source: &Source,
+    ) -> Result<(), Error> {
+        match key {
+            ScopedKey::Global(key) => self.global.set_value(key, value, first, source),
+            ScopedKey::Command(key) => self.command.set_value(key, value, first, source),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Flag, FlagValue};
+
+    use super::*;
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn command(invocation: Invocation) -> Matched {
+        match invocation {
+            Invocation::Command(matched) => matched,
+            Invocation::External(external) => panic!("unexpected {external:?}"),
+        }
+    }
+
+    fn commands() -> Commands {
+        let mut build = Flags::new();
+        build.add(Flag::new("release", FlagValue::Bool(false)));
+        let mut run = Flags::new();
+        run.add(Flag::new("port", FlagValue::Int64(0)));
+
+        let mut commands = Commands::new();
+        commands.add(Command::new("build", build).alias("b"));
+        commands.add(Command::new("run", run).hidden_alias("serve"));
+        commands
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let mut commands = commands();
+        let matched = command(
+            commands
+                .parse_args(strings(&["b", "--release", "x"]))
+                .unwrap(),
+        );
+        assert_eq!(
+            matched,
+            Matched {
+                name: "build".into(),
+                positionals: strings(&["x"]),
+            }
+        );
+        assert_eq!(
+            commands.get("build").flags.get("release").value,
+            FlagValue::Bool(true)
+        );
+
+        let matched = command(commands.parse_args(strings(&["serve"])).unwrap());
+        assert_eq!(matched.name, "run");
+
+        let err = commands.parse_args(strings(&["--port", "80"])).unwrap_err();
+        assert!(matches!(err, Error::UnknownCommand { name, .. } if name == "--port"));
+        let err = commands.parse_args(vec![]).unwrap_err();
+        assert!(matches!(err, Error::MissingCommand));
+
+        commands.global.help_args = strings(&["--help"]);
+        let err = commands.parse_args(strings(&["--help"])).unwrap_err();
+        assert!(matches!(err, Error::HelpRequested));
+        let err = commands
+            .parse_args(strings(&["build", "--help"]))
+            .unwrap_err();
+        assert!(matches!(err, Error::HelpRequested));
+    }
+
+    #[test]
+    fn test_suggestion() {
+        let mut commands = commands();
+        let suggestion = |commands: &mut Commands, arg: &str| match commands
+            .parse_args(strings(&[arg]))
+            .unwrap_err()
+        {
+            Error::UnknownCommand {
+                suggestion,
+                commands,
+                ..
+            } => {
+                assert_eq!(commands, ["build", "run"]);
+                suggestion
+            }
+            err => panic!("unexpected {err}"),
+        };
+        assert_eq!(suggestion(&mut commands, "biuld"), Some("build".into()));
+        assert_eq!(suggestion(&mut commands, "buil"), Some("build".into()));
+        assert_eq!(suggestion(&mut commands, "rnu"), Some("run".into()));
+        assert_eq!(suggestion(&mut commands, "test"), None);
+        // Hidden aliases stay hidden
+        assert_eq!(suggestion(&mut commands, "serv"), None);
+
+        assert_eq!(distance("", "abc"), 3);
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("ab", "ba"), 1);
+    }
+
+    #[test]
+    fn test_positionals() {
+        let mut commands = commands();
+        commands.commands[0].flags.positionals = Some(vec![Positional::new("TARGET")]);
+        let err = commands.parse_args(strings(&["build"])).unwrap_err();
+        assert!(matches!(err, Error::MissingPositional(name) if name == "TARGET"));
+        let err = commands
+            .parse_args(strings(&["build", "x", "y"]))
+            .unwrap_err();
+        assert!(matches!(err, Error::UnexpectedPositional(arg) if arg == "y"));
+        commands.parse_args(strings(&["build", "x"])).unwrap();
+        // Commands that don't name their positionals take any number
+        commands.parse_args(strings(&["run", "x", "y"])).unwrap();
+    }
+
+    #[test]
+    fn test_arg_syntax() {
+        let mut commands = commands();
+        commands.commands[0].flags.arg_syntax.double_dash = engine::SpecialArg::KeepAndEnd;
+        let matched = command(commands.parse_args(strings(&["build", "--", "x"])).unwrap());
+        assert_eq!(matched.positionals, ["--", "x"]);
+        let matched = command(commands.parse_args(strings(&["run", "--", "x"])).unwrap());
+        assert_eq!(matched.positionals, ["x"]);
+
+        commands.global.arg_syntax.double_dash = engine::SpecialArg::KeepAndEnd;
+        let matched = command(commands.parse_args(strings(&["run", "--", "x"])).unwrap());
+        assert_eq!(matched.positionals, ["--", "x"]);
+    }
+
+    #[test]
+    fn test_print_config() {
+        let mut commands = commands();
+        commands.global.print_config_flag = Some("print-config".into());
+        commands
+            .global
+            .add(Flag::new("color", FlagValue::String("auto".into())));
+        commands
+            .global
+            .add(Flag::new("port", FlagValue::String("shadowed".into())));
+        let err = commands
+            .parse_args(strings(&["run", "--port", "80", "--print-config=env"]))
+            .unwrap_err();
+        let Error::PrintConfigRequested(out) = err else {
+            panic!("unexpected {err}");
+        };
+        assert_eq!(
+            out,
+            "# from the default\nCOLOR=auto\n# from argument 3 on the command line\nPORT=80\n"
+        );
+
+        let scoped = Scoped {
+            global: &mut commands.global,
+            command: &mut commands.commands[1].flags,
+        };
+        assert_eq!(
+            scoped.flag_name(&ScopedKey::Global("color".into())),
+            Some("color".into())
+        );
+        assert_eq!(
+            scoped
+                .sources()
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            ["color", "port"]
+        );
+    }
+
+    #[test]
+    fn test_global() {
+        let mut commands = commands();
+        commands
+            .global
+            .add(Flag::new("verbose", FlagValue::Bool(false)).shorthand('v'));
+        commands
+            .global
+            .add(Flag::new("color", FlagValue::String("auto".into())));
+        commands
+            .global
+            .add(Flag::new("port", FlagValue::String("shadowed".into())));
+
+        let matched = command(
+            commands
+                .parse_args(strings(&["--color", "never", "run", "-v", "--port", "80"]))
+                .unwrap(),
+        );
+        assert_eq!(matched.name, "run");
+        assert_eq!(commands.global.get("verbose").value, FlagValue::Bool(true));
+        assert_eq!(
+            commands.global.get("color").value,
+            FlagValue::String("never".into())
+        );
+        assert_eq!(
+            commands.get("run").flags.get("port").value,
+            FlagValue::Int64(80)
+        );
+        assert_eq!(
+            commands.global.get("port").value,
+            FlagValue::String("shadowed".into())
+        );
+
+        let matched = command(
+            commands
+                .parse_args(strings(&["--color=always", "build"]))
+                .unwrap(),
+        );
+        assert_eq!(matched.name, "build");
+        assert_eq!(
+            commands.global.get("color").value,
+            FlagValue::String("always".into())
+        );
+
+        let err = commands
+            .parse_args(strings(&["-v", "--release", "build"]))
+            .unwrap_err();
+        assert!(matches!(err, Error::UnknownCommand { name, .. } if name == "--release"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_external() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let dir = env::temp_dir().join(format!("cliconf-test-external-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("mytool-foo");
+        fs::write(&program, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(dir.join("mytool-data"), "").unwrap();
+
+        let path = env::join_paths([Path::new("/nonexistent"), &dir]).unwrap();
+        assert_eq!(find_program("mytool-foo", &path), Some(program));
+        assert_eq!(find_program("mytool-data", &path), None);
+        assert_eq!(find_program("mytool-bar", &path), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_external_fallback() {
+        let mut commands = commands();
+        commands.external_prefix = Some("cliconf-test-no-such-program".into());
+
+        let matched = command(commands.parse_args(strings(&["build"])).unwrap());
+        assert_eq!(matched.name, "build");
+        let err = commands.parse_args(strings(&["foo"])).unwrap_err();
+        assert!(matches!(err, Error::UnknownCommand { name, .. } if name == "foo"));
+    }
+
+    #[test]
+    fn test_default() {
+        let mut commands = commands();
+        commands.default = Some("run".into());
+
+        let matched = command(commands.parse_args(strings(&["--port", "80"])).unwrap());
+        assert_eq!(matched.name, "run");
+        assert_eq!(
+            commands.get("run").flags.get("port").value,
+            FlagValue::Int64(80)
+        );
+
+        let matched = command(commands.parse_args(strings(&["build"])).unwrap());
+        assert_eq!(matched.name, "build");
+        let matched = command(commands.parse_args(strings(&["file.txt"])).unwrap());
+        assert_eq!(matched.name, "run");
+        assert_eq!(matched.positionals, ["file.txt"]);
+    }
+}
@@ -0,0 +1,220 @@
+//! Shell completion, computed by the program itself.
+//!
+//! Completion scripts call the program back with [`COMPLETE_ARG`] followed by
+//! the words typed so far, the last of which is the one being completed:
+//!
+//! ```text
+//! mytool __complete --cluster pro
+//! ```
+//!
+//! Since the program answers, values can come from anywhere, e.g. a
+//! [`Completer`] that lists resources from an API.
+
+use std::{fmt, ops::BitOr};
+
+use crate::engine::{self, Dispatch};
+
+/// The hidden first argument that asks for completions instead of running.
+pub const COMPLETE_ARG: &str = "__complete";
+
+/// Suggests values for a flag given the prefix typed so far. Candidates that
+/// don't start with the prefix are filtered out afterwards.
+pub type Completer = fn(prefix: &str) -> Vec<String>;
+
+/// A possible completion of the word being typed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub value: String,
+    /// Shown next to the value by shells that support it.
+    pub description: Option<String>,
+}
+
+/// Tells the completion script what to do with the candidates. The values
+/// are those of cobra's `ShellCompDirective`, so existing scripts work too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Directive(pub u32);
+
+impl Directive {
+    /// Complete the candidates, or fall back to file names if there are none.
+    pub const DEFAULT: Directive = Directive(0);
+    /// Completion failed, and nothing should be completed.
+    pub const ERROR: Directive = Directive(1);
+    /// Don't add a space after completing a candidate.
+    pub const NO_SPACE: Directive = Directive(2);
+    /// Don't fall back to file names when there are no candidates.
+    pub const NO_FILE_COMP: Directive = Directive(4);
+    /// Keep the candidates in the order given instead of sorting them.
+    pub const KEEP_ORDER: Directive = Directive(32);
+
+    pub fn contains(self, other: Directive) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Directive {
+    type Output = Directive;
+
+    fn bitor(self, rhs: Directive) -> Directive {
+        Directive(self.0 | rhs.0)
+    }
+}
+
+/// The answer to a completion request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completions {
+    pub candidates: Vec<Candidate>,
+    pub directive: Directive,
+}
+
+/// Formats the completions the way completion scripts read them: one
+/// candidate per line, with a tab before its description, followed by a line
+/// with `:` and the directive.
+///
+/// ```text
+/// --cluster\tThe cluster to deploy to
+/// --count
+/// :4
+/// ```
+impl fmt::Display for Completions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for candidate in &self.candidates {
+            // Tabs and newlines would break the line-based protocol
+            let clean = |s: &str| s.replace(['\t', '\n', '\r'], " ");
+            write!(f, "{}", clean(&candidate.value))?;
+            if let Some(description) = &candidate.description {
+                write!(f, "\t{}", clean(description))?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, ":{}", self.directive.0)
+    }
+}
+
+/// Returns the completions for `args` if they start with [`COMPLETE_ARG`],
+/// or `None` if the program should run as usual. Print the result to stdout
+/// and exit. Works with [`crate::Flags`], derived structs (which also have a
+/// `complete` method) and commands alike.
+pub fn intercept<D: Dispatch>(d: &D, args: &[String]) -> Option<Completions> {
+    match args.split_first() {
+        Some((first, words)) if first == COMPLETE_ARG => Some(complete(d, words)),
+        _ => None,
+    }
+}
+
+/// Completes the last of `words`.
+pub fn complete<D: Dispatch>(d: &D, words: &[String]) -> Completions {
+    let Some((prefix, before)) = words.split_last() else {
+        return long_flags(d, "");
+    };
+    if let Some(key) = before
+        .last()
+        .and_then(|prev| engine::pending_value(d, prev).flatten())
+    {
+        let Some(completer) = d.completer(&key) else {
+            // Without a completer, file names are the best guess
+            return Completions {
+                candidates: vec![],
+                directive: Directive::DEFAULT,
+            };
+        };
+        let candidates = completer(prefix)
+            .into_iter()
+            .filter(|value| value.starts_with(prefix.as_str()))
+            .map(|value| Candidate {
+                value,
+                description: None,
+            })
+            .collect();
+        return Completions {
+            candidates,
+            directive: Directive::NO_FILE_COMP,
+        };
+    }
+    if prefix.starts_with('-') {
+        return long_flags(d, prefix);
+    }
+    Completions {
+        candidates: vec![],
+        directive: Directive::DEFAULT,
+    }
+}
+
+fn long_flags<D: Dispatch>(d: &D, prefix: &str) -> Completions {
+    let mut candidates: Vec<Candidate> = d
+        .metadata()
+        .into_iter()
+        .map(|flag| Candidate {
+            value: format!("--{}", flag.name),
+            description: flag.description,
+        })
+        .filter(|candidate| candidate.value.starts_with(prefix))
+        .collect();
+    candidates.sort_by(|a, b| a.value.cmp(&b.value));
+    Completions {
+        candidates,
+        directive: Directive::NO_FILE_COMP,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Flag, FlagValue, Flags};
+
+    use super::*;
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn output(flags: &Flags, args: &[&str]) -> Option<String> {
+        intercept(flags, &strings(args)).map(|completions| completions.to_string())
+    }
+
+    #[test]
+    fn test_intercept() {
+        let mut flags = Flags::new();
+        flags.add(
+            Flag::new("cluster", FlagValue::String("".into()))
+                .shorthand('c')
+                .description("The cluster\tto deploy to")
+                .completer(|_| vec!["prod-eu".into(), "prod-us".into(), "staging".into()]),
+        );
+        flags.add(Flag::new("count", FlagValue::Int64(0)));
+        flags.add(Flag::new("verbose", FlagValue::Bool(false)).shorthand('v'));
+        flags.add(Flag::new("secret", FlagValue::Bool(false)).exclude_from_usage());
+
+        assert_eq!(output(&flags, &["--cluster", "prod"]), None);
+        assert_eq!(
+            output(&flags, &["__complete", "--cluster", "prod"]).unwrap(),
+            "prod-eu\nprod-us\n:4\n"
+        );
+        assert_eq!(
+            output(&flags, &["__complete", "-c", ""]).unwrap(),
+            "prod-eu\nprod-us\nstaging\n:4\n"
+        );
+        assert_eq!(
+            output(&flags, &["__complete", "-vc", "st"]).unwrap(),
+            "staging\n:4\n"
+        );
+        assert_eq!(
+            output(&flags, &["__complete", "--c"]).unwrap(),
+            "--cluster\tThe cluster to deploy to\n--count\n:4\n"
+        );
+        assert_eq!(
+            output(&flags, &["__complete", "--count", ""]).unwrap(),
+            ":0\n"
+        );
+        assert_eq!(
+            output(&flags, &["__complete", "--verbose", "--v"]).unwrap(),
+            "--verbose\n:4\n"
+        );
+    }
+
+    #[test]
+    fn test_directive() {
+        let directive = Directive::NO_SPACE | Directive::NO_FILE_COMP;
+        assert_eq!(directive, Directive(6));
+        assert!(directive.contains(Directive::NO_SPACE));
+        assert!(!directive.contains(Directive::ERROR));
+    }
+}
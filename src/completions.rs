@@ -0,0 +1,374 @@
+//! Completion scripts for bash, zsh and fish, generated from the flags'
+//! metadata.
+//!
+//! These scripts are installed once and know the flags, their shorthands
+//! and what their values are, e.g. file names or a fixed set of choices.
+//! For any other value they call the program back with
+//! [`crate::complete::COMPLETE_ARG`], so the flag's
+//! [`crate::complete::Completer`] can suggest it.
+//!
+//! ```
+//! use cliconf::{completions::{self, Shell}, Flag, FlagValue, Flags};
+//!
+//! let mut flags = Flags::new();
+//! flags.add(Flag::new("verbose", FlagValue::Bool(false)).shorthand('v'));
+//! let script = completions::generate(Shell::Fish, "mytool", &flags.flags_metadata());
+//! assert_eq!(script, "complete -c mytool -l verbose -s v\n");
+//! ```
+
+use std::{fmt::Write, str::FromStr};
+
+use crate::{
+    dump::shell_quote,
+    meta::{FlagMeta, ValueHint},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The completion script for the program `name` in `shell`.
+pub fn generate(shell: Shell, name: &str, flags: &[FlagMeta]) -> String {
+    match shell {
+        Shell::Bash => bash(name, flags),
+        Shell::Zsh => zsh(name, flags),
+        Shell::Fish => fish(name, flags),
+    }
+}
+
+/// The ways to write a flag on the command line, e.g. `--name` and `-n`.
+fn spellings(flag: &FlagMeta) -> Vec<String> {
+    let mut spellings = vec![format!("--{}", flag.name)];
+    spellings.extend(flag.shorthand.map(|c| format!("-{c}")));
+    spellings
+}
+
+/// `name` as it can appear in a shell function's name.
+fn function_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Whether a flag's values are suggested by the program, rather than the
+/// script.
+fn asks_program(flag: &FlagMeta) -> bool {
+    flag.value_hint == Some(ValueHint::Any)
+}
+
+/// The first line of the description, which is all that fits next to a
+/// candidate.
+fn summary(flag: &FlagMeta) -> Option<&str> {
+    let line = flag.description.as_deref()?.lines().next()?.trim();
+    (!line.is_empty()).then_some(line)
+}
+
+fn bash(name: &str, flags: &[FlagMeta]) -> String {
+    let function = function_name(name);
+    let mut out = String::new();
+    if flags.iter().any(asks_program) {
+        // Candidates are one per line with an optional tab and description,
+        // then a line with the directive
+        let _ = writeln!(out, "_{function}_values() {{");
+        out += "    local IFS=$'\\n'\n";
+        out += "    local lines=($(\"${COMP_WORDS[0]}\" __complete \"${COMP_WORDS[@]:1:COMP_CWORD}\" 2>/dev/null))\n";
+        out += "    local last=$(( ${#lines[@]} - 1 ))\n";
+        out += "    [[ $last -ge 0 && ${lines[last]} =~ ^:([0-9]+)$ ]] || return\n";
+        out += "    local directive=${BASH_REMATCH[1]}\n";
+        out += "    unset \"lines[last]\"\n";
+        out += "    COMPREPLY=(\"${lines[@]%%$'\\t'*}\")\n";
+        out += "    if [[ ${#COMPREPLY[@]} -eq 0 && $(( directive & 4 )) -eq 0 ]]; then\n";
+        out += "        COMPREPLY=($(compgen -f -- \"$cur\"))\n";
+        out += "    fi\n";
+        out += "}\n";
+    }
+    let _ = writeln!(out, "_{function}() {{");
+    out += "    local cur=\"${COMP_WORDS[COMP_CWORD]}\"\n";
+    out += "    local prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n";
+    let value_flags: Vec<_> = flags
+        .iter()
+        .filter_map(|flag| Some((flag, flag.value_hint.as_ref()?)))
+        .collect();
+    if !value_flags.is_empty() {
+        out += "    case \"$prev\" in\n";
+        for (flag, hint) in value_flags {
+            let _ = writeln!(out, "        {})", spellings(flag).join("|"));
+            match hint {
+                ValueHint::Any => {
+                    let _ = writeln!(out, "            _{function}_values");
+                }
+                ValueHint::Path => out += "            COMPREPLY=($(compgen -f -- \"$cur\"))\n",
+                ValueHint::Choices(choices) => {
+                    let _ = writeln!(
+                        out,
+                        "            COMPREPLY=($(compgen -W {} -- \"$cur\"))",
+                        shell_quote(&choices.join(" "))
+                    );
+                }
+            }
+            out += "            return\n";
+            out += "            ;;\n";
+        }
+        out += "    esac\n";
+    }
+    let all: Vec<String> = flags.iter().flat_map(spellings).collect();
+    out += "    if [[ \"$cur\" == -* ]]; then\n";
+    let _ = writeln!(
+        out,
+        "        COMPREPLY=($(compgen -W {} -- \"$cur\"))",
+        shell_quote(&all.join(" "))
+    );
+    out += "        return\n";
+    out += "    fi\n";
+    out += "    COMPREPLY=($(compgen -f -- \"$cur\"))\n";
+    out += "}\n";
+    let _ = writeln!(out, "complete -F _{function} {}", shell_quote(name));
+    out
+}
+
+/// Escapes the characters that `_arguments` gives a meaning to in `text`.
+fn zsh_escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if "\\[]:()".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn zsh(name: &str, flags: &[FlagMeta]) -> String {
+    let function = function_name(name);
+    let mut out = format!("#compdef {name}\n\n");
+    if flags.iter().any(asks_program) {
+        let _ = writeln!(out, "_{function}_values() {{");
+        out += "    local -a lines candidates\n";
+        out += "    local line\n";
+        out += "    lines=(\"${(@f)$(${words[1]} __complete \"${(@)words[2,CURRENT]}\" 2>/dev/null)}\")\n";
+        out += "    [[ ${lines[-1]} == :<-> ]] || return 1\n";
+        out += "    for line in \"${(@)lines[1,-2]}\"; do\n";
+        out += "        if [[ $line == *$'\\t'* ]]; then\n";
+        out += "            candidates+=(\"${${line%%$'\\t'*}//:/\\\\:}:${line#*$'\\t'}\")\n";
+        out += "        else\n";
+        out += "            candidates+=(\"${line//:/\\\\:}\")\n";
+        out += "        fi\n";
+        out += "    done\n";
+        out += "    if (( ${#candidates} )); then\n";
+        out += "        _describe value candidates\n";
+        out += "    elif (( !(${lines[-1]#:} & 4) )); then\n";
+        out += "        _files\n";
+        out += "    fi\n";
+        out += "}\n\n";
+    }
+    out += "_arguments -s \\\n";
+    for flag in flags {
+        let description = summary(flag)
+            .map(|summary| format!("[{}]", zsh_escape(summary)))
+            .unwrap_or_default();
+        let value = flag.value_hint.as_ref().map(|hint| {
+            let placeholder = flag.name.replace('-', "_").to_uppercase();
+            let action = match hint {
+                ValueHint::Any => format!("_{function}_values"),
+                ValueHint::Path => "_files".into(),
+                ValueHint::Choices(choices) => {
+                    let choices: Vec<String> = choices
+                        .iter()
+                        .map(|choice| zsh_escape(choice).replace(' ', "\\ "))
+                        .collect();
+                    format!("({})", choices.join(" "))
+                }
+            };
+            format!(":{placeholder}:{action}")
+        });
+        for spelling in spellings(flag) {
+            let spec = format!("{spelling}{description}{}", value.as_deref().unwrap_or(""));
+            let _ = writeln!(out, "    {} \\", shell_quote(&spec));
+        }
+    }
+    out += "    '*:file:_files'\n";
+    out
+}
+
+fn fish(name: &str, flags: &[FlagMeta]) -> String {
+    let function = function_name(name);
+    let mut out = String::new();
+    if flags.iter().any(asks_program) {
+        // Fish reads the candidates and their descriptions as they are, so
+        // only the directive is left out
+        let _ = writeln!(out, "function __{function}_values");
+        out += "    set -l words (commandline -opc) (commandline -ct)\n";
+        out +=
+            "    $words[1] __complete $words[2..-1] 2>/dev/null | string match -v -r '^:[0-9]+$'\n";
+        out += "end\n";
+    }
+    for flag in flags {
+        let _ = write!(out, "complete -c {} -l {}", shell_quote(name), flag.name);
+        if let Some(c) = flag.shorthand {
+            let _ = write!(out, " -s {c}");
+        }
+        match &flag.value_hint {
+            None => {}
+            Some(ValueHint::Any) => {
+                let _ = write!(out, " -x -a '(__{function}_values)'");
+            }
+            Some(ValueHint::Path) => out += " -r -F",
+            Some(ValueHint::Choices(choices)) => {
+                let _ = write!(out, " -x -a {}", shell_quote(&choices.join(" ")));
+            }
+        }
+        if let Some(summary) = summary(flag) {
+            let _ = write!(out, " -d {}", shell_quote(summary));
+        }
+        out += "\n";
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::meta::FlagKind;
+
+    use super::*;
+
+    fn flags() -> Vec<FlagMeta> {
+        let flag = |name: &str, shorthand, value_hint| FlagMeta {
+            name: name.into(),
+            shorthand,
+            description: None,
+            env_var: None,
+            required: false,
+            value_hint,
+            kind: FlagKind::String,
+            is_array: false,
+            default: None,
+            unit: None,
+            secret: false,
+        };
+        let mut output = flag("output", Some('o'), Some(ValueHint::Path));
+        output.description = Some("Where to write [default: stdout]\n\nMore".into());
+        vec![
+            flag(
+                "color",
+                None,
+                Some(ValueHint::Choices(vec!["auto".into(), "never".into()])),
+            ),
+            flag("name", None, Some(ValueHint::Any)),
+            output,
+            flag("verbose", Some('v'), None),
+        ]
+    }
+
+    #[test]
+    fn test_bash() {
+        assert_eq!(
+            generate(Shell::Bash, "my-tool", &flags()),
+            r#"_my_tool_values() {
+    local IFS=$'\n'
+    local lines=($("${COMP_WORDS[0]}" __complete "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null))
+    local last=$(( ${#lines[@]} - 1 ))
+    [[ $last -ge 0 && ${lines[last]} =~ ^:([0-9]+)$ ]] || return
+    local directive=${BASH_REMATCH[1]}
+    unset "lines[last]"
+    COMPREPLY=("${lines[@]%%$'\t'*}")
+    if [[ ${#COMPREPLY[@]} -eq 0 && $(( directive & 4 )) -eq 0 ]]; then
+        COMPREPLY=($(compgen -f -- "$cur"))
+    fi
+}
+_my_tool() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    local prev="${COMP_WORDS[COMP_CWORD-1]}"
+    case "$prev" in
+        --color)
+            COMPREPLY=($(compgen -W 'auto never' -- "$cur"))
+            return
+            ;;
+        --name)
+            _my_tool_values
+            return
+            ;;
+        --output|-o)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return
+            ;;
+    esac
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W '--color --name --output -o --verbose -v' -- "$cur"))
+        return
+    fi
+    COMPREPLY=($(compgen -f -- "$cur"))
+}
+complete -F _my_tool my-tool
+"#
+        );
+    }
+
+    #[test]
+    fn test_zsh() {
+        assert_eq!(
+            generate(Shell::Zsh, "mytool", &flags()),
+            r#"#compdef mytool
+
+_mytool_values() {
+    local -a lines candidates
+    local line
+    lines=("${(@f)$(${words[1]} __complete "${(@)words[2,CURRENT]}" 2>/dev/null)}")
+    [[ ${lines[-1]} == :<-> ]] || return 1
+    for line in "${(@)lines[1,-2]}"; do
+        if [[ $line == *$'\t'* ]]; then
+            candidates+=("${${line%%$'\t'*}//:/\\:}:${line#*$'\t'}")
+        else
+            candidates+=("${line//:/\\:}")
+        fi
+    done
+    if (( ${#candidates} )); then
+        _describe value candidates
+    elif (( !(${lines[-1]#:} & 4) )); then
+        _files
+    fi
+}
+
+_arguments -s \
+    '--color:COLOR:(auto never)' \
+    --name:NAME:_mytool_values \
+    '--output[Where to write \[default\: stdout\]]:OUTPUT:_files' \
+    '-o[Where to write \[default\: stdout\]]:OUTPUT:_files' \
+    --verbose \
+    -v \
+    '*:file:_files'
+"#
+        );
+    }
+
+    #[test]
+    fn test_fish() {
+        assert_eq!(
+            generate(Shell::Fish, "mytool", &flags()),
+            r#"function __mytool_values
+    set -l words (commandline -opc) (commandline -ct)
+    $words[1] __complete $words[2..-1] 2>/dev/null | string match -v -r '^:[0-9]+$'
+end
+complete -c mytool -l color -x -a 'auto never'
+complete -c mytool -l name -x -a '(__mytool_values)'
+complete -c mytool -l output -s o -r -F -d 'Where to write [default: stdout]'
+complete -c mytool -l verbose -s v
+"#
+        );
+    }
+}
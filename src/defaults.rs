@@ -0,0 +1,185 @@
+//! Per-user preferences from macOS `defaults` domains, e.g. ones a companion
+//! GUI app writes with `UserDefaults`.
+//!
+//! A setting is named by its domain and key, as in `com.example.app
+//! ShowSidebar`. Each domain is read once with `defaults export`, which sees
+//! changes the preferences daemon hasn't written to disk yet. Domains that
+//! can't be read, including every domain on other platforms, are skipped.
+
+use std::{collections::HashMap, io::Cursor, process::Stdio, time::Duration};
+
+use crate::{engine::Dispatch, exec, file, Error, Source};
+
+/// How long reading a single domain may take.
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Sets each key to the value of its setting, for those that are set. Call
+/// it before reading the environment and arguments, so they override the
+/// user's preferences.
+pub fn parse<D: Dispatch, S: AsRef<str>>(d: &mut D, settings: &[(D::Key, S)]) -> Result<(), Error> {
+    apply(d, settings, read)
+}
+
+fn apply<D: Dispatch, S: AsRef<str>>(
+    d: &mut D,
+    settings: &[(D::Key, S)],
+    read: impl Fn(&str) -> Option<plist::Dictionary>,
+) -> Result<(), Error> {
+    let mut domains: HashMap<&str, Option<plist::Dictionary>> = HashMap::new();
+    for (key, setting) in settings {
+        let setting = setting.as_ref();
+        let Some((domain, name)) = setting.split_once(' ') else {
+            continue;
+        };
+        let Some(value) = domains
+            .entry(domain)
+            .or_insert_with(|| read(domain))
+            .as_ref()
+            .and_then(|domain| domain.get(name.trim()))
+        else {
+            continue;
+        };
+        let json = to_json(value).ok_or_else(|| Error::InvalidValue {
+            flag: setting.into(),
+            value: format!("{value:?}"),
+            source: None,
+        })?;
+        file::apply_value(d, key, setting, &json, &Source::Setting(setting.into()))?;
+    }
+    Ok(())
+}
+
+/// Reads every key in a domain, or `None` if it can't be read.
+pub fn read(domain: &str) -> Option<plist::Dictionary> {
+    if !cfg!(target_os = "macos") {
+        return None;
+    }
+    let words = ["defaults", "export", domain, "-"].map(String::from);
+    let xml = exec::run(&words, TIMEOUT, Stdio::null()).ok()?;
+    plist::Value::from_reader(Cursor::new(xml))
+        .ok()?
+        .into_dictionary()
+}
+
+/// Converts a plist value to JSON. Strings, booleans, numbers, dates and
+/// arrays of them are supported; dictionaries and data aren't.
+pub fn to_json(value: &plist::Value) -> Option<serde_json::Value> {
+    let json = match value {
+        plist::Value::String(s) => s.clone().into(),
+        plist::Value::Boolean(b) => (*b).into(),
+        plist::Value::Integer(n) => match n.as_signed() {
+            Some(n) => n.into(),
+            None => n.as_unsigned()?.into(),
+        },
+        plist::Value::Real(n) => serde_json::Number::from_f64(*n)?.into(),
+        plist::Value::Date(date) => date.to_xml_format().into(),
+        plist::Value::Array(items) => items.iter().map(to_json).collect::<Option<_>>()?,
+        _ => return None,
+    };
+    Some(json)
+}
+
+/// Generates `parse_defaults` for the derive macro, which can't tell whether
+/// the `plist` feature is enabled itself.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __parse_defaults {
+    ($vis:vis $name:ident, [$(($key:expr, $setting:expr)),*]) => {
+        /// Reads the macOS preference of each field that names one, skipping
+        /// those that aren't set. Call it before the other methods, so the
+        /// environment and arguments override the preferences.
+        $vis fn $name(&mut self) -> ::std::result::Result<(), $crate::Error> {
+            $crate::defaults::parse(self, &[$(($key, $setting)),*])
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use crate::{Flag, FlagValue, Flags};
+
+    use super::*;
+
+    const EXPORT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>ShowSidebar</key>
+    <true/>
+    <key>FontSize</key>
+    <integer>13</integer>
+    <key>RecentFiles</key>
+    <array>
+        <string>a.txt</string>
+        <string>b.txt</string>
+    </array>
+    <key>LastOpened</key>
+    <date>2024-01-02T03:04:05Z</date>
+    <key>Window</key>
+    <dict>
+        <key>Width</key>
+        <integer>800</integer>
+    </dict>
+</dict>
+</plist>"#;
+
+    #[test]
+    fn test_apply() {
+        let mut flags = Flags::new();
+        flags.add(Flag::new("sidebar", FlagValue::Bool(false)));
+        flags.add(Flag::new("font-size", FlagValue::Int64(12)));
+        flags.add(Flag::new("recent", FlagValue::StringArray(vec![])));
+        flags.add(Flag::new("last-opened", FlagValue::String("".into())));
+        flags.add(Flag::new("theme", FlagValue::String("light".into())));
+
+        let reads = Cell::new(0);
+        let read = |domain: &str| {
+            reads.set(reads.get() + 1);
+            (domain == "com.example.app").then(|| {
+                plist::Value::from_reader(Cursor::new(EXPORT))
+                    .unwrap()
+                    .into_dictionary()
+                    .unwrap()
+            })
+        };
+        let settings = [
+            ("sidebar".to_string(), "com.example.app ShowSidebar"),
+            ("font-size".to_string(), "com.example.app FontSize"),
+            ("recent".to_string(), "com.example.app RecentFiles"),
+            ("last-opened".to_string(), "com.example.app LastOpened"),
+            ("theme".to_string(), "com.example.app Theme"),
+        ];
+        apply(&mut flags, &settings, read).unwrap();
+        // Each domain is only exported once
+        assert_eq!(reads.get(), 1);
+        assert_eq!(flags.get("sidebar").value, FlagValue::Bool(true));
+        assert_eq!(
+            flags.get("sidebar").source,
+            Source::Setting("com.example.app ShowSidebar".into())
+        );
+        assert_eq!(flags.get("font-size").value, FlagValue::Int64(13));
+        assert_eq!(
+            flags.get("recent").value,
+            FlagValue::StringArray(vec!["a.txt".into(), "b.txt".into()])
+        );
+        assert_eq!(
+            flags.get("last-opened").value,
+            FlagValue::String("2024-01-02T03:04:05Z".into())
+        );
+        // Unset keys are skipped
+        assert_eq!(flags.get("theme").source, Source::Default);
+
+        let settings = [("theme".to_string(), "com.example.app Window")];
+        let err = apply(&mut flags, &settings, read).unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidValue { ref flag, .. } if flag == "com.example.app Window"),
+            "{err}"
+        );
+
+        let settings = [("theme".to_string(), "com.example.missing Theme")];
+        apply(&mut flags, &settings, read).unwrap();
+        assert_eq!(flags.get("theme").source, Source::Default);
+    }
+}
@@ -0,0 +1,209 @@
+//! Dockerfile and Compose snippets generated from the flags' metadata, so
+//! container images can be configured through the same environment
+//! variables the program reads.
+//!
+//! ```
+//! use cliconf::{docker, Flag, FlagValue, Flags};
+//!
+//! let mut flags = Flags::new();
+//! flags.add(Flag::new("port", FlagValue::Int64(8080)).description("Where to listen"));
+//! assert_eq!(
+//!     docker::dockerfile(&flags.flags_metadata()),
+//!     "# Where to listen\nARG PORT=8080\nENV PORT=${PORT}\n"
+//! );
+//! ```
+
+use std::fmt::Write;
+
+use crate::meta::FlagMeta;
+
+/// `ARG` and `ENV` lines for every flag that has an environment variable, so
+/// each can be set with `--build-arg` and is then passed on to the program.
+/// Descriptions become comments. Flags without a known default get an `ARG`
+/// with no value and a commented-out `ENV`, since an empty value might not
+/// parse.
+pub fn dockerfile(flags: &[FlagMeta]) -> String {
+    let mut out = String::new();
+    for (i, (flag, var)) in with_env_vars(flags).enumerate() {
+        if i > 0 {
+            out += "\n";
+        }
+        comment(&mut out, "", flag);
+        match default(flag) {
+            Some(default) => {
+                let _ = writeln!(out, "ARG {var}={}", dockerfile_quote(default));
+                let _ = writeln!(out, "ENV {var}=${{{var}}}");
+            }
+            None => {
+                let _ = writeln!(out, "ARG {var}");
+                let _ = writeln!(out, "# ENV {var}=${{{var}}}");
+            }
+        }
+    }
+    out
+}
+
+/// An `environment:` block for a Compose service, with every flag that has
+/// an environment variable set to its default. Descriptions become
+/// comments, and flags without a known default are commented out.
+pub fn compose_environment(flags: &[FlagMeta]) -> String {
+    let mut out = String::from("environment:\n");
+    for (flag, var) in with_env_vars(flags) {
+        comment(&mut out, "  ", flag);
+        match default(flag) {
+            Some(default) => {
+                let _ = writeln!(out, "  {var}: {}", yaml_quote(default));
+            }
+            None => {
+                let _ = writeln!(out, "  # {var}:");
+            }
+        }
+    }
+    out
+}
+
+fn with_env_vars(flags: &[FlagMeta]) -> impl Iterator<Item = (&FlagMeta, &str)> {
+    flags
+        .iter()
+        .filter_map(|flag| Some((flag, flag.env_var.as_deref()?)))
+}
+
+/// The default as the environment variable would hold it. Arrays are left
+/// out, since the delimiter their variables are split on isn't known here.
+fn default(flag: &FlagMeta) -> Option<&str> {
+    match flag.is_array {
+        true => None,
+        false => flag.default.as_deref(),
+    }
+}
+
+/// Writes the flag's description as comments, one per line.
+fn comment(out: &mut String, indentation: &str, flag: &FlagMeta) {
+    let Some(description) = &flag.description else {
+        return;
+    };
+    for line in description.lines() {
+        match line.trim_end() {
+            "" => {
+                let _ = writeln!(out, "{indentation}#");
+            }
+            line => {
+                let _ = writeln!(out, "{indentation}# {line}");
+            }
+        }
+    }
+}
+
+/// Quotes a value for `ARG` if it needs it, escaping `$` so it isn't
+/// substituted.
+fn dockerfile_quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.,:/@+".contains(c));
+    if plain {
+        return value.into();
+    }
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' | '\\' | '$' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted += "\\n",
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A double-quoted YAML string, which Compose keeps as a string even for
+/// values like `true` or `8080`. `$` is doubled so it isn't substituted.
+fn yaml_quote(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '$' => quoted += "$$",
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::meta::FlagKind;
+
+    use super::*;
+
+    fn flags() -> Vec<FlagMeta> {
+        let flag = |name: &str, default: Option<&str>| FlagMeta {
+            name: name.into(),
+            shorthand: None,
+            description: None,
+            env_var: Some(name.replace('-', "_").to_uppercase()),
+            required: false,
+            value_hint: None,
+            kind: FlagKind::String,
+            is_array: false,
+            default: default.map(Into::into),
+            unit: None,
+            secret: false,
+        };
+        let mut port = flag("port", Some("8080"));
+        port.description = Some("Where to listen\n\nAny port".into());
+        let mut greeting = flag("greeting", Some("hi \"$USER\""));
+        greeting.env_var = Some("APP_GREETING".into());
+        let mut tags = flag("tags", Some("a,b"));
+        tags.is_array = true;
+        let mut internal = flag("internal", Some("x"));
+        internal.env_var = None;
+        vec![port, greeting, tags, internal, flag("api-key", None)]
+    }
+
+    #[test]
+    fn test_dockerfile() {
+        assert_eq!(
+            dockerfile(&flags()),
+            r#"# Where to listen
+#
+# Any port
+ARG PORT=8080
+ENV PORT=${PORT}
+
+ARG APP_GREETING="hi \"\$USER\""
+ENV APP_GREETING=${APP_GREETING}
+
+ARG TAGS
+# ENV TAGS=${TAGS}
+
+ARG API_KEY
+# ENV API_KEY=${API_KEY}
+"#
+        );
+    }
+
+    #[test]
+    fn test_compose_environment() {
+        assert_eq!(
+            compose_environment(&flags()),
+            r#"environment:
+  # Where to listen
+  #
+  # Any port
+  PORT: "8080"
+  APP_GREETING: "hi \"$$USER\""
+  # TAGS:
+  # API_KEY:
+"#
+        );
+    }
+}
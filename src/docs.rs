@@ -0,0 +1,125 @@
+//! Markdown documentation generated from the flags' metadata, for pasting
+//! into a README or a docs site.
+//!
+//! ```
+//! use cliconf::{docs, meta::About, Flag, FlagValue, Flags};
+//!
+//! let mut flags = Flags::new();
+//! flags.add(Flag::new("port", FlagValue::Int64(8080)).description("Where to listen"));
+//! let about = About {
+//!     name: "mytool".into(),
+//!     version: "1.2.0".into(),
+//!     about: None,
+//!     authors: None,
+//! };
+//! let markdown = docs::markdown(&about, &flags.flags_metadata());
+//! assert!(markdown.ends_with("| `--port` | `8080` | `PORT` | Where to listen |\n"));
+//! ```
+
+use std::fmt::Write;
+
+use crate::meta::{About, FlagMeta};
+
+/// A section for the program `about`: a heading with its name, its
+/// description, and a table of `flags` with their shorthands, defaults,
+/// environment variables and descriptions.
+pub fn markdown(about: &About, flags: &[FlagMeta]) -> String {
+    let mut out = format!("# {}\n\n", about.name);
+    if let Some(description) = &about.about {
+        let _ = writeln!(out, "{description}\n");
+    }
+    out += "| Flag | Default | Environment variable | Description |\n";
+    out += "| --- | --- | --- | --- |\n";
+    for flag in flags {
+        let mut name = code(&format!("--{}", flag.name));
+        if let Some(c) = flag.shorthand {
+            name += &format!(", {}", code(&format!("-{c}")));
+        }
+        let default = match (&flag.default, flag.required) {
+            (_, true) => "*required*".into(),
+            (Some(default), false) => code(default),
+            (None, false) => String::new(),
+        };
+        let env_var = flag.env_var.as_deref().map(code).unwrap_or_default();
+        let description = flag.description.as_deref().map(cell).unwrap_or_default();
+        let _ = writeln!(out, "| {name} | {default} | {env_var} | {description} |");
+    }
+    out
+}
+
+/// Text that fits in one table cell: pipes are escaped, lines are joined,
+/// and paragraphs are separated by a line break.
+fn cell(text: &str) -> String {
+    text.split("\n\n")
+        .map(|paragraph| paragraph.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<_>>()
+        .join("<br>")
+        .replace('|', "\\|")
+}
+
+/// A code span in a table cell, with enough backticks around it that any in
+/// `text` don't end it early.
+fn code(text: &str) -> String {
+    let text = text.replace('|', "\\|").replace('\n', " ");
+    let longest_run = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run + 1);
+    if text.is_empty() {
+        "`\"\"`".into()
+    } else if longest_run > 0 {
+        format!("{fence} {text} {fence}")
+    } else {
+        format!("{fence}{text}{fence}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::meta::FlagKind;
+
+    use super::*;
+
+    #[test]
+    fn test_markdown() {
+        let flag = |name: &str, default: Option<&str>| FlagMeta {
+            name: name.into(),
+            shorthand: None,
+            description: None,
+            env_var: Some(name.replace('-', "_").to_uppercase()),
+            required: false,
+            value_hint: None,
+            kind: FlagKind::String,
+            is_array: false,
+            default: default.map(Into::into),
+            unit: None,
+            secret: false,
+        };
+        let mut name = flag("name", Some(""));
+        name.shorthand = Some('n');
+        name.description = Some("Who to greet,\neither a | b.\n\nMore".into());
+        let mut api_key = flag("api-key", None);
+        api_key.required = true;
+        let mut quote = flag("quote", Some("`"));
+        quote.env_var = None;
+        let about = About {
+            name: "mytool".into(),
+            version: "1.2.0".into(),
+            about: Some("Does things".into()),
+            authors: None,
+        };
+        assert_eq!(
+            markdown(&about, &[name, api_key, quote, flag("dir", None)]),
+            "# mytool
+
+Does things
+
+| Flag | Default | Environment variable | Description |
+| --- | --- | --- | --- |
+| `--name`, `-n` | `\"\"` | `NAME` | Who to greet, either a \\| b.<br>More |
+| `--api-key` | *required* | `API_KEY` |  |
+| `--quote` | `` ` `` |  |  |
+| `--dir` |  | `DIR` |  |
+"
+        );
+    }
+}
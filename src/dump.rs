@@ -0,0 +1,230 @@
+//! Printing the effective configuration, e.g. for `--print-config`, with
+//! secrets redacted and each value annotated with where it came from.
+
+use std::{fmt::Write, str::FromStr};
+
+use crate::Source;
+
+/// What secret values are printed as.
+pub const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DumpFormat {
+    /// An object per flag, holding its value and where it came from.
+    Json,
+    /// A config file, with where each value came from in a comment above it.
+    #[default]
+    Toml,
+    /// `VAR=value` lines for a `.env` file or a shell, likewise commented.
+    Env,
+}
+
+impl FromStr for DumpFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(DumpFormat::Json),
+            "toml" => Ok(DumpFormat::Toml),
+            "env" => Ok(DumpFormat::Env),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A flag's current value, as [`render`] prints it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The long name, without the leading `--`.
+    pub name: String,
+    pub env_var: Option<String>,
+    /// The value as it would be written on the command line, one item per
+    /// value for arrays.
+    pub values: Vec<String>,
+    pub is_array: bool,
+    pub secret: bool,
+    /// Where the value came from, if that's tracked.
+    pub source: Option<Source>,
+    /// The unit of a number, which is printed after it, e.g. `500ms`.
+    pub unit: Option<String>,
+}
+
+impl Entry {
+    fn values(&self) -> Vec<String> {
+        let unit = self.unit.as_deref().unwrap_or("");
+        match self.secret {
+            true => vec![REDACTED.into(); self.values.len().max(1)],
+            false => self
+                .values
+                .iter()
+                .map(|value| format!("{value}{unit}"))
+                .collect(),
+        }
+    }
+}
+
+/// Prints `entries` in `format`, in the order given.
+pub fn render(entries: &[Entry], format: DumpFormat) -> String {
+    let mut out = String::new();
+    match format {
+        DumpFormat::Json => {
+            out += "{";
+            for (i, entry) in entries.iter().enumerate() {
+                let values: Vec<String> = entry.values().iter().map(|v| quote(v)).collect();
+                let value = match entry.is_array {
+                    true => format!("[{}]", values.join(", ")),
+                    false => values.join(""),
+                };
+                let separator = if i == 0 { "" } else { "," };
+                let _ = write!(
+                    out,
+                    "{separator}\n  {}: {{ \"value\": {value}",
+                    quote(&entry.name)
+                );
+                if let Some(source) = &entry.source {
+                    let _ = write!(out, ", \"source\": {}", quote(&source.to_string()));
+                }
+                out += " }";
+            }
+            out += "\n}\n";
+        }
+        DumpFormat::Toml => {
+            for entry in entries {
+                comment(&mut out, entry);
+                let values: Vec<String> = entry.values().iter().map(|v| quote(v)).collect();
+                let _ = match entry.is_array {
+                    true => writeln!(out, "{} = [{}]", entry.name, values.join(", ")),
+                    false => writeln!(out, "{} = {}", entry.name, values.join("")),
+                };
+            }
+        }
+        DumpFormat::Env => {
+            for entry in entries {
+                let Some(var) = &entry.env_var else {
+                    continue;
+                };
+                comment(&mut out, entry);
+                let _ = writeln!(out, "{var}={}", shell_quote(&entry.values().join(",")));
+            }
+        }
+    }
+    out
+}
+
+fn comment(out: &mut String, entry: &Entry) {
+    if let Some(source) = &entry.source {
+        let _ = writeln!(out, "# from {}", source.to_string().replace('\n', " "));
+    }
+}
+
+/// A JSON string, which TOML reads the same way.
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            '\r' => quoted += "\\r",
+            '\t' => quoted += "\\t",
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted + "\""
+}
+
+/// Quotes a value for a shell, unless it's plain enough not to need it.
+pub(crate) fn shell_quote(s: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./:,@%+=".contains(c);
+    if !s.is_empty() && s.chars().all(plain) {
+        return s.into();
+    }
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            Entry {
+                name: "name".into(),
+                env_var: Some("NAME".into()),
+                values: vec!["it's \"x\"".into()],
+                is_array: false,
+                secret: false,
+                source: Some(Source::Arg(None)),
+                unit: None,
+            },
+            Entry {
+                name: "tags".into(),
+                env_var: Some("TAGS".into()),
+                values: vec!["a".into(), "b".into()],
+                is_array: true,
+                secret: false,
+                source: None,
+                unit: None,
+            },
+            Entry {
+                name: "token".into(),
+                env_var: Some("TOKEN".into()),
+                values: vec!["hunter2".into()],
+                is_array: false,
+                secret: true,
+                source: Some(Source::Env("TOKEN".into())),
+                unit: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render(&entries(), DumpFormat::Toml),
+            r#"# from the command line
+name = "it's \"x\""
+tags = ["a", "b"]
+# from $TOKEN
+token = "<redacted>"
+"#
+        );
+        assert_eq!(
+            render(&entries(), DumpFormat::Env),
+            r#"# from the command line
+NAME='it'\''s "x"'
+TAGS=a,b
+# from $TOKEN
+TOKEN='<redacted>'
+"#
+        );
+        assert_eq!(
+            render(&entries(), DumpFormat::Json),
+            r#"{
+  "name": { "value": "it's \"x\"", "source": "the command line" },
+  "tags": { "value": ["a", "b"] },
+  "token": { "value": "<redacted>", "source": "$TOKEN" }
+}
+"#
+        );
+        assert_eq!(render(&[], DumpFormat::Json), "{\n}\n");
+
+        let wait = Entry {
+            name: "wait".into(),
+            env_var: Some("WAIT".into()),
+            values: vec!["2000".into()],
+            is_array: false,
+            secret: false,
+            source: None,
+            unit: Some("ms".into()),
+        };
+        assert_eq!(
+            render(std::slice::from_ref(&wait), DumpFormat::Toml),
+            "wait = \"2000ms\"\n"
+        );
+        assert_eq!(render(&[wait], DumpFormat::Env), "WAIT=2000ms\n");
+    }
+}
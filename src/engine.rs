@@ -0,0 +1,947 @@
+//! The parsing rules shared by [`crate::Flags`] and `#[derive(Parse)]`.
+//!
+//! Both describe their flags through [`Dispatch`], and the loops in this
+//! module do the rest, so the two always accept exactly the same input.
+
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    ffi::OsString,
+    hash::Hash,
+};
+
+use crate::{
+    auto::Resolvers,
+    complete::Completer,
+    dump::{self, DumpFormat},
+    lint,
+    meta::{FlagMeta, Positional},
+    Error, Source,
+};
+
+/// What a flag expects to follow it on the command line.
+pub enum Arity {
+    /// The flag is a boolean switch and takes no value.
+    Switch,
+    /// The flag consumes the next argument as its value.
+    Value,
+}
+
+/// What [`parse_args`] does with one of the arguments in [`ArgSyntax`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialArg {
+    /// Keep it as a positional argument and go on parsing flags.
+    Positional,
+    /// Drop it, and treat every argument after it as positional.
+    End,
+    /// Keep it as a positional argument, and treat every argument after it as
+    /// positional too, e.g. so a wrapper can pass `--` on to the program it
+    /// runs.
+    KeepAndEnd,
+    /// Drop it.
+    Skip,
+}
+
+/// How [`parse_args`] treats the arguments that aren't flags but mean
+/// something to many programs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArgSyntax {
+    /// `-`, which usually stands for stdin. Positional by default.
+    pub dash: SpecialArg,
+    /// `--`, which ends the flags by default.
+    pub double_dash: SpecialArg,
+    /// An empty argument. Positional by default.
+    pub empty: SpecialArg,
+}
+
+impl Default for ArgSyntax {
+    fn default() -> Self {
+        Self {
+            dash: SpecialArg::Positional,
+            double_dash: SpecialArg::End,
+            empty: SpecialArg::Positional,
+        }
+    }
+}
+
+impl ArgSyntax {
+    fn special(&self, arg: &str) -> Option<SpecialArg> {
+        match arg {
+            "-" => Some(self.dash),
+            "--" => Some(self.double_dash),
+            "" => Some(self.empty),
+            _ => None,
+        }
+    }
+}
+
+/// An environment variable that sets a flag.
+pub struct EnvVar<K> {
+    pub key: K,
+    pub var: String,
+    /// Splits the variable into items for array flags. Array flags without a
+    /// delimiter can't be set from the environment.
+    pub delimiter: Option<String>,
+    pub is_array: bool,
+}
+
+/// A family of environment variables that fills a map flag, one entry per
+/// variable. The entry's key is the rest of the variable's name, lowercased.
+pub struct EnvPrefix<K> {
+    pub key: K,
+    pub prefix: String,
+}
+
+/// A flag whose default is worked out when the program starts.
+pub struct AutoDefault<K> {
+    pub key: K,
+    /// The flag as it's written on the command line, for errors.
+    pub flag: String,
+    /// The name of the resolver in [`Resolvers`].
+    pub resolver: String,
+}
+
+/// The field-dispatch table that the parsing loops drive.
+pub trait Dispatch {
+    /// Identifies a flag, e.g. its name or field index.
+    type Key: Clone + Eq + Hash;
+
+    /// Resolves a long flag name (without the leading `--`).
+    fn long(&self, name: &str) -> Option<(Self::Key, Arity)>;
+
+    /// Resolves a shorthand character.
+    fn short(&self, c: char) -> Option<(Self::Key, Arity)>;
+
+    /// Lists the environment variables that can set flags.
+    fn env_vars(&self) -> Vec<EnvVar<Self::Key>>;
+
+    /// A flag's environment variable as a struct flattening this one names
+    /// it: without this struct's `env_prefix`, to go after the outer
+    /// struct's prefix, or `None` for a name given in full, which is used as
+    /// written. Only the derive macro implements it.
+    #[doc(hidden)]
+    fn env_stem(var: &str) -> Option<&str>
+    where
+        Self: Sized,
+    {
+        Some(var)
+    }
+
+    /// Lists the environment variable prefixes that fill map flags.
+    fn env_prefixes(&self) -> Vec<EnvPrefix<Self::Key>> {
+        vec![]
+    }
+
+    /// Inserts an entry into a map flag. `first` is true for the first entry
+    /// from the current source, so that earlier sources can be discarded.
+    fn set_entry(
+        &mut self,
+        key: &Self::Key,
+        name: &str,
+        value: &str,
+        first: bool,
+        source: &Source,
+    ) -> Result<(), Error> {
+        let _ = (key, value, first, source);
+        unreachable!("no map flag declared for entry {name}")
+    }
+
+    /// Lists the flags with auto defaults.
+    fn auto_defaults(&self) -> Vec<AutoDefault<Self::Key>> {
+        vec![]
+    }
+
+    /// A value that resets a flag to its default instead of being parsed,
+    /// e.g. `--name @unset`.
+    fn unset_sentinel(&self) -> Option<&str> {
+        None
+    }
+
+    /// The name of a flag that resets the flag named after it to its default,
+    /// e.g. `unset` for `--unset name`.
+    fn unset_flag(&self) -> Option<&str> {
+        None
+    }
+
+    /// The name of a flag that sets any other flag by its path, e.g. `set` for
+    /// `--set db.host=localhost`.
+    fn set_flag(&self) -> Option<&str> {
+        None
+    }
+
+    /// How to treat `-`, `--` and empty arguments.
+    fn arg_syntax(&self) -> ArgSyntax {
+        ArgSyntax::default()
+    }
+
+    /// Whether `arg` asks for help, e.g. `--help`. Parsing then stops with
+    /// [`Error::HelpRequested`], so the caller decides what to show and
+    /// whether to exit.
+    fn is_help_arg(&self, arg: &str) -> bool {
+        let _ = arg;
+        false
+    }
+
+    /// Whether `arg` asks for full help, e.g. `--help-all`. Parsing then
+    /// stops with [`Error::HelpAllRequested`].
+    fn is_help_all_arg(&self, arg: &str) -> bool {
+        let _ = arg;
+        false
+    }
+
+    /// Whether `arg` asks for the version, e.g. `--version`. Parsing then
+    /// stops with [`Error::VersionRequested`].
+    fn is_version_arg(&self, arg: &str) -> bool {
+        let _ = arg;
+        false
+    }
+
+    /// The name of a flag that prints the effective configuration, e.g.
+    /// `print-config` for `--print-config` or `--print-config=json`. Parsing
+    /// then finishes with [`Error::PrintConfigRequested`].
+    fn print_config_flag(&self) -> Option<&str> {
+        None
+    }
+
+    /// Every flag's current value, for printing the configuration with
+    /// [`dump`] or [`Dispatch::print_config_flag`].
+    fn dump_entries(&self) -> Vec<dump::Entry> {
+        vec![]
+    }
+
+    /// The current values of the flags marked for telemetry, for
+    /// [`telemetry::snapshot`](crate::telemetry::snapshot).
+    fn telemetry_entries(&self) -> Vec<dump::Entry> {
+        vec![]
+    }
+
+    /// The flag's name, without the leading `--`, e.g. for the changes in a
+    /// [`crate::ChangeSet`].
+    fn flag_name(&self, key: &Self::Key) -> Option<String> {
+        let _ = key;
+        None
+    }
+
+    /// Where the flag's value came from, or `None` if that isn't recorded,
+    /// e.g. for a derived struct without a `sources` field.
+    fn source(&self, key: &Self::Key) -> Option<Source> {
+        let _ = key;
+        None
+    }
+
+    /// Where each flag's value came from, by name.
+    fn sources(&self) -> Vec<(String, Source)> {
+        vec![]
+    }
+
+    /// The names of required flags that no source has set.
+    fn missing_required(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Each source that set a deprecated flag, by the flag's name, with why
+    /// the flag is deprecated.
+    #[doc(hidden)]
+    fn deprecated_uses(&self) -> Vec<(String, &'static str, Source)> {
+        vec![]
+    }
+
+    /// The positional arguments the program takes, if it names them, so
+    /// that [`parse_args`] can check that the right number were given.
+    fn declared_positionals(&self) -> Option<Vec<Positional>> {
+        None
+    }
+
+    /// Resets a flag to its default value. Only called when
+    /// [`Dispatch::unset_sentinel`] or [`Dispatch::unset_flag`] is set.
+    fn reset(&mut self, key: &Self::Key, source: &Source) -> Result<(), Error> {
+        let _ = (key, source);
+        unreachable!("reset called without unset support")
+    }
+
+    /// Swaps a flag's value with the same flag in `other`, e.g. to reset a
+    /// flattened struct's field to its default. Only the derive macro
+    /// implements it.
+    #[doc(hidden)]
+    fn swap_field(&mut self, key: &Self::Key, other: &mut Self)
+    where
+        Self: Sized,
+    {
+        let _ = (key, other);
+        unreachable!("swap_field called on a type that doesn't support it")
+    }
+
+    /// Swaps the immutable flags' values with the same flags in `other`,
+    /// adding the flags whose values differ to `restart`, e.g. so a
+    /// reloaded configuration keeps them. Only the derive macro implements
+    /// it.
+    #[doc(hidden)]
+    fn swap_immutable(&mut self, other: &mut Self, restart: &mut Vec<String>)
+    where
+        Self: Sized,
+    {
+        let _ = (other, restart);
+    }
+
+    /// Empties an array flag, e.g. for an empty array in a config file.
+    /// Returns false if the flag isn't an array.
+    fn clear_array(&mut self, key: &Self::Key, source: &Source) -> bool {
+        let _ = (key, source);
+        false
+    }
+
+    /// Called with each environment variable that's about to set a flag,
+    /// before it's parsed, e.g. to keep a record of the inputs.
+    fn read_env(&mut self, key: &Self::Key, var: &str, value: &str) {
+        let _ = (key, var, value);
+    }
+
+    /// Whether [`parse_env`] matches variable names regardless of case. This
+    /// defaults to true on Windows, where the OS itself ignores case, so that
+    /// e.g. `Path` still matches `PATH`.
+    fn env_case_insensitive(&self) -> bool {
+        cfg!(windows)
+    }
+
+    /// Whether config file values of the wrong type are converted where
+    /// that's unambiguous, e.g. `1` for a boolean. Strings and numbers are
+    /// always read as each other, since config values are parsed from text.
+    fn coerce(&self) -> bool {
+        false
+    }
+
+    /// What suggests values for the flag, for [`complete`](crate::complete).
+    fn completer(&self, key: &Self::Key) -> Option<Completer> {
+        let _ = key;
+        None
+    }
+
+    /// The flags shown in help, e.g. for [`complete`](crate::complete) to
+    /// complete their names.
+    fn metadata(&self) -> Vec<FlagMeta> {
+        vec![]
+    }
+
+    /// Turns on a boolean switch.
+    fn set_switch(&mut self, key: &Self::Key, source: &Source) -> Result<(), Error>;
+
+    /// Applies a value to a flag. `first` is true the first time the flag is
+    /// set by the current source, so that arrays can discard earlier sources.
+    fn set_value(
+        &mut self,
+        key: &Self::Key,
+        value: &str,
+        first: bool,
+        source: &Source,
+    ) -> Result<(), Error>;
+}
+
+pub fn parse_env<D: Dispatch>(d: &mut D, vars: HashMap<String, String>) -> Result<(), Error> {
+    let all = || {
+        vars.iter()
+            .map(|(var, value)| (var.clone(), OsString::from(value)))
+            .collect()
+    };
+    if !d.env_case_insensitive() {
+        return parse_env_with(d, |var| vars.get(var).map(OsString::from), all);
+    }
+    let folded = fold_env(all());
+    parse_env_with(
+        d,
+        |var| {
+            // An exact match wins over one that only differs in case
+            vars.get(var)
+                .map(OsString::from)
+                .or_else(|| folded.get(&var.to_uppercase()).cloned())
+        },
+        all,
+    )
+}
+
+/// Like [`parse_env`], but reads straight from the process environment. Only
+/// the variables that the flags declare are looked up, so nothing else in the
+/// environment is copied or can affect parsing. The exceptions are map flags
+/// with an [`EnvPrefix`], and matching regardless of case on an OS whose
+/// environment doesn't, which both have to scan the whole environment.
+pub fn parse_process_env<D: Dispatch>(d: &mut D) -> Result<(), Error> {
+    let all = || {
+        env::vars_os()
+            .filter_map(|(var, value)| Some((var.into_string().ok()?, value)))
+            .collect()
+    };
+    // Windows already ignores case when looking a variable up
+    if !d.env_case_insensitive() || cfg!(windows) {
+        return parse_env_with(d, |var| env::var_os(var), all);
+    }
+    let mut folded = None;
+    parse_env_with(
+        d,
+        |var| {
+            // An exact match wins over one that only differs in case
+            env::var_os(var).or_else(|| {
+                folded
+                    .get_or_insert_with(|| fold_env(all()))
+                    .get(&var.to_uppercase())
+                    .cloned()
+            })
+        },
+        all,
+    )
+}
+
+/// The variables by their upper-cased names. Of several variables that only
+/// differ in case, the first by name wins, so the result doesn't depend on
+/// the environment's order.
+fn fold_env(mut vars: Vec<(String, OsString)>) -> HashMap<String, OsString> {
+    vars.sort();
+    let mut folded = HashMap::new();
+    for (var, value) in vars {
+        folded.entry(var.to_uppercase()).or_insert(value);
+    }
+    folded
+}
+
+/// Parses flags from an environment. `lookup` finds a single variable, and
+/// `all` lists every variable, which is only needed for prefixes.
+fn parse_env_with<D: Dispatch>(
+    d: &mut D,
+    mut lookup: impl FnMut(&str) -> Option<OsString>,
+    all: impl FnOnce() -> Vec<(String, OsString)>,
+) -> Result<(), Error> {
+    for env_var in d.env_vars() {
+        let Some(value) = lookup(&env_var.var) else {
+            continue;
+        };
+        let value = value.into_string().map_err(|value| Error::InvalidValue {
+            flag: env_var.var.clone(),
+            value: value.to_string_lossy().into(),
+            source: Some(Source::Env(env_var.var.clone())),
+        })?;
+        apply_env(d, env_var, &value)?;
+    }
+
+    let prefixes = d.env_prefixes();
+    if prefixes.is_empty() {
+        return Ok(());
+    }
+    let mut vars = all();
+    vars.sort();
+    let case_insensitive = d.env_case_insensitive();
+    for env_prefix in prefixes {
+        let prefix = if case_insensitive {
+            env_prefix.prefix.to_uppercase()
+        } else {
+            env_prefix.prefix.clone()
+        };
+        let mut first = true;
+        for (var, value) in &vars {
+            let folded = if case_insensitive {
+                var.to_uppercase()
+            } else {
+                var.clone()
+            };
+            let Some(name) = folded.strip_prefix(&prefix) else {
+                continue;
+            };
+            let value = value.to_str().ok_or_else(|| Error::InvalidValue {
+                flag: var.clone(),
+                value: value.to_string_lossy().into(),
+                source: Some(Source::Env(var.clone())),
+            })?;
+            apply_env_entry(d, &env_prefix.key, var, name, value, first)?;
+            first = false;
+        }
+    }
+    Ok(())
+}
+
+/// Applies the value of `env_var`, read from the environment.
+fn apply_env<D: Dispatch>(d: &mut D, env_var: EnvVar<D::Key>, value: &str) -> Result<(), Error> {
+    d.read_env(&env_var.key, &env_var.var, value);
+    let source = Source::Env(env_var.var.clone());
+    let result = if env_var.is_array {
+        let Some(delimiter) = &env_var.delimiter else {
+            return Ok(());
+        };
+        value
+            .split(delimiter.as_str())
+            .enumerate()
+            .try_for_each(|(i, item)| d.set_value(&env_var.key, item, i == 0, &source))
+    } else {
+        d.set_value(&env_var.key, value, true, &source)
+    };
+    // Name the variable rather than the flag, since that's what the user
+    // actually wrote
+    result.map_err(|err| match err {
+        Error::InvalidValue { value, .. } => Error::InvalidValue {
+            flag: env_var.var,
+            value,
+            source: Some(source),
+        },
+        Error::InvalidChoice { value, choices, .. } => Error::InvalidChoice {
+            flag: env_var.var,
+            value,
+            choices,
+        },
+        err => err,
+    })
+}
+
+/// Applies `var`, which starts with the prefix of the map flag `key` and
+/// ends with `name`.
+fn apply_env_entry<D: Dispatch>(
+    d: &mut D,
+    key: &D::Key,
+    var: &str,
+    name: &str,
+    value: &str,
+    first: bool,
+) -> Result<(), Error> {
+    d.read_env(key, var, value);
+    let source = Source::Env(var.into());
+    d.set_entry(key, &name.to_lowercase(), value, first, &source)
+        .map_err(|err| match err {
+            Error::InvalidValue { value, .. } => Error::InvalidValue {
+                flag: var.into(),
+                value,
+                source: Some(source),
+            },
+            Error::InvalidChoice { value, choices, .. } => Error::InvalidChoice {
+                flag: var.into(),
+                value,
+                choices,
+            },
+            err => err,
+        })
+}
+
+/// Applies a single environment variable, for hosts that get variables one
+/// at a time rather than as a whole environment. Returns whether any flag
+/// reads `var`. A map flag's entry is added to the entries it already has.
+pub fn apply_env_var<D: Dispatch>(d: &mut D, var: &str, value: &str) -> Result<bool, Error> {
+    let case_insensitive = d.env_case_insensitive();
+    let fold = |s: &str| {
+        if case_insensitive {
+            s.to_uppercase()
+        } else {
+            s.into()
+        }
+    };
+    let folded = fold(var);
+    if let Some(env_var) = d
+        .env_vars()
+        .into_iter()
+        .find(|env_var| fold(&env_var.var) == folded)
+    {
+        if env_var.is_array && env_var.delimiter.is_none() {
+            return Ok(false);
+        }
+        apply_env(d, env_var, value)?;
+        return Ok(true);
+    }
+    for env_prefix in d.env_prefixes() {
+        if let Some(name) = folded.strip_prefix(&fold(&env_prefix.prefix)) {
+            apply_env_entry(d, &env_prefix.key, var, name, value, false)?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Sets every flag with an auto default to what its resolver works out,
+/// like [`crate::Flags::resolve_auto`]. Flags that some source already set
+/// are left alone, if `d` can tell; see [`Dispatch::source`].
+pub fn resolve_auto<D: Dispatch>(d: &mut D, resolvers: &Resolvers) -> Result<(), Error> {
+    for auto in d.auto_defaults() {
+        if d.source(&auto.key)
+            .is_some_and(|source| !matches!(source, Source::Default | Source::Auto(_)))
+        {
+            continue;
+        }
+        let value = resolvers.resolve(&auto.flag, &auto.resolver)?;
+        d.set_value(&auto.key, &value, true, &Source::Auto(auto.resolver))?;
+    }
+    Ok(())
+}
+
+/// Checks that every required flag was set by some source, listing the
+/// ones that weren't.
+pub fn validate<D: Dispatch>(d: &D) -> Result<(), Error> {
+    let mut missing = d.missing_required();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    missing.sort();
+    Err(Error::MissingRequired(missing))
+}
+
+/// Validates the configuration, then runs the [built-in lints](lint::builtin)
+/// and `lints` over its flags, like [`crate::Flags::check`] does.
+pub fn check<D: Dispatch>(d: &D, lints: &[&dyn lint::Lint]) -> Result<Vec<lint::Warning>, Error> {
+    validate(d)?;
+    Ok(lint::run(&lint::states(d), lints))
+}
+
+/// The effective configuration in `format`, with secrets redacted.
+pub fn dump<D: Dispatch>(d: &D, format: dump::DumpFormat) -> String {
+    dump::render(&d.dump_entries(), format)
+}
+
+/// Replaces the first argument with its expansion while it names an alias,
+/// so that with `b = "build --release"`, `b -v` becomes
+/// `build --release -v`. An alias that leads back to itself is an error.
+pub fn expand_aliases(
+    mut args: Vec<String>,
+    aliases: &HashMap<String, Vec<String>>,
+) -> Result<Vec<String>, Error> {
+    let mut expanded = HashSet::new();
+    while let Some(expansion) = args.first().and_then(|first| aliases.get(first)) {
+        let name = args.remove(0);
+        if !expanded.insert(name.clone()) {
+            return Err(Error::AliasLoop(name));
+        }
+        args.splice(0..0, expansion.iter().cloned());
+    }
+    Ok(args)
+}
+
+pub fn parse_args<D: Dispatch>(d: &mut D, args: Vec<String>) -> Result<Vec<String>, Error> {
+    parse_args_without(d, args, None)
+}
+
+/// Like [`parse_args`], for arguments that had the one at `removed` taken
+/// out, e.g. a subcommand's name, so sources still give each argument's
+/// position as the user wrote it.
+pub(crate) fn parse_args_without<D: Dispatch>(
+    d: &mut D,
+    args: Vec<String>,
+    removed: Option<usize>,
+) -> Result<Vec<String>, Error> {
+    let mut positionals = vec![];
+    let mut need_value_for: Option<(D::Key, String)> = None;
+    let mut need_unset_name: Option<String> = None;
+    let mut need_assignment: Option<String> = None;
+    let mut as_positionals = false;
+    let mut print_config = None;
+    let mut seen = HashSet::new();
+    let syntax = d.arg_syntax();
+
+    for (i, arg) in args.into_iter().enumerate() {
+        let position = match removed {
+            Some(removed) if i >= removed => i + 2,
+            _ => i + 1,
+        };
+        let source = Source::Arg(Some(position));
+        if as_positionals {
+            positionals.push(arg);
+        } else if let Some((key, _)) = need_value_for.take() {
+            apply_value(d, &mut seen, key, &arg, &source)?;
+        } else if let Some(set_arg) = need_assignment.take() {
+            apply_assignment(d, &mut seen, &set_arg, &arg, &source)?;
+        } else if need_unset_name.take().is_some() {
+            apply_unset(d, &mut seen, &arg)?;
+        } else if d.is_help_arg(&arg) {
+            return Err(Error::HelpRequested);
+        } else if d.is_help_all_arg(&arg) {
+            return Err(Error::HelpAllRequested);
+        } else if d.is_version_arg(&arg) {
+            return Err(Error::VersionRequested);
+        } else if let Some(special) = syntax.special(&arg) {
+            match special {
+                SpecialArg::Positional => positionals.push(arg),
+                SpecialArg::End => as_positionals = true,
+                SpecialArg::KeepAndEnd => {
+                    positionals.push(arg);
+                    as_positionals = true;
+                }
+                SpecialArg::Skip => {}
+            }
+        } else if let Some(rest) = arg.strip_prefix("--") {
+            // The value can follow in the same argument, as in `--name=john`
+            let (name, inline) = match rest.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (rest, None),
+            };
+            if d.unset_flag() == Some(name) {
+                match inline {
+                    Some(value) => apply_unset(d, &mut seen, value)?,
+                    None => need_unset_name = Some(arg),
+                }
+                continue;
+            }
+            if d.set_flag() == Some(name) {
+                match inline {
+                    Some(value) => {
+                        apply_assignment(d, &mut seen, &format!("--{name}"), value, &source)?
+                    }
+                    None => need_assignment = Some(arg),
+                }
+                continue;
+            }
+            // The format can only follow an `=`, so that a positional
+            // argument after the flag isn't taken for one
+            if d.print_config_flag() == Some(name) {
+                let format = match inline {
+                    Some(format) => format.parse().map_err(|_| Error::InvalidValue {
+                        flag: format!("--{name}"),
+                        value: format.into(),
+                        source: Some(source.clone()),
+                    })?,
+                    None => DumpFormat::default(),
+                };
+                print_config = Some(format);
+                continue;
+            }
+            let Some(resolved) = d.long(name) else {
+                return Err(Error::UnknownFlag(format!("--{name}")));
+            };
+            if let Some(key) = apply_flag(d, &mut seen, resolved, inline, &source)? {
+                need_value_for = Some((key, arg));
+            }
+        } else if let Some(cluster) = arg.strip_prefix('-') {
+            // Shorthands can be grouped, as in `-vqo file`. A shorthand that
+            // takes a value ends the group, and the rest of the group is its
+            // value, as in `-ofile` or `-o=file`.
+            for (i, c) in cluster.char_indices() {
+                let Some(resolved) = d.short(c) else {
+                    return Err(Error::UnknownFlag(format!("-{c}")));
+                };
+                let rest = &cluster[i + c.len_utf8()..];
+                let inline = match (&resolved.1, rest.strip_prefix('=')) {
+                    (_, Some(value)) => Some(value),
+                    (Arity::Value, None) if !rest.is_empty() => Some(rest),
+                    _ => None,
+                };
+                let ends_group = inline.is_some() || rest.is_empty();
+                if let Some(key) = apply_flag(d, &mut seen, resolved, inline, &source)? {
+                    need_value_for = Some((key, format!("-{c}")));
+                }
+                if ends_group {
+                    break;
+                }
+            }
+        } else {
+            positionals.push(arg);
+        }
+    }
+
+    if let Some((_, arg)) = need_value_for {
+        return Err(Error::MissingValue(arg));
+    }
+    if let Some(arg) = need_unset_name.or(need_assignment) {
+        return Err(Error::MissingValue(arg));
+    }
+    // Only now that every argument has been applied is the configuration
+    // final
+    if let Some(format) = print_config {
+        return Err(Error::PrintConfigRequested(dump::render(
+            &d.dump_entries(),
+            format,
+        )));
+    }
+
+    if let Some(declared) = d.declared_positionals() {
+        check_positionals(&declared, &positionals)?;
+    }
+    Ok(positionals)
+}
+
+/// Checks that `args` has an argument for each required positional in
+/// `declared`, and no more than they can take.
+pub fn check_positionals(declared: &[Positional], args: &[String]) -> Result<(), Error> {
+    if let Some(missing) = declared
+        .iter()
+        .skip(args.len())
+        .find(|positional| positional.required)
+    {
+        return Err(Error::MissingPositional(missing.name.clone()));
+    }
+    if declared.last().is_some_and(|last| last.variadic) {
+        return Ok(());
+    }
+    match args.get(declared.len()) {
+        Some(arg) => Err(Error::UnexpectedPositional(arg.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Applies a single flag by its long name, without the leading `--`, for
+/// hosts with their own tokenizer, e.g. a command palette. `value` is what
+/// would follow the flag on the command line; switches take `None`. Each
+/// call counts as a separate source, so an array flag's values are replaced
+/// rather than appended to.
+pub fn apply_arg<D: Dispatch>(d: &mut D, name: &str, value: Option<&str>) -> Result<(), Error> {
+    let mut seen = HashSet::new();
+    let missing = || Error::MissingValue(format!("--{name}"));
+    if d.unset_flag() == Some(name) {
+        return apply_unset(d, &mut seen, value.ok_or_else(missing)?);
+    }
+    if d.set_flag() == Some(name) {
+        return apply_assignment(
+            d,
+            &mut seen,
+            &format!("--{name}"),
+            value.ok_or_else(missing)?,
+            &Source::Arg(None),
+        );
+    }
+    let Some(resolved) = d.long(name) else {
+        return Err(Error::UnknownFlag(format!("--{name}")));
+    };
+    match apply_flag(d, &mut seen, resolved, value, &Source::Arg(None))? {
+        Some(_) => Err(missing()),
+        None => Ok(()),
+    }
+}
+
+/// Whether `arg` is a flag of `d`, and if so, which flag takes the next
+/// argument as its value. This lets callers skip over flags and their values
+/// without applying them.
+pub(crate) fn pending_value<D: Dispatch>(d: &D, arg: &str) -> Option<Option<D::Key>> {
+    if let Some(rest) = arg.strip_prefix("--") {
+        if rest.is_empty() {
+            return None;
+        }
+        return match rest.split_once('=') {
+            Some((name, _)) => d.long(name).map(|_| None),
+            None => d.long(rest).map(|(key, arity)| match arity {
+                Arity::Value => Some(key),
+                Arity::Switch => None,
+            }),
+        };
+    }
+    let cluster = arg
+        .strip_prefix('-')
+        .filter(|cluster| !cluster.is_empty())?;
+    for (i, c) in cluster.char_indices() {
+        let (key, arity) = d.short(c)?;
+        let rest = &cluster[i + c.len_utf8()..];
+        match arity {
+            Arity::Value if rest.is_empty() => return Some(Some(key)),
+            Arity::Value => return Some(None),
+            Arity::Switch if rest.is_empty() || rest.starts_with('=') => return Some(None),
+            Arity::Switch => {}
+        }
+    }
+    None
+}
+
+/// Applies a flag given with its value inline or none at all. Returns the
+/// key if the flag's value is in the next argument.
+fn apply_flag<D: Dispatch>(
+    d: &mut D,
+    seen: &mut HashSet<D::Key>,
+    (key, arity): (D::Key, Arity),
+    inline: Option<&str>,
+    source: &Source,
+) -> Result<Option<D::Key>, Error> {
+    match (arity, inline) {
+        (Arity::Switch, None) => d.set_switch(&key, source)?,
+        // Switches take an explicit value too, as in `--verbose=false`
+        (_, Some(value)) => apply_value(d, seen, key, value, source)?,
+        (Arity::Value, None) => return Ok(Some(key)),
+    }
+    Ok(None)
+}
+
+fn apply_value<D: Dispatch>(
+    d: &mut D,
+    seen: &mut HashSet<D::Key>,
+    key: D::Key,
+    value: &str,
+    source: &Source,
+) -> Result<(), Error> {
+    if d.unset_sentinel() == Some(value) {
+        // Let the next value replace the default rather than append to it
+        seen.remove(&key);
+        d.reset(&key, &Source::Unset)
+    } else {
+        let first = seen.insert(key.clone());
+        d.set_value(&key, value, first, source)
+            .map_err(|err| err.with_source(source))
+    }
+}
+
+/// Applies `path=value` given to the set flag `set_arg`.
+fn apply_assignment<D: Dispatch>(
+    d: &mut D,
+    seen: &mut HashSet<D::Key>,
+    set_arg: &str,
+    assignment: &str,
+    source: &Source,
+) -> Result<(), Error> {
+    let Some((path, value)) = assignment.split_once('=') else {
+        return Err(Error::InvalidValue {
+            flag: set_arg.into(),
+            value: assignment.into(),
+            source: Some(source.clone()),
+        });
+    };
+    // Paths use field names, so `db.max_conns` is the same flag as
+    // `--db-max-conns`
+    let name = path.replace(['.', '_'], "-");
+    let Some((key, _)) = d.long(&name) else {
+        return Err(Error::UnknownKey(path.into()));
+    };
+    let first = seen.insert(key.clone());
+    d.set_value(&key, value, first, source)
+        .map_err(|err| err.with_source(source))
+}
+
+/// Resets the flag named by the argument after the unset flag.
+fn apply_unset<D: Dispatch>(
+    d: &mut D,
+    seen: &mut HashSet<D::Key>,
+    name: &str,
+) -> Result<(), Error> {
+    let Some((key, _)) = d.long(name.trim_start_matches("--")) else {
+        return Err(Error::UnknownFlag(name.into()));
+    };
+    seen.remove(&key);
+    d.reset(&key, &Source::Unset)
+}
+
+/// Names a flattened struct's environment variable the way the outer struct
+/// does, e.g. `PORT` as `APP_DB_PORT`. See [`Dispatch::env_stem`].
+#[doc(hidden)]
+pub fn nested_env_var<D: Dispatch>(prefix: &str, var: &str) -> String {
+    match D::env_stem(var) {
+        Some(stem) => format!("{prefix}{stem}"),
+        None => var.into(),
+    }
+}
+
+/// Names a flattened struct's flag the way the outer struct does, e.g.
+/// `--host` as `--db-host`, in an error from setting it.
+#[doc(hidden)]
+pub fn prefix_error(err: Error, prefix: &str) -> Error {
+    let rename = |flag: String| match flag.strip_prefix("--") {
+        Some(name) => format!("--{prefix}{name}"),
+        None => flag,
+    };
+    match err {
+        Error::InvalidValue {
+            flag,
+            value,
+            source,
+        } => Error::InvalidValue {
+            flag: rename(flag),
+            value,
+            source,
+        },
+        Error::InvalidChoice {
+            flag,
+            value,
+            choices,
+        } => Error::InvalidChoice {
+            flag: rename(flag),
+            value,
+            choices,
+        },
+        Error::Command { flag, message } => Error::Command {
+            flag: rename(flag),
+            message,
+        },
+        err => err,
+    }
+}
@@ -0,0 +1,326 @@
+use std::{fmt, io, path::PathBuf};
+
+use crate::Source;
+
+/// Everything that can go wrong while parsing or validating flags.
+///
+/// Each variant has a stable [`Error::code`] for tools that need to tell
+/// failures apart without matching on the message, which may change.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A command-line flag that doesn't exist, as it was written (e.g. `--nmae`).
+    UnknownFlag(String),
+    /// A config file key that doesn't match any flag.
+    UnknownKey(String),
+    /// A flag that takes a value appeared as the last argument.
+    MissingValue(String),
+    /// A value that couldn't be converted to the flag's type, with where it
+    /// came from when that's known.
+    InvalidValue {
+        flag: String,
+        value: String,
+        source: Option<Source>,
+    },
+    /// A value that isn't one of the names an enum flag takes, with the
+    /// names it could have been.
+    InvalidChoice {
+        flag: String,
+        value: String,
+        choices: Vec<String>,
+    },
+    /// A config file value of the wrong type for its key, e.g. a string for
+    /// an integer flag.
+    TypeMismatch {
+        key: String,
+        expected: String,
+        found: String,
+    },
+    /// A validator rejected the flag's final value, which came from `source`.
+    Invalid {
+        flag: String,
+        message: String,
+        source: Source,
+    },
+    /// Required flags that no source provided.
+    MissingRequired(Vec<String>),
+    /// A config file couldn't be read.
+    Io(PathBuf, io::Error),
+    /// A config document couldn't be parsed.
+    Format(String),
+    /// An alias that ends up expanding to itself.
+    AliasLoop(String),
+    /// The first argument isn't a subcommand, and there's no default one.
+    /// Lists the commands there are, and the closest one if it's a likely
+    /// typo.
+    UnknownCommand {
+        name: String,
+        suggestion: Option<String>,
+        commands: Vec<String>,
+    },
+    /// No subcommand was given, and there's no default one.
+    MissingCommand,
+    /// A required positional argument wasn't given, by its declared name,
+    /// e.g. `SRC`.
+    MissingPositional(String),
+    /// A positional argument beyond the ones the program takes.
+    UnexpectedPositional(String),
+    /// A config file exceeded a size or time limit.
+    Limit(PathBuf, String),
+    /// A help argument like `--help` was given. Not really an error: parsing
+    /// stops so the program can show help and exit successfully.
+    HelpRequested,
+    /// A full help argument like `--help-all` was given, asking for help
+    /// with nothing cut short, e.g. long defaults.
+    HelpAllRequested,
+    /// A version argument like `--version` was given.
+    VersionRequested,
+    /// The print-config flag was given, e.g. `--print-config`. Holds the
+    /// effective configuration, for the program to print before it exits
+    /// successfully.
+    PrintConfigRequested(String),
+    /// A config provider couldn't be reached.
+    Unavailable { provider: String, message: String },
+    /// The command that gives a flag its value failed or timed out.
+    Command { flag: String, message: String },
+}
+
+/// Why a JSON value couldn't be stored in a [`crate::FlagValue`], with each
+/// type described for people, e.g. `an integer`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeMismatch {
+    pub expected: String,
+    pub found: String,
+}
+
+impl TypeMismatch {
+    /// The error for this mismatch at the config file key `key`.
+    pub fn at(self, key: impl Into<String>) -> Error {
+        Error::TypeMismatch {
+            key: key.into(),
+            expected: self.expected,
+            found: self.found,
+        }
+    }
+}
+
+impl Error {
+    /// A machine-readable code for the kind of error, e.g.
+    /// `CLICONF_E_UNKNOWN_FLAG`. Codes never change once released.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::UnknownFlag(_) => "CLICONF_E_UNKNOWN_FLAG",
+            Error::UnknownKey(_) => "CLICONF_E_UNKNOWN_KEY",
+            Error::MissingValue(_) => "CLICONF_E_MISSING_VALUE",
+            Error::InvalidValue { .. } => "CLICONF_E_INVALID_VALUE",
+            Error::InvalidChoice { .. } => "CLICONF_E_INVALID_CHOICE",
+            Error::TypeMismatch { .. } => "CLICONF_E_TYPE_MISMATCH",
+            Error::Invalid { .. } => "CLICONF_E_INVALID",
+            Error::MissingRequired(_) => "CLICONF_E_MISSING_REQUIRED",
+            Error::Io(..) => "CLICONF_E_IO",
+            Error::Format(_) => "CLICONF_E_FORMAT",
+            Error::AliasLoop(_) => "CLICONF_E_ALIAS_LOOP",
+            Error::UnknownCommand { .. } => "CLICONF_E_UNKNOWN_COMMAND",
+            Error::MissingCommand => "CLICONF_E_MISSING_COMMAND",
+            Error::MissingPositional(_) => "CLICONF_E_MISSING_POSITIONAL",
+            Error::UnexpectedPositional(_) => "CLICONF_E_UNEXPECTED_POSITIONAL",
+            Error::Unavailable { .. } => "CLICONF_E_UNAVAILABLE",
+            Error::Limit(..) => "CLICONF_E_LIMIT",
+            Error::Command { .. } => "CLICONF_E_COMMAND",
+            Error::HelpRequested => "CLICONF_E_HELP_REQUESTED",
+            Error::HelpAllRequested => "CLICONF_E_HELP_ALL_REQUESTED",
+            Error::VersionRequested => "CLICONF_E_VERSION_REQUESTED",
+            Error::PrintConfigRequested(_) => "CLICONF_E_PRINT_CONFIG_REQUESTED",
+        }
+    }
+
+    /// Says a value came from `source`, if it's an invalid one whose source
+    /// isn't already known.
+    pub(crate) fn with_source(self, source: &Source) -> Error {
+        match self {
+            Error::InvalidValue {
+                flag,
+                value,
+                source: None,
+            } => Error::InvalidValue {
+                flag,
+                value,
+                source: Some(source.clone()),
+            },
+            err => err,
+        }
+    }
+
+    /// The error as a JSON object with its `code` and `message`, for tools
+    /// that consume failures programmatically.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "code": self.code(),
+            "message": self.to_string(),
+        })
+    }
+
+    /// Whether the user asked for help, the version or the configuration
+    /// rather than something going wrong, so the program should exit
+    /// successfully.
+    pub fn is_request(&self) -> bool {
+        matches!(
+            self,
+            Error::HelpRequested
+                | Error::HelpAllRequested
+                | Error::VersionRequested
+                | Error::PrintConfigRequested(_)
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownFlag(arg) => write!(f, "Unknown flag: {arg}"),
+            Error::UnknownKey(key) => write!(f, "Unknown config key: {key}"),
+            Error::MissingValue(arg) => write!(f, "Missing value for flag: {arg}"),
+            Error::InvalidValue {
+                flag,
+                value,
+                source,
+            } => {
+                write!(f, "Invalid value for {flag}: {value:?}")?;
+                match source {
+                    // An environment variable is already named as the flag
+                    Some(Source::Env(var)) if var == flag => Ok(()),
+                    Some(source) => write!(f, " (from {source})"),
+                    None => Ok(()),
+                }
+            }
+            Error::InvalidChoice {
+                flag,
+                value,
+                choices,
+            } => write!(
+                f,
+                "Invalid value for {flag}: {value:?} (expected one of: {})",
+                choices.join(", ")
+            ),
+            Error::TypeMismatch {
+                key,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Invalid value for {key}: expected {expected}, found {found}"
+            ),
+            Error::Invalid {
+                flag,
+                message,
+                source,
+            } => write!(f, "Invalid value for {flag}: {message} (from {source})"),
+            Error::MissingRequired(names) => {
+                write!(f, "Missing required flags: --{}", names.join(", --"))
+            }
+            Error::Io(path, err) => write!(f, "Failed to read {}: {err}", path.display()),
+            Error::Format(message) => write!(f, "Failed to parse config: {message}"),
+            Error::AliasLoop(name) => write!(f, "Alias {name} expands to itself"),
+            Error::UnknownCommand {
+                name,
+                suggestion,
+                commands,
+            } => {
+                write!(f, "Unknown command: {name}")?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, " (did you mean {suggestion}?)")?;
+                }
+                match commands.is_empty() {
+                    true => Ok(()),
+                    false => write!(f, "; the commands are {}", commands.join(", ")),
+                }
+            }
+            Error::MissingCommand => write!(f, "Missing command"),
+            Error::MissingPositional(name) => write!(f, "Missing argument: <{name}>"),
+            Error::UnexpectedPositional(arg) => write!(f, "Unexpected argument: {arg}"),
+            Error::HelpRequested => write!(f, "Help requested"),
+            Error::HelpAllRequested => write!(f, "Full help requested"),
+            Error::VersionRequested => write!(f, "Version requested"),
+            Error::PrintConfigRequested(_) => write!(f, "Config requested"),
+            Error::Limit(path, message) => {
+                write!(f, "Refusing to read {}: {message}", path.display())
+            }
+            Error::Unavailable { provider, message } => {
+                write!(f, "Config provider {provider} is unavailable: {message}")
+            }
+            Error::Command { flag, message } => {
+                write!(f, "Failed to get the value for {flag}: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code() {
+        let err = Error::UnknownFlag("--nmae".into());
+        assert_eq!(err.code(), "CLICONF_E_UNKNOWN_FLAG");
+        assert_eq!(err.to_string(), "Unknown flag: --nmae");
+
+        let err = Error::Invalid {
+            flag: "--threads".into(),
+            message: "must be positive".into(),
+            source: Source::Env("THREADS".into()),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Invalid value for --threads: must be positive (from $THREADS)"
+        );
+
+        let err = Error::UnknownCommand {
+            name: "biuld".into(),
+            suggestion: Some("build".into()),
+            commands: vec!["build".into(), "run".into()],
+        };
+        assert_eq!(
+            err.to_string(),
+            "Unknown command: biuld (did you mean build?); the commands are build, run"
+        );
+
+        let err = Error::InvalidChoice {
+            flag: "--level".into(),
+            value: "loud".into(),
+            choices: vec!["debug".into(), "info".into()],
+        };
+        assert_eq!(err.code(), "CLICONF_E_INVALID_CHOICE");
+        assert_eq!(
+            err.to_string(),
+            r#"Invalid value for --level: "loud" (expected one of: debug, info)"#
+        );
+
+        let err = Error::Io("a.json".into(), io::ErrorKind::NotFound.into());
+        assert_eq!(err.code(), "CLICONF_E_IO");
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_to_json() {
+        let err = Error::MissingValue("--name".into());
+        assert_eq!(
+            err.to_json(),
+            serde_json::json!({
+                "code": "CLICONF_E_MISSING_VALUE",
+                "message": "Missing value for flag: --name",
+            })
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! Values that come from running a command, e.g. to read a token from a
+//! password manager instead of keeping it in the environment or a file.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{report, shell, Error};
+
+/// Marks a value as a command to run, as in `cmd://pass show api-token`.
+pub const SCHEME: &str = "cmd://";
+
+/// How long a command may run before it's killed, unless the flag sets its
+/// own timeout.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a flag gets its value from a command. Only flags that opt in run
+/// anything, since otherwise whoever controls the environment or a config
+/// file could run any program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FromCommand {
+    /// A command that every value is passed to as its last argument, like a
+    /// git credential helper: with `pass show`, the value `api-token` runs
+    /// `pass show api-token`. Without one, values written as `cmd://COMMAND`
+    /// run COMMAND, and others are used as they are.
+    pub command: Option<String>,
+    pub timeout: Duration,
+}
+
+impl Default for FromCommand {
+    fn default() -> Self {
+        Self {
+            command: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl FromCommand {
+    /// Runs the command for `value`, if any, and passes its output to `set`.
+    /// The output is often a secret, so an error from `set` names `value`
+    /// rather than the output.
+    pub fn apply(
+        &self,
+        flag: &str,
+        value: &str,
+        set: impl FnOnce(&str) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let words = match (&self.command, value.strip_prefix(SCHEME)) {
+            (Some(command), _) => {
+                let mut words = shell::split(command)?;
+                words.push(value.into());
+                words
+            }
+            (None, Some(command)) => shell::split(command)?,
+            (None, None) => return set(value),
+        };
+        let output =
+            run(&words, self.timeout, Stdio::piped()).map_err(|message| Error::Command {
+                flag: flag.into(),
+                message,
+            })?;
+        set(&output).map_err(|err| match err {
+            Error::InvalidValue { flag, source, .. } => Error::InvalidValue {
+                flag,
+                value: value.into(),
+                source,
+            },
+            Error::InvalidChoice { flag, choices, .. } => Error::InvalidChoice {
+                flag,
+                value: value.into(),
+                choices,
+            },
+            err => err,
+        })
+    }
+}
+
+/// Runs `words` without a shell and returns what it printed, without the
+/// final newline. With [`Stdio::piped`], each line it prints to stderr goes
+/// to the [reporter](report::set_reporter) as a warning, e.g. a password
+/// manager saying why it failed.
+pub(crate) fn run(words: &[String], timeout: Duration, stderr: Stdio) -> Result<String, String> {
+    run_with_input(words, None, timeout, stderr)
+}
+
+/// Like [`run`], but writes `input` to the command's stdin, e.g. a document
+/// for it to evaluate.
+pub(crate) fn run_with_input(
+    words: &[String],
+    input: Option<&str>,
+    timeout: Duration,
+    stderr: Stdio,
+) -> Result<String, String> {
+    let Some((program, args)) = words.split_first() else {
+        return Err("the command is empty".into());
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(match input {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        })
+        .stdout(Stdio::piped())
+        .stderr(stderr)
+        .spawn()
+        .map_err(|e| format!("failed to run {program}: {e}"))?;
+
+    // Write on another thread too, so a command that prints before it has
+    // read all of its input can't deadlock with us. Dropping stdin once
+    // everything's written tells the command that the input has ended.
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+        let input = input.to_owned();
+        thread::spawn(move || stdin.write_all(input.as_bytes()));
+    }
+
+    // Read on another thread, so a command that prints a lot doesn't block
+    // on a full pipe while we wait for it to exit
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = thread::spawn(move || {
+        let mut output = vec![];
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+    let forwarder = child.stderr.take().map(|stderr| {
+        let program = program.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                report::warning(&format!("{program}: {line}"));
+            }
+        })
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("{program} timed out after {timeout:?}"));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    // Report everything the command printed before anything about how it
+    // ended
+    if let Some(forwarder) = forwarder {
+        let _ = forwarder.join();
+    }
+    if !status.success() {
+        return Err(format!("{program} failed with {status}"));
+    }
+    let output = reader
+        .join()
+        .expect("reading the output panicked")
+        .map_err(|e| e.to_string())?;
+    let mut output =
+        String::from_utf8(output).map_err(|_| format!("{program} printed invalid UTF-8"))?;
+    if output.ends_with('\n') {
+        output.pop();
+        if output.ends_with('\r') {
+            output.pop();
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{
+        report::{Captured, Reporter},
+        Flag, FlagValue, Flags,
+    };
+
+    use super::*;
+
+    /// Reports to a [`Captured`] that the test can still read.
+    struct Shared(Arc<Mutex<Captured>>);
+
+    impl Reporter for Shared {
+        fn help(&mut self, text: &str) {
+            self.0.lock().unwrap().help(text);
+        }
+
+        fn version(&mut self, text: &str) {
+            self.0.lock().unwrap().version(text);
+        }
+
+        fn error(&mut self, err: &Error) {
+            self.0.lock().unwrap().error(err);
+        }
+
+        fn warning(&mut self, message: &str) {
+            self.0.lock().unwrap().warning(message);
+        }
+    }
+
+    fn apply(from_command: &FromCommand, value: &str) -> Result<String, Error> {
+        let mut result = String::new();
+        from_command.apply("--token", value, |output| {
+            result = output.into();
+            Ok(())
+        })?;
+        Ok(result)
+    }
+
+    #[test]
+    fn test_from_command() {
+        let from_command = FromCommand::default();
+        assert_eq!(apply(&from_command, "plain").unwrap(), "plain");
+        assert_eq!(
+            apply(&from_command, "cmd://printf 'a b\\n'").unwrap(),
+            "a b"
+        );
+
+        let helper = FromCommand {
+            command: Some("echo secret:".into()),
+            ..FromCommand::default()
+        };
+        assert_eq!(apply(&helper, "api-token").unwrap(), "secret: api-token");
+
+        assert_eq!(
+            run_with_input(
+                &["cat".into()],
+                Some("a\nb\n"),
+                DEFAULT_TIMEOUT,
+                Stdio::null()
+            ),
+            Ok("a\nb".into())
+        );
+
+        let err = apply(&from_command, "cmd://false").unwrap_err();
+        assert!(matches!(err, Error::Command { ref flag, .. } if flag == "--token"));
+        assert_eq!(err.code(), "CLICONF_E_COMMAND");
+
+        let slow = FromCommand {
+            timeout: Duration::from_millis(50),
+            ..FromCommand::default()
+        };
+        let started = Instant::now();
+        let err = apply(&slow, "cmd://sleep 5").unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // The output may be a secret, so it's left out of errors
+        let err = from_command
+            .apply("--port", "cmd://echo hunter2", |_| {
+                Err(Error::InvalidValue {
+                    flag: "--port".into(),
+                    value: "hunter2".into(),
+                    source: None,
+                })
+            })
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { value, .. } if value == "cmd://echo hunter2"));
+
+        let mut flags = Flags::new();
+        flags.add(
+            Flag::new("token", FlagValue::String("".into())).from_command(FromCommand::default()),
+        );
+        flags.add(Flag::new("name", FlagValue::String("".into())));
+        flags
+            .parse_args(
+                ["--token=cmd://printf x", "--name=cmd://printf x"]
+                    .map(String::from)
+                    .into(),
+            )
+            .unwrap();
+        assert_eq!(flags.get("token").value, FlagValue::String("x".into()));
+        assert_eq!(
+            flags.get("name").value,
+            FlagValue::String("cmd://printf x".into())
+        );
+    }
+
+    #[test]
+    fn test_stderr_reported() {
+        let captured = Arc::new(Mutex::new(Captured::default()));
+        report::set_reporter(Shared(Arc::clone(&captured)));
+
+        let from_command = FromCommand::default();
+        let err = apply(&from_command, "cmd://ls /cliconf-test-missing").unwrap_err();
+        assert!(matches!(err, Error::Command { .. }));
+        let stderr = captured.lock().unwrap().stderr.clone();
+        assert!(
+            stderr.starts_with("warning: ls: ") && stderr.contains("cliconf-test-missing"),
+            "{stderr}"
+        );
+    }
+}
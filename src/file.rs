@@ -0,0 +1,611 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use crate::{engine::Dispatch, Error, Source, TypeMismatch};
+
+/// Bounds on reading a config file, protecting against huge or never-ending
+/// inputs (e.g. `/dev/zero` or a stuck network mount).
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// The largest file to read, in bytes.
+    pub max_size: Option<u64>,
+    /// How long reading a file may take. A read that takes longer is
+    /// abandoned but can't be stopped, so without a `max_size` it's capped
+    /// at [`TIMEOUT_MAX_SIZE`] bytes to keep it from filling memory.
+    pub timeout: Option<Duration>,
+}
+
+/// The most a read with a timeout but no `max_size` reads, in bytes.
+pub const TIMEOUT_MAX_SIZE: u64 = 64 << 20;
+
+/// A config file format. Which ones exist depends on the enabled features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Format {
+    Json,
+    #[cfg(feature = "toml")]
+    Toml,
+    #[cfg(feature = "yaml")]
+    Yaml,
+    /// JSON with comments, trailing commas and the rest of JSON5, for
+    /// hand-edited files.
+    #[cfg(feature = "json5")]
+    Json5,
+    /// HashiCorp's configuration language. Blocks are tables, and each label
+    /// of a block is a table of its own, so `port` in `server "api" { ... }`
+    /// sets `server_api_port`.
+    #[cfg(feature = "hcl")]
+    Hcl,
+    /// A typed configuration language, evaluated with the `dhall-to-json`
+    /// command. Relative imports are resolved from the working directory.
+    #[cfg(feature = "dhall")]
+    Dhall,
+    /// A typed configuration language, evaluated with the `nickel` command.
+    /// Relative imports are resolved from the working directory.
+    #[cfg(feature = "nickel")]
+    Nickel,
+}
+
+impl Format {
+    /// The format for a file, going by its extension.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()? {
+            "json" => Some(Format::Json),
+            #[cfg(feature = "toml")]
+            "toml" => Some(Format::Toml),
+            #[cfg(feature = "yaml")]
+            "yaml" | "yml" => Some(Format::Yaml),
+            #[cfg(feature = "json5")]
+            "json5" | "jsonc" => Some(Format::Json5),
+            #[cfg(feature = "hcl")]
+            "hcl" => Some(Format::Hcl),
+            #[cfg(feature = "dhall")]
+            "dhall" => Some(Format::Dhall),
+            #[cfg(feature = "nickel")]
+            "ncl" => Some(Format::Nickel),
+            _ => None,
+        }
+    }
+
+    /// Parses a document in this format.
+    pub fn parse(self, data: &str) -> Result<serde_json::Value, Error> {
+        self.parser()(data).map_err(Error::Format)
+    }
+
+    /// Parses a document in this format from raw bytes, e.g. an asset bundled
+    /// with a mobile app. The bytes must be UTF-8, optionally with a BOM.
+    pub fn parse_bytes(self, data: &[u8]) -> Result<serde_json::Value, Error> {
+        self.parse(decode(data)?)
+    }
+
+    fn parser(self) -> Parser {
+        match self {
+            Format::Json => |data| serde_json::from_str(data).map_err(|e| e.to_string()),
+            #[cfg(feature = "toml")]
+            Format::Toml => |data| {
+                toml::from_str(data)
+                    .map(toml_to_json)
+                    .map_err(|e| e.to_string())
+            },
+            #[cfg(feature = "yaml")]
+            Format::Yaml => |data| serde_yaml::from_str(data).map_err(|e| e.to_string()),
+            #[cfg(feature = "json5")]
+            Format::Json5 => |data| json5::from_str(data).map_err(|e| e.to_string()),
+            #[cfg(feature = "hcl")]
+            Format::Hcl => |data| hcl::from_str(data).map_err(|e| e.to_string()),
+            #[cfg(feature = "dhall")]
+            Format::Dhall => |data| evaluate(&["dhall-to-json"], data),
+            #[cfg(feature = "nickel")]
+            Format::Nickel => |data| evaluate(&["nickel", "export", "--format", "json"], data),
+        }
+    }
+}
+
+/// Parses a config document into JSON, returning a message explaining why
+/// it couldn't.
+pub type Parser = fn(data: &str) -> Result<serde_json::Value, String>;
+
+/// The config file formats, by extension, for reading files and for finding
+/// them with [`crate::Loader::for_app`]. Other crates can register more,
+/// e.g. INI or XML, and their files are then read and merged like the
+/// built-in ones.
+#[derive(Debug, Clone)]
+pub struct FormatRegistry {
+    formats: Vec<(String, Parser)>,
+}
+
+impl FormatRegistry {
+    /// No formats at all.
+    pub fn empty() -> Self {
+        Self { formats: vec![] }
+    }
+
+    /// Adds a format for files ending in `.<extension>`, replacing any
+    /// built-in one for the same extension.
+    pub fn register(&mut self, extension: &str, parser: Parser) {
+        match self.formats.iter_mut().find(|(ext, _)| ext == extension) {
+            Some((_, existing)) => *existing = parser,
+            None => self.formats.push((extension.into(), parser)),
+        }
+    }
+
+    /// The parser for a file, going by its extension.
+    pub fn get(&self, path: &Path) -> Option<Parser> {
+        let extension = path.extension()?.to_str()?;
+        self.formats
+            .iter()
+            .find(|(ext, _)| ext == extension)
+            .map(|(_, parser)| *parser)
+    }
+
+    /// Every registered extension, in the order they were registered.
+    pub fn extensions(&self) -> impl Iterator<Item = &str> {
+        self.formats.iter().map(|(ext, _)| ext.as_str())
+    }
+}
+
+/// The built-in formats of the enabled features: `json`, `toml`, `yaml` and
+/// `yml`, `json5` and `jsonc`, `hcl`, `dhall`, and `ncl`.
+impl Default for FormatRegistry {
+    fn default() -> Self {
+        let mut formats = Self::empty();
+        formats.register("json", Format::Json.parser());
+        #[cfg(feature = "toml")]
+        formats.register("toml", Format::Toml.parser());
+        #[cfg(feature = "yaml")]
+        {
+            formats.register("yaml", Format::Yaml.parser());
+            formats.register("yml", Format::Yaml.parser());
+        }
+        #[cfg(feature = "json5")]
+        {
+            formats.register("json5", Format::Json5.parser());
+            formats.register("jsonc", Format::Json5.parser());
+        }
+        #[cfg(feature = "hcl")]
+        formats.register("hcl", Format::Hcl.parser());
+        #[cfg(feature = "dhall")]
+        formats.register("dhall", Format::Dhall.parser());
+        #[cfg(feature = "nickel")]
+        formats.register("ncl", Format::Nickel.parser());
+        formats
+    }
+}
+
+/// How long evaluating a Dhall or Nickel document may take, e.g. while it
+/// fetches remote imports.
+#[cfg(any(feature = "dhall", feature = "nickel"))]
+pub const EVALUATE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Evaluates a document by passing it to `command`, which prints it as JSON.
+/// The command's own errors, e.g. a type error, go to the
+/// [reporter](crate::report::set_reporter).
+#[cfg(any(feature = "dhall", feature = "nickel"))]
+fn evaluate(command: &[&str], data: &str) -> Result<serde_json::Value, String> {
+    let words: Vec<String> = command.iter().map(|&word| word.into()).collect();
+    let json = crate::exec::run_with_input(
+        &words,
+        Some(data),
+        EVALUATE_TIMEOUT,
+        std::process::Stdio::piped(),
+    )?;
+    serde_json::from_str(&json).map_err(|e| e.to_string())
+}
+
+/// Decodes a document, dropping the byte order mark some editors add.
+fn decode(data: &[u8]) -> Result<&str, Error> {
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    std::str::from_utf8(data).map_err(|e| Error::Format(format!("invalid UTF-8: {e}")))
+}
+
+/// Converts a TOML document to JSON. Going through serde would turn dates
+/// and times into tables, so they become strings here instead.
+#[cfg(feature = "toml")]
+fn toml_to_json(value: toml::Value) -> serde_json::Value {
+    match value {
+        toml::Value::String(s) => s.into(),
+        toml::Value::Integer(n) => n.into(),
+        toml::Value::Float(n) => n.into(),
+        toml::Value::Boolean(b) => b.into(),
+        toml::Value::Datetime(datetime) => datetime.to_string().into(),
+        toml::Value::Array(items) => items.into_iter().map(toml_to_json).collect(),
+        toml::Value::Table(table) => table
+            .into_iter()
+            .map(|(key, value)| (key, toml_to_json(value)))
+            .collect(),
+    }
+}
+
+/// Applies the config file at `path`, choosing the format from its
+/// extension. Keys name flags, and nested tables name flags by their path,
+/// so `host` in a `db` table sets `--db-host`. An array replaces an array
+/// flag's values, as an environment variable does.
+pub fn parse<D: Dispatch>(d: &mut D, path: &Path, limits: &Limits) -> Result<(), Error> {
+    parse_with_formats(d, path, limits, &FormatRegistry::default())
+}
+
+/// Like [`parse`], but choosing the format from `formats`.
+pub fn parse_with_formats<D: Dispatch>(
+    d: &mut D,
+    path: &Path,
+    limits: &Limits,
+    formats: &FormatRegistry,
+) -> Result<(), Error> {
+    let (doc, data) = read_document(path, limits, formats)?;
+    apply_with_lines(d, doc, Some(&data), &Source::File(path.into(), None))
+}
+
+/// Applies a config document held in a string, e.g. one compiled into the
+/// program. See [`parse`].
+pub fn parse_str<D: Dispatch>(d: &mut D, data: &str, format: Format) -> Result<(), Error> {
+    let doc = format.parse(data)?;
+    apply_with_lines(d, doc, Some(data), &Source::Inline(None))
+}
+
+/// Applies a config document from raw bytes, for platforms where config
+/// doesn't live at a path, like assets bundled with a mobile app or values
+/// from a keystore. See [`parse`].
+pub fn parse_bytes<D: Dispatch>(d: &mut D, data: &[u8], format: Format) -> Result<(), Error> {
+    let doc = format.parse_bytes(data)?;
+    let text = std::str::from_utf8(data).ok();
+    apply_with_lines(d, doc, text, &Source::Inline(None))
+}
+
+/// Applies a parsed config document. See [`parse`]. Every key is looked up
+/// before any is applied, so an unknown one leaves the flags as they were.
+pub fn apply<D: Dispatch>(d: &mut D, doc: serde_json::Value, source: &Source) -> Result<(), Error> {
+    apply_with_lines(d, doc, None, source)
+}
+
+/// Like [`apply`], but with the text the document was parsed from, so each
+/// value's source can say which line of it the value is on.
+pub(crate) fn apply_with_lines<D: Dispatch>(
+    d: &mut D,
+    doc: serde_json::Value,
+    data: Option<&str>,
+    source: &Source,
+) -> Result<(), Error> {
+    let serde_json::Value::Object(table) = doc else {
+        return Err(Error::Format("expected a table of flags".into()));
+    };
+    let mut entries = vec![];
+    collect_table(d, "", table, &mut entries)?;
+    for (key, path, json) in entries {
+        let source = source.at_line(data.and_then(|data| find_line(data, &path)));
+        apply_value(d, &key, &path, &json, &source)?;
+    }
+    Ok(())
+}
+
+/// The line, counting from 1, where the key at `path` is written in a config
+/// document. Parsed documents don't keep positions, so this looks for each
+/// key of the path in turn, on or after the line of the one before it,
+/// which finds it in any of the supported formats unless the same key is
+/// written earlier in a way that looks like a key, e.g. in a string.
+fn find_line(data: &str, path: &str) -> Option<usize> {
+    let lines: Vec<&str> = data.lines().collect();
+    let mut at = 0;
+    for key in path.split('.') {
+        at += lines[at..].iter().position(|line| {
+            let comment = line.trim_start().starts_with(['#', '/']);
+            !comment && declares(line, key)
+        })?;
+    }
+    Some(at + 1)
+}
+
+/// Whether `line` has `key` written as a key, bare or quoted, followed by
+/// what separates a key from its value or a table's name from its keys.
+fn declares(line: &str, key: &str) -> bool {
+    line.match_indices(key).any(|(i, _)| {
+        let before = line[..i].chars().next_back();
+        let before =
+            before.map(|c| matches!(c, '"' | '\'' | '.' | '[' | '{' | ',') || c.is_whitespace());
+        let after = line[i + key.len()..]
+            .trim_start_matches(['"', '\''])
+            .trim_start();
+        before.unwrap_or(true) && after.starts_with([':', '=', '.', ']', '{'])
+    })
+}
+
+/// Finds the flag of each value in a table, along with the path of keys
+/// leading to it.
+fn collect_table<D: Dispatch>(
+    d: &D,
+    prefix: &str,
+    table: serde_json::Map<String, serde_json::Value>,
+    entries: &mut Vec<(D::Key, String, serde_json::Value)>,
+) -> Result<(), Error> {
+    for (key, json) in table {
+        let path = format!("{prefix}{key}");
+        if let serde_json::Value::Object(table) = json {
+            collect_table(d, &format!("{path}."), table, entries)?;
+            continue;
+        }
+        // The same names `--set` accepts, so `log_level` and `log-level` are
+        // both --log-level, unless a flag is named exactly as written
+        let Some((key, _)) = d
+            .long(&path)
+            .or_else(|| d.long(&path.replace(['.', '_'], "-")))
+        else {
+            return Err(Error::UnknownKey(path));
+        };
+        entries.push((key, path, json));
+    }
+    Ok(())
+}
+
+/// Sets a flag to a JSON value by way of the strings it would take on the
+/// command line, so any flag type that parses from a string works. `name`
+/// is what errors call the flag.
+pub(crate) fn apply_value<D: Dispatch>(
+    d: &mut D,
+    key: &D::Key,
+    name: &str,
+    json: &serde_json::Value,
+    source: &Source,
+) -> Result<(), Error> {
+    let invalid = || invalid_value(name, json, source);
+    let items = match json {
+        serde_json::Value::Array(items) if items.is_empty() => {
+            return d.clear_array(key, source).then_some(()).ok_or_else(invalid);
+        }
+        serde_json::Value::Array(items) => items.iter().collect(),
+        json => vec![json],
+    };
+    for (i, item) in items.into_iter().enumerate() {
+        let value = match item {
+            serde_json::Value::String(s) => s.clone(),
+            serde_json::Value::Bool(_) | serde_json::Value::Number(_) => item.to_string(),
+            _ => {
+                return Err(TypeMismatch {
+                    expected: "a string, number or boolean".into(),
+                    found: describe_json(item).into(),
+                }
+                .at(name))
+            }
+        };
+        let mut result = d.set_value(key, &value, i == 0, source);
+        if let (Err(Error::InvalidValue { .. }), true) = (&result, d.coerce()) {
+            // `0` and `1` may be meant as booleans
+            let switch = match value.as_str() {
+                "0" => Some("false"),
+                "1" => Some("true"),
+                _ => None,
+            };
+            if let Some(switch) = switch {
+                result = d.set_value(key, switch, i == 0, source);
+            }
+        }
+        result.map_err(|err| match err {
+            Error::InvalidValue { .. } => invalid(),
+            Error::InvalidChoice { value, choices, .. } => Error::InvalidChoice {
+                flag: name.into(),
+                value,
+                choices,
+            },
+            err => err,
+        })?;
+    }
+    Ok(())
+}
+
+/// The error for a config file value that doesn't parse, naming it by its
+/// key as written in the file.
+pub(crate) fn invalid_value(name: &str, json: &serde_json::Value, source: &Source) -> Error {
+    Error::InvalidValue {
+        flag: name.into(),
+        value: json.to_string(),
+        source: Some(source.clone()),
+    }
+}
+
+/// The type of a JSON value, described for people, e.g. `a string`.
+pub(crate) fn describe_json(json: &serde_json::Value) -> &'static str {
+    match json {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "a boolean",
+        serde_json::Value::Number(n) if is_integer(n) => "an integer",
+        serde_json::Value::Number(_) => "a number",
+        serde_json::Value::String(_) => "a string",
+        serde_json::Value::Array(_) => "a list",
+        serde_json::Value::Object(_) => "a table",
+    }
+}
+
+/// Whether the number is written as an integer, however large.
+pub(crate) fn is_integer(n: &serde_json::Number) -> bool {
+    let n = n.to_string();
+    let digits = n.strip_prefix('-').unwrap_or(&n);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Reads a config file into a JSON document, choosing the format from its
+/// extension. The file's contents are returned alongside.
+pub(crate) fn read_document(
+    path: &Path,
+    limits: &Limits,
+    formats: &FormatRegistry,
+) -> Result<(serde_json::Value, String), Error> {
+    let data = match limits.timeout {
+        Some(timeout) => {
+            // Read on a thread that can be abandoned if the read hangs
+            let (tx, rx) = mpsc::channel();
+            let owned = path.to_path_buf();
+            let max_size = Some(limits.max_size.unwrap_or(TIMEOUT_MAX_SIZE));
+            thread::spawn(move || {
+                let _ = tx.send(read_limited(&owned, max_size));
+            });
+            rx.recv_timeout(timeout).unwrap_or_else(|_| {
+                Err(Error::Limit(
+                    path.into(),
+                    format!("reading took longer than {timeout:?}"),
+                ))
+            })?
+        }
+        None => read_limited(path, limits.max_size)?,
+    };
+    let Some(parser) = formats.get(path) else {
+        return Err(Error::Format(format!(
+            "unsupported file extension: {}",
+            path.display()
+        )));
+    };
+    Ok((parser(&data).map_err(Error::Format)?, data))
+}
+
+/// Reads several config files at once, so that slow filesystems (e.g. NFS)
+/// cost the time of the slowest file rather than the sum of all of them. The
+/// documents are returned in the same order as `paths`.
+pub(crate) fn read_documents<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    limits: &Limits,
+    formats: &FormatRegistry,
+) -> Vec<Result<(serde_json::Value, String), Error>> {
+    thread::scope(|s| {
+        let handles: Vec<_> = paths
+            .iter()
+            .map(|path| s.spawn(move || read_document(path.as_ref(), limits, formats)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Config file reader panicked"))
+            .collect()
+    })
+}
+
+fn read_limited(path: &Path, max_size: Option<u64>) -> Result<String, Error> {
+    let io_err = |e| Error::Io(PathBuf::from(path), e);
+    let file = File::open(path).map_err(io_err)?;
+    let mut data = String::new();
+    match max_size {
+        Some(max_size) => {
+            // Read one byte past the limit to tell a file that fits exactly
+            // from one that's too large, without trusting its metadata
+            file.take(max_size + 1)
+                .read_to_string(&mut data)
+                .map_err(io_err)?;
+            if data.len() as u64 > max_size {
+                return Err(Error::Limit(
+                    path.into(),
+                    format!("larger than {max_size} bytes"),
+                ));
+            }
+        }
+        None => {
+            let mut file = file;
+            file.read_to_string(&mut data).map_err(io_err)?;
+        }
+    }
+    Ok(data)
+}
+
+/// Generates `parse_embedded_defaults` for the derive macro. The file is
+/// found relative to the crate's root, since paths in `include_str!` would
+/// be relative to whichever file the struct is in.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __parse_embedded_defaults {
+    ($vis:vis $name:ident, $format:ident, $path:literal) => {
+        /// Applies the defaults file compiled into the program. Call it
+        /// before anything else, so every other source overrides it.
+        $vis fn $name(&mut self) -> ::std::result::Result<(), $crate::Error> {
+            $crate::file::parse_str(
+                self,
+                ::std::include_str!(::std::concat!(::std::env!("CARGO_MANIFEST_DIR"), "/", $path)),
+                $crate::Format::$format,
+            )
+        }
+    };
+}
+
+/// Generates `parse_file` for the derive macro, which can't tell whether the
+/// `json` feature is enabled itself.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __parse_file {
+    ($vis:vis $name:ident, $with_limits:ident, $parse_str:ident, $parse_bytes:ident) => {
+        /// Applies a config file, choosing the format from its extension.
+        /// Keys are field names, with `-` or `_` between words, and nested
+        /// tables prefix their keys with the table's name. Arrays replace a
+        /// `Vec` field's items.
+        $vis fn $name(
+            &mut self,
+            path: &::std::path::Path,
+        ) -> ::std::result::Result<(), $crate::Error> {
+            $crate::file::parse(
+                self,
+                path,
+                &<$crate::Limits as ::std::default::Default>::default(),
+            )
+        }
+
+        /// Applies a config file like the method above, reading it within
+        /// `limits`, e.g. a maximum size or a timeout.
+        $vis fn $with_limits(
+            &mut self,
+            path: &::std::path::Path,
+            limits: &$crate::Limits,
+        ) -> ::std::result::Result<(), $crate::Error> {
+            $crate::file::parse(self, path, limits)
+        }
+
+        /// Applies a config document held in a string, in `format`.
+        $vis fn $parse_str(
+            &mut self,
+            data: &str,
+            format: $crate::Format,
+        ) -> ::std::result::Result<(), $crate::Error> {
+            $crate::file::parse_str(self, data, format)
+        }
+
+        /// Applies a config document from raw bytes, e.g. an asset bundled
+        /// with a mobile app, in `format`.
+        $vis fn $parse_bytes(
+            &mut self,
+            data: &[u8],
+            format: $crate::Format,
+        ) -> ::std::result::Result<(), $crate::Error> {
+            $crate::file::parse_bytes(self, data, format)
+        }
+    };
+}
+
+#[cfg(all(test, unix, any(feature = "dhall", feature = "nickel")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        // `cat` stands in for an evaluator whose input is already JSON
+        assert_eq!(
+            evaluate(&["cat"], r#"{ "port": 8080 }"#),
+            Ok(serde_json::json!({ "port": 8080 }))
+        );
+        assert!(evaluate(&["false"], "{}").is_err());
+        let err = evaluate(&["cliconf-no-such-evaluator"], "{}").unwrap_err();
+        assert!(err.starts_with("failed to run"), "{err}");
+    }
+
+    #[test]
+    fn test_find_line() {
+        let toml = "# port = 1\nport = 2\n\n[db]\nhost = \"h\"\nhostname = \"n\"\n";
+        assert_eq!(find_line(toml, "port"), Some(2));
+        assert_eq!(find_line(toml, "db.host"), Some(5));
+        assert_eq!(find_line(toml, "db.hostname"), Some(6));
+        assert_eq!(find_line("db.host = 1\n", "db.host"), Some(1));
+
+        let yaml = "db:\n  port: 1\nport: 2\n";
+        assert_eq!(find_line(yaml, "db.port"), Some(2));
+        assert_eq!(find_line(yaml, "missing"), None);
+
+        let json = "{\n  \"db\": { \"port\": 1 },\n  \"port\": 2\n}";
+        assert_eq!(find_line(json, "db.port"), Some(2));
+    }
+}
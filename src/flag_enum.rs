@@ -0,0 +1,128 @@
+//! Enums as flag types, for flags that take one of a fixed set of values,
+//! like a log level.
+//!
+//! `#[derive(cliconf::FlagEnum)]` on an enum of unit variants implements
+//! [`FlagEnum`], `FromStr` and `Display` for it. Each variant is named in
+//! kebab case, e.g. `WarnOnce` is `warn-once`, unless it's given a name with
+//! `#[cliconf(rename = "...")]`. Names are matched regardless of case.
+//! Fields of the type then list the names in help and complete them in the
+//! shell, and reject other values with an error that lists them.
+//!
+//! ```
+//! use cliconf::FlagEnum;
+//!
+//! #[derive(FlagEnum, Debug, PartialEq)]
+//! enum LogLevel {
+//!     Debug,
+//!     Info,
+//!     #[cliconf(rename = "warning")]
+//!     Warn,
+//! }
+//!
+//! assert_eq!(LogLevel::NAMES, ["debug", "info", "warning"]);
+//! assert_eq!("INFO".parse(), Ok(LogLevel::Info));
+//! assert_eq!(LogLevel::Warn.to_string(), "warning");
+//! let err = "verbose".parse::<LogLevel>().unwrap_err();
+//! assert_eq!(err.to_string(), r#"invalid value "verbose", expected one of: debug, info, warning"#);
+//! ```
+
+use std::{fmt, marker::PhantomData};
+
+use crate::{meta::ValueHint, Error};
+
+/// A type that flags take one of a fixed set of values for, by name.
+pub trait FlagEnum: Sized {
+    /// Every value's name, in the order they're declared.
+    const NAMES: &'static [&'static str];
+
+    /// The value with this name, ignoring case.
+    fn from_name(name: &str) -> Option<Self>;
+
+    fn name(&self) -> &'static str;
+}
+
+/// A name that isn't one of a [`FlagEnum`]'s.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidChoice {
+    pub value: String,
+    pub choices: &'static [&'static str],
+}
+
+impl fmt::Display for InvalidChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid value {:?}, expected one of: {}",
+            self.value,
+            self.choices.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for InvalidChoice {}
+
+/// Parses a name for a derived `FromStr`.
+#[doc(hidden)]
+pub fn parse<T: FlagEnum>(s: &str) -> Result<T, InvalidChoice> {
+    T::from_name(s.trim()).ok_or_else(|| InvalidChoice {
+        value: s.into(),
+        choices: T::NAMES,
+    })
+}
+
+/// Tells the derive macro whether a field's type is a [`FlagEnum`], without
+/// requiring every type to be one: calling `choices` on a `&Probe<T>` finds
+/// [`IsFlagEnum`] if `T` implements it, and falls back to [`NotFlagEnum`]
+/// otherwise.
+#[doc(hidden)]
+pub struct Probe<T>(pub PhantomData<T>);
+
+#[doc(hidden)]
+pub trait IsFlagEnum {
+    fn choices(&self) -> Option<&'static [&'static str]>;
+}
+
+impl<T: FlagEnum> IsFlagEnum for Probe<T> {
+    fn choices(&self) -> Option<&'static [&'static str]> {
+        Some(T::NAMES)
+    }
+}
+
+#[doc(hidden)]
+pub trait NotFlagEnum {
+    fn choices(&self) -> Option<&'static [&'static str]>;
+}
+
+impl<T> NotFlagEnum for &Probe<T> {
+    fn choices(&self) -> Option<&'static [&'static str]> {
+        None
+    }
+}
+
+/// A field's value hint: its type's names if it's a [`FlagEnum`], or any
+/// value otherwise.
+#[doc(hidden)]
+pub fn value_hint(choices: Option<&'static [&'static str]>) -> ValueHint {
+    match choices {
+        Some(choices) => ValueHint::Choices(choices.iter().map(|&choice| choice.into()).collect()),
+        None => ValueHint::Any,
+    }
+}
+
+/// The error for a value that doesn't parse as a field's type, listing the
+/// names it could have been if it's a [`FlagEnum`].
+#[doc(hidden)]
+pub fn invalid_value(flag: &str, value: &str, choices: Option<&'static [&'static str]>) -> Error {
+    match choices {
+        Some(choices) => Error::InvalidChoice {
+            flag: flag.into(),
+            value: value.into(),
+            choices: choices.iter().map(|&choice| choice.into()).collect(),
+        },
+        None => Error::InvalidValue {
+            flag: flag.into(),
+            value: value.into(),
+            source: None,
+        },
+    }
+}
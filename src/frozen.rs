@@ -0,0 +1,29 @@
+use std::{fmt, ops::Deref};
+
+/// Configuration that is finished loading and can no longer change.
+///
+/// It derefs to the configuration for reading, but never hands out `&mut`
+/// access, so nothing can parse more arguments into it or `--set` a value
+/// after startup. Share it between threads with an `Arc`.
+#[derive(Clone)]
+pub struct Frozen<T>(T);
+
+impl<T> Frozen<T> {
+    pub fn new(conf: T) -> Self {
+        Self(conf)
+    }
+}
+
+impl<T> Deref for Frozen<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Frozen<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Frozen").field(&self.0).finish()
+    }
+}
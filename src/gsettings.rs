@@ -0,0 +1,309 @@
+//! Desktop settings from GSettings and dconf, for programs that should follow
+//! system-wide preferences like the proxy or theme on Linux desktops.
+//!
+//! Settings are read with the `gsettings` and `dconf` commands, so there's
+//! nothing to link against. A setting is named either by its schema and key,
+//! as in `org.gnome.system.proxy.http host`, or by its dconf path, as in
+//! `/org/gnome/desktop/interface/gtk-theme`. Settings that can't be read,
+//! e.g. because there's no desktop or the schema isn't installed, are
+//! skipped, so the same program still works on a server.
+
+use std::{process::Stdio, time::Duration};
+
+use crate::{engine::Dispatch, exec, file, Error, Source};
+
+/// How long reading a single setting may take, e.g. while D-Bus starts.
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Sets each key to the value of its setting, for those that can be read.
+/// Call it before reading the environment and arguments, so they override
+/// the desktop's settings.
+pub fn parse<D: Dispatch, S: AsRef<str>>(d: &mut D, settings: &[(D::Key, S)]) -> Result<(), Error> {
+    apply(d, settings, read)
+}
+
+fn apply<D: Dispatch, S: AsRef<str>>(
+    d: &mut D,
+    settings: &[(D::Key, S)],
+    read: impl Fn(&str) -> Option<String>,
+) -> Result<(), Error> {
+    for (key, setting) in settings {
+        let setting = setting.as_ref();
+        let Some(text) = read(setting) else {
+            continue;
+        };
+        let json = parse_value(&text).ok_or_else(|| Error::InvalidValue {
+            flag: setting.into(),
+            value: text.clone(),
+            source: None,
+        })?;
+        file::apply_value(d, key, setting, &json, &Source::Setting(setting.into()))?;
+    }
+    Ok(())
+}
+
+/// Reads a setting as GVariant text, e.g. `'Adwaita'` or `uint32 8080`, or
+/// `None` if it can't be read or dconf has no value for it.
+pub fn read(setting: &str) -> Option<String> {
+    let words: Vec<String> = if setting.starts_with('/') {
+        vec!["dconf".into(), "read".into(), setting.into()]
+    } else {
+        let (schema, key) = setting.split_once(' ')?;
+        vec![
+            "gsettings".into(),
+            "get".into(),
+            schema.into(),
+            key.trim().into(),
+        ]
+    };
+    exec::run(&words, TIMEOUT, Stdio::null())
+        .ok()
+        .filter(|text| !text.is_empty())
+}
+
+/// Converts a value in GVariant text format to JSON. Strings, booleans,
+/// numbers and arrays of them are supported, with or without a type
+/// annotation like `@as` or `uint32`.
+pub fn parse_value(text: &str) -> Option<serde_json::Value> {
+    let mut parser = Parser { rest: text };
+    let value = parser.value()?;
+    parser.rest.trim().is_empty().then_some(value)
+}
+
+/// The types GVariant text may name before a value, as in `uint32 8080`.
+const TYPE_KEYWORDS: &[&str] = &[
+    "boolean",
+    "byte",
+    "int16",
+    "uint16",
+    "int32",
+    "uint32",
+    "handle",
+    "int64",
+    "uint64",
+    "double",
+    "string",
+    "objectpath",
+    "signature",
+];
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Option<serde_json::Value> {
+        self.skip_annotation();
+        match self.rest.chars().next()? {
+            '[' => self.array(),
+            '\'' | '"' => self.string().map(serde_json::Value::String),
+            _ => self.scalar(),
+        }
+    }
+
+    /// Skips `@as` or `uint32`, which only say what type follows.
+    fn skip_annotation(&mut self) {
+        self.rest = self.rest.trim_start();
+        let word_end = self.rest.find(char::is_whitespace);
+        let Some(word) = word_end.map(|end| &self.rest[..end]) else {
+            return;
+        };
+        if word.starts_with('@') || TYPE_KEYWORDS.contains(&word) {
+            self.rest = self.rest[word.len()..].trim_start();
+        }
+    }
+
+    fn array(&mut self) -> Option<serde_json::Value> {
+        self.rest = self.rest.strip_prefix('[')?.trim_start();
+        let mut items = vec![];
+        if let Some(rest) = self.rest.strip_prefix(']') {
+            self.rest = rest;
+            return Some(serde_json::Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.rest = self.rest.trim_start();
+            if let Some(rest) = self.rest.strip_prefix(',') {
+                self.rest = rest;
+            } else {
+                self.rest = self.rest.strip_prefix(']')?;
+                return Some(serde_json::Value::Array(items));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let mut chars = self.rest.char_indices();
+        let (_, quote) = chars.next()?;
+        let mut s = String::new();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                c if c == quote => {
+                    self.rest = &self.rest[i + c.len_utf8()..];
+                    return Some(s);
+                }
+                '\\' => {
+                    let (_, escaped) = chars.next()?;
+                    s.push(match escaped {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        'a' => '\x07',
+                        'b' => '\x08',
+                        'f' => '\x0c',
+                        'v' => '\x0b',
+                        'u' | 'U' => {
+                            let digits = if escaped == 'u' { 4 } else { 8 };
+                            let hex: String = chars.by_ref().take(digits).map(|(_, c)| c).collect();
+                            char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                        }
+                        c => c,
+                    });
+                }
+                c => s.push(c),
+            }
+        }
+        None
+    }
+
+    /// A boolean or number, which runs until the next `,`, `]` or space.
+    fn scalar(&mut self) -> Option<serde_json::Value> {
+        let end = self
+            .rest
+            .find(|c: char| c == ',' || c == ']' || c.is_whitespace())
+            .unwrap_or(self.rest.len());
+        let (word, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        let value = match word {
+            "true" => true.into(),
+            "false" => false.into(),
+            _ => {
+                let (negative, digits) = match word.strip_prefix('-') {
+                    Some(digits) => (true, digits),
+                    None => (false, word.strip_prefix('+').unwrap_or(word)),
+                };
+                if let Some(hex) = digits.strip_prefix("0x") {
+                    let n = i64::from_str_radix(hex, 16).ok()?;
+                    (if negative { -n } else { n }).into()
+                } else if let Ok(n) = word.parse::<i64>() {
+                    n.into()
+                } else if let Ok(n) = word.parse::<u64>() {
+                    n.into()
+                } else {
+                    // JSON has no infinity or NaN, so those aren't supported
+                    serde_json::Number::from_f64(word.parse().ok()?)?.into()
+                }
+            }
+        };
+        Some(value)
+    }
+}
+
+/// Generates `parse_gsettings` for the derive macro, which can't tell whether
+/// the `gsettings` feature is enabled itself.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __parse_gsettings {
+    ($vis:vis $name:ident, [$(($key:expr, $setting:expr)),*]) => {
+        /// Reads the desktop setting of each field that names one, skipping
+        /// those that can't be read. Call it before the other methods, so the
+        /// environment and arguments override the desktop.
+        $vis fn $name(&mut self) -> ::std::result::Result<(), $crate::Error> {
+            $crate::gsettings::parse(self, &[$(($key, $setting)),*])
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use crate::{Flag, FlagValue, Flags};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(parse_value("'Adwaita'"), Some(json!("Adwaita")));
+        assert_eq!(parse_value(r#""it's""#), Some(json!("it's")));
+        assert_eq!(parse_value(r"'a\'b\né'"), Some(json!("a'b\né")));
+        assert_eq!(parse_value("true"), Some(json!(true)));
+        assert_eq!(parse_value("8080"), Some(json!(8080)));
+        assert_eq!(parse_value("uint32 8080"), Some(json!(8080)));
+        assert_eq!(parse_value("byte 0x10"), Some(json!(16)));
+        assert_eq!(parse_value("-1.5"), Some(json!(-1.5)));
+        assert_eq!(
+            parse_value("['localhost', '127.0.0.0/8']"),
+            Some(json!(["localhost", "127.0.0.0/8"]))
+        );
+        assert_eq!(parse_value("@as []"), Some(json!([])));
+        assert_eq!(parse_value("[1, 2]"), Some(json!([1, 2])));
+
+        assert_eq!(parse_value("('a', 1)"), None);
+        assert_eq!(parse_value("{'a': 1}"), None);
+        assert_eq!(parse_value("'unterminated"), None);
+        assert_eq!(parse_value("'a' 'b'"), None);
+        assert_eq!(parse_value("inf"), None);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut flags = Flags::new();
+        flags.add(Flag::new("proxy-host", FlagValue::String("".into())));
+        flags.add(Flag::new("proxy-port", FlagValue::Int64(0)));
+        flags.add(Flag::new(
+            "no-proxy",
+            FlagValue::StringArray(vec!["example.com".into()]),
+        ));
+        flags.add(Flag::new("theme", FlagValue::String("light".into())));
+
+        let desktop = HashMap::from([
+            ("org.gnome.system.proxy.http host", "'proxy.example.com'"),
+            ("org.gnome.system.proxy.http port", "8080"),
+            ("org.gnome.system.proxy ignore-hosts", "@as []"),
+        ]);
+        let settings = [
+            ("proxy-host".to_string(), "org.gnome.system.proxy.http host"),
+            ("proxy-port".to_string(), "org.gnome.system.proxy.http port"),
+            (
+                "no-proxy".to_string(),
+                "org.gnome.system.proxy ignore-hosts",
+            ),
+            (
+                "theme".to_string(),
+                "/org/gnome/desktop/interface/gtk-theme",
+            ),
+        ];
+        apply(&mut flags, &settings, |setting| {
+            desktop.get(setting).map(|text| text.to_string())
+        })
+        .unwrap();
+        assert_eq!(
+            flags.get("proxy-host").value,
+            FlagValue::String("proxy.example.com".into())
+        );
+        assert_eq!(
+            flags.get("proxy-host").source,
+            Source::Setting("org.gnome.system.proxy.http host".into())
+        );
+        assert_eq!(flags.get("proxy-port").value, FlagValue::Int64(8080));
+        assert_eq!(flags.get("no-proxy").value, FlagValue::StringArray(vec![]));
+        // Unreadable settings are skipped
+        assert_eq!(flags.get("theme").source, Source::Default);
+
+        let err = apply(&mut flags, &settings[1..2], |_| Some("'many'".into())).unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidValue { ref flag, .. } if flag == "org.gnome.system.proxy.http port"),
+            "{err}"
+        );
+        let err = apply(&mut flags, &settings[..1], |_| Some("('a', 1)".into())).unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { value, .. } if value == "('a', 1)"));
+
+        // Without a desktop, or with a setting that doesn't exist, nothing
+        // is read
+        assert_eq!(read("org.example.cliconf.missing key"), None);
+        assert_eq!(read("no-key"), None);
+    }
+}
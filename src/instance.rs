@@ -0,0 +1,108 @@
+//! Running several named instances of the same program on one host, each
+//! with its own environment variables and config file, e.g. `APP_BLUE_PORT`
+//! and `app-blue.toml` for `--instance blue`.
+
+use std::env;
+
+use crate::{Flag, FlagValue};
+
+/// The name of the flag that selects an instance.
+pub const INSTANCE_FLAG: &str = "instance";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instance {
+    pub app: String,
+    /// `None` for the unnamed, default instance.
+    pub name: Option<String>,
+}
+
+impl Instance {
+    /// Finds the instance from `--instance NAME` or `--instance=NAME` in
+    /// `args`, or else from the `<APP>_INSTANCE` environment variable. This
+    /// runs before parsing, since the instance decides which variables and
+    /// files to parse.
+    pub fn detect(app: &str, args: &[String]) -> Self {
+        let var = format!("{}_INSTANCE", env_name(app));
+        Self::detect_with(app, args, env::var(var).ok())
+    }
+
+    fn detect_with(app: &str, args: &[String], from_env: Option<String>) -> Self {
+        let flag = format!("--{INSTANCE_FLAG}");
+        let inline = format!("{flag}=");
+        let mut rest = args.iter().take_while(|arg| *arg != "--");
+        let mut from_args = None;
+        while let Some(arg) = rest.next() {
+            if *arg == flag {
+                from_args = rest.next().cloned();
+                break;
+            }
+            if let Some(name) = arg.strip_prefix(&inline) {
+                from_args = Some(name.into());
+                break;
+            }
+        }
+        Self {
+            app: app.into(),
+            name: from_args.or(from_env).filter(|name| !name.is_empty()),
+        }
+    }
+
+    /// The prefix for environment variables, e.g. `APP_` or `APP_BLUE_`. Use
+    /// it as [`crate::Flags::env_prefix`].
+    pub fn env_prefix(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{}_{}_", env_name(&self.app), env_name(name)),
+            None => format!("{}_", env_name(&self.app)),
+        }
+    }
+
+    /// The config file name with extension `ext`, e.g. `app.toml` or
+    /// `app-blue.toml`.
+    pub fn file_name(&self, ext: &str) -> String {
+        match &self.name {
+            Some(name) => format!("{}-{name}.{ext}", self.app),
+            None => format!("{}.{ext}", self.app),
+        }
+    }
+}
+
+/// The `--instance` flag, so that parsing accepts it.
+pub fn instance_flag() -> Flag {
+    Flag::new(INSTANCE_FLAG, FlagValue::String("".into()))
+        .description("The named instance to run, with its own environment and config file")
+}
+
+fn env_name(name: &str) -> String {
+    name.to_uppercase().replace('-', "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_detect() {
+        let args = strings(&["-v", "--instance", "blue-2", "x"]);
+        let instance = Instance::detect_with("my-app", &args, Some("green".into()));
+        assert_eq!(instance.name.as_deref(), Some("blue-2"));
+        assert_eq!(instance.env_prefix(), "MY_APP_BLUE_2_");
+        assert_eq!(instance.file_name("toml"), "my-app-blue-2.toml");
+
+        let args = strings(&["--instance=blue", "x"]);
+        let instance = Instance::detect_with("my-app", &args, None);
+        assert_eq!(instance.name.as_deref(), Some("blue"));
+
+        let args = strings(&["--", "--instance", "blue"]);
+        let instance = Instance::detect_with("my-app", &args, Some("green".into()));
+        assert_eq!(instance.name.as_deref(), Some("green"));
+
+        let instance = Instance::detect_with("my-app", &[], None);
+        assert_eq!(instance.name, None);
+        assert_eq!(instance.env_prefix(), "MY_APP_");
+        assert_eq!(instance.file_name("json"), "my-app.json");
+    }
+}
@@ -1,245 +1,3013 @@
-use proc_macro::TokenStream;
-use quote::quote;
-use std::collections::HashMap;
-use syn::{
-    parse_macro_input, Attribute, Data, DeriveInput, Fields, GenericArgument, LitChar, LitStr,
-    Meta, MetaList, PathArguments, Type, TypePath,
+#[cfg(feature = "json")]
+use std::path::Path;
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    path::PathBuf,
+    time::Duration,
 };
 
-#[allow(dead_code)]
-trait Parse {
-    fn parse_env(&mut self, vars: HashMap<String, String>);
-    fn parse_args(&mut self, args: Vec<String>) -> Vec<String>;
+pub use changes::ChangeSet;
+pub use cliconf_derive::{FlagEnum, Parse};
+pub use complete::Completer;
+pub use error::{Error, TypeMismatch};
+#[cfg(feature = "json")]
+pub use file::{Format, FormatRegistry, Limits};
+pub use flag_enum::FlagEnum;
+pub use frozen::Frozen;
+#[cfg(feature = "json")]
+pub use loader::Loader;
+/// The `serde_json` that cliconf uses, e.g. for the type of
+/// `sanitized_snapshot`.
+#[cfg(feature = "json")]
+pub use serde_json;
+
+#[cfg(feature = "audit")]
+pub mod audit;
+pub mod auto;
+pub mod changes;
+pub mod command;
+pub mod complete;
+pub mod completions;
+#[cfg(feature = "plist")]
+pub mod defaults;
+pub mod docker;
+pub mod docs;
+pub mod dump;
+pub mod engine;
+mod error;
+pub mod exec;
+#[cfg(feature = "json")]
+pub mod file;
+pub mod flag_enum;
+mod frozen;
+#[cfg(feature = "gsettings")]
+pub mod gsettings;
+pub mod instance;
+pub mod lint;
+#[cfg(feature = "json")]
+pub mod loader;
+#[cfg(feature = "json")]
+pub mod manifest;
+pub mod meta;
+pub mod nix;
+pub mod paths;
+#[cfg(feature = "json")]
+pub mod provider;
+pub mod repl;
+pub mod report;
+pub mod shell;
+#[cfg(feature = "json")]
+pub mod telemetry;
+pub mod units;
+#[cfg(feature = "usage")]
+pub mod usage;
+
+use engine::{Arity, Dispatch, EnvVar};
+
+/// Without the `json` feature there's nothing to replay manifests with, so the
+/// derive macro's `replay_manifest` is left out.
+#[cfg(not(feature = "json"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __replay_manifest {
+    ($($tokens:tt)*) => {};
 }
 
-fn is_bool(ty: &Type) -> bool {
-    if let Type::Path(TypePath { path, .. }) = ty {
-        if let Some(segment) = path.segments.first() {
-            if let PathArguments::None = segment.arguments {
-                return segment.ident == "bool";
-            }
+/// Likewise, the derive macro's `parse_file` needs the `json` feature.
+#[cfg(not(feature = "json"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __parse_file {
+    ($($tokens:tt)*) => {};
+}
+
+/// Likewise for `load` and `load_with`.
+#[cfg(not(feature = "json"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __load {
+    ($($tokens:tt)*) => {};
+}
+
+/// The derive macro's `parse_embedded_defaults` needs the `json` feature too,
+/// but it's only generated when asked for, so asking without the feature is
+/// an error.
+#[cfg(not(feature = "json"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __parse_embedded_defaults {
+    ($($tokens:tt)*) => {
+        ::std::compile_error!("`embedded_defaults` needs cliconf's `json` feature");
+    };
+}
+
+/// Likewise, fields that name a GSettings key need the `gsettings` feature.
+#[cfg(not(feature = "gsettings"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __parse_gsettings {
+    ($($tokens:tt)*) => {
+        ::std::compile_error!("the `gsettings` attribute needs cliconf's `gsettings` feature");
+    };
+}
+
+/// Likewise for `parse_defaults` and the `plist` feature.
+#[cfg(not(feature = "plist"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __parse_defaults {
+    ($($tokens:tt)*) => {
+        ::std::compile_error!("the `defaults` attribute needs cliconf's `plist` feature");
+    };
+}
+
+/// Likewise for `sanitized_snapshot`, which is only generated for structs
+/// with telemetry fields.
+#[cfg(not(feature = "json"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __sanitized_snapshot {
+    ($($tokens:tt)*) => {
+        ::std::compile_error!("the `telemetry` attribute needs cliconf's `json` feature");
+    };
+}
+
+/// Likewise, the derive macro's `usage` needs the `usage` feature.
+#[cfg(not(feature = "usage"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __usage {
+    ($($tokens:tt)*) => {};
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlagValue {
+    Bool(bool),
+    String(String),
+    Int64(i64),
+    Int128(i128),
+    Float64(f64),
+    /// Read from values like `30s` or `1h30m`, or a plain number of seconds.
+    Duration(Duration),
+    StringArray(Vec<String>),
+    Int64Array(Vec<i64>),
+    Int128Array(Vec<i128>),
+    Float64Array(Vec<f64>),
+}
+
+impl FlagValue {
+    fn is_array(&self) -> bool {
+        matches!(
+            self,
+            FlagValue::StringArray(_)
+                | FlagValue::Int64Array(_)
+                | FlagValue::Int128Array(_)
+                | FlagValue::Float64Array(_)
+        )
+    }
+
+    /// The value as strings that [`FlagValue::try_set_str`] parses back to
+    /// it, one per item for arrays.
+    pub fn to_strings(&self) -> Vec<String> {
+        fn all<T: ToString>(items: &[T]) -> Vec<String> {
+            items.iter().map(T::to_string).collect()
+        }
+        match self {
+            FlagValue::Bool(v) => vec![v.to_string()],
+            FlagValue::String(v) => vec![v.clone()],
+            FlagValue::Int64(v) => vec![v.to_string()],
+            FlagValue::Int128(v) => vec![v.to_string()],
+            FlagValue::Float64(v) => vec![v.to_string()],
+            FlagValue::Duration(v) => vec![units::format_duration(v)],
+            FlagValue::StringArray(a) => a.clone(),
+            FlagValue::Int64Array(a) => all(a),
+            FlagValue::Int128Array(a) => all(a),
+            FlagValue::Float64Array(a) => all(a),
         }
     }
-    false
-}
 
-fn is_vec(ty: &Type) -> bool {
-    if let Type::Path(TypePath { path, .. }) = ty {
-        if let Some(segment) = path.segments.last() {
-            if segment.ident == "Vec" {
-                if let PathArguments::AngleBracketed(args) = &segment.arguments {
-                    for arg in &args.args {
-                        if let GenericArgument::Type(inner_ty) = arg {
-                            if is_bool(&inner_ty) {
-                                panic!("cliconf::Parse does not support Vec<bool>!");
-                            }
-                            return true;
-                        }
+    /// Parses `s` as this value's type. Arrays get `s` appended, after being
+    /// cleared first if `append` is false. Returns false if `s` doesn't parse,
+    /// in which case the value is left untouched.
+    pub fn try_set_str(&mut self, s: &str, append: bool) -> bool {
+        fn push<T: std::str::FromStr>(a: &mut Vec<T>, s: &str, append: bool) -> bool {
+            match s.parse() {
+                Ok(v) => {
+                    if !append {
+                        a.clear();
                     }
+                    a.push(v);
+                    true
+                }
+                Err(_) => false,
+            }
+        }
+        fn set<T: std::str::FromStr>(v: &mut T, s: &str) -> bool {
+            match s.parse() {
+                Ok(parsed) => {
+                    *v = parsed;
+                    true
+                }
+                Err(_) => false,
+            }
+        }
+        match self {
+            FlagValue::Bool(v) => set(v, s),
+            FlagValue::String(v) => set(v, s),
+            FlagValue::Int64(v) => set(v, s),
+            FlagValue::Int128(v) => set(v, s),
+            FlagValue::Float64(v) => set(v, s),
+            FlagValue::Duration(v) => match units::parse_duration(s) {
+                Some(duration) => {
+                    *v = duration;
+                    true
+                }
+                None => false,
+            },
+            FlagValue::StringArray(a) => push(a, s, append),
+            FlagValue::Int64Array(a) => push(a, s, append),
+            FlagValue::Int128Array(a) => push(a, s, append),
+            FlagValue::Float64Array(a) => push(a, s, append),
+        }
+    }
+
+    /// Replaces the value with `json` if it holds a matching type. On a
+    /// mismatch the value is left untouched, and the error describes both
+    /// types.
+    #[cfg(feature = "json")]
+    pub fn try_set_json(&mut self, json: &serde_json::Value) -> Result<(), TypeMismatch> {
+        fn collect<T>(
+            json: &serde_json::Value,
+            f: impl Fn(&serde_json::Value) -> Option<T>,
+        ) -> Option<Vec<T>> {
+            json.as_array()?.iter().map(f).collect()
+        }
+        // Beyond 64 bits, numbers need serde_json's `arbitrary_precision`
+        // feature, or a string like the one `to_json` writes
+        fn int128(json: &serde_json::Value) -> Option<i128> {
+            match json {
+                serde_json::Value::Number(n) => n.to_string().parse().ok(),
+                serde_json::Value::String(s) => s.parse().ok(),
+                _ => None,
+            }
+        }
+        let parsed = match self {
+            FlagValue::Bool(_) => json.as_bool().map(FlagValue::Bool),
+            FlagValue::String(_) => json.as_str().map(|s| FlagValue::String(s.into())),
+            FlagValue::Int64(_) => json.as_i64().map(FlagValue::Int64),
+            FlagValue::Int128(_) => int128(json).map(FlagValue::Int128),
+            FlagValue::Float64(_) => json.as_f64().map(FlagValue::Float64),
+            // Seconds, or a string like `30s`
+            FlagValue::Duration(_) => match json {
+                serde_json::Value::Number(n) => n
+                    .as_f64()
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok()),
+                serde_json::Value::String(s) => units::parse_duration(s),
+                _ => None,
+            }
+            .map(FlagValue::Duration),
+            FlagValue::StringArray(_) => {
+                collect(json, |v| v.as_str().map(String::from)).map(FlagValue::StringArray)
+            }
+            FlagValue::Int64Array(_) => collect(json, |v| v.as_i64()).map(FlagValue::Int64Array),
+            FlagValue::Int128Array(_) => collect(json, int128).map(FlagValue::Int128Array),
+            FlagValue::Float64Array(_) => {
+                collect(json, |v| v.as_f64()).map(FlagValue::Float64Array)
+            }
+        };
+        match parsed {
+            Some(value) => {
+                *self = value;
+                Ok(())
+            }
+            None => Err(self.mismatch(json)),
+        }
+    }
+
+    /// Describes how `json` fails to match the value's type. For a list,
+    /// that's the first item that doesn't fit.
+    #[cfg(feature = "json")]
+    fn mismatch(&self, json: &serde_json::Value) -> TypeMismatch {
+        let item = self.item();
+        let describe = |json: &serde_json::Value| -> String {
+            let bits = match item {
+                FlagValue::Int64(_) => 64,
+                FlagValue::Int128(_) => 128,
+                _ => return file::describe_json(json).into(),
+            };
+            match json {
+                // Any other integer would have fit
+                serde_json::Value::Number(n) if file::is_integer(n) => {
+                    format!("{n}, which overflows a {bits}-bit integer")
+                }
+                json => file::describe_json(json).into(),
+            }
+        };
+        let found = match json {
+            serde_json::Value::Array(items) if self.is_array() => {
+                match items
+                    .iter()
+                    .find(|json| item.clone().try_set_json(json).is_err())
+                {
+                    Some(bad) => format!("a list containing {}", describe(bad)),
+                    None => "a list".into(),
+                }
+            }
+            json => describe(json),
+        };
+        TypeMismatch {
+            expected: self.type_name().into(),
+            found,
+        }
+    }
+
+    /// The value's type, described for people, e.g. `a list of integers`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            FlagValue::Bool(_) => "a boolean",
+            FlagValue::String(_) => "a string",
+            FlagValue::Int64(_) | FlagValue::Int128(_) => "an integer",
+            FlagValue::Float64(_) => "a number",
+            FlagValue::Duration(_) => "a duration",
+            FlagValue::StringArray(_) => "a list of strings",
+            FlagValue::Int64Array(_) | FlagValue::Int128Array(_) => "a list of integers",
+            FlagValue::Float64Array(_) => "a list of numbers",
+        }
+    }
+
+    /// A scalar of an array's item type, or the value itself if it isn't an
+    /// array.
+    #[cfg(feature = "json")]
+    fn item(&self) -> FlagValue {
+        match self {
+            FlagValue::StringArray(_) => FlagValue::String(String::new()),
+            FlagValue::Int64Array(_) => FlagValue::Int64(0),
+            FlagValue::Int128Array(_) => FlagValue::Int128(0),
+            FlagValue::Float64Array(_) => FlagValue::Float64(0.0),
+            scalar => scalar.clone(),
+        }
+    }
+
+    /// Like [`FlagValue::try_set_json`], but first converts between the types
+    /// that hand-written config files tend to mix up: numbers and booleans
+    /// become strings, numeric strings become numbers, and `0`, `1`, `"0"`,
+    /// `"1"`, `"true"` and `"false"` become booleans. Array items are
+    /// converted one by one.
+    #[cfg(feature = "json")]
+    pub fn try_coerce_json(&mut self, json: &serde_json::Value) -> Result<(), TypeMismatch> {
+        use serde_json::Value;
+        // An array's items are converted like a scalar of the item type
+        let item = self.item();
+        let coerce = |json: &Value| -> Value {
+            let coerced = match (&item, json) {
+                (FlagValue::Bool(_), Value::Number(n)) => match n.as_u64() {
+                    Some(0) => Some(false.into()),
+                    Some(1) => Some(true.into()),
+                    _ => None,
+                },
+                (FlagValue::Bool(_), Value::String(s)) => match s.trim() {
+                    "0" | "false" => Some(false.into()),
+                    "1" | "true" => Some(true.into()),
+                    _ => None,
+                },
+                (FlagValue::String(_), Value::Number(_) | Value::Bool(_)) => {
+                    Some(json.to_string().into())
+                }
+                (FlagValue::Int64(_), Value::String(s)) => {
+                    s.trim().parse::<i64>().ok().map(Value::from)
+                }
+                // Kept as a string, since it may not fit in a JSON number
+                (FlagValue::Int128(_), Value::String(s)) => {
+                    s.trim().parse::<i128>().ok().map(|n| n.to_string().into())
                 }
+                (FlagValue::Float64(_), Value::String(s)) => s
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(serde_json::Number::from_f64)
+                    .map(Value::Number),
+                _ => None,
+            };
+            coerced.unwrap_or_else(|| json.clone())
+        };
+        let coerced = match json {
+            Value::Array(items) if self.is_array() => items.iter().map(coerce).collect(),
+            json => coerce(json),
+        };
+        self.try_set_json(&coerced)
+    }
+
+    /// Converts the value to JSON. 128-bit integers that don't fit in JSON's
+    /// number range become strings.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> serde_json::Value {
+        fn int128(v: i128) -> serde_json::Value {
+            match i64::try_from(v) {
+                Ok(v) => v.into(),
+                Err(_) => v.to_string().into(),
             }
         }
+        match self {
+            FlagValue::Bool(v) => (*v).into(),
+            FlagValue::String(v) => v.as_str().into(),
+            FlagValue::Int64(v) => (*v).into(),
+            FlagValue::Int128(v) => int128(*v),
+            FlagValue::Float64(v) => (*v).into(),
+            FlagValue::Duration(v) => v.as_secs_f64().into(),
+            FlagValue::StringArray(a) => a.as_slice().into(),
+            FlagValue::Int64Array(a) => a.as_slice().into(),
+            FlagValue::Int128Array(a) => a.iter().copied().map(int128).collect(),
+            FlagValue::Float64Array(a) => a.as_slice().into(),
+        }
+    }
+}
+
+/// Converts between each integer type and the `FlagValue` that holds it:
+/// `Int64` for the types that always fit in an `i64`, `Int128` for the rest.
+/// Reading one back fails if the flag isn't an integer or its value doesn't
+/// fit, e.g. a `u16` set to `70000` on the command line.
+macro_rules! integer_conversions {
+    ($scalar:ident, $array:ident, $stored:ty: $($int:ty),*) => {
+        $(
+            impl From<$int> for FlagValue {
+                fn from(v: $int) -> Self {
+                    FlagValue::$scalar(v as $stored)
+                }
+            }
+
+            impl From<Vec<$int>> for FlagValue {
+                fn from(a: Vec<$int>) -> Self {
+                    FlagValue::$array(a.into_iter().map(|v| v as $stored).collect())
+                }
+            }
+        )*
+    };
+    ($($int:ty),*) => {
+        $(
+            impl TryFrom<&FlagValue> for $int {
+                type Error = TypeMismatch;
+
+                fn try_from(value: &FlagValue) -> Result<Self, Self::Error> {
+                    let v = match value {
+                        FlagValue::Int64(v) => *v as i128,
+                        FlagValue::Int128(v) => *v,
+                        value => return Err(int_mismatch::<$int>(value.type_name().into())),
+                    };
+                    <$int>::try_from(v).map_err(|_| int_mismatch::<$int>(v.to_string()))
+                }
+            }
+
+            impl TryFrom<&FlagValue> for Vec<$int> {
+                type Error = TypeMismatch;
+
+                fn try_from(value: &FlagValue) -> Result<Self, Self::Error> {
+                    let items: Vec<i128> = match value {
+                        FlagValue::Int64Array(a) => a.iter().map(|&v| v as i128).collect(),
+                        FlagValue::Int128Array(a) => a.clone(),
+                        value => return Err(int_mismatch::<$int>(value.type_name().into())),
+                    };
+                    items
+                        .into_iter()
+                        .map(|v| <$int>::try_from(v).map_err(|_| int_mismatch::<$int>(v.to_string())))
+                        .collect()
+                }
+            }
+        )*
+    };
+}
+
+integer_conversions!(Int64, Int64Array, i64: i8, i16, i32, i64, isize, u8, u16, u32);
+integer_conversions!(Int128, Int128Array, i128: i128, u64, usize);
+integer_conversions!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+/// The error for reading a flag as the integer type `T`, e.g. `a u16` but
+/// found `70000`.
+fn int_mismatch<T>(found: String) -> TypeMismatch {
+    TypeMismatch {
+        expected: format!("a {}", std::any::type_name::<T>()),
+        found,
     }
-    false
 }
 
-#[derive(Default)]
-struct CliconfAttrs {
-    shorthand: Option<char>,
-    delimiter: Option<String>,
+/// Paths are held as strings. Ones that aren't valid Unicode are converted
+/// lossily, as they would be when read from the command line.
+impl From<PathBuf> for FlagValue {
+    fn from(path: PathBuf) -> Self {
+        FlagValue::String(path.to_string_lossy().into_owned())
+    }
 }
 
-fn get_meta<'a>(attrs: &'a [Attribute], name: &str) -> Option<&'a Meta> {
-    for attr in attrs {
-        if attr.meta.path().is_ident(name) {
-            return Some(&attr.meta);
-        }
+impl From<Vec<PathBuf>> for FlagValue {
+    fn from(paths: Vec<PathBuf>) -> Self {
+        FlagValue::StringArray(
+            paths
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect(),
+        )
     }
-    None
 }
 
-fn get_meta_list<'a>(attrs: &'a [Attribute], name: &str) -> Option<&'a MetaList> {
-    if let Some(Meta::List(meta_list)) = get_meta(attrs, name) {
-        return Some(&meta_list);
+impl TryFrom<&FlagValue> for PathBuf {
+    type Error = TypeMismatch;
+
+    fn try_from(value: &FlagValue) -> Result<Self, Self::Error> {
+        match value {
+            FlagValue::String(s) => Ok(s.into()),
+            value => Err(TypeMismatch {
+                expected: "a path".into(),
+                found: value.type_name().into(),
+            }),
+        }
     }
-    None
 }
 
-fn get_cliconf_attrs(attrs: &[Attribute]) -> CliconfAttrs {
-    let mut result = CliconfAttrs::default();
-    if let Some(meta_list) = get_meta_list(attrs, "cliconf") {
-        meta_list
-            .parse_nested_meta(|meta| {
-                if meta.path.is_ident("shorthand") {
-                    let value = meta.value()?;
-                    let c: LitChar = value.parse()?;
-                    result.shorthand = Some(c.value());
+/// Converts between network addresses and the strings that hold them.
+/// Reading one back fails if the flag isn't a string or doesn't hold an
+/// address of that type, e.g. `localhost:8080` for a `SocketAddr`.
+macro_rules! address_conversions {
+    ($($addr:ty: $name:literal, $plural:literal);*) => {
+        $(
+            impl From<$addr> for FlagValue {
+                fn from(addr: $addr) -> Self {
+                    FlagValue::String(addr.to_string())
                 }
-                if meta.path.is_ident("delimiter") {
-                    let value = meta.value()?;
-                    let s: LitStr = value.parse()?;
-                    result.delimiter = Some(s.value());
+            }
+
+            impl From<Vec<$addr>> for FlagValue {
+                fn from(addrs: Vec<$addr>) -> Self {
+                    FlagValue::StringArray(addrs.iter().map(ToString::to_string).collect())
                 }
-                Ok(())
-            })
-            .expect("Failed to parse cliconf attribute");
-    }
-    result
-}
-
-#[proc_macro_derive(Parse, attributes(cliconf))]
-pub fn derive_flags(input: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(input as DeriveInput);
-    let name = &input.ident;
-
-    let mut parse_env = vec![];
-    let mut parse_arg = vec![];
-    let mut need_arg = vec![];
-    let mut need_arg_shorthand = vec![];
-    if let Data::Struct(data_struct) = &input.data {
-        if let Fields::Named(fields_named) = &data_struct.fields {
-            for f in fields_named.named.iter() {
-                let field_name = &f.ident;
-                let field_name_string = field_name.clone().unwrap().to_string();
-                let var_name = field_name_string.to_uppercase();
-                let arg_name = field_name_string.replace("_", "-");
-                let field_is_vec = is_vec(&f.ty);
-
-                let cliconf_attrs = get_cliconf_attrs(&f.attrs);
-
-                let parse_env_value = quote! {
-                    let value = value.parse().expect(&format!("Failed to parse environment variable {}", #var_name));
-                };
-
-                let parse_arg_value = quote! {
-                    let value = arg.parse().expect(&format!("Failed to parse command-line argument {}", #arg_name));
-                };
-
-                let parse_env_op = if field_is_vec {
-                    if let Some(delimiter) = cliconf_attrs.delimiter {
-                        quote! {
-                            self.#field_name.clear();
-                            for value in value.split(&#delimiter) {
-                                #parse_env_value
-                                self.#field_name.push(value);
-                            }
-                        }
-                    } else {
-                        quote! {}
-                    }
-                } else {
-                    quote! {
-                        #parse_env_value
-                        self.#field_name = value;
-                    }
-                };
+            }
 
-                parse_env.push(quote! {
-                    if let Some(value) = vars.get(#var_name) {
-                        #parse_env_op
-                    }
-                });
+            impl TryFrom<&FlagValue> for $addr {
+                type Error = TypeMismatch;
 
-                let parse_arg_op = if field_is_vec {
-                    quote! {
-                        if !cleared_vecs.contains(#field_name_string) {
-                            self.#field_name.clear();
-                            cleared_vecs.insert(#field_name_string);
-                        }
-                        #parse_arg_value
-                        self.#field_name.push(value);
+                fn try_from(value: &FlagValue) -> Result<Self, Self::Error> {
+                    let mismatch = |found: String| TypeMismatch {
+                        expected: $name.into(),
+                        found,
+                    };
+                    match value {
+                        FlagValue::String(s) => s.parse().map_err(|_| mismatch(format!("{s:?}"))),
+                        value => Err(mismatch(value.type_name().into())),
                     }
-                } else {
-                    quote! {
-                        #parse_arg_value
-                        self.#field_name = value;
-                    }
-                };
+                }
+            }
 
-                parse_arg.push(quote! {
-                    #field_name_string => {
-                        #parse_arg_op
-                    }
-                });
+            impl TryFrom<&FlagValue> for Vec<$addr> {
+                type Error = TypeMismatch;
 
-                let need_arg_op = if is_bool(&f.ty) {
-                    quote! {
-                        self.#field_name = true
-                    }
-                } else {
-                    quote! {
-                        need_value_for_name = Some(#field_name_string)
+                fn try_from(value: &FlagValue) -> Result<Self, Self::Error> {
+                    match value {
+                        FlagValue::StringArray(a) => a
+                            .iter()
+                            .map(|s| <$addr>::try_from(&FlagValue::String(s.clone())))
+                            .collect(),
+                        value => Err(TypeMismatch {
+                            expected: concat!("a list of ", $plural).into(),
+                            found: value.type_name().into(),
+                        }),
                     }
-                };
+                }
+            }
+        )*
+    };
+}
 
-                need_arg.push(quote! {
-                    #arg_name => #need_arg_op,
-                });
+address_conversions!(
+    IpAddr: "an IP address", "IP addresses";
+    Ipv4Addr: "an IPv4 address", "IPv4 addresses";
+    Ipv6Addr: "an IPv6 address", "IPv6 addresses";
+    SocketAddr: "an address and port", "addresses and ports";
+    SocketAddrV4: "an IPv4 address and port", "IPv4 addresses and ports";
+    SocketAddrV6: "an IPv6 address and port", "IPv6 addresses and ports"
+);
 
-                if let Some(shorthand) = cliconf_attrs.shorthand {
-                    let shorthand = shorthand.to_string();
-                    need_arg_shorthand.push(quote! {
-                        #shorthand => #need_arg_op,
-                    });
-                }
+/// Duration flags accept values like `500ms` or `1h30m`, and take plain
+/// numbers as seconds, or in the flag's [unit](Flag::unit) if it has one.
+impl From<Duration> for FlagValue {
+    fn from(duration: Duration) -> Self {
+        FlagValue::Duration(duration)
+    }
+}
+
+impl TryFrom<&FlagValue> for Duration {
+    type Error = TypeMismatch;
+
+    fn try_from(value: &FlagValue) -> Result<Self, Self::Error> {
+        let secs = match value {
+            FlagValue::Duration(v) => return Ok(*v),
+            FlagValue::Int64(v) => Some(*v as f64),
+            FlagValue::Int128(v) => Some(*v as f64),
+            FlagValue::Float64(v) => Some(*v),
+            _ => None,
+        };
+        secs.and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .ok_or_else(|| TypeMismatch {
+                expected: "a duration in seconds".into(),
+                found: match secs {
+                    Some(secs) => secs.to_string(),
+                    None => value.type_name().into(),
+                },
+            })
+    }
+}
+
+impl TryFrom<&FlagValue> for Vec<PathBuf> {
+    type Error = TypeMismatch;
+
+    fn try_from(value: &FlagValue) -> Result<Self, Self::Error> {
+        match value {
+            FlagValue::StringArray(a) => Ok(a.iter().map(PathBuf::from).collect()),
+            value => Err(TypeMismatch {
+                expected: "a list of paths".into(),
+                found: value.type_name().into(),
+            }),
+        }
+    }
+}
+
+/// Checks a flag's final value, returning a message explaining why it was
+/// rejected.
+pub type Validator = fn(&FlagValue) -> Result<(), String>;
+
+/// Where a flag's current value came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Default,
+    /// A config file loaded from disk, with the line of the value when it
+    /// could be found.
+    File(PathBuf, Option<usize>),
+    /// A config document passed in as a string, with the line of the value
+    /// when it could be found.
+    Inline(Option<usize>),
+    /// An environment variable, by name.
+    Env(String),
+    /// A [`provider::Provider`], by name.
+    Provider(String),
+    /// The command line, with the position of the argument that held the
+    /// value, counting from 1 after the program name, when it was parsed
+    /// from a list of arguments.
+    Arg(Option<usize>),
+    /// Explicitly reset to its default on the command line, overriding any
+    /// other source.
+    Unset,
+    /// An auto default, by the name of its resolver. See [`auto`].
+    Auto(String),
+    /// A desktop setting, by its GSettings schema and key, dconf path, or
+    /// macOS defaults domain and key.
+    Setting(String),
+}
+
+/// Names the source for messages, e.g. `$PORT` or `/etc/app.toml`.
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "the default"),
+            Source::File(path, None) => write!(f, "{}", path.display()),
+            Source::File(path, Some(line)) => write!(f, "{}:{line}", path.display()),
+            Source::Inline(None) => write!(f, "an inline config"),
+            Source::Inline(Some(line)) => write!(f, "line {line} of an inline config"),
+            Source::Env(var) => write!(f, "${var}"),
+            Source::Provider(name) => write!(f, "provider {name}"),
+            Source::Arg(None) => write!(f, "the command line"),
+            Source::Arg(Some(position)) => write!(f, "argument {position} on the command line"),
+            Source::Unset => write!(f, "an unset on the command line"),
+            Source::Auto(resolver) => write!(f, "auto default {resolver}"),
+            Source::Setting(setting) => write!(f, "desktop setting {setting}"),
+        }
+    }
+}
+
+impl Source {
+    /// Whether the value is a default rather than something the user set.
+    pub fn is_default(&self) -> bool {
+        matches!(self, Source::Default | Source::Unset | Source::Auto(_))
+    }
+
+    /// The same source at `line` of a config document, for a value found
+    /// there. Other sources have no lines and are returned as they are.
+    #[cfg(feature = "json")]
+    pub(crate) fn at_line(&self, line: Option<usize>) -> Source {
+        match self {
+            Source::File(path, _) => Source::File(path.clone(), line),
+            Source::Inline(_) => Source::Inline(line),
+            source => source.clone(),
+        }
+    }
+
+    /// Whether both are the same source, wherever in it the values were.
+    pub(crate) fn same_as(&self, other: &Source) -> bool {
+        match (self, other) {
+            (Source::File(a, _), Source::File(b, _)) => a == b,
+            (Source::Inline(_), Source::Inline(_)) | (Source::Arg(_), Source::Arg(_)) => true,
+            (a, b) => a == b,
+        }
+    }
+}
+
+/// Where each field of a derived struct got its value, kept in a field marked
+/// `#[cliconf(sources)]`. Fields are named by their flags, without the
+/// leading `--`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sources {
+    sources: HashMap<String, Source>,
+    /// The sources that set deprecated flags, in order
+    deprecated_uses: Vec<(String, Source)>,
+}
+
+impl Sources {
+    /// Where the flag's value came from, [`Source::Default`] if no source
+    /// has set it.
+    pub fn get(&self, name: &str) -> &Source {
+        static DEFAULT: Source = Source::Default;
+        self.sources.get(name).unwrap_or(&DEFAULT)
+    }
+
+    /// Records that `source` just set the flag.
+    pub fn record(&mut self, name: &str, source: &Source) {
+        self.sources.insert(name.into(), source.clone());
+    }
+
+    /// Records that `source` set a deprecated flag, once per source, so that
+    /// a derived struct's `check` warns about each of them.
+    #[doc(hidden)]
+    pub fn record_deprecated(&mut self, name: &str, source: &Source) {
+        let used = (name.to_string(), source.clone());
+        if !source.is_default() && !self.deprecated_uses.contains(&used) {
+            self.deprecated_uses.push(used);
+        }
+    }
+
+    /// Every source that set the deprecated flag, in order.
+    pub fn deprecated_uses<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Source> {
+        self.deprecated_uses
+            .iter()
+            .filter(move |(flag, _)| flag == name)
+            .map(|(_, source)| source)
+    }
+
+    /// Swaps the flag's source with the same flag's in `other`, along with
+    /// its value.
+    #[doc(hidden)]
+    pub fn swap(&mut self, other: &mut Sources, name: &str) {
+        let source = self.get(name).clone();
+        let other_source = other.get(name).clone();
+        self.record(name, &other_source);
+        other.record(name, &source);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Flag {
+    pub name: String,
+    pub shorthand: Option<char>,
+    pub description: Option<String>,
+    pub delimiter: Option<String>,
+    pub required: bool,
+    pub exclude_from_usage: bool,
+    /// Why the flag shouldn't be used anymore, and what to use instead.
+    pub deprecated: Option<String>,
+    /// Every source that set a deprecated flag, in order, so that
+    /// [`lint::Deprecated`] warns about each of them and not just the last.
+    pub deprecated_uses: Vec<Source>,
+    /// Whether the value is sensitive, like a password or token.
+    pub secret: bool,
+    /// Whether the value is safe to include in
+    /// [`Flags::sanitized_snapshot`], e.g. for crash reports.
+    pub telemetry: bool,
+    /// Whether an `Int64` flag counts how often it's given, as in `-vvv`,
+    /// rather than taking a value.
+    pub count: bool,
+    /// The [resolver](auto::Resolvers) that works out the default when the
+    /// program starts, e.g. `free_port` or `cpus * 2`.
+    pub auto: Option<String>,
+    /// The unit of a numeric flag, e.g. `ms`. Values with another unit of
+    /// the same kind are converted, so `2s` sets `2000`. See [`units`].
+    pub unit: Option<String>,
+    /// Whether a string flag's values are paths whose `~` and environment
+    /// variables are expanded. See [`paths`].
+    pub expand_path: bool,
+    /// Whether [`Flags::reload`] keeps the current value, e.g. for a listen
+    /// address that only applies at startup.
+    pub immutable: bool,
+    /// Lets values come from running a command. See [`exec`].
+    pub from_command: Option<exec::FromCommand>,
+    /// The desktop setting that [`Flags::parse_gsettings`] reads. See
+    /// [`gsettings`].
+    #[cfg(feature = "gsettings")]
+    pub gsettings: Option<String>,
+    /// The macOS preference that [`Flags::parse_defaults`] reads. See
+    /// [`defaults`].
+    #[cfg(feature = "plist")]
+    pub defaults: Option<String>,
+    pub default_value: FlagValue,
+    pub value: FlagValue,
+    pub source: Source,
+    pub validator: Option<Validator>,
+    pub completer: Option<Completer>,
+}
+
+impl Flag {
+    pub fn new(name: &str, default_value: FlagValue) -> Self {
+        Self {
+            name: name.into(),
+            shorthand: None,
+            description: None,
+            delimiter: None,
+            required: false,
+            exclude_from_usage: false,
+            deprecated: None,
+            deprecated_uses: vec![],
+            secret: false,
+            telemetry: false,
+            count: false,
+            auto: None,
+            unit: None,
+            expand_path: false,
+            immutable: false,
+            from_command: None,
+            #[cfg(feature = "gsettings")]
+            gsettings: None,
+            #[cfg(feature = "plist")]
+            defaults: None,
+            value: default_value.clone(),
+            default_value,
+            source: Source::Default,
+            validator: None,
+            completer: None,
+        }
+    }
+
+    pub fn shorthand(mut self, c: char) -> Self {
+        self.shorthand = Some(c);
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Splits array values read from environment variables. Without a
+    /// delimiter, array flags can't be set from the environment.
+    pub fn delimiter(mut self, delimiter: &str) -> Self {
+        self.delimiter = Some(delimiter.into());
+        self
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    pub fn exclude_from_usage(mut self) -> Self {
+        self.exclude_from_usage = true;
+        self
+    }
+
+    pub fn deprecated(mut self, message: &str) -> Self {
+        self.deprecated = Some(message.into());
+        self
+    }
+
+    pub fn secret(mut self) -> Self {
+        self.secret = true;
+        self
+    }
+
+    /// Includes the flag in [`Flags::sanitized_snapshot`]. Secrets and string
+    /// flags can't be included, since they're where personal data ends up.
+    pub fn telemetry(mut self) -> Self {
+        self.telemetry = true;
+        self
+    }
+
+    /// Makes an `Int64` flag a switch that adds one each time it's given,
+    /// e.g. for `-vvv`. An explicit value like `--verbose=2` still sets it.
+    pub fn count(mut self) -> Self {
+        self.count = true;
+        self
+    }
+
+    pub fn auto(mut self, resolver: &str) -> Self {
+        self.auto = Some(resolver.into());
+        self
+    }
+
+    pub fn unit(mut self, unit: &str) -> Self {
+        self.unit = Some(unit.into());
+        self
+    }
+
+    /// Expands `~` and `$VAR` in the values of a string flag, e.g. so that
+    /// `--cache-dir ~/.cache` works where no shell expanded it, like in a
+    /// config file.
+    pub fn expand_path(mut self) -> Self {
+        self.expand_path = true;
+        self
+    }
+
+    pub fn immutable(mut self) -> Self {
+        self.immutable = true;
+        self
+    }
+
+    pub fn from_command(mut self, from_command: exec::FromCommand) -> Self {
+        self.from_command = Some(from_command);
+        self
+    }
+
+    #[cfg(feature = "gsettings")]
+    pub fn gsettings(mut self, setting: &str) -> Self {
+        self.gsettings = Some(setting.into());
+        self
+    }
+
+    #[cfg(feature = "plist")]
+    pub fn defaults(mut self, setting: &str) -> Self {
+        self.defaults = Some(setting.into());
+        self
+    }
+
+    pub fn validator(mut self, validator: Validator) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+
+    /// Suggests values for shell completion, e.g. resource names fetched from
+    /// an API. See [`complete`].
+    pub fn completer(mut self, completer: Completer) -> Self {
+        self.completer = Some(completer);
+        self
+    }
+
+    /// The environment variable that sets this flag, e.g. `MY_FLAG` for
+    /// `my-flag`.
+    pub fn env_var(&self) -> String {
+        self.name.to_uppercase().replace('-', "_")
+    }
+
+    fn set_str(&mut self, s: &str, append: bool, source: Source) -> Result<(), Error> {
+        match self.from_command.clone() {
+            Some(from_command) => from_command.apply(&format!("--{}", self.name), s, |output| {
+                self.parse_str(output, append, source)
+            }),
+            None => self.parse_str(s, append, source),
+        }
+    }
+
+    fn parse_str(&mut self, s: &str, append: bool, source: Source) -> Result<(), Error> {
+        let converted = match (&self.unit, self.expand_path) {
+            // Durations read units themselves, so only a plain number needs one
+            (Some(unit), _) if matches!(self.value, FlagValue::Duration(_)) => {
+                units::convert(s, unit).map(|number| format!("{number}{unit}"))
             }
-        } else {
-            panic!("cliconf::Parse can only be derived for structs with named fields");
+            (Some(unit), _) => units::convert(s, unit),
+            (None, true) => paths::expand(s),
+            (None, false) => Some(s.into()),
+        };
+        if !converted.is_some_and(|converted| self.value.try_set_str(&converted, append)) {
+            return Err(Error::InvalidValue {
+                flag: format!("--{}", self.name),
+                value: s.into(),
+                source: Some(source.clone()),
+            });
         }
-    } else {
-        panic!("cliconf::Parse can only be derived for structs");
-    };
+        self.set_source(source);
+        Ok(())
+    }
 
-    let expanded = quote! {
-        impl #name {
-            pub fn parse_env(&mut self, vars: std::collections::HashMap<String, String>) {
-                #(#parse_env)*
-            }
-
-            pub fn parse_args(&mut self, args: Vec<String>) -> Vec<String> {
-                let mut positionals = vec![];
-                let mut need_value_for_name: Option<&str> = None;
-                let mut as_positionals = false;
-                let mut cleared_vecs = std::collections::HashSet::<&str>::new();
-
-                for arg in args {
-                    if as_positionals {
-                        positionals.push(arg);
-                    } else if let Some(name) = need_value_for_name {
-                        match name {
-                            #(#parse_arg)*
-                            _ => panic!("Unknown flag: --{name}")
-                        };
-                        need_value_for_name = None;
-                    } else if arg == "-" {
-                        // Some programs use "-" to signify that data will be read from
-                        // stdin, so we treat it as a positional argument
-                        positionals.push(arg);
-                    } else if arg == "--" {
-                        // "--" is a special flag that treats all of the remaining
-                        // arguments as positional arguments
-                        as_positionals = true;
-                    } else if arg.starts_with("--") {
-                        let name = &arg[2..];
-                        match name {
-                            #(#need_arg)*
-                            _ => panic!("Unknown flag: --{name}")
-                        }
-                    } else if arg.starts_with("-") {
-                        let name = &arg[1..];
-                        match name {
-                            #(#need_arg_shorthand)*
-                            _ => panic!("Unknown flag: -{name}")
-                        }
-                    } else {
-                        positionals.push(arg);
-                    }
+    /// Records that `source` set the flag, and that it was used if it's
+    /// deprecated.
+    fn set_source(&mut self, source: Source) {
+        if self.deprecated.is_some()
+            && !source.is_default()
+            && !self.deprecated_uses.contains(&source)
+        {
+            self.deprecated_uses.push(source.clone());
+        }
+        self.source = source;
+    }
+}
+
+/// The config file table that defines aliases, unless a flag has this name.
+#[cfg(feature = "json")]
+const ALIASES_KEY: &str = "aliases";
+
+#[derive(Debug, Clone, Default)]
+pub struct Flags {
+    pub flags: HashMap<String, Flag>,
+    /// Prepended to every flag's environment variable, e.g. `APP_` for
+    /// `APP_MY_FLAG`. An `_` is added if the prefix doesn't end with one.
+    pub env_prefix: Option<String>,
+    /// Whether `parse_env` matches variable names regardless of case. When
+    /// unset, it does so only on Windows. A variable with the exact name wins
+    /// over others that differ in case, and otherwise the first of them by
+    /// name does.
+    pub env_case_insensitive: Option<bool>,
+    /// A value that resets a flag to its default, e.g. `@unset` for
+    /// `--name @unset`.
+    pub unset_sentinel: Option<String>,
+    /// The name of a flag that resets another flag to its default, e.g.
+    /// `unset` for `--unset name`.
+    pub unset_flag: Option<String>,
+    /// The name of a flag that sets any other flag, e.g. `set` for
+    /// `--set name=value`.
+    pub set_flag: Option<String>,
+    /// The name of a flag that prints the effective configuration, e.g.
+    /// `print-config` for `--print-config[=json|toml|env]`, with secrets
+    /// redacted.
+    pub print_config_flag: Option<String>,
+    /// Bounds on reading config files.
+    #[cfg(feature = "json")]
+    pub file_limits: Limits,
+    /// The config file formats, by extension. Register more to read other
+    /// kinds of files.
+    #[cfg(feature = "json")]
+    pub formats: FormatRegistry,
+    /// Whether config file values of the wrong type are converted where
+    /// that's unambiguous, e.g. `1` for a boolean. Strings like `"8"` are
+    /// read as numbers either way, as they would be on the command line.
+    #[cfg(feature = "json")]
+    pub coerce: bool,
+    /// Config files that [`Flags::search_files`] looked for but didn't find.
+    pub missing_files: Vec<PathBuf>,
+    /// How [`Flags::parse_args`] treats `-`, `--` and empty arguments.
+    pub arg_syntax: engine::ArgSyntax,
+    /// Arguments that ask for help, e.g. `--help` and `-h`. They make
+    /// [`Flags::parse_args`] return [`Error::HelpRequested`].
+    pub help_args: Vec<String>,
+    /// Arguments that ask for full help, e.g. `--help-all`. They make
+    /// [`Flags::parse_args`] return [`Error::HelpAllRequested`], for help
+    /// from `usage::generate_all`.
+    pub help_all_args: Vec<String>,
+    /// Arguments that ask for the version, e.g. `--version`. They make
+    /// [`Flags::parse_args`] return [`Error::VersionRequested`].
+    pub version_args: Vec<String>,
+    /// The positional arguments the program takes, e.g. `SRC` and then any
+    /// number of `DEST`. When set, [`Flags::parse_args`] fails if there are
+    /// too few or too many.
+    pub positionals: Option<Vec<meta::Positional>>,
+    /// Expansions for the first argument, e.g. `b` for `build --release`.
+    /// Config files can add to these with an `aliases` table.
+    pub aliases: HashMap<String, Vec<String>>,
+    /// Set to record every input that parsing reads, e.g.
+    /// `Some(Audit::new())`.
+    #[cfg(feature = "audit")]
+    pub audit: Option<audit::Audit>,
+    shorthands: HashMap<char, String>,
+}
+
+impl Flags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, flag: Flag) {
+        if self.flags.contains_key(&flag.name) {
+            panic!("Flag --{} was added twice", flag.name);
+        }
+        let is_string = matches!(flag.value, FlagValue::String(_) | FlagValue::StringArray(_));
+        if flag.telemetry && (flag.secret || is_string) {
+            panic!(
+                "Flag --{} can't be included in telemetry, being a secret or a string",
+                flag.name
+            );
+        }
+        if let Some(c) = flag.shorthand {
+            if let Some(other) = self.shorthands.insert(c, flag.name.clone()) {
+                panic!(
+                    "Flags --{other} and --{} both use shorthand -{c}",
+                    flag.name
+                );
+            }
+        }
+        self.flags.insert(flag.name.clone(), flag);
+    }
+
+    pub fn get(&self, name: &str) -> &Flag {
+        self.flags
+            .get(name)
+            .unwrap_or_else(|| panic!("Unknown flag: --{name}"))
+    }
+
+    pub fn parse_env(&mut self, vars: HashMap<String, String>) -> Result<(), Error> {
+        engine::parse_env(self, vars)
+    }
+
+    /// Reads only the environment variables that the flags use, straight from
+    /// the process environment.
+    pub fn parse_process_env(&mut self) -> Result<(), Error> {
+        engine::parse_process_env(self)
+    }
+
+    pub fn parse_args(&mut self, args: Vec<String>) -> Result<Vec<String>, Error> {
+        #[cfg(feature = "audit")]
+        self.record(|| audit::Input::Args(args.clone()));
+        let args = engine::expand_aliases(args, &self.aliases)?;
+        engine::parse_args(self, args)
+    }
+
+    /// Applies a single flag by name, e.g. from a command palette. See
+    /// [`engine::apply_arg`].
+    pub fn apply_arg(&mut self, name: &str, value: Option<&str>) -> Result<(), Error> {
+        #[cfg(feature = "audit")]
+        self.record(|| {
+            audit::Input::Args(vec![match value {
+                Some(value) => format!("--{name}={value}"),
+                None => format!("--{name}"),
+            }])
+        });
+        engine::apply_arg(self, name, value)
+    }
+
+    /// Applies a single environment variable, returning whether any flag
+    /// reads it. See [`engine::apply_env_var`].
+    pub fn apply_env_var(&mut self, var: &str, value: &str) -> Result<bool, Error> {
+        engine::apply_env_var(self, var, value)
+    }
+
+    /// Applies a config document held in a string, in `format`. Keys name
+    /// flags like they do in config files, see [`file::parse`].
+    #[cfg(feature = "json")]
+    pub fn parse_str(&mut self, data: &str, format: file::Format) -> Result<(), Error> {
+        let doc = format.parse(data)?;
+        self.record_inline(data.as_bytes());
+        self.apply_document(doc, Some(data), Source::Inline(None))
+    }
+
+    /// Applies a config document from raw bytes, e.g. an asset bundled with a
+    /// mobile app or a value from a keystore, for platforms where config
+    /// doesn't live at a path.
+    #[cfg(feature = "json")]
+    pub fn parse_bytes(&mut self, data: &[u8], format: file::Format) -> Result<(), Error> {
+        let doc = format.parse_bytes(data)?;
+        self.record_inline(data);
+        let text = std::str::from_utf8(data).ok();
+        self.apply_document(doc, text, Source::Inline(None))
+    }
+
+    /// The flags marked for telemetry as a JSON object, e.g. to attach to
+    /// crash reports. No other flag is ever included.
+    #[cfg(feature = "json")]
+    pub fn sanitized_snapshot(&self) -> serde_json::Value {
+        telemetry::snapshot(&self.telemetry_entries())
+    }
+
+    /// Loads a config file, choosing the format from its extension.
+    #[cfg(feature = "json")]
+    pub fn parse_file(&mut self, path: &Path) -> Result<(), Error> {
+        let (doc, data) = file::read_document(path, &self.file_limits, &self.formats)?;
+        self.record_file(path, &data);
+        self.apply_document(doc, Some(&data), Source::File(path.into(), None))
+    }
+
+    /// Loads several config files, with later files overriding earlier ones.
+    /// The files are read concurrently but always applied in order.
+    #[cfg(feature = "json")]
+    pub fn parse_files<P: AsRef<Path> + Sync>(&mut self, paths: &[P]) -> Result<(), Error> {
+        let docs = file::read_documents(paths, &self.file_limits, &self.formats);
+        for (path, doc) in paths.iter().zip(docs) {
+            let (doc, data) = doc?;
+            self.record_file(path.as_ref(), &data);
+            self.apply_document(doc, Some(&data), Source::File(path.as_ref().into(), None))?;
+        }
+        Ok(())
+    }
+
+    /// Like [`Flags::parse_files`], but files that don't exist are skipped
+    /// and listed in [`Flags::missing_files`], e.g. for searching the usual
+    /// config locations.
+    #[cfg(feature = "json")]
+    pub fn search_files<P: AsRef<Path> + Sync>(&mut self, paths: &[P]) -> Result<(), Error> {
+        let docs = file::read_documents(paths, &self.file_limits, &self.formats);
+        for (path, doc) in paths.iter().zip(docs) {
+            let path = path.as_ref();
+            match doc {
+                Ok((doc, data)) => {
+                    self.record_file(path, &data);
+                    self.apply_document(doc, Some(&data), Source::File(path.into(), None))?
                 }
+                Err(Error::Io(_, err)) if err.kind() == std::io::ErrorKind::NotFound => {
+                    self.missing_files.push(path.into())
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the desktop setting of each flag that has one, skipping those
+    /// that can't be read. Call it before parsing the environment and
+    /// arguments, so they override the desktop.
+    #[cfg(feature = "gsettings")]
+    pub fn parse_gsettings(&mut self) -> Result<(), Error> {
+        let settings: Vec<(String, String)> = self
+            .flags
+            .values()
+            .filter_map(|flag| Some((flag.name.clone(), flag.gsettings.clone()?)))
+            .collect();
+        gsettings::parse(self, &settings)
+    }
 
-                positionals
+    /// Reads the macOS preference of each flag that has one, skipping those
+    /// that aren't set. Call it before parsing the environment and arguments,
+    /// so they override the preferences.
+    #[cfg(feature = "plist")]
+    pub fn parse_defaults(&mut self) -> Result<(), Error> {
+        let settings: Vec<(String, String)> = self
+            .flags
+            .values()
+            .filter_map(|flag| Some((flag.name.clone(), flag.defaults.clone()?)))
+            .collect();
+        defaults::parse(self, &settings)
+    }
+
+    /// Applies the document from a remote provider, retrying and falling back
+    /// as `policy` says.
+    #[cfg(feature = "json")]
+    pub fn parse_provider<P: provider::Provider>(
+        &mut self,
+        provider: &std::sync::Arc<P>,
+        policy: &provider::Policy,
+    ) -> Result<(), Error> {
+        match provider::fetch(provider, policy)? {
+            Some(doc) => {
+                #[cfg(feature = "audit")]
+                self.record(|| audit::Input::Provider {
+                    name: provider.name().into(),
+                    sha256: audit::sha256(doc.to_string().as_bytes()),
+                });
+                self.apply_document(doc, None, Source::Provider(provider.name().into()))
             }
+            None => Ok(()),
         }
-    };
+    }
+
+    /// Adds the input to [`Flags::audit`] if it's recording.
+    #[cfg(feature = "audit")]
+    fn record(&mut self, input: impl FnOnce() -> audit::Input) {
+        if let Some(audit) = &mut self.audit {
+            audit.record(input());
+        }
+    }
 
-    TokenStream::from(expanded)
+    #[cfg(feature = "json")]
+    fn record_inline(&mut self, data: &[u8]) {
+        #[cfg(feature = "audit")]
+        self.record(|| audit::Input::Inline {
+            sha256: audit::sha256(data),
+        });
+        #[cfg(not(feature = "audit"))]
+        let _ = data;
+    }
+
+    #[cfg(feature = "json")]
+    fn record_file(&mut self, path: &Path, data: &str) {
+        #[cfg(feature = "audit")]
+        self.record(|| audit::Input::File {
+            path: path.into(),
+            sha256: audit::sha256(data.as_bytes()),
+        });
+        #[cfg(not(feature = "audit"))]
+        let _ = (path, data);
+    }
+
+    /// Applies a config document the way derived structs do, with
+    /// [`file::apply`], finding the line of each value in `data`, the text
+    /// it was parsed from, if there is any. It's applied to a copy of the flags first, so a bad
+    /// key or value leaves every flag as it was.
+    #[cfg(feature = "json")]
+    fn apply_document(
+        &mut self,
+        mut doc: serde_json::Value,
+        data: Option<&str>,
+        source: Source,
+    ) -> Result<(), Error> {
+        let aliases = match &mut doc {
+            serde_json::Value::Object(table) if !self.flags.contains_key(ALIASES_KEY) => {
+                table.remove(ALIASES_KEY)
+            }
+            _ => None,
+        };
+        let mut scratch = self.clone();
+        file::apply_with_lines(&mut scratch, doc, data, &source)?;
+        if let Some(aliases) = aliases {
+            scratch.apply_aliases(aliases)?;
+        }
+        *self = scratch;
+        Ok(())
+    }
+
+    /// Describes the flags as JSON, for IDEs and wrapper scripts, e.g. in
+    /// response to `--help --format=json`. Flags excluded from usage are left
+    /// out.
+    #[cfg(feature = "json")]
+    pub fn spec(&self) -> serde_json::Value {
+        let mut flags: Vec<&Flag> = self
+            .flags
+            .values()
+            .filter(|flag| !flag.exclude_from_usage)
+            .collect();
+        flags.sort_by(|a, b| a.name.cmp(&b.name));
+        let flags: Vec<serde_json::Value> = flags
+            .into_iter()
+            .map(|flag| {
+                serde_json::json!({
+                    "name": flag.name,
+                    "shorthand": flag.shorthand.map(String::from),
+                    "description": flag.description,
+                    "env": self.env_var(flag),
+                    "required": flag.required,
+                    "unit": flag.unit,
+                    "auto": flag.auto,
+                    "default": flag.default_value.to_json(),
+                })
+            })
+            .collect();
+        serde_json::json!({ "flags": flags })
+    }
+
+    /// Describes the flags, sorted by name, like the derive macro's
+    /// `flags_metadata`, e.g. for [`completions`]. Flags excluded from usage
+    /// are left out.
+    pub fn flags_metadata(&self) -> Vec<meta::FlagMeta> {
+        let mut flags: Vec<&Flag> = self
+            .flags
+            .values()
+            .filter(|flag| !flag.exclude_from_usage)
+            .collect();
+        flags.sort_by(|a, b| a.name.cmp(&b.name));
+        flags.into_iter().map(|flag| self.flag_meta(flag)).collect()
+    }
+
+    fn flag_meta(&self, flag: &Flag) -> meta::FlagMeta {
+        meta::FlagMeta {
+            name: flag.name.clone(),
+            shorthand: flag.shorthand,
+            description: flag.description.clone(),
+            env_var: Some(self.env_var(flag)),
+            required: flag.required,
+            value_hint: match flag.value {
+                FlagValue::Bool(_) => None,
+                _ => Some(meta::ValueHint::Any),
+            },
+            kind: match flag.value {
+                FlagValue::Bool(_) => meta::FlagKind::Bool,
+                FlagValue::String(_) | FlagValue::StringArray(_) => meta::FlagKind::String,
+                FlagValue::Int64(_)
+                | FlagValue::Int128(_)
+                | FlagValue::Int64Array(_)
+                | FlagValue::Int128Array(_) => meta::FlagKind::Integer,
+                FlagValue::Float64(_) | FlagValue::Float64Array(_) => meta::FlagKind::Float,
+                FlagValue::Duration(_) => meta::FlagKind::Other("Duration".into()),
+            },
+            is_array: flag.value.is_array(),
+            default: Some(flag.default_value.to_strings().join(",")),
+            unit: flag.unit.clone(),
+            secret: flag.secret,
+        }
+    }
+
+    /// Every flag, sorted by name, for the lints to check.
+    fn lint_states(&self) -> Vec<lint::FlagState> {
+        let mut flags: Vec<&Flag> = self.flags.values().collect();
+        flags.sort_by(|a, b| a.name.cmp(&b.name));
+        flags
+            .into_iter()
+            .map(|flag| lint::FlagState {
+                meta: self.flag_meta(flag),
+                source: Some(flag.source.clone()),
+                values: Some(flag.value.to_strings()),
+                deprecated_uses: flag
+                    .deprecated_uses
+                    .iter()
+                    .map(|source| {
+                        let message = flag.deprecated.clone().unwrap_or_default();
+                        (message, source.clone())
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Describes this run's configuration so it can be replayed later with
+    /// [`Flags::replay_manifest`], e.g. alongside a crash report or benchmark
+    /// result: the program's `version`, every flag's value and where it came
+    /// from, and with the `audit` feature, the inputs that were read. Secret
+    /// values are left out.
+    #[cfg(feature = "json")]
+    pub fn manifest(&self, version: &str) -> serde_json::Value {
+        let mut config = serde_json::Map::new();
+        let mut sources = serde_json::Map::new();
+        let mut flags: Vec<&Flag> = self.flags.values().collect();
+        flags.sort_by(|a, b| a.name.cmp(&b.name));
+        for flag in flags {
+            if !flag.secret {
+                config.insert(flag.name.clone(), flag.value.to_json());
+            }
+            sources.insert(flag.name.clone(), flag.source.to_string().into());
+        }
+        if !self.aliases.is_empty() && !self.flags.contains_key(ALIASES_KEY) {
+            let aliases: std::collections::BTreeMap<_, _> = self.aliases.iter().collect();
+            config.insert(ALIASES_KEY.into(), serde_json::json!(aliases));
+        }
+        let mut manifest = serde_json::Map::new();
+        manifest.insert("version".into(), version.into());
+        manifest.insert("config".into(), config.into());
+        manifest.insert("sources".into(), sources.into());
+        #[cfg(feature = "audit")]
+        if let Some(audit) = &self.audit {
+            let inputs: Vec<String> = audit
+                .entries
+                .iter()
+                .map(|entry| entry.input.to_string())
+                .collect();
+            manifest.insert("inputs".into(), inputs.into());
+        }
+        manifest.into()
+    }
+
+    /// Writes [`Flags::manifest`] to `path` as JSON.
+    #[cfg(feature = "json")]
+    pub fn write_manifest(&self, path: &Path, version: &str) -> Result<(), Error> {
+        let manifest = serde_json::to_string_pretty(&self.manifest(version))
+            .map_err(|e| Error::Format(e.to_string()))?;
+        std::fs::write(path, manifest + "\n").map_err(|e| Error::Io(path.into(), e))
+    }
+
+    /// Restores the configuration from a manifest written by
+    /// [`Flags::write_manifest`], as the only source: every flag is reset to
+    /// its default first, and nothing else should be parsed afterwards.
+    /// Returns warnings if the manifest was written by a `version` other than
+    /// this one or names flags that no longer exist. See [`manifest::replay`].
+    #[cfg(feature = "json")]
+    pub fn replay_manifest(
+        &mut self,
+        path: &Path,
+        version: &str,
+    ) -> Result<Vec<lint::Warning>, Error> {
+        let mut manifest = manifest::read(path)?;
+        for flag in self.flags.values_mut() {
+            flag.value = flag.default_value.clone();
+            flag.source = Source::Default;
+            flag.deprecated_uses.clear();
+        }
+        self.aliases.clear();
+        if let Some(config) = manifest["config"].as_object_mut() {
+            if !self.flags.contains_key(ALIASES_KEY) {
+                if let Some(aliases) = config.remove(ALIASES_KEY) {
+                    self.apply_aliases(aliases)?;
+                }
+            }
+        }
+        manifest::apply(self, manifest, path, version)
+    }
+
+    /// Reads an `aliases` table, whose values are either command lines to
+    /// split or arrays of arguments.
+    #[cfg(feature = "json")]
+    fn apply_aliases(&mut self, json: serde_json::Value) -> Result<(), Error> {
+        let serde_json::Value::Object(map) = json else {
+            return Err(Error::Format(format!("expected a table of {ALIASES_KEY}")));
+        };
+        for (name, expansion) in map {
+            let args = match &expansion {
+                serde_json::Value::String(line) => shell::split(line)?,
+                serde_json::Value::Array(items) => items
+                    .iter()
+                    .map(|item| item.as_str().map(String::from))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| Error::InvalidValue {
+                        flag: format!("{ALIASES_KEY}.{name}"),
+                        value: expansion.to_string(),
+                        source: None,
+                    })?,
+                _ => {
+                    return Err(Error::InvalidValue {
+                        flag: format!("{ALIASES_KEY}.{name}"),
+                        value: expansion.to_string(),
+                        source: None,
+                    })
+                }
+            };
+            self.aliases.insert(name, args);
+        }
+        Ok(())
+    }
+
+    /// Works out the defaults of flags with an auto default, which then
+    /// show up in usage and with [`Source::Auto`]. Flags that some source
+    /// already set are left alone.
+    pub fn resolve_auto(&mut self, resolvers: &auto::Resolvers) -> Result<(), Error> {
+        for flag in self.flags.values_mut() {
+            let Some(name) = &flag.auto else {
+                continue;
+            };
+            let value = resolvers.resolve(&format!("--{}", flag.name), name)?;
+            let mut default_value = flag.default_value.clone();
+            if !default_value.try_set_str(&value, false) {
+                return Err(Error::InvalidValue {
+                    flag: format!("--{}", flag.name),
+                    value,
+                    source: Some(Source::Auto(name.clone())),
+                });
+            }
+            flag.default_value = default_value.clone();
+            if matches!(flag.source, Source::Default | Source::Auto(_)) {
+                flag.value = default_value;
+                flag.source = Source::Auto(name.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies freshly loaded flags, except for immutable ones, which keep
+    /// their current values. Returns the immutable flags whose values
+    /// changed, which need a restart to apply. If the result doesn't
+    /// [validate](Flags::validate), nothing changes and the error says why.
+    pub fn reload(&mut self, mut new: Flags) -> Result<Vec<String>, Error> {
+        let mut candidate = self.clone();
+        let mut restart = vec![];
+        for flag in candidate.flags.values_mut() {
+            let Some(new) = new.flags.remove(&flag.name) else {
+                continue;
+            };
+            if !flag.immutable {
+                flag.value = new.value;
+                flag.source = new.source;
+                flag.deprecated_uses = new.deprecated_uses;
+            } else if flag.value != new.value {
+                restart.push(flag.name.clone());
+            }
+        }
+        candidate.validate()?;
+        *self = candidate;
+        restart.sort();
+        Ok(restart)
+    }
+
+    /// Marks the flags as final, e.g. once the program has started.
+    pub fn freeze(self) -> Frozen<Flags> {
+        Frozen::new(self)
+    }
+
+    /// Validates the flags, then runs the [built-in lints](lint::builtin)
+    /// and `lints`, returning what they found for the program to report.
+    pub fn check(&self, lints: &[&dyn lint::Lint]) -> Result<Vec<lint::Warning>, Error> {
+        self.validate()?;
+        Ok(lint::run(&self.lint_states(), lints))
+    }
+
+    /// Like [`Flags::check`], but hands each warning to `reporter`.
+    pub fn check_with(
+        &self,
+        lints: &[&dyn lint::Lint],
+        reporter: &mut impl report::Reporter,
+    ) -> Result<(), Error> {
+        for warning in self.check(lints)? {
+            reporter.warning(&warning.to_string());
+        }
+        Ok(())
+    }
+
+    /// The environment variable that sets `flag`, including any prefix.
+    fn env_var(&self, flag: &Flag) -> String {
+        match self.env_prefix.as_deref() {
+            Some(prefix) if !prefix.is_empty() && !prefix.ends_with('_') => {
+                format!("{prefix}_{}", flag.env_var())
+            }
+            prefix => format!("{}{}", prefix.unwrap_or(""), flag.env_var()),
+        }
+    }
+
+    /// Arguments that recreate the current values, e.g. to forward the
+    /// configuration to a child process. Only flags that differ from their
+    /// defaults are included, so parsing the arguments into freshly defined
+    /// flags gives the same values. The exception is an array emptied from a
+    /// non-empty default, which no argument can express.
+    pub fn to_args(&self) -> Vec<String> {
+        let mut flags: Vec<&Flag> = self
+            .flags
+            .values()
+            .filter(|flag| flag.value != flag.default_value)
+            .collect();
+        flags.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut args = vec![];
+        for flag in flags {
+            match flag.value {
+                FlagValue::Bool(true) => args.push(format!("--{}", flag.name)),
+                // Values always follow an `=`, so ones that start with `-`
+                // aren't mistaken for flags
+                _ => args.extend(
+                    flag.value
+                        .to_strings()
+                        .into_iter()
+                        .map(|value| format!("--{}={value}", flag.name)),
+                ),
+            }
+        }
+        args
+    }
+
+    /// Whether every flag still has its default, i.e. no file, variable or
+    /// argument set anything. Useful for pointing first-time users to setup
+    /// instructions instead of silently running with defaults.
+    pub fn is_pristine(&self) -> bool {
+        self.flags
+            .values()
+            .all(|flag| matches!(flag.source, Source::Default | Source::Auto(_)))
+    }
+
+    /// The effective configuration in `format`, sorted by flag name, with
+    /// secrets redacted and where each value came from.
+    pub fn dump(&self, format: dump::DumpFormat) -> String {
+        engine::dump(self, format)
+    }
+
+    /// Checks that every required flag was provided by some source and that
+    /// every validator accepts its flag's final value.
+    pub fn validate(&self) -> Result<(), Error> {
+        engine::validate(self)?;
+        for flag in self.flags.values() {
+            if let Some(validator) = flag.validator {
+                validator(&flag.value).map_err(|message| Error::Invalid {
+                    flag: format!("--{}", flag.name),
+                    message,
+                    source: flag.source.clone(),
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Dispatch for Flags {
+    type Key = String;
+
+    fn long(&self, name: &str) -> Option<(String, Arity)> {
+        let flag = self.flags.get(name)?;
+        let arity = match flag.value {
+            FlagValue::Bool(_) => Arity::Switch,
+            FlagValue::Int64(_) if flag.count => Arity::Switch,
+            _ => Arity::Value,
+        };
+        Some((flag.name.clone(), arity))
+    }
+
+    fn short(&self, c: char) -> Option<(String, Arity)> {
+        self.long(self.shorthands.get(&c)?)
+    }
+
+    fn env_vars(&self) -> Vec<EnvVar<String>> {
+        self.flags
+            .values()
+            .map(|flag| EnvVar {
+                key: flag.name.clone(),
+                var: self.env_var(flag),
+                delimiter: flag.delimiter.clone(),
+                is_array: flag.value.is_array(),
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "json")]
+    fn coerce(&self) -> bool {
+        self.coerce
+    }
+
+    fn completer(&self, key: &String) -> Option<complete::Completer> {
+        self.flags.get(key)?.completer
+    }
+
+    fn metadata(&self) -> Vec<meta::FlagMeta> {
+        self.flags_metadata()
+    }
+
+    fn sources(&self) -> Vec<(String, Source)> {
+        let mut sources: Vec<_> = self
+            .flags
+            .values()
+            .map(|flag| (flag.name.clone(), flag.source.clone()))
+            .collect();
+        sources.sort_by(|a, b| a.0.cmp(&b.0));
+        sources
+    }
+
+    fn flag_name(&self, name: &String) -> Option<String> {
+        Some(name.clone())
+    }
+
+    fn source(&self, name: &String) -> Option<Source> {
+        Some(self.flags.get(name)?.source.clone())
+    }
+
+    fn missing_required(&self) -> Vec<String> {
+        self.flags
+            .values()
+            .filter(|flag| flag.required && flag.source.is_default())
+            .map(|flag| flag.name.clone())
+            .collect()
+    }
+
+    fn declared_positionals(&self) -> Option<Vec<meta::Positional>> {
+        self.positionals.clone()
+    }
+
+    fn env_case_insensitive(&self) -> bool {
+        self.env_case_insensitive.unwrap_or(cfg!(windows))
+    }
+
+    #[cfg(feature = "audit")]
+    fn read_env(&mut self, name: &String, var: &str, value: &str) {
+        let secret = self.flags.get(name).is_some_and(|flag| flag.secret);
+        self.record(|| audit::Input::Env {
+            var: var.into(),
+            value: (!secret).then(|| value.into()),
+        });
+    }
+
+    fn unset_sentinel(&self) -> Option<&str> {
+        self.unset_sentinel.as_deref()
+    }
+
+    fn unset_flag(&self) -> Option<&str> {
+        self.unset_flag.as_deref()
+    }
+
+    fn set_flag(&self) -> Option<&str> {
+        self.set_flag.as_deref()
+    }
+
+    fn arg_syntax(&self) -> engine::ArgSyntax {
+        self.arg_syntax
+    }
+
+    fn print_config_flag(&self) -> Option<&str> {
+        self.print_config_flag.as_deref()
+    }
+
+    fn dump_entries(&self) -> Vec<dump::Entry> {
+        let mut names: Vec<&String> = self.flags.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| {
+                let flag = self.get(name);
+                dump::Entry {
+                    name: name.clone(),
+                    env_var: Some(self.env_var(flag)),
+                    values: flag.value.to_strings(),
+                    is_array: flag.value.is_array(),
+                    secret: flag.secret,
+                    source: Some(flag.source.clone()),
+                    unit: flag.unit.clone(),
+                }
+            })
+            .collect()
+    }
+
+    fn telemetry_entries(&self) -> Vec<dump::Entry> {
+        let mut entries = self.dump_entries();
+        entries.retain(|entry| self.get(&entry.name).telemetry);
+        entries
+    }
+
+    fn is_help_arg(&self, arg: &str) -> bool {
+        self.help_args.iter().any(|help| help == arg)
+    }
+
+    fn is_help_all_arg(&self, arg: &str) -> bool {
+        self.help_all_args.iter().any(|help| help == arg)
+    }
+
+    fn is_version_arg(&self, arg: &str) -> bool {
+        self.version_args.iter().any(|version| version == arg)
+    }
+
+    fn reset(&mut self, name: &String, source: &Source) -> Result<(), Error> {
+        let flag = self
+            .flags
+            .get_mut(name)
+            .expect("dispatched to unknown flag");
+        flag.value = flag.default_value.clone();
+        flag.set_source(source.clone());
+        Ok(())
+    }
+
+    fn clear_array(&mut self, name: &String, source: &Source) -> bool {
+        let flag = self
+            .flags
+            .get_mut(name)
+            .expect("dispatched to unknown flag");
+        match &mut flag.value {
+            FlagValue::StringArray(a) => a.clear(),
+            FlagValue::Int64Array(a) => a.clear(),
+            FlagValue::Int128Array(a) => a.clear(),
+            FlagValue::Float64Array(a) => a.clear(),
+            _ => return false,
+        }
+        flag.set_source(source.clone());
+        true
+    }
+
+    fn set_switch(&mut self, name: &String, source: &Source) -> Result<(), Error> {
+        let flag = self
+            .flags
+            .get_mut(name)
+            .expect("dispatched to unknown flag");
+        match &mut flag.value {
+            FlagValue::Int64(n) if flag.count => *n = n.saturating_add(1),
+            value => *value = FlagValue::Bool(true),
+        }
+        flag.set_source(source.clone());
+        Ok(())
+    }
+
+    fn set_value(
+        &mut self,
+        name: &String,
+        value: &str,
+        first: bool,
+        source: &Source,
+    ) -> Result<(), Error> {
+        let flag = self
+            .flags
+            .get_mut(name)
+            .expect("dispatched to unknown flag");
+        flag.set_str(value, !first, source.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn flags() -> Flags {
+        let mut flags = Flags::new();
+        flags.add(Flag::new("my-bool", FlagValue::Bool(false)).shorthand('b'));
+        flags.add(Flag::new("my-string", FlagValue::String("".into())).shorthand('s'));
+        flags.add(Flag::new("my-num", FlagValue::Int64(0)));
+        flags.add(
+            Flag::new("my-string-vec", FlagValue::StringArray(vec!["0".into()]))
+                .shorthand('v')
+                .delimiter(","),
+        );
+        flags
+    }
+
+    fn assertions(flags: &Flags) {
+        assert_eq!(flags.get("my-bool").value, FlagValue::Bool(true));
+        assert_eq!(flags.get("my-string").value, FlagValue::String("1".into()));
+        assert_eq!(flags.get("my-num").value, FlagValue::Int64(1));
+        assert_eq!(
+            flags.get("my-string-vec").value,
+            FlagValue::StringArray(vec!["1".into(), "2".into()])
+        );
+    }
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_env() {
+        let mut flags = flags();
+        let vars = HashMap::from([
+            ("MY_BOOL".into(), "true".into()),
+            ("MY_STRING".into(), "1".into()),
+            ("MY_NUM".into(), "1".into()),
+            ("MY_STRING_VEC".into(), "1,2".into()),
+        ]);
+        flags.parse_env(vars).unwrap();
+        assertions(&flags);
+        assert_eq!(flags.get("my-num").source, Source::Env("MY_NUM".into()));
+    }
+
+    #[test]
+    fn test_env_prefix() {
+        let mut flags = flags();
+        flags.env_prefix = Some("APP_".into());
+        let vars = HashMap::from([
+            ("MY_NUM".to_string(), "1".to_string()),
+            ("APP_MY_STRING".to_string(), "1".to_string()),
+        ]);
+        flags.parse_env(vars.clone()).unwrap();
+        assert_eq!(flags.get("my-num").value, FlagValue::Int64(0));
+        assert_eq!(flags.get("my-string").value, FlagValue::String("1".into()));
+
+        let mut flags = self::flags();
+        flags.env_prefix = Some("APP".into());
+        flags.parse_env(vars).unwrap();
+        assert_eq!(flags.get("my-string").value, FlagValue::String("1".into()));
+    }
+
+    #[test]
+    fn test_env_case_insensitive() {
+        let mut flags = flags();
+        flags.env_case_insensitive = Some(true);
+        let vars = HashMap::from([
+            ("My_String".into(), "folded".into()),
+            ("my_num".into(), "2".into()),
+            ("MY_NUM".into(), "1".into()),
+        ]);
+        flags.parse_env(vars.clone()).unwrap();
+        assert_eq!(
+            flags.get("my-string").value,
+            FlagValue::String("folded".into())
+        );
+        assert_eq!(flags.get("my-num").value, FlagValue::Int64(1));
+
+        // Without an exact match, the first name in sorted order wins
+        for _ in 0..8 {
+            let mut flags = self::flags();
+            flags.env_case_insensitive = Some(true);
+            flags
+                .parse_env(HashMap::from([
+                    ("my_String".into(), "lower".into()),
+                    ("MY_string".into(), "upper".into()),
+                    ("My_String".into(), "mixed".into()),
+                ]))
+                .unwrap();
+            assert_eq!(
+                flags.get("my-string").value,
+                FlagValue::String("upper".into())
+            );
+        }
+
+        let mut flags = self::flags();
+        flags.env_case_insensitive = Some(false);
+        flags.parse_env(vars).unwrap();
+        assert_eq!(flags.get("my-string").value, FlagValue::String("".into()));
+    }
+
+    #[test]
+    fn test_args() {
+        let mut flags = flags();
+        let args = strings(&[
+            "-b", "-s", "1", "pos", "--my-num", "1", "-v", "1", "-v", "2",
+        ]);
+        let positionals = flags.parse_args(args).unwrap();
+        assertions(&flags);
+        assert_eq!(positionals, ["pos"]);
+        assert_eq!(flags.get("my-num").source, Source::Arg(Some(6)));
+    }
+
+    #[test]
+    fn test_args_errors() {
+        let mut flags = flags();
+        let err = flags.parse_args(strings(&["--nope"])).unwrap_err();
+        assert!(matches!(err, Error::UnknownFlag(arg) if arg == "--nope"));
+        let err = flags.parse_args(strings(&["--my-num"])).unwrap_err();
+        assert!(matches!(err, Error::MissingValue(arg) if arg == "--my-num"));
+        let err = flags.parse_args(strings(&["--my-num", "x"])).unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { value, .. } if value == "x"));
+    }
+
+    #[test]
+    fn test_inline_values() {
+        let mut flags = flags();
+        flags.unset_flag = Some("unset".into());
+        flags.set_flag = Some("set".into());
+        let args = strings(&[
+            "--my-bool=true",
+            "-s=1",
+            "--my-num=1",
+            "-v=1",
+            "--my-string-vec=2",
+            "pos=x",
+        ]);
+        let positionals = flags.parse_args(args).unwrap();
+        assertions(&flags);
+        assert_eq!(positionals, ["pos=x"]);
+
+        let args = strings(&["--my-bool=false", "--set=my_num=2", "--unset=my-string"]);
+        flags.parse_args(args).unwrap();
+        assert_eq!(flags.get("my-bool").value, FlagValue::Bool(false));
+        assert_eq!(flags.get("my-num").value, FlagValue::Int64(2));
+        assert_eq!(flags.get("my-string").source, Source::Unset);
+
+        let args = strings(&["--my-string=a=b", "--my-num="]);
+        let err = flags.parse_args(args).unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { value, .. } if value.is_empty()));
+        assert_eq!(
+            flags.get("my-string").value,
+            FlagValue::String("a=b".into())
+        );
+        let err = flags.parse_args(strings(&["--nope=1"])).unwrap_err();
+        assert!(matches!(err, Error::UnknownFlag(arg) if arg == "--nope"));
+    }
+
+    #[test]
+    fn test_short_clusters() {
+        let mut flags = flags();
+        flags.add(Flag::new("quiet", FlagValue::Bool(false)).shorthand('q'));
+        let positionals = flags
+            .parse_args(strings(&["-bqs", "1", "--my-num=1", "-bv1", "-v=2", "pos"]))
+            .unwrap();
+        assertions(&flags);
+        assert_eq!(flags.get("quiet").value, FlagValue::Bool(true));
+        assert_eq!(positionals, ["pos"]);
+
+        let err = flags.parse_args(strings(&["-bx"])).unwrap_err();
+        assert!(matches!(err, Error::UnknownFlag(arg) if arg == "-x"));
+        let err = flags.parse_args(strings(&["-qs"])).unwrap_err();
+        assert!(matches!(err, Error::MissingValue(arg) if arg == "-s"));
+    }
+
+    #[test]
+    fn test_arg_syntax() {
+        let args = || strings(&["-", "", "--my-num", "1", "--", "-b", "--"]);
+        let mut flags = flags();
+        let positionals = flags.parse_args(args()).unwrap();
+        assert_eq!(positionals, ["-", "", "-b", "--"]);
+
+        let mut flags = self::flags();
+        flags.arg_syntax = engine::ArgSyntax {
+            dash: engine::SpecialArg::End,
+            double_dash: engine::SpecialArg::Positional,
+            empty: engine::SpecialArg::Skip,
+        };
+        let positionals = flags.parse_args(args()).unwrap();
+        assert_eq!(positionals, ["", "--my-num", "1", "--", "-b", "--"]);
+        assert_eq!(flags.get("my-num").value, FlagValue::Int64(0));
+
+        flags.arg_syntax.dash = engine::SpecialArg::Skip;
+        let positionals = flags.parse_args(args()).unwrap();
+        assert_eq!(positionals, ["--", "--"]);
+        assert_eq!(flags.get("my-bool").value, FlagValue::Bool(true));
+
+        flags.arg_syntax.double_dash = engine::SpecialArg::KeepAndEnd;
+        let positionals = flags.parse_args(args()).unwrap();
+        assert_eq!(positionals, ["--", "-b", "--"]);
+    }
+
+    #[test]
+    fn test_apply_arg() {
+        let mut flags = flags();
+        flags.unset_flag = Some("unset".into());
+        flags.set_flag = Some("set".into());
+        flags.apply_arg("my-bool", None).unwrap();
+        flags.apply_arg("my-string", Some("1")).unwrap();
+        flags.apply_arg("set", Some("my_num=1")).unwrap();
+        flags.apply_arg("my-string-vec", Some("1")).unwrap();
+        flags.apply_arg("my-string-vec", Some("2")).unwrap();
+        assert_eq!(
+            flags.get("my-string-vec").value,
+            FlagValue::StringArray(vec!["2".into()])
+        );
+        assert!(flags.apply_env_var("MY_STRING_VEC", "1,2").unwrap());
+        assertions(&flags);
+
+        flags.apply_arg("unset", Some("my-num")).unwrap();
+        assert_eq!(flags.get("my-num").source, Source::Unset);
+        assert!(!flags.apply_env_var("OTHER", "1").unwrap());
+
+        let err = flags.apply_arg("my-num", None).unwrap_err();
+        assert!(matches!(err, Error::MissingValue(arg) if arg == "--my-num"));
+        let err = flags.apply_arg("nope", Some("1")).unwrap_err();
+        assert!(matches!(err, Error::UnknownFlag(arg) if arg == "--nope"));
+        let err = flags.apply_env_var("MY_NUM", "x").unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { flag, .. } if flag == "MY_NUM"));
+    }
+
+    #[test]
+    fn test_help_args() {
+        let mut flags = flags();
+        flags.help_args = strings(&["--help", "-h"]);
+        flags.version_args = strings(&["--version"]);
+
+        let err = flags
+            .parse_args(strings(&["--my-num", "1", "-h", "--nope"]))
+            .unwrap_err();
+        assert!(matches!(err, Error::HelpRequested));
+        assert!(err.is_request());
+        flags.help_all_args = strings(&["--help-all"]);
+        let err = flags.parse_args(strings(&["--help-all"])).unwrap_err();
+        assert!(matches!(err, Error::HelpAllRequested));
+        assert!(err.is_request());
+        let err = flags.parse_args(strings(&["--version"])).unwrap_err();
+        assert!(matches!(err, Error::VersionRequested));
+        let rest = flags
+            .parse_args(strings(&["--my-string", "--help", "--", "-h"]))
+            .unwrap();
+        assert_eq!(rest, ["-h"]);
+    }
+
+    #[test]
+    fn test_unset() {
+        let mut flags = flags();
+        flags.unset_sentinel = Some("@unset".into());
+        flags.unset_flag = Some("unset".into());
+        let vars = HashMap::from([
+            ("MY_BOOL".into(), "true".into()),
+            ("MY_STRING".into(), "env".into()),
+            ("MY_STRING_VEC".into(), "1,2".into()),
+        ]);
+        flags.parse_env(vars).unwrap();
+
+        let args = strings(&[
+            "--unset", "my-bool", "-s", "@unset", "-v", "@unset", "-v", "3",
+        ]);
+        flags.parse_args(args).unwrap();
+
+        assert_eq!(flags.get("my-bool").value, FlagValue::Bool(false));
+        assert_eq!(flags.get("my-bool").source, Source::Unset);
+        assert_eq!(flags.get("my-string").value, FlagValue::String("".into()));
+        assert_eq!(flags.get("my-string").source, Source::Unset);
+        assert_eq!(
+            flags.get("my-string-vec").value,
+            FlagValue::StringArray(vec!["3".into()])
+        );
+    }
+
+    #[test]
+    fn test_set_flag() {
+        let mut flags = flags();
+        flags.set_flag = Some("set".into());
+        let args = strings(&[
+            "--set",
+            "my_bool=true",
+            "--set",
+            "my-string=a=b",
+            "--set",
+            "my_num=1",
+            "--set",
+            "my_string_vec=1",
+            "-v",
+            "2",
+        ]);
+        flags.parse_args(args).unwrap();
+        assert_eq!(flags.get("my-bool").value, FlagValue::Bool(true));
+        assert_eq!(
+            flags.get("my-string").value,
+            FlagValue::String("a=b".into())
+        );
+        assert_eq!(flags.get("my-num").value, FlagValue::Int64(1));
+        assert_eq!(
+            flags.get("my-string-vec").value,
+            FlagValue::StringArray(vec!["1".into(), "2".into()])
+        );
+
+        let err = flags.parse_args(strings(&["--set", "nope=1"])).unwrap_err();
+        assert!(matches!(err, Error::UnknownKey(key) if key == "nope"));
+        let err = flags.parse_args(strings(&["--set", "my_num"])).unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { flag, .. } if flag == "--set"));
+    }
+
+    #[test]
+    fn test_count() {
+        let mut flags = flags();
+        flags.add(
+            Flag::new("verbose", FlagValue::Int64(0))
+                .shorthand('V')
+                .count(),
+        );
+        flags
+            .parse_env(HashMap::from([("VERBOSE".into(), "1".into())]))
+            .unwrap();
+        let rest = flags
+            .parse_args(strings(&["-VV", "--verbose", "pos", "-bV"]))
+            .unwrap();
+        assert_eq!(rest, ["pos"]);
+        assert_eq!(flags.get("verbose").value, FlagValue::Int64(5));
+        assert_eq!(flags.get("my-bool").value, FlagValue::Bool(true));
+
+        flags.parse_args(strings(&["--verbose=0"])).unwrap();
+        assert_eq!(flags.get("verbose").value, FlagValue::Int64(0));
+    }
+
+    #[test]
+    fn test_integer_conversions() {
+        let mut flags = Flags::new();
+        flags.add(Flag::new("port", 8080u16.into()));
+        flags.add(Flag::new("size", u64::MAX.into()));
+        flags.add(Flag::new("ids", vec![1u8, 2].into()));
+        assert_eq!(flags.get("port").value, FlagValue::Int64(8080));
+        assert_eq!(flags.get("size").value, FlagValue::Int128(u64::MAX.into()));
+        assert_eq!(u64::try_from(&flags.get("size").value), Ok(u64::MAX));
+
+        flags
+            .parse_args(strings(&["--port", "443", "--ids", "3", "--ids", "255"]))
+            .unwrap();
+        assert_eq!(u16::try_from(&flags.get("port").value), Ok(443));
+        assert_eq!(
+            Vec::<u8>::try_from(&flags.get("ids").value),
+            Ok(vec![3, 255])
+        );
+
+        flags.parse_args(strings(&["--port", "70000"])).unwrap();
+        assert_eq!(
+            u16::try_from(&flags.get("port").value),
+            Err(TypeMismatch {
+                expected: "a u16".into(),
+                found: "70000".into(),
+            })
+        );
+        assert_eq!(
+            i32::try_from(&FlagValue::Bool(true)).unwrap_err().found,
+            "a boolean"
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_sanitized_snapshot() {
+        let mut flags = flags();
+        flags.add(Flag::new("jobs", FlagValue::Int64(4)).telemetry());
+        flags.add(Flag::new("ratios", FlagValue::Float64Array(vec![0.5])).telemetry());
+        flags.parse_args(strings(&["--my-num=2"])).unwrap();
+        assert_eq!(
+            flags.sanitized_snapshot(),
+            serde_json::json!({ "jobs": 4, "ratios": [0.5] })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "can't be included in telemetry")]
+    fn test_telemetry_string() {
+        let mut flags = Flags::new();
+        flags.add(Flag::new("user", FlagValue::String("".into())).telemetry());
+    }
+
+    #[test]
+    fn test_print_config() {
+        let mut flags = flags();
+        flags.add(Flag::new("token", FlagValue::String("".into())).secret());
+        flags.print_config_flag = Some("print-config".into());
+        flags
+            .parse_env(HashMap::from([("TOKEN".into(), "hunter2".into())]))
+            .unwrap();
+
+        // Arguments after the flag still count
+        let err = flags
+            .parse_args(strings(&["--print-config=env", "--my-num", "3"]))
+            .unwrap_err();
+        assert!(err.is_request());
+        let Error::PrintConfigRequested(out) = err else {
+            panic!("{err}");
+        };
+        assert_eq!(
+            out,
+            "# from the default\nMY_BOOL=false\n\
+             # from argument 3 on the command line\nMY_NUM=3\n\
+             # from the default\nMY_STRING=''\n\
+             # from the default\nMY_STRING_VEC=0\n\
+             # from $TOKEN\nTOKEN='<redacted>'\n"
+        );
+        let err = flags.parse_args(strings(&["--print-config"])).unwrap_err();
+        assert!(matches!(err, Error::PrintConfigRequested(out) if out.contains("my-num = \"3\"")));
+        let err = flags
+            .parse_args(strings(&["--print-config=xml"]))
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { flag, .. } if flag == "--print-config"));
+        assert!(flags
+            .dump(dump::DumpFormat::Toml)
+            .ends_with("# from $TOKEN\ntoken = \"<redacted>\"\n"));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json() {
+        let mut flags = flags();
+        let data = r#"
+            {
+                "my-bool": true,
+                "my-string": "1",
+                "my-num": 1,
+                "my-string-vec": ["1", "2"]
+            }
+        "#;
+        flags.parse_str(data, file::Format::Json).unwrap();
+        assertions(&flags);
+        assert_eq!(flags.get("my-num").source, Source::Inline(Some(5)));
+
+        // Keys and values are read like derived structs read them
+        flags
+            .parse_str(
+                r#"{ "my": { "num": "2" }, "my_string": 3, "my-string-vec": ["1", 2] }"#,
+                file::Format::Json,
+            )
+            .unwrap();
+        assert_eq!(flags.get("my-num").value, FlagValue::Int64(2));
+        assert_eq!(flags.get("my-string").value, FlagValue::String("3".into()));
+        assert_eq!(
+            flags.get("my-string-vec").value,
+            FlagValue::StringArray(vec!["1".into(), "2".into()])
+        );
+
+        // Nothing is applied from a document with a bad key or value
+        let err = flags
+            .parse_str(r#"{ "my-num": 3, "my-bool": "yes" }"#, file::Format::Json)
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { flag, .. } if flag == "my-bool"));
+        let err = flags
+            .parse_str("{\n  \"my-bool\": \"yes\"\n}", file::Format::Json)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid value for my-bool: \"\\\"yes\\\"\" (from line 2 of an inline config)"
+        );
+        let err = flags
+            .parse_str(
+                r#"{ "my-num": 3, "db": { "host": "h" } }"#,
+                file::Format::Json,
+            )
+            .unwrap_err();
+        assert!(matches!(err, Error::UnknownKey(key) if key == "db.host"));
+        assert_eq!(flags.get("my-num").value, FlagValue::Int64(2));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_large_integers() {
+        let mut value = FlagValue::Int128(0);
+        value.try_set_json(&serde_json::json!(u64::MAX)).unwrap();
+        assert_eq!(value, FlagValue::Int128(u64::MAX.into()));
+        let max = FlagValue::Int128(i128::MAX);
+        value.try_set_json(&max.to_json()).unwrap();
+        assert_eq!(value, max);
+
+        let mut value = FlagValue::Int64Array(vec![]);
+        let err = value
+            .try_set_json(&serde_json::json!([1, u64::MAX]))
+            .unwrap_err();
+        assert_eq!(
+            err.found,
+            "a list containing 18446744073709551615, which overflows a 64-bit integer"
+        );
+        let err = value.try_set_json(&serde_json::json!([1.5])).unwrap_err();
+        assert_eq!(err.found, "a list containing a number");
+    }
+
+    #[cfg(feature = "arbitrary_precision")]
+    #[test]
+    fn test_json_arbitrary_precision() {
+        let mut value = FlagValue::Int128(0);
+        let json = serde_json::from_str(&i128::MIN.to_string()).unwrap();
+        value.try_set_json(&json).unwrap();
+        assert_eq!(value, FlagValue::Int128(i128::MIN));
+
+        let json = serde_json::from_str(&format!("{}0", i128::MAX)).unwrap();
+        let err = value.try_set_json(&json).unwrap_err();
+        assert_eq!(
+            err.found,
+            "1701411834604692317316873037158841057270, which overflows a 128-bit integer"
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_coerce() {
+        let mut value = FlagValue::Bool(false);
+        value.try_coerce_json(&serde_json::json!(1)).unwrap();
+        assert_eq!(value, FlagValue::Bool(true));
+        value.try_coerce_json(&serde_json::json!("false")).unwrap();
+        assert_eq!(value, FlagValue::Bool(false));
+        assert!(value.try_coerce_json(&serde_json::json!(2)).is_err());
+        let mut value = FlagValue::Float64Array(vec![]);
+        value
+            .try_coerce_json(&serde_json::json!(["0.5", 2]))
+            .unwrap();
+        assert_eq!(value, FlagValue::Float64Array(vec![0.5, 2.0]));
+        let mut value = FlagValue::Int128Array(vec![]);
+        value
+            .try_coerce_json(&serde_json::json!([
+                " 170141183460469231731687303715884105727 ",
+                "-9223372036854775809",
+                2
+            ]))
+            .unwrap();
+        assert_eq!(
+            value,
+            FlagValue::Int128Array(vec![i128::MAX, i64::MIN as i128 - 1, 2])
+        );
+
+        let mut flags = flags();
+        let data = r#"{ "my-bool": 1, "my-string": 1, "my-num": "1", "my-string-vec": [1, "2"] }"#;
+        assert!(flags.parse_str(data, file::Format::Json).is_err());
+        flags.coerce = true;
+        flags.parse_str(data, file::Format::Json).unwrap();
+        assertions(&flags);
+
+        let err = flags
+            .parse_str(r#"{ "my-num": "one" }"#, file::Format::Json)
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { flag, .. } if flag == "my-num"));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_bytes() {
+        let mut flags = flags();
+        let data = b"\xef\xbb\xbf{ \"my-bool\": true, \"my-string\": \"1\", \"my-num\": 1, \"my-string-vec\": [\"1\", \"2\"] }";
+        flags.parse_bytes(data, file::Format::Json).unwrap();
+        assertions(&flags);
+
+        let err = flags
+            .parse_bytes(b"{ \"my-string\": \"\xff\" }", file::Format::Json)
+            .unwrap_err();
+        assert!(matches!(err, Error::Format(message) if message.starts_with("invalid UTF-8")));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_spec() {
+        let mut flags = Flags::new();
+        flags.add(
+            Flag::new("name", FlagValue::String("john".into()))
+                .shorthand('n')
+                .description("The person we want to greet"),
+        );
+        flags.add(Flag::new("big", FlagValue::Int128(i128::MAX)).required());
+        flags.add(Flag::new("hidden", FlagValue::Bool(false)).exclude_from_usage());
+
+        assert_eq!(
+            flags.spec(),
+            serde_json::json!({
+                "flags": [
+                    {
+                        "name": "big",
+                        "shorthand": null,
+                        "description": null,
+                        "env": "BIG",
+                        "required": true,
+                        "unit": null,
+                        "auto": null,
+                        "default": i128::MAX.to_string(),
+                    },
+                    {
+                        "name": "name",
+                        "shorthand": "n",
+                        "description": "The person we want to greet",
+                        "env": "NAME",
+                        "required": false,
+                        "unit": null,
+                        "auto": null,
+                        "default": "john",
+                    },
+                ]
+            })
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_files() {
+        let dir = std::env::temp_dir().join(format!("cliconf-test-files-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let system = dir.join("system.json");
+        let user = dir.join("user.json");
+        std::fs::write(&system, r#"{ "my-string": "system", "my-num": 1 }"#).unwrap();
+        std::fs::write(&user, r#"{ "my-string": "user" }"#).unwrap();
+
+        let mut flags = flags();
+        flags.parse_files(&[&system, &user]).unwrap();
+        assert_eq!(
+            flags.get("my-string").value,
+            FlagValue::String("user".into())
+        );
+        assert_eq!(
+            flags.get("my-string").source,
+            Source::File(user.clone(), Some(1))
+        );
+        assert_eq!(
+            flags.get("my-num").source,
+            Source::File(system.clone(), Some(1))
+        );
+
+        let mut limited = self::flags();
+        limited.file_limits.max_size = Some(10);
+        let err = limited.parse_file(&system).unwrap_err();
+        assert!(matches!(err, Error::Limit(..)));
+        limited.file_limits.max_size = Some(100);
+        limited.parse_file(&system).unwrap();
+
+        let missing = dir.join("missing.json");
+        let err = flags.parse_files(&[&system, &missing]).unwrap_err();
+        assert!(matches!(err, Error::Io(..)));
+
+        let mut searched = self::flags();
+        assert!(searched.is_pristine());
+        searched.search_files(&[&missing]).unwrap();
+        assert!(searched.is_pristine());
+        searched.search_files(&[&user, &missing]).unwrap();
+        assert!(!searched.is_pristine());
+        assert_eq!(searched.missing_files, [missing.clone(), missing]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_manifest() {
+        let dir =
+            std::env::temp_dir().join(format!("cliconf-test-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("manifest.json");
+
+        let mut flags = flags();
+        flags.add(Flag::new("token", FlagValue::String("".into())).secret());
+        flags.aliases.insert("b".into(), strings(&["-b"]));
+        flags
+            .parse_env(HashMap::from([("MY_NUM".into(), "7".into())]))
+            .unwrap();
+        flags
+            .parse_args(strings(&["-s", "x", "--token", "hunter2"]))
+            .unwrap();
+        let manifest = flags.manifest("1.2.3");
+        assert_eq!(manifest["config"]["my-num"], 7);
+        assert_eq!(
+            manifest["config"]["aliases"]["b"],
+            serde_json::json!(["-b"])
+        );
+        assert_eq!(manifest["config"].get("token"), None);
+        assert_eq!(manifest["sources"]["my-num"], "$MY_NUM");
+        assert_eq!(manifest["sources"]["my-bool"], "the default");
+        flags.write_manifest(&path, "1.2.3").unwrap();
+
+        let mut replayed = self::flags();
+        replayed.add(Flag::new("token", FlagValue::String("".into())).secret());
+        replayed.parse_args(strings(&["-b"])).unwrap();
+        assert_eq!(replayed.replay_manifest(&path, "1.2.3").unwrap(), []);
+        assert_eq!(replayed.get("my-bool").value, FlagValue::Bool(false));
+        assert_eq!(replayed.get("my-num").value, FlagValue::Int64(7));
+        assert_eq!(
+            replayed.get("my-string").source,
+            Source::File(path.clone(), None)
+        );
+        assert_eq!(replayed.get("token").value, FlagValue::String("".into()));
+        assert_eq!(replayed.aliases, flags.aliases);
+
+        let mut changed = Flags::new();
+        changed.add(Flag::new("my-num", FlagValue::Int128(0)));
+        let warnings = changed.replay_manifest(&path, "1.3.0").unwrap();
+        assert_eq!(changed.get("my-num").value, FlagValue::Int128(7));
+        let warnings: Vec<String> = warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            warnings,
+            [
+                "the manifest was written by version 1.2.3, not 1.3.0; flags may have changed since [manifest]",
+                "--my-bool: is in the manifest but is no longer a flag [manifest]",
+                "--my-string: is in the manifest but is no longer a flag [manifest]",
+                "--my-string-vec: is in the manifest but is no longer a flag [manifest]",
+            ]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_aliases() {
+        let mut flags = flags();
+        flags
+            .parse_str(
+                r#"{
+                    "my-num": 2,
+                    "aliases": {
+                        "quiet": ["--my-bool", "--my-string", "a b"],
+                        "q": "quiet -v '1'",
+                        "loop": "loop2",
+                        "loop2": "loop"
+                    }
+                }"#,
+                file::Format::Json,
+            )
+            .unwrap();
+        let rest = flags.parse_args(strings(&["q", "-v", "2", "x"])).unwrap();
+        assert_eq!(rest, ["x"]);
+        assert_eq!(flags.get("my-bool").value, FlagValue::Bool(true));
+        assert_eq!(
+            flags.get("my-string").value,
+            FlagValue::String("a b".into())
+        );
+        assert_eq!(
+            flags.get("my-string-vec").value,
+            FlagValue::StringArray(vec!["1".into(), "2".into()])
+        );
+
+        let err = flags.parse_args(strings(&["loop"])).unwrap_err();
+        assert!(matches!(err, Error::AliasLoop(name) if name == "loop"));
+        let err = flags
+            .parse_str(r#"{ "aliases": { "x": 1 } }"#, file::Format::Json)
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { flag, .. } if flag == "aliases.x"));
+    }
+
+    #[test]
+    fn test_reload() {
+        let mut flags = Flags::new();
+        flags.add(Flag::new("listen", FlagValue::String("".into())).immutable());
+        flags.add(Flag::new("log-level", FlagValue::String("".into())));
+        flags
+            .parse_args(strings(&["--listen", ":80", "--log-level", "info"]))
+            .unwrap();
+
+        let mut new = Flags::new();
+        new.add(Flag::new("listen", FlagValue::String("".into())).immutable());
+        new.add(Flag::new("log-level", FlagValue::String("".into())));
+        new.parse_args(strings(&["--listen", ":8080", "--log-level", "debug"]))
+            .unwrap();
+
+        assert_eq!(flags.reload(new).unwrap(), ["listen"]);
+        assert_eq!(flags.get("listen").value, FlagValue::String(":80".into()));
+        assert_eq!(
+            flags.get("log-level").value,
+            FlagValue::String("debug".into())
+        );
+
+        // A reload that doesn't validate changes nothing
+        let mut flags = Flags::new();
+        flags.add(
+            Flag::new("log-level", FlagValue::String("".into())).validator(|value| match value {
+                FlagValue::String(level) if level == "loud" => Err("unknown level".into()),
+                _ => Ok(()),
+            }),
+        );
+        flags.add(Flag::new("workers", FlagValue::Int64(1)));
+        let mut new = Flags::new();
+        new.add(Flag::new("log-level", FlagValue::String("".into())));
+        new.add(Flag::new("workers", FlagValue::Int64(1)));
+        new.parse_args(strings(&["--log-level", "loud", "--workers", "8"]))
+            .unwrap();
+        let err = flags.reload(new).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid value for --log-level: unknown level (from argument 2 on the command line)"
+        );
+        assert_eq!(flags.get("workers").value, FlagValue::Int64(1));
+    }
+
+    #[test]
+    fn test_unit() {
+        let mut flags = Flags::new();
+        flags.add(Flag::new("timeout", FlagValue::Int64(0)).unit("ms"));
+        flags.add(Flag::new("ratio", FlagValue::Float64(0.0)).unit("s"));
+
+        flags
+            .parse_args(strings(&["--timeout", "2s", "--ratio", "1500ms"]))
+            .unwrap();
+        assert_eq!(flags.get("timeout").value, FlagValue::Int64(2000));
+        assert_eq!(flags.get("ratio").value, FlagValue::Float64(1.5));
+
+        let err = flags
+            .parse_args(strings(&["--timeout", "1.5ms"]))
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { value, .. } if value == "1.5ms"));
+        let err = flags
+            .parse_args(strings(&["--timeout", "2GB"]))
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { .. }));
+    }
+
+    #[test]
+    fn test_addresses() {
+        let mut flags = Flags::new();
+        let listen: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        flags.add(Flag::new("listen", listen.into()));
+        flags.add(Flag::new("dns", Vec::<IpAddr>::new().into()));
+        assert_eq!(SocketAddr::try_from(&flags.get("listen").value), Ok(listen));
+
+        flags
+            .parse_args(strings(&[
+                "--listen",
+                "[::1]:443",
+                "--dns",
+                "1.1.1.1",
+                "--dns",
+                "2606:4700::1111",
+            ]))
+            .unwrap();
+        assert_eq!(
+            SocketAddrV6::try_from(&flags.get("listen").value),
+            Ok("[::1]:443".parse().unwrap())
+        );
+        assert_eq!(
+            Vec::<IpAddr>::try_from(&flags.get("dns").value),
+            Ok(vec![
+                "1.1.1.1".parse().unwrap(),
+                "2606:4700::1111".parse().unwrap()
+            ])
+        );
+        assert_eq!(
+            Vec::<Ipv4Addr>::try_from(&flags.get("dns").value),
+            Err(TypeMismatch {
+                expected: "an IPv4 address".into(),
+                found: "\"2606:4700::1111\"".into(),
+            })
+        );
+
+        flags
+            .parse_args(strings(&["--listen", "localhost:80"]))
+            .unwrap();
+        assert_eq!(
+            SocketAddr::try_from(&flags.get("listen").value),
+            Err(TypeMismatch {
+                expected: "an address and port".into(),
+                found: "\"localhost:80\"".into(),
+            })
+        );
+        assert_eq!(
+            Ipv6Addr::try_from(&FlagValue::Int64(1)).unwrap_err().found,
+            "an integer"
+        );
+    }
+
+    #[test]
+    fn test_duration() {
+        let mut flags = Flags::new();
+        flags.add(Flag::new("timeout", Duration::from_secs(30).into()).unit("s"));
+        assert_eq!(
+            Duration::try_from(&flags.get("timeout").value),
+            Ok(Duration::from_secs(30))
+        );
+
+        flags.parse_args(strings(&["--timeout", "1h30m"])).unwrap();
+        assert_eq!(
+            Duration::try_from(&flags.get("timeout").value),
+            Ok(Duration::from_secs(5400))
+        );
+        flags.parse_args(strings(&["--timeout", "0.5"])).unwrap();
+        assert_eq!(
+            Duration::try_from(&flags.get("timeout").value),
+            Ok(Duration::from_millis(500))
+        );
+        assert_eq!(
+            Duration::try_from(&FlagValue::Int64(-1)).unwrap_err().found,
+            "-1"
+        );
+
+        // Without a unit, plain numbers are seconds
+        flags.add(Flag::new("t", Duration::from_secs(30).into()));
+        flags.add(Flag::new("poll", Duration::ZERO.into()).unit("ms"));
+        flags
+            .parse_args(strings(&["--t", "30s", "--poll", "250"]))
+            .unwrap();
+        assert_eq!(
+            flags.get("t").value,
+            FlagValue::Duration(Duration::from_secs(30))
+        );
+        assert_eq!(
+            flags.get("poll").value,
+            FlagValue::Duration(Duration::from_millis(250))
+        );
+        flags
+            .parse_args(strings(&["--t", "5m", "--poll", "1s"]))
+            .unwrap();
+        assert_eq!(
+            flags.get("t").value,
+            FlagValue::Duration(Duration::from_secs(300))
+        );
+        assert_eq!(
+            flags.get("poll").value,
+            FlagValue::Duration(Duration::from_secs(1))
+        );
+        flags.parse_args(strings(&["--t", "90"])).unwrap();
+        assert_eq!(
+            flags.get("t").value,
+            FlagValue::Duration(Duration::from_secs(90))
+        );
+        assert_eq!(flags.get("t").value.to_strings(), ["1m30s"]);
+        let err = flags.parse_args(strings(&["--t", "-5s"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid value for --t: \"-5s\" (from argument 2 on the command line)"
+        );
+    }
+
+    #[test]
+    fn test_expand_path() {
+        let home = std::env::var("HOME").unwrap();
+        let mut flags = Flags::new();
+        flags.add(Flag::new("dir", PathBuf::from("/tmp").into()).expand_path());
+        flags.add(Flag::new("raw", FlagValue::String("".into())));
+
+        flags
+            .parse_args(strings(&["--dir", "~/cache", "--raw", "~/cache"]))
+            .unwrap();
+        assert_eq!(
+            PathBuf::try_from(&flags.get("dir").value),
+            Ok(PathBuf::from(format!("{home}/cache")))
+        );
+        assert_eq!(flags.get("raw").value, FlagValue::String("~/cache".into()));
+
+        let err = flags
+            .parse_args(strings(&["--dir", "$CLICONF_TEST_UNSET/cache"]))
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidValue { value, .. } if value == "$CLICONF_TEST_UNSET/cache")
+        );
+
+        #[cfg(feature = "json")]
+        {
+            flags
+                .parse_str(r#"{ "dir": "${HOME}/data" }"#, file::Format::Json)
+                .unwrap();
+            assert_eq!(
+                flags.get("dir").value,
+                FlagValue::String(format!("{home}/data"))
+            );
+            let err = flags
+                .parse_str(r#"{ "dir": "$CLICONF_TEST_UNSET" }"#, file::Format::Json)
+                .unwrap_err();
+            assert_eq!(err.code(), "CLICONF_E_INVALID_VALUE");
+            assert_eq!(
+                err.to_string(),
+                file::invalid_value(
+                    "dir",
+                    &serde_json::json!("$CLICONF_TEST_UNSET"),
+                    &Source::Inline(Some(1))
+                )
+                .to_string()
+            );
+            assert!(
+                matches!(err, Error::InvalidValue { flag, value, .. } if flag == "dir" && value == r#""$CLICONF_TEST_UNSET""#)
+            );
+        }
+    }
+
+    #[test]
+    fn test_resolve_auto() {
+        let mut flags = Flags::new();
+        flags.add(Flag::new("workers", FlagValue::Int64(0)).auto("two"));
+        flags.add(Flag::new("port", FlagValue::Int64(0)).auto("two"));
+        let mut resolvers = auto::Resolvers::empty();
+        resolvers.register("two", || Ok("2".into()));
+
+        flags.parse_args(strings(&["--port", "80"])).unwrap();
+        flags.resolve_auto(&resolvers).unwrap();
+        assert_eq!(flags.get("workers").value, FlagValue::Int64(2));
+        assert_eq!(flags.get("workers").source, Source::Auto("two".into()));
+        assert_eq!(flags.get("port").value, FlagValue::Int64(80));
+        assert_eq!(flags.get("port").default_value, FlagValue::Int64(2));
+        assert!(!flags.is_pristine());
+
+        resolvers.register("two", || Ok("two".into()));
+        let err = flags.resolve_auto(&resolvers).unwrap_err();
+        assert!(matches!(err, Error::InvalidValue { value, .. } if value == "two"));
+    }
+
+    fn round_trip_flags() -> Flags {
+        let mut flags = flags();
+        flags.add(Flag::new("my-big-num", FlagValue::Int128(0)));
+        flags.add(Flag::new("my-float", FlagValue::Float64(0.5)));
+        flags.add(Flag::new("my-nums", FlagValue::Int64Array(vec![])));
+        flags
+    }
+
+    proptest! {
+        #[test]
+        fn test_to_args_round_trip(
+            b: bool,
+            s: String,
+            n: i64,
+            big: i128,
+            x in any::<f64>().prop_filter("NaN never equals itself", |x| !x.is_nan()),
+            // An array can't be emptied from a non-empty default
+            v in prop::collection::vec(any::<String>(), 1..4),
+            nums: Vec<i64>,
+        ) {
+            let mut flags = round_trip_flags();
+            for (name, value) in [
+                ("my-bool", FlagValue::Bool(b)),
+                ("my-string", FlagValue::String(s)),
+                ("my-num", FlagValue::Int64(n)),
+                ("my-big-num", FlagValue::Int128(big)),
+                ("my-float", FlagValue::Float64(x)),
+                ("my-string-vec", FlagValue::StringArray(v)),
+                ("my-nums", FlagValue::Int64Array(nums)),
+            ] {
+                flags.flags.get_mut(name).unwrap().value = value;
+            }
+
+            let mut parsed = round_trip_flags();
+            let positionals = parsed.parse_args(flags.to_args()).unwrap();
+            prop_assert!(positionals.is_empty());
+            for flag in flags.flags.values() {
+                prop_assert_eq!(&parsed.get(&flag.name).value, &flag.value);
+            }
+
+            #[cfg(feature = "json")]
+            {
+                let mut replayed = round_trip_flags();
+                let manifest = flags.manifest("1");
+                manifest::apply(&mut replayed, manifest, Path::new("m.json"), "1").unwrap();
+                for flag in flags.flags.values() {
+                    prop_assert_eq!(&replayed.get(&flag.name).value, &flag.value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut flags = Flags::new();
+        flags.add(Flag::new("name", FlagValue::String("".into())).required());
+        flags.add(
+            Flag::new("port", FlagValue::Int64(0)).validator(|v| match v {
+                FlagValue::Int64(1..=65535) => Ok(()),
+                _ => Err("must be between 1 and 65535".into()),
+            }),
+        );
+        assert!(
+            matches!(flags.validate(), Err(Error::MissingRequired(names)) if names == ["name"])
+        );
+        flags.parse_args(strings(&["--name", "john"])).unwrap();
+        assert!(matches!(
+            flags.validate(),
+            Err(Error::Invalid {
+                source: Source::Default,
+                ..
+            })
+        ));
+        flags
+            .parse_env(HashMap::from([("PORT".into(), "0".into())]))
+            .unwrap();
+        assert_eq!(
+            flags.validate().unwrap_err().to_string(),
+            "Invalid value for --port: must be between 1 and 65535 (from $PORT)"
+        );
+        flags.parse_args(strings(&["--port", "80"])).unwrap();
+        flags.validate().unwrap();
+    }
+
+    #[test]
+    fn test_flags_metadata() {
+        let mut flags = Flags::new();
+        flags.env_prefix = Some("APP".into());
+        flags.add(Flag::new("ports", FlagValue::Int64Array(vec![80, 443])).required());
+        flags.add(Flag::new("verbose", FlagValue::Bool(false)).shorthand('v'));
+        flags.add(Flag::new("secret", FlagValue::Bool(false)).exclude_from_usage());
+        assert_eq!(
+            flags.flags_metadata(),
+            [
+                meta::FlagMeta {
+                    name: "ports".into(),
+                    shorthand: None,
+                    description: None,
+                    env_var: Some("APP_PORTS".into()),
+                    required: true,
+                    value_hint: Some(meta::ValueHint::Any),
+                    kind: meta::FlagKind::Integer,
+                    is_array: true,
+                    default: Some("80,443".into()),
+                    unit: None,
+                    secret: false,
+                },
+                meta::FlagMeta {
+                    name: "verbose".into(),
+                    shorthand: Some('v'),
+                    description: None,
+                    env_var: Some("APP_VERBOSE".into()),
+                    required: false,
+                    value_hint: None,
+                    kind: meta::FlagKind::Bool,
+                    is_array: false,
+                    default: Some("false".into()),
+                    unit: None,
+                    secret: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_positionals() {
+        let mut flags = Flags::new();
+        flags.add(Flag::new("force", FlagValue::Bool(false)));
+        flags.help_args = strings(&["--help"]);
+        flags.positionals = Some(vec![
+            meta::Positional::new("SRC"),
+            meta::Positional::new("DEST"),
+        ]);
+        assert_eq!(
+            flags.parse_args(strings(&["a", "b"])).unwrap(),
+            strings(&["a", "b"])
+        );
+        let err = flags.parse_args(strings(&["--force", "a"])).unwrap_err();
+        assert!(matches!(&err, Error::MissingPositional(name) if name == "DEST"));
+        assert_eq!(err.to_string(), "Missing argument: <DEST>");
+        let err = flags.parse_args(strings(&["a", "b", "c"])).unwrap_err();
+        assert_eq!(err.to_string(), "Unexpected argument: c");
+        assert!(matches!(
+            flags.parse_args(strings(&["--help"])),
+            Err(Error::HelpRequested)
+        ));
+
+        flags.positionals = Some(vec![
+            meta::Positional::new("SRC"),
+            meta::Positional::new("DEST").optional().variadic(),
+        ]);
+        flags.parse_args(strings(&["a"])).unwrap();
+        flags.parse_args(strings(&["a", "b", "c"])).unwrap();
+        let err = flags.parse_args(vec![]).unwrap_err();
+        assert!(matches!(err, Error::MissingPositional(name) if name == "SRC"));
+    }
 }
@@ -0,0 +1,327 @@
+//! Checks for configurations that are valid but probably not what the user
+//! wants, e.g. a password passed on the command line where any other user
+//! can see it in `ps`.
+
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    engine::Dispatch,
+    meta::{FlagKind, FlagMeta},
+    Source,
+};
+
+/// Something a [`Lint`] found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// The name of the lint that found it.
+    pub lint: &'static str,
+    pub flag: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.flag {
+            Some(flag) => write!(f, "--{flag}: {} [{}]", self.message, self.lint),
+            None => write!(f, "{} [{}]", self.message, self.lint),
+        }
+    }
+}
+
+/// What a [`Lint`] sees of a flag, so that the same lints check
+/// [`crate::Flags`] and derived structs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlagState {
+    pub meta: FlagMeta,
+    /// Where the value came from, or `None` if that isn't recorded, e.g. for
+    /// a derived struct without a `sources` field.
+    pub source: Option<Source>,
+    /// The value as it would be written on the command line, one item per
+    /// value for arrays, or `None` if it isn't known, e.g. for a derived
+    /// struct that can't dump its configuration.
+    pub values: Option<Vec<String>>,
+    /// Each source that set the flag while it's deprecated, in order, with
+    /// why it's deprecated.
+    pub deprecated_uses: Vec<(String, Source)>,
+}
+
+pub trait Lint {
+    fn name(&self) -> &'static str;
+
+    /// Checks the flags, which are sorted by name.
+    fn check(&self, flags: &[FlagState]) -> Vec<Warning>;
+}
+
+/// The lints that [`crate::Flags::check`] and derived structs' `check`
+/// always run. [`Conflicts`] isn't one of them, since only the program knows
+/// which of its flags conflict.
+pub fn builtin() -> Vec<Box<dyn Lint>> {
+    vec![
+        Box::new(Deprecated),
+        Box::new(SecretInArgs),
+        Box::new(DefaultSecret),
+    ]
+}
+
+/// Runs the [built-in lints](builtin) and `lints` over `flags`.
+pub fn run(flags: &[FlagState], lints: &[&dyn Lint]) -> Vec<Warning> {
+    let builtin = builtin();
+    let builtin = builtin.iter().map(|lint| lint.as_ref());
+    builtin
+        .chain(lints.iter().copied())
+        .flat_map(|lint| lint.check(flags))
+        .collect()
+}
+
+/// Each of `d`'s flags, sorted by name, from its [metadata], sources,
+/// [dumped values] and deprecated uses.
+///
+/// [metadata]: Dispatch::metadata
+/// [dumped values]: Dispatch::dump_entries
+pub fn states<D: Dispatch>(d: &D) -> Vec<FlagState> {
+    let mut sources: HashMap<String, Source> = d.sources().into_iter().collect();
+    let mut values: HashMap<String, Vec<String>> = d
+        .dump_entries()
+        .into_iter()
+        .map(|entry| (entry.name, entry.values))
+        .collect();
+    let mut deprecated_uses: HashMap<String, Vec<(String, Source)>> = HashMap::new();
+    for (flag, message, source) in d.deprecated_uses() {
+        deprecated_uses
+            .entry(flag)
+            .or_default()
+            .push((message.into(), source));
+    }
+    let mut states: Vec<FlagState> = d
+        .metadata()
+        .into_iter()
+        .map(|meta| FlagState {
+            source: sources.remove(&meta.name),
+            values: values.remove(&meta.name),
+            deprecated_uses: deprecated_uses.remove(&meta.name).unwrap_or_default(),
+            meta,
+        })
+        .collect();
+    states.sort_by(|a, b| a.meta.name.cmp(&b.meta.name));
+    states
+}
+
+/// Flags that are going away but were set anyway, with a warning for each
+/// source that set them. Flags set outside the command line name where they
+/// were set, since whoever has to migrate may never see the arguments.
+pub struct Deprecated;
+
+impl Lint for Deprecated {
+    fn name(&self) -> &'static str {
+        "deprecated"
+    }
+
+    fn check(&self, flags: &[FlagState]) -> Vec<Warning> {
+        flags
+            .iter()
+            .flat_map(|flag| {
+                flag.deprecated_uses
+                    .iter()
+                    .map(|(message, source)| deprecation(&flag.meta.name, message, source))
+            })
+            .collect()
+    }
+}
+
+/// The [`Deprecated`] warning for `source` setting `flag`.
+#[doc(hidden)]
+pub fn deprecation(flag: &str, message: &str, source: &Source) -> Warning {
+    let message = match source {
+        Source::Env(var) => format!("{message} (set by ${var})"),
+        Source::File(..) => format!("{message} (set by `{flag}` in {source})"),
+        Source::Inline(None) => format!("{message} (set by `{flag}` in config)"),
+        Source::Inline(Some(line)) => {
+            format!("{message} (set by `{flag}` on line {line} of config)")
+        }
+        Source::Provider(provider) => format!("{message} (set by `{flag}` from {provider})"),
+        Source::Setting(setting) => format!("{message} (set by desktop setting {setting})"),
+        _ => message.into(),
+    };
+    Warning {
+        lint: Deprecated.name(),
+        flag: Some(flag.into()),
+        message,
+    }
+}
+
+/// Secrets given as arguments, which other users can read from the process
+/// list.
+pub struct SecretInArgs;
+
+impl Lint for SecretInArgs {
+    fn name(&self) -> &'static str {
+        "secret-in-args"
+    }
+
+    fn check(&self, flags: &[FlagState]) -> Vec<Warning> {
+        flags
+            .iter()
+            .filter(|flag| flag.meta.secret && matches!(flag.source, Some(Source::Arg(_))))
+            .map(|flag| Warning {
+                lint: self.name(),
+                flag: Some(flag.meta.name.clone()),
+                message: match &flag.meta.env_var {
+                    Some(var) => format!(
+                        "secrets on the command line are visible to other users; use ${var} or a config file instead"
+                    ),
+                    None => "secrets on the command line are visible to other users; use a config file instead".into(),
+                },
+            })
+            .collect()
+    }
+}
+
+/// Secrets set to a well-known default like `admin` or `changeme`.
+pub struct DefaultSecret;
+
+const DEFAULT_SECRETS: &[&str] = &[
+    "admin", "changeme", "default", "letmein", "password", "root", "secret", "123456",
+];
+
+impl Lint for DefaultSecret {
+    fn name(&self) -> &'static str {
+        "default-secret"
+    }
+
+    fn check(&self, flags: &[FlagState]) -> Vec<Warning> {
+        flags
+            .iter()
+            .filter(|flag| {
+                flag.meta.secret && flag.meta.kind == FlagKind::String && !flag.meta.is_array
+            })
+            .filter(|flag| match flag.values.as_deref() {
+                Some([value]) => DEFAULT_SECRETS.contains(&value.to_lowercase().as_str()),
+                _ => false,
+            })
+            .map(|flag| Warning {
+                lint: self.name(),
+                flag: Some(flag.meta.name.clone()),
+                message: "the secret is a well-known default".into(),
+            })
+            .collect()
+    }
+}
+
+/// Pairs of flags that don't make sense together, e.g. `--quiet` and
+/// `--verbose`. Flags whose sources aren't recorded never conflict.
+pub struct Conflicts(pub Vec<(String, String)>);
+
+impl Lint for Conflicts {
+    fn name(&self) -> &'static str {
+        "conflicts"
+    }
+
+    fn check(&self, flags: &[FlagState]) -> Vec<Warning> {
+        let is_set = |name: &str| {
+            flags.iter().any(|flag| {
+                flag.meta.name == name
+                    && flag
+                        .source
+                        .as_ref()
+                        .is_some_and(|source| !source.is_default())
+            })
+        };
+        self.0
+            .iter()
+            .filter(|(a, b)| is_set(a) && is_set(b))
+            .map(|(a, b)| Warning {
+                lint: self.name(),
+                flag: None,
+                message: format!("--{a} and --{b} conflict"),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{Error, Flag, FlagValue, Flags};
+
+    use super::*;
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_check() {
+        let mut flags = Flags::new();
+        flags.add(Flag::new("old", FlagValue::Bool(false)).deprecated("use --new instead"));
+        flags.add(Flag::new("password", FlagValue::String("".into())).secret());
+        flags.add(Flag::new("quiet", FlagValue::Bool(false)));
+        flags.add(Flag::new("verbose", FlagValue::Bool(false)));
+        let conflicts = Conflicts(vec![("quiet".into(), "verbose".into())]);
+
+        assert_eq!(flags.check(&[&conflicts]).unwrap(), []);
+
+        flags
+            .parse_args(strings(&[
+                "--old",
+                "--password",
+                "Admin",
+                "--quiet",
+                "--verbose",
+            ]))
+            .unwrap();
+        let warnings: Vec<String> = flags
+            .check(&[&conflicts])
+            .unwrap()
+            .iter()
+            .map(|warning| warning.to_string())
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                "--old: use --new instead [deprecated]",
+                "--password: secrets on the command line are visible to other users; use $PASSWORD or a config file instead [secret-in-args]",
+                "--password: the secret is a well-known default [default-secret]",
+                "--quiet and --verbose conflict [conflicts]",
+            ]
+        );
+
+        let mut flags = Flags::new();
+        flags.env_prefix = Some("APP_".into());
+        flags.add(Flag::new("old", FlagValue::Bool(false)).deprecated("use --new instead"));
+        flags
+            .parse_env(HashMap::from([("APP_OLD".into(), "true".into())]))
+            .unwrap();
+        let warnings = flags.check(&[]).unwrap();
+        assert_eq!(warnings[0].message, "use --new instead (set by $APP_OLD)");
+        #[cfg(feature = "json")]
+        {
+            flags
+                .parse_str(r#"{ "old": true }"#, crate::Format::Json)
+                .unwrap();
+            let warnings = flags.check(&[]).unwrap();
+            assert_eq!(
+                warnings[1].message,
+                "use --new instead (set by `old` on line 1 of config)"
+            );
+        }
+        // Every source is named, even once another overrides it
+        flags
+            .parse_args(strings(&["--old", "--old=false"]))
+            .unwrap();
+        let warnings: Vec<String> = flags
+            .check(&[])
+            .unwrap()
+            .into_iter()
+            .map(|warning| warning.message)
+            .collect();
+        assert_eq!(
+            warnings.first().unwrap(),
+            "use --new instead (set by $APP_OLD)"
+        );
+        assert_eq!(warnings.last().unwrap(), "use --new instead");
+
+        flags.add(Flag::new("name", FlagValue::String("".into())).required());
+        assert!(matches!(flags.check(&[]), Err(Error::MissingRequired(_))));
+    }
+}
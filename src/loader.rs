@@ -0,0 +1,430 @@
+//! Loading every source in one fixed order, so that programs don't each
+//! reimplement it and get the precedence subtly wrong.
+
+use std::{collections::HashMap, env, fmt, io::ErrorKind, path::PathBuf, sync::Arc, thread};
+
+use crate::{
+    engine::{self, Dispatch},
+    file::{self, Format, FormatRegistry, Limits},
+    provider::{self, Policy, Provider},
+    Error, Source,
+};
+
+/// Applies sources in this order, each overriding the ones before it:
+///
+/// 1. built-in defaults: whatever the struct held before loading, then the
+///    [defaults document](Loader::defaults), if there is one
+/// 2. system config files, e.g. `/etc/<app>/config.toml`
+/// 3. user config files, e.g. `~/.config/<app>/config.toml`
+/// 4. remote [providers](Loader::provider)
+/// 5. environment variables
+/// 6. command-line arguments
+///
+/// Config files that don't exist are skipped. Files and providers are all
+/// read at once, so startup waits on the slowest of them rather than their
+/// sum, but within each kind they're applied in the order they were added.
+#[derive(Clone, Default)]
+pub struct Loader {
+    defaults: Option<(String, Format)>,
+    /// The directories of [`Loader::for_app`], holding a `config` file with
+    /// any extension in `formats`.
+    system_app_dir: Option<PathBuf>,
+    user_app_dir: Option<PathBuf>,
+    system_files: Vec<PathBuf>,
+    user_files: Vec<PathBuf>,
+    providers: Vec<(Arc<dyn Provider>, Policy)>,
+    env: Option<HashMap<String, String>>,
+    args: Option<Vec<String>>,
+    limits: Limits,
+    formats: FormatRegistry,
+}
+
+impl Loader {
+    /// A loader that reads the process's environment and arguments, and no
+    /// config files.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A loader that also reads `app`'s config files from where each OS
+    /// keeps them: [`system_dir`] and [`user_dir`], each holding a `config`
+    /// file with any extension in [`Loader::formats`].
+    pub fn for_app(app: &str) -> Self {
+        Self {
+            system_app_dir: system_dir().map(|dir| dir.join(app)),
+            user_app_dir: user_dir().map(|dir| dir.join(app)),
+            ..Self::default()
+        }
+    }
+
+    /// A document to apply first, e.g. defaults compiled in with
+    /// `include_str!`.
+    pub fn defaults(mut self, data: &str, format: Format) -> Self {
+        self.defaults = Some((data.into(), format));
+        self
+    }
+
+    pub fn system_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.system_files.push(path.into());
+        self
+    }
+
+    pub fn user_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.user_files.push(path.into());
+        self
+    }
+
+    /// Fetches a document from `provider`, retrying and falling back as
+    /// `policy` says.
+    pub fn provider(mut self, provider: Arc<dyn Provider>, policy: Policy) -> Self {
+        self.providers.push((provider, policy));
+        self
+    }
+
+    /// Reads these variables instead of the process's environment.
+    pub fn env(mut self, vars: HashMap<String, String>) -> Self {
+        self.env = Some(vars);
+        self
+    }
+
+    /// Parses these arguments instead of the process's, which don't include
+    /// the program's name.
+    pub fn args(mut self, args: Vec<String>) -> Self {
+        self.args = Some(args);
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// The config file formats, by extension, instead of the built-in ones.
+    pub fn formats(mut self, formats: FormatRegistry) -> Self {
+        self.formats = formats;
+        self
+    }
+
+    /// Every config file the loader looks for, in the order they're applied.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files = vec![];
+        for (app_dir, added) in [
+            (&self.system_app_dir, &self.system_files),
+            (&self.user_app_dir, &self.user_files),
+        ] {
+            if let Some(dir) = app_dir {
+                files.extend(
+                    self.formats
+                        .extensions()
+                        .map(|ext| dir.join(format!("config.{ext}"))),
+                );
+            }
+            files.extend(added.iter().cloned());
+        }
+        files
+    }
+
+    /// Applies every source to `d`, returning the positional arguments.
+    pub fn load<D: Dispatch>(&self, d: &mut D) -> Result<Vec<String>, Error> {
+        if let Some((data, format)) = &self.defaults {
+            file::parse_str(d, data, *format)?;
+        }
+        let files = self.files();
+        let (documents, fetched) = thread::scope(|s| {
+            let handles: Vec<_> = self
+                .providers
+                .iter()
+                .map(|(provider, policy)| s.spawn(move || provider::fetch(provider, policy)))
+                .collect();
+            let documents = file::read_documents(&files, &self.limits, &self.formats);
+            let fetched: Vec<_> = handles
+                .into_iter()
+                .map(|handle| handle.join().expect("Config provider panicked"))
+                .collect();
+            (documents, fetched)
+        });
+        for (path, document) in files.into_iter().zip(documents) {
+            let (doc, data) = match document {
+                Err(Error::Io(_, err)) if err.kind() == ErrorKind::NotFound => continue,
+                result => result?,
+            };
+            file::apply_with_lines(d, doc, Some(&data), &Source::File(path, None))?;
+        }
+        for ((provider, _), doc) in self.providers.iter().zip(fetched) {
+            if let Some(doc) = doc? {
+                let source = Source::Provider(provider.name().into());
+                file::apply_with_lines(d, doc, None, &source)?;
+            }
+        }
+        match &self.env {
+            Some(vars) => engine::parse_env(d, vars.clone())?,
+            None => engine::parse_process_env(d)?,
+        }
+        let args = match &self.args {
+            Some(args) => args.clone(),
+            None => env::args().skip(1).collect(),
+        };
+        engine::parse_args(d, args)
+    }
+}
+
+impl fmt::Debug for Loader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let providers: Vec<_> = self
+            .providers
+            .iter()
+            .map(|(provider, policy)| (provider.name(), policy))
+            .collect();
+        f.debug_struct("Loader")
+            .field("defaults", &self.defaults)
+            .field("system_app_dir", &self.system_app_dir)
+            .field("user_app_dir", &self.user_app_dir)
+            .field("system_files", &self.system_files)
+            .field("user_files", &self.user_files)
+            .field("providers", &providers)
+            .field("env", &self.env)
+            .field("args", &self.args)
+            .field("limits", &self.limits)
+            .field("formats", &self.formats)
+            .finish()
+    }
+}
+
+/// Where system-wide config lives: `%ProgramData%` on Windows, and `/etc`
+/// everywhere else.
+pub fn system_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("ProgramData").map(PathBuf::from)
+    } else {
+        Some(PathBuf::from("/etc"))
+    }
+}
+
+/// Where a user's config lives, following each OS's convention, e.g.
+/// `$XDG_CONFIG_HOME` on Linux.
+pub fn user_dir() -> Option<PathBuf> {
+    let var = |name| env::var_os(name).filter(|value| !value.is_empty());
+    if cfg!(windows) {
+        var("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        Some(PathBuf::from(var("HOME")?).join("Library/Application Support"))
+    } else {
+        var("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| Some(PathBuf::from(var("HOME")?).join(".config")))
+    }
+}
+
+/// Generates `load` and `load_with` for the derive macro, which can't tell
+/// whether the `json` feature is enabled itself.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __load {
+    ($vis:vis $load:ident, $load_with:ident, $about:ident, [$($embedded:ident)?]) => {
+        /// Loads the struct from the program's config files, the
+        /// environment and the arguments, over its current values, in the
+        /// order [`cliconf::Loader`] documents. Returns the positional
+        /// arguments.
+        $vis fn $load(
+            &mut self,
+        ) -> ::std::result::Result<::std::vec::Vec<::std::string::String>, $crate::Error> {
+            self.$load_with(&$crate::Loader::for_app(&Self::$about().name))
+        }
+
+        /// Like `load`, but with the sources `loader` names.
+        $vis fn $load_with(
+            &mut self,
+            loader: &$crate::Loader,
+        ) -> ::std::result::Result<::std::vec::Vec<::std::string::String>, $crate::Error> {
+            $(self.$embedded()?;)?
+            loader.load(self)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{Flag, FlagValue, Flags, Source};
+
+    use super::*;
+
+    #[test]
+    fn test_precedence() {
+        let dir = env::temp_dir().join(format!("cliconf-test-loader-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let system = dir.join("system.json");
+        let user = dir.join("user.json");
+        fs::write(
+            &system,
+            r#"{ "a": "system", "b": "system", "c": "system" }"#,
+        )
+        .unwrap();
+        fs::write(&user, r#"{ "b": "user", "c": "user" }"#).unwrap();
+
+        let mut flags = Flags::new();
+        for name in ["a", "b", "c", "d", "e"] {
+            flags.add(Flag::new(name, FlagValue::String("".into())));
+        }
+        let loader = Loader::new()
+            .defaults(r#"{ "a": "defaults", "e": "defaults" }"#, Format::Json)
+            .user_file(&user)
+            .system_file(&system)
+            .system_file(dir.join("missing.json"))
+            .env(HashMap::from([
+                ("C".into(), "env".into()),
+                ("D".into(), "env".into()),
+            ]))
+            .args(vec!["--d=args".into(), "pos".into()]);
+        let positionals = loader.load(&mut flags).unwrap();
+        assert_eq!(positionals, ["pos"]);
+
+        let value = |name| match &flags.get(name).value {
+            FlagValue::String(s) => s.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(value("a"), "system");
+        assert_eq!(value("b"), "user");
+        assert_eq!(value("c"), "env");
+        assert_eq!(value("d"), "args");
+        assert_eq!(value("e"), "defaults");
+        assert_eq!(flags.get("b").source, Source::File(user.clone(), Some(1)));
+
+        fs::write(&user, "{").unwrap();
+        let err = loader.load(&mut flags).unwrap_err();
+        assert!(matches!(err, Error::Format(_)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Returns `doc` after waiting `delay`.
+    struct Slow {
+        name: &'static str,
+        delay: std::time::Duration,
+        doc: serde_json::Value,
+    }
+
+    impl Provider for Slow {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn fetch(&self) -> Result<serde_json::Value, Error> {
+            std::thread::sleep(self.delay);
+            Ok(self.doc.clone())
+        }
+    }
+
+    #[test]
+    fn test_providers() {
+        let dir = env::temp_dir().join(format!("cliconf-test-providers-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("user.json");
+        fs::write(&user, r#"{ "a": "file", "b": "file", "c": "file" }"#).unwrap();
+
+        let mut flags = Flags::new();
+        for name in ["a", "b", "c"] {
+            flags.add(Flag::new(name, FlagValue::String("".into())));
+        }
+        let delay = std::time::Duration::from_millis(300);
+        let slow = |name, doc| Arc::new(Slow { name, delay, doc }) as Arc<dyn Provider>;
+        let loader = Loader::new()
+            .user_file(&user)
+            .provider(
+                slow("first", serde_json::json!({ "b": "first", "c": "first" })),
+                Policy::default(),
+            )
+            .provider(
+                slow("second", serde_json::json!({ "c": "second" })),
+                Policy::default(),
+            )
+            .env(HashMap::new())
+            .args(vec![]);
+        let start = std::time::Instant::now();
+        loader.load(&mut flags).unwrap();
+        // Both providers were fetched at once
+        assert!(start.elapsed() < 2 * delay);
+
+        assert_eq!(flags.get("a").value, FlagValue::String("file".into()));
+        assert_eq!(flags.get("b").value, FlagValue::String("first".into()));
+        assert_eq!(flags.get("c").value, FlagValue::String("second".into()));
+        assert_eq!(flags.get("c").source, Source::Provider("second".into()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A format for `key=value` lines.
+    fn parse_kv(data: &str) -> Result<serde_json::Value, String> {
+        let mut table = serde_json::Map::new();
+        for line in data.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, found {line:?}"))?;
+            table.insert(key.trim().into(), value.trim().into());
+        }
+        Ok(table.into())
+    }
+
+    #[test]
+    fn test_formats() {
+        let dir = env::temp_dir().join(format!("cliconf-test-formats-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let kv = dir.join("config.kv");
+        fs::write(&kv, "a = kv\nb = kv\n").unwrap();
+        let json = dir.join("config.json");
+        fs::write(&json, r#"{ "b": "json" }"#).unwrap();
+
+        let mut flags = Flags::new();
+        flags.add(Flag::new("a", FlagValue::String("".into())));
+        flags.add(Flag::new("b", FlagValue::String("".into())));
+        let loader = Loader::new()
+            .system_file(&kv)
+            .user_file(&json)
+            .env(HashMap::new())
+            .args(vec![]);
+        let err = loader.load(&mut flags).unwrap_err();
+        assert!(matches!(err, Error::Format(message) if message.contains("unsupported")));
+
+        let mut formats = FormatRegistry::default();
+        formats.register("kv", parse_kv);
+        loader.formats(formats.clone()).load(&mut flags).unwrap();
+        assert_eq!(flags.get("a").value, FlagValue::String("kv".into()));
+        assert_eq!(flags.get("b").value, FlagValue::String("json".into()));
+        assert_eq!(flags.get("a").source, Source::File(kv.clone(), Some(1)));
+
+        fs::write(&kv, "nope").unwrap();
+        let mut flags = Flags::new();
+        flags.formats = formats;
+        let err = flags.parse_file(&kv).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to parse config: expected key=value, found \"nope\""
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_for_app() {
+        let loader = Loader::for_app("my-app");
+        let files = loader.files();
+        let extensions = FormatRegistry::default().extensions().count();
+        assert_eq!(files.len(), 2 * extensions);
+        assert_eq!(
+            files.first(),
+            system_dir()
+                .map(|dir| dir.join("my-app/config.json"))
+                .as_ref()
+        );
+
+        let mut formats = FormatRegistry::empty();
+        formats.register("conf", |_| Ok(serde_json::json!({})));
+        let files = Loader::for_app("my-app")
+            .user_file("extra.conf")
+            .formats(formats)
+            .files();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files.last(), Some(&PathBuf::from("extra.conf")));
+    }
+}
@@ -0,0 +1,85 @@
+//! Replaying run manifests written by [`Flags::write_manifest`], so a crash
+//! report or benchmark result can be re-run with exactly the same
+//! configuration.
+
+use std::path::Path;
+
+#[cfg(doc)]
+use crate::Flags;
+use crate::{engine::Dispatch, file, lint::Warning, Error, Source};
+
+/// Applies the values in the manifest at `path` as if they came from a config
+/// file. Unlike a config file, keys that no longer name a flag and a manifest
+/// written by another `version` of the program are warnings rather than
+/// errors, since the manifest may predate changes to the flags.
+pub fn replay<D: Dispatch>(d: &mut D, path: &Path, version: &str) -> Result<Vec<Warning>, Error> {
+    let manifest = read(path)?;
+    apply(d, manifest, path, version)
+}
+
+pub(crate) fn read(path: &Path) -> Result<serde_json::Value, Error> {
+    let data = std::fs::read_to_string(path).map_err(|e| Error::Io(path.into(), e))?;
+    serde_json::from_str(&data).map_err(|e| Error::Format(e.to_string()))
+}
+
+pub(crate) fn apply<D: Dispatch>(
+    d: &mut D,
+    mut manifest: serde_json::Value,
+    path: &Path,
+    version: &str,
+) -> Result<Vec<Warning>, Error> {
+    let warning = |flag: Option<&str>, message: String| Warning {
+        lint: "manifest",
+        flag: flag.map(String::from),
+        message,
+    };
+    let mut warnings = vec![];
+    match manifest["version"].as_str() {
+        Some(written_by) if written_by != version => warnings.push(warning(
+            None,
+            format!("the manifest was written by version {written_by}, not {version}; flags may have changed since"),
+        )),
+        Some(_) => {}
+        None => return Err(Error::Format("manifest has no version".into())),
+    }
+    let serde_json::Value::Object(config) = manifest["config"].take() else {
+        return Err(Error::Format("manifest has no config".into()));
+    };
+
+    let source = Source::File(path.into(), None);
+    for (name, json) in config {
+        let Some((key, _)) = d.long(&name) else {
+            warnings.push(warning(
+                Some(&name),
+                "is in the manifest but is no longer a flag".into(),
+            ));
+            continue;
+        };
+        // Values go through the same parsing as any other source, so they
+        // stay valid as long as the flag's type does
+        if !json.is_null() {
+            file::apply_value(d, &key, &name, &json, &source)?;
+        }
+    }
+    Ok(warnings)
+}
+
+/// Generates `replay_manifest` for the derive macro, which can't tell
+/// whether the `json` feature is enabled itself.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __replay_manifest {
+    ($vis:vis $name:ident, $about:ident) => {
+        /// Restores the configuration from a manifest written by
+        /// `Flags::write_manifest`, on top of the current values. Returns
+        /// warnings if the manifest was written by another version of the
+        /// program or names fields that no longer exist.
+        $vis fn $name(
+            &mut self,
+            path: &::std::path::Path,
+        ) -> ::std::result::Result<::std::vec::Vec<$crate::lint::Warning>, $crate::Error> {
+            let __cliconf_version = Self::$about().version;
+            $crate::manifest::replay(self, path, &__cliconf_version)
+        }
+    };
+}
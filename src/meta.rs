@@ -0,0 +1,230 @@
+//! Descriptions of flags, for help output and generated documentation.
+
+use std::fmt;
+
+/// What a flag is and how to set it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlagMeta {
+    /// The long name, without the leading `--`.
+    pub name: String,
+    pub shorthand: Option<char>,
+    pub description: Option<String>,
+    /// The environment variable that sets the flag, if any.
+    pub env_var: Option<String>,
+    /// Whether some source has to set the flag.
+    pub required: bool,
+    /// What the flag's value is, or `None` for a switch, which takes none.
+    pub value_hint: Option<ValueHint>,
+    /// The type of the flag's value, or of each item for an array.
+    pub kind: FlagKind,
+    /// Whether the flag takes any number of values.
+    pub is_array: bool,
+    /// The default as it would be written on the command line, with an
+    /// array's items separated by commas. `None` if it isn't known without
+    /// running any code, e.g. a derived field without a `default`
+    /// attribute.
+    pub default: Option<String>,
+    /// The unit of a number, e.g. `ms`, which values in other units of the
+    /// same kind are converted to.
+    pub unit: Option<String>,
+    /// Whether the value is a secret, e.g. a password, which is redacted
+    /// wherever it's shown.
+    pub secret: bool,
+}
+
+/// The type of a flag's value, for documentation generators and GUIs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlagKind {
+    Bool,
+    Integer,
+    Float,
+    String,
+    Path,
+    /// Any other type, by its name as written, e.g. `Duration`.
+    Other(String),
+}
+
+/// What a flag's value is, so that shell completion knows what to suggest.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ValueHint {
+    /// Anything, e.g. a name or a number. Completion scripts ask the program
+    /// for suggestions, see [`crate::completions`].
+    #[default]
+    Any,
+    /// A file or directory.
+    Path,
+    /// One of a fixed set of values.
+    Choices(Vec<String>),
+}
+
+/// A positional argument, as help names it, e.g. `<SRC>` or `[FILE]...`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Positional {
+    pub name: String,
+    pub required: bool,
+    /// Whether it takes any number of arguments, which only the last one
+    /// can.
+    pub variadic: bool,
+}
+
+impl Positional {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            required: true,
+            variadic: false,
+        }
+    }
+
+    /// Any number of arguments, for programs that don't name theirs:
+    /// `[ARGS]...`.
+    pub fn any() -> Self {
+        Self::new("ARGS").optional().variadic()
+    }
+
+    pub fn optional(mut self) -> Self {
+        self.required = false;
+        self
+    }
+
+    pub fn variadic(mut self) -> Self {
+        self.variadic = true;
+        self
+    }
+}
+
+impl fmt::Display for Positional {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.required {
+            true => write!(f, "<{}>", self.name)?,
+            false => write!(f, "[{}]", self.name)?,
+        }
+        if self.variadic {
+            write!(f, "...")?;
+        }
+        Ok(())
+    }
+}
+
+/// What a program is, for the top of its help and its version output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct About {
+    /// The name to show in usage, e.g. the binary's name.
+    pub name: String,
+    pub version: String,
+    /// A one-line description.
+    pub about: Option<String>,
+    pub authors: Option<String>,
+}
+
+impl About {
+    /// The first lines of help, e.g. `mytool 1.2.0` followed by the authors
+    /// and description.
+    pub fn help_header(&self) -> String {
+        let mut header = format!("{} {}\n", self.name, self.version);
+        if let Some(authors) = &self.authors {
+            header += &format!("{authors}\n");
+        }
+        if let Some(about) = &self.about {
+            header += &format!("{about}\n");
+        }
+        header
+    }
+
+    /// The output of `--version`, e.g. `mytool 1.2.0`.
+    pub fn version_text(&self) -> String {
+        format!("{} {}\n", self.name, self.version)
+    }
+}
+
+/// The line at the top of help that shows how to call the program, e.g.
+/// `Usage: mytool --host <HOST> [OPTIONS] [--] <FILE>...`, with the
+/// required flags spelled out.
+pub fn synopsis(name: &str, flags: &[FlagMeta], positionals: &[Positional]) -> String {
+    let mut synopsis = format!("Usage: {name}");
+    for flag in flags.iter().filter(|flag| flag.required) {
+        let placeholder = flag.name.replace('-', "_").to_uppercase();
+        synopsis += &format!(" --{} <{placeholder}>", flag.name);
+    }
+    if flags.iter().any(|flag| !flag.required) {
+        synopsis += " [OPTIONS]";
+    }
+    if !positionals.is_empty() {
+        synopsis += " [--]";
+    }
+    for positional in positionals {
+        synopsis += &format!(" {positional}");
+    }
+    synopsis + "\n"
+}
+
+/// Builds an [`About`] from the calling crate's Cargo metadata: the binary's
+/// name (or the package's, outside of a binary), version, description, and
+/// authors. Empty fields in `Cargo.toml` are left out.
+#[macro_export]
+macro_rules! about {
+    () => {
+        $crate::meta::About {
+            name: ::std::borrow::ToOwned::to_owned(match ::std::option_env!("CARGO_BIN_NAME") {
+                ::std::option::Option::Some(name) => name,
+                ::std::option::Option::None => ::std::env!("CARGO_PKG_NAME"),
+            }),
+            version: ::std::borrow::ToOwned::to_owned(::std::env!("CARGO_PKG_VERSION")),
+            about: $crate::meta::non_empty(::std::env!("CARGO_PKG_DESCRIPTION")),
+            authors: $crate::meta::non_empty(&::std::env!("CARGO_PKG_AUTHORS").replace(':', ", ")),
+        }
+    };
+}
+
+#[doc(hidden)]
+pub fn non_empty(s: &str) -> Option<String> {
+    (!s.is_empty()).then(|| s.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_about() {
+        let about = crate::about!();
+        assert_eq!(about.name, "cliconf");
+        assert_eq!(about.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(about.version_text(), format!("cliconf {}\n", about.version));
+
+        let about = About {
+            name: "mytool".into(),
+            version: "1.2.0".into(),
+            about: Some("Does things".into()),
+            authors: None,
+        };
+        assert_eq!(about.help_header(), "mytool 1.2.0\nDoes things\n");
+    }
+
+    #[test]
+    fn test_synopsis() {
+        let flag = |name: &str, required| FlagMeta {
+            name: name.into(),
+            shorthand: None,
+            description: None,
+            env_var: None,
+            required,
+            value_hint: Some(ValueHint::Any),
+            kind: FlagKind::String,
+            is_array: false,
+            default: None,
+            unit: None,
+            secret: false,
+        };
+        let flags = [flag("verbose", false), flag("api-key", true)];
+        let positionals = [
+            Positional::new("SRC"),
+            Positional::new("DEST").optional().variadic(),
+        ];
+        assert_eq!(
+            synopsis("mytool", &flags, &positionals),
+            "Usage: mytool --api-key <API_KEY> [OPTIONS] [--] <SRC> [DEST]...\n"
+        );
+        assert_eq!(synopsis("mytool", &[], &[]), "Usage: mytool\n");
+    }
+}
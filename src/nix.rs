@@ -0,0 +1,259 @@
+//! NixOS modules generated from the flags' metadata, for packaging a program
+//! as a NixOS service without writing its options by hand.
+//!
+//! Each flag becomes an option under `services.<name>.settings`, with its
+//! type, default and description. The settings are written to
+//! `/etc/<name>/config.json`, where [`crate::Loader::for_app`] finds them.
+//!
+//! ```
+//! use cliconf::{meta::About, nix, Flag, FlagValue, Flags};
+//!
+//! let mut flags = Flags::new();
+//! flags.add(Flag::new("port", FlagValue::Int64(8080)).description("Where to listen"));
+//! let about = About {
+//!     name: "mytool".into(),
+//!     version: "1.2.0".into(),
+//!     about: None,
+//!     authors: None,
+//! };
+//! let module = nix::module(&about, &flags.flags_metadata());
+//! assert!(module.contains("      port = lib.mkOption {\n        type = lib.types.int;\n"));
+//! ```
+
+use std::fmt::Write;
+
+use crate::meta::{About, FlagKind, FlagMeta, ValueHint};
+
+/// A NixOS module for the program `about`, with an `enable` option, a
+/// `package` option, and a `settings` option for each of `flags`.
+pub fn module(about: &About, flags: &[FlagMeta]) -> String {
+    let name = &about.name;
+    let attr = attr_name(name);
+    let mut out = String::new();
+    out += "{ config, lib, pkgs, ... }:\n\n";
+    let _ = writeln!(out, "let\n  cfg = config.services.{attr};\nin\n{{");
+    let _ = writeln!(out, "  options.services.{attr} = {{");
+    let _ = writeln!(out, "    enable = lib.mkEnableOption {};\n", string(name));
+    let _ = writeln!(
+        out,
+        "    package = lib.mkPackageOption pkgs {} {{ }};\n",
+        string(name)
+    );
+    out += "    settings = {\n";
+    for (i, flag) in flags.iter().enumerate() {
+        if i > 0 {
+            out += "\n";
+        }
+        option(&mut out, flag);
+    }
+    out += "    };\n  };\n\n";
+    out += "  config = lib.mkIf cfg.enable {\n";
+    let _ = writeln!(
+        out,
+        "    environment.etc.{}.text = builtins.toJSON (lib.filterAttrs (_: value: value != null) cfg.settings);",
+        string(&format!("{name}/config.json"))
+    );
+    out += "  };\n}\n";
+    out
+}
+
+/// Writes the `mkOption` for one flag. Flags that aren't required and have
+/// no known default are `null` unless set, and left out of the config file.
+fn option(out: &mut String, flag: &FlagMeta) {
+    let item = match &flag.value_hint {
+        Some(ValueHint::Choices(choices)) => {
+            let choices: Vec<String> = choices.iter().map(|choice| string(choice)).collect();
+            format!("lib.types.enum [ {} ]", choices.join(" "))
+        }
+        _ => match flag.kind {
+            FlagKind::Bool => "lib.types.bool".into(),
+            FlagKind::Integer => "lib.types.int".into(),
+            FlagKind::Float => "lib.types.float".into(),
+            FlagKind::Path => "lib.types.path".into(),
+            FlagKind::String | FlagKind::Other(_) => "lib.types.str".into(),
+        },
+    };
+    let ty = match flag.is_array {
+        true => format!("lib.types.listOf {}", parenthesize(&item)),
+        false => item,
+    };
+    let default = match (&flag.default, flag.required) {
+        (_, true) => None,
+        (Some(default), false) => Some(literal(flag, default)),
+        (None, false) => Some("null".into()),
+    };
+    let ty = match default.as_deref() {
+        Some("null") => format!("lib.types.nullOr {}", parenthesize(&ty)),
+        _ => ty,
+    };
+    let _ = writeln!(out, "      {} = lib.mkOption {{", attr_name(&flag.name));
+    let _ = writeln!(out, "        type = {ty};");
+    if let Some(default) = default {
+        let _ = writeln!(out, "        default = {default};");
+    }
+    if let Some(description) = &flag.description {
+        let _ = writeln!(out, "        description = {};", string(description));
+    }
+    out.push_str("      };\n");
+}
+
+/// A flag's default as a Nix value of the option's type. Array defaults are
+/// the items separated by commas.
+fn literal(flag: &FlagMeta, default: &str) -> String {
+    let scalar = |value: &str| match flag.kind {
+        FlagKind::Bool if value == "true" || value == "false" => value.into(),
+        FlagKind::Integer if value.parse::<i64>().is_ok() => value.into(),
+        // Nix floats need a digit on either side of the point
+        FlagKind::Float if value.parse::<f64>().is_ok_and(f64::is_finite) => {
+            match value.contains(['.', 'e', 'E']) {
+                true => value.into(),
+                false => format!("{value}.0"),
+            }
+        }
+        _ => string(value),
+    };
+    match flag.is_array {
+        true if default.is_empty() => "[ ]".into(),
+        true => {
+            let items: Vec<String> = default.split(',').map(scalar).collect();
+            format!("[ {} ]", items.join(" "))
+        }
+        false => scalar(default),
+    }
+}
+
+/// A Nix string literal, with `"`, `\` and `${` escaped.
+fn string(s: &str) -> String {
+    let mut quoted = String::from('"');
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            '\t' => quoted += "\\t",
+            '$' if chars.peek() == Some(&'{') => quoted += "\\$",
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// An attribute name, quoted unless it's a valid Nix identifier.
+fn attr_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || "_-'".contains(c));
+    match valid {
+        true => name.into(),
+        false => string(name),
+    }
+}
+
+fn parenthesize(ty: &str) -> String {
+    match ty.contains(' ') {
+        true => format!("({ty})"),
+        false => ty.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module() {
+        let flag = |name: &str, kind, default: Option<&str>| FlagMeta {
+            name: name.into(),
+            shorthand: None,
+            description: None,
+            env_var: None,
+            required: false,
+            value_hint: Some(ValueHint::Any),
+            kind,
+            is_array: false,
+            default: default.map(Into::into),
+            unit: None,
+            secret: false,
+        };
+        let mut port = flag("port", FlagKind::Integer, Some("8080"));
+        port.description = Some("Where to listen, e.g. \"${PORT}\"".into());
+        let mut color = flag("color", FlagKind::String, Some("auto"));
+        color.value_hint = Some(ValueHint::Choices(vec!["auto".into(), "never".into()]));
+        let mut tags = flag("tags", FlagKind::String, Some("a,b"));
+        tags.is_array = true;
+        let mut token = flag("api-token", FlagKind::String, None);
+        token.required = true;
+        let about = About {
+            name: "my tool".into(),
+            version: "1.2.0".into(),
+            about: None,
+            authors: None,
+        };
+        assert_eq!(
+            module(
+                &about,
+                &[
+                    port,
+                    color,
+                    tags,
+                    token,
+                    flag("ratio", FlagKind::Float, Some("1")),
+                    flag("dir", FlagKind::Path, None),
+                ]
+            ),
+            r#"{ config, lib, pkgs, ... }:
+
+let
+  cfg = config.services."my tool";
+in
+{
+  options.services."my tool" = {
+    enable = lib.mkEnableOption "my tool";
+
+    package = lib.mkPackageOption pkgs "my tool" { };
+
+    settings = {
+      port = lib.mkOption {
+        type = lib.types.int;
+        default = 8080;
+        description = "Where to listen, e.g. \"\${PORT}\"";
+      };
+
+      color = lib.mkOption {
+        type = lib.types.enum [ "auto" "never" ];
+        default = "auto";
+      };
+
+      tags = lib.mkOption {
+        type = lib.types.listOf lib.types.str;
+        default = [ "a" "b" ];
+      };
+
+      api-token = lib.mkOption {
+        type = lib.types.str;
+      };
+
+      ratio = lib.mkOption {
+        type = lib.types.float;
+        default = 1.0;
+      };
+
+      dir = lib.mkOption {
+        type = lib.types.nullOr lib.types.path;
+        default = null;
+      };
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    environment.etc."my tool/config.json".text = builtins.toJSON (lib.filterAttrs (_: value: value != null) cfg.settings);
+  };
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,76 @@
+//! Expands `~` and environment variables in paths, so that `~/.cache/app`
+//! or `$XDG_DATA_HOME/app` work wherever a path is given, not just where a
+//! shell has already expanded them.
+
+use std::env;
+
+/// Expands a leading `~` to the home directory, and `$VAR` and `${VAR}` to
+/// the variable's value. A `$` that doesn't start a variable name is kept.
+/// Returns `None` if there's no home directory or a variable isn't set, since
+/// the path would point somewhere else than meant.
+pub fn expand(value: &str) -> Option<String> {
+    expand_with(value, |var| env::var(var).ok())
+}
+
+/// Like [`expand`], but reading variables from `lookup`, which also gives
+/// the home directory as `HOME` (or `USERPROFILE` on Windows).
+pub fn expand_with(value: &str, lookup: impl Fn(&str) -> Option<String>) -> Option<String> {
+    let home = || lookup("HOME").or_else(|| lookup("USERPROFILE"));
+    let mut expanded = String::new();
+    let mut rest = value;
+    if let Some(after) = value.strip_prefix('~') {
+        if after.is_empty() || after.starts_with(['/', '\\']) {
+            expanded += &home()?;
+            rest = after;
+        }
+    }
+    while let Some(dollar) = rest.find('$') {
+        expanded += &rest[..dollar];
+        rest = &rest[dollar + 1..];
+        let (name, after) = match rest.strip_prefix('{') {
+            Some(braced) => {
+                let end = braced.find('}')?;
+                (&braced[..end], &braced[end + 1..])
+            }
+            None => {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                rest.split_at(end)
+            }
+        };
+        if name.is_empty() {
+            expanded.push('$');
+            continue;
+        }
+        expanded += &lookup(name)?;
+        rest = after;
+    }
+    Some(expanded + rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let lookup = |var: &str| match var {
+            "HOME" => Some("/home/jo".into()),
+            "DATA" => Some("/srv/data".into()),
+            _ => None,
+        };
+        let expand = |value| expand_with(value, lookup);
+        assert_eq!(expand("~").as_deref(), Some("/home/jo"));
+        assert_eq!(expand("~/.cache").as_deref(), Some("/home/jo/.cache"));
+        assert_eq!(expand("~jo/x").as_deref(), Some("~jo/x"));
+        assert_eq!(expand("a/~/b").as_deref(), Some("a/~/b"));
+        assert_eq!(expand("$DATA/app").as_deref(), Some("/srv/data/app"));
+        assert_eq!(expand("${DATA}app").as_deref(), Some("/srv/dataapp"));
+        assert_eq!(expand("~/$DATA").as_deref(), Some("/home/jo//srv/data"));
+        assert_eq!(expand("cost$ 5$").as_deref(), Some("cost$ 5$"));
+        assert_eq!(expand("$MISSING/app"), None);
+        assert_eq!(expand("${DATA"), None);
+        assert_eq!(expand_with("~/x", |_| None), None);
+    }
+}
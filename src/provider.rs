@@ -0,0 +1,351 @@
+//! Config documents fetched from somewhere other than the local disk, e.g. a
+//! config service, with a policy for when that somewhere is unreachable.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+use crate::{Error, Flag, FlagValue};
+
+/// The conventional name for a flag that sets [`Policy::refresh`].
+pub const REFRESH_FLAG: &str = "refresh-config";
+
+/// The longest [`Policy::backoff`] grows to between retries.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Fetches a config document whose keys are flag names.
+pub trait Provider: Send + Sync + 'static {
+    /// Names the provider in errors and in [`crate::Source::Provider`].
+    fn name(&self) -> &str;
+
+    fn fetch(&self) -> Result<serde_json::Value, Error>;
+}
+
+/// What to do when a provider still fails after every retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnUnavailable {
+    /// Return the error.
+    Fail,
+    /// Use the document from the last successful fetch, if it was cached.
+    UseCached,
+    /// Carry on without the provider's values.
+    Skip,
+}
+
+#[derive(Debug, Clone)]
+pub struct Policy {
+    /// How long a single fetch may take before it counts as failed. A fetch
+    /// that times out can't be stopped, so the next retry waits on it
+    /// rather than starting another.
+    pub timeout: Duration,
+    /// How many more times to try after the first failure.
+    pub retries: u32,
+    /// How long to wait before the first retry, doubling for each one after,
+    /// up to [`MAX_BACKOFF`].
+    pub backoff: Duration,
+    pub on_unavailable: OnUnavailable,
+    /// Where to keep the last successfully fetched document, e.g. from
+    /// [`cache_path`].
+    pub cache: Option<PathBuf>,
+    /// How long a cached document stays fresh. While it is, it's used
+    /// without fetching at all, which also lets the program start offline.
+    pub ttl: Option<Duration>,
+    /// Fetch even if the cached document is still fresh, e.g. for a
+    /// [`refresh_flag`].
+    pub refresh: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            retries: 2,
+            backoff: Duration::from_millis(200),
+            on_unavailable: OnUnavailable::Fail,
+            cache: None,
+            ttl: None,
+            refresh: false,
+        }
+    }
+}
+
+/// A `--refresh-config` flag for setting [`Policy::refresh`].
+pub fn refresh_flag() -> Flag {
+    Flag::new(REFRESH_FLAG, FlagValue::Bool(false))
+        .description("Fetch remote config even if a cached copy is still fresh")
+}
+
+/// Where to cache a provider's documents for `app`, following each OS's
+/// convention, e.g. `$XDG_CACHE_HOME/<app>/<provider>.json` on Linux.
+pub fn cache_path(app: &str, provider: &str) -> Option<PathBuf> {
+    let var = |name| env::var_os(name).filter(|value| !value.is_empty());
+    let dir = if cfg!(windows) {
+        PathBuf::from(var("LOCALAPPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(var("HOME")?).join("Library/Caches")
+    } else {
+        var("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| Some(PathBuf::from(var("HOME")?).join(".cache")))?
+    };
+    Some(dir.join(app).join(format!("{provider}.json")))
+}
+
+/// The document to apply, or `None` if the provider should be skipped.
+pub(crate) fn fetch<P: Provider + ?Sized>(
+    provider: &Arc<P>,
+    policy: &Policy,
+) -> Result<Option<serde_json::Value>, Error> {
+    if !policy.refresh {
+        if let Some(doc) = fresh_cache(policy) {
+            return Ok(Some(doc));
+        }
+    }
+    let mut backoff = policy.backoff;
+    let mut attempt = 0;
+    let mut in_flight = None;
+    let err = loop {
+        match fetch_once(provider, policy.timeout, &mut in_flight) {
+            Ok(doc) => {
+                if let Some(cache) = &policy.cache {
+                    // Caching is best-effort, and a failure here shouldn't
+                    // stop a fetch that worked
+                    let _ = write_cache(cache, &doc);
+                }
+                return Ok(Some(doc));
+            }
+            Err(err) if attempt == policy.retries => break err,
+            Err(_) => {
+                thread::sleep(backoff);
+                backoff = next_backoff(backoff);
+                attempt += 1;
+            }
+        }
+    };
+    match policy.on_unavailable {
+        OnUnavailable::Fail => Err(err),
+        OnUnavailable::Skip => Ok(None),
+        OnUnavailable::UseCached => {
+            let Some(data) = policy
+                .cache
+                .as_ref()
+                .and_then(|cache| fs::read_to_string(cache).ok())
+            else {
+                return Err(err);
+            };
+            serde_json::from_str(&data)
+                .map(Some)
+                .map_err(|e| Error::Format(e.to_string()))
+        }
+    }
+}
+
+/// Doubles `backoff`, up to [`MAX_BACKOFF`].
+fn next_backoff(backoff: Duration) -> Duration {
+    backoff.saturating_mul(2).min(MAX_BACKOFF)
+}
+
+/// The cached document, if it's younger than the policy's TTL.
+fn fresh_cache(policy: &Policy) -> Option<serde_json::Value> {
+    let cache = policy.cache.as_ref()?;
+    let age = fs::metadata(cache).ok()?.modified().ok()?.elapsed().ok()?;
+    if age >= policy.ttl? {
+        return None;
+    }
+    serde_json::from_str(&fs::read_to_string(cache).ok()?).ok()
+}
+
+type Attempt = mpsc::Receiver<Result<serde_json::Value, Error>>;
+
+/// Fetches on another thread, so that a hung provider can be abandoned. An
+/// attempt that times out is kept in `in_flight`, and waited on again by the
+/// next call instead of starting another thread.
+fn fetch_once<P: Provider + ?Sized>(
+    provider: &Arc<P>,
+    timeout: Duration,
+    in_flight: &mut Option<Attempt>,
+) -> Result<serde_json::Value, Error> {
+    let rx = in_flight.take().unwrap_or_else(|| {
+        let (tx, rx) = mpsc::channel();
+        let thread_provider = Arc::clone(provider);
+        thread::spawn(move || {
+            let _ = tx.send(thread_provider.fetch());
+        });
+        rx
+    });
+    match rx.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            *in_flight = Some(rx);
+            Err(unavailable(provider.name(), "timed out"))
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            Err(unavailable(provider.name(), "the fetch panicked"))
+        }
+    }
+}
+
+fn write_cache(path: &Path, doc: &serde_json::Value) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, doc.to_string())
+}
+
+/// An error for a provider that couldn't be reached.
+pub fn unavailable(provider: &str, message: &str) -> Error {
+    Error::Unavailable {
+        provider: provider.into(),
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    /// Fails until it has been asked `failures` times.
+    struct Flaky {
+        failures: u32,
+        calls: AtomicU32,
+        delay: Duration,
+    }
+
+    impl Provider for Flaky {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn fetch(&self) -> Result<serde_json::Value, Error> {
+            thread::sleep(self.delay);
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(unavailable(self.name(), "connection refused"));
+            }
+            Ok(serde_json::json!({ "my-num": 1 }))
+        }
+    }
+
+    fn flaky(failures: u32, delay: Duration) -> Arc<Flaky> {
+        Arc::new(Flaky {
+            failures,
+            calls: AtomicU32::new(0),
+            delay,
+        })
+    }
+
+    fn policy(on_unavailable: OnUnavailable) -> Policy {
+        Policy {
+            timeout: Duration::from_millis(200),
+            retries: 2,
+            backoff: Duration::from_millis(1),
+            on_unavailable,
+            cache: None,
+            ttl: None,
+            refresh: false,
+        }
+    }
+
+    #[test]
+    fn test_retry() {
+        let provider = flaky(2, Duration::ZERO);
+        let doc = fetch(&provider, &policy(OnUnavailable::Fail)).unwrap();
+        assert_eq!(doc, Some(serde_json::json!({ "my-num": 1 })));
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 3);
+
+        let provider = flaky(3, Duration::ZERO);
+        let err = fetch(&provider, &policy(OnUnavailable::Fail)).unwrap_err();
+        assert!(
+            matches!(err, Error::Unavailable { message, .. } if message == "connection refused")
+        );
+        let doc = fetch(&flaky(3, Duration::ZERO), &policy(OnUnavailable::Skip)).unwrap();
+        assert_eq!(doc, None);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let provider = flaky(0, Duration::from_secs(5));
+        let policy = Policy {
+            retries: 0,
+            ..policy(OnUnavailable::Fail)
+        };
+        let err = fetch(&provider, &policy).unwrap_err();
+        assert!(matches!(err, Error::Unavailable { message, .. } if message == "timed out"));
+
+        // The retry waits on the fetch that timed out instead of starting
+        // another
+        let provider = flaky(0, Duration::from_millis(300));
+        let policy = Policy {
+            retries: 1,
+            ..policy
+        };
+        fetch(&provider, &policy).unwrap();
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_next_backoff() {
+        assert_eq!(
+            next_backoff(Duration::from_millis(200)),
+            Duration::from_millis(400)
+        );
+        let mut backoff = Duration::from_millis(200);
+        for _ in 0..64 {
+            backoff = next_backoff(backoff);
+        }
+        assert_eq!(backoff, MAX_BACKOFF);
+        assert_eq!(next_backoff(Duration::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_use_cached() {
+        let cache = std::env::temp_dir()
+            .join(format!("cliconf-test-cache-{}", std::process::id()))
+            .join("flaky.json");
+        let policy = Policy {
+            cache: Some(cache.clone()),
+            ..policy(OnUnavailable::UseCached)
+        };
+
+        let err = fetch(&flaky(3, Duration::ZERO), &policy).unwrap_err();
+        assert!(matches!(err, Error::Unavailable { .. }));
+        let fetched = fetch(&flaky(0, Duration::ZERO), &policy).unwrap();
+        let cached = fetch(&flaky(3, Duration::ZERO), &policy).unwrap();
+        assert_eq!(fetched, cached);
+
+        fs::remove_dir_all(cache.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_ttl() {
+        let cache = std::env::temp_dir()
+            .join(format!("cliconf-test-ttl-{}", std::process::id()))
+            .join("flaky.json");
+        let mut policy = Policy {
+            cache: Some(cache.clone()),
+            ttl: Some(Duration::from_secs(3600)),
+            ..policy(OnUnavailable::Fail)
+        };
+
+        let provider = flaky(0, Duration::ZERO);
+        fetch(&provider, &policy).unwrap();
+        fetch(&provider, &policy).unwrap();
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+
+        policy.refresh = true;
+        fetch(&provider, &policy).unwrap();
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 2);
+
+        policy.refresh = false;
+        policy.ttl = Some(Duration::ZERO);
+        fetch(&provider, &policy).unwrap();
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 3);
+
+        fs::remove_dir_all(cache.parent().unwrap()).unwrap();
+    }
+}
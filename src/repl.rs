@@ -0,0 +1,92 @@
+//! An interactive shell that parses each line like a command line.
+
+use std::io::{self, BufRead, Write};
+
+use crate::{report::Reporter, shell, Error};
+
+/// Whether the loop in [`run`] goes on after a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    Continue,
+    Exit,
+}
+
+/// Reads lines from `input` until it ends or `handle` asks to exit. Each line
+/// is split like a shell would (see [`shell::split`]) and handed to `handle`,
+/// which typically parses the words with [`crate::Flags::parse_args`] or
+/// [`crate::command::Commands::parse_args`] and acts on them. Errors are
+/// given to `reporter` instead of ending the loop. Blank lines are skipped,
+/// and `prompt` is written to `output` before each line.
+pub fn run<R, W, P, F>(
+    input: R,
+    mut output: W,
+    prompt: &str,
+    reporter: &mut P,
+    mut handle: F,
+) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+    P: Reporter,
+    F: FnMut(Vec<String>) -> Result<Control, Error>,
+{
+    let mut lines = input.lines();
+    loop {
+        output.write_all(prompt.as_bytes())?;
+        output.flush()?;
+        let Some(line) = lines.next().transpose()? else {
+            return Ok(());
+        };
+        let result = shell::split(&line).and_then(|words| {
+            if words.is_empty() {
+                return Ok(Control::Continue);
+            }
+            handle(words)
+        });
+        match result {
+            Ok(Control::Continue) => {}
+            Ok(Control::Exit) => return Ok(()),
+            Err(err) => reporter.error(&err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{report::Captured, Flag, FlagValue, Flags};
+
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let mut flags = Flags::new();
+        flags.add(Flag::new("name", FlagValue::String("".into())));
+        let input = "--name 'john doe'\n\n--nope\n\"unterminated\nexit\n--name never\n";
+        let mut output = vec![];
+        let mut reporter = Captured::default();
+        let mut names = vec![];
+
+        run(
+            input.as_bytes(),
+            &mut output,
+            "> ",
+            &mut reporter,
+            |words| {
+                if words == ["exit"] {
+                    return Ok(Control::Exit);
+                }
+                flags.parse_args(words)?;
+                names.push(flags.get("name").value.clone());
+                Ok(Control::Continue)
+            },
+        )
+        .unwrap();
+
+        assert_eq!(names, [FlagValue::String("john doe".into())]);
+        assert_eq!(String::from_utf8(output).unwrap(), "> ".repeat(5));
+        assert_eq!(
+            reporter.stderr,
+            "error: Unknown flag: --nope\nerror: Failed to parse config: unterminated quote or escape in \"\\\"unterminated\"\n"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Where human-facing output goes.
+//!
+//! Anything cliconf would otherwise print (help, version, errors, warnings) is
+//! handed to a [`Reporter`] instead, so GUI hosts and tests can capture it and
+//! TUI apps can render it themselves. [`Stdio`] prints it the usual way.
+//!
+//! [`outcome`] reports how parsing ended, and
+//! [`Flags::check_with`](crate::Flags::check_with) reports lint warnings,
+//! e.g. for deprecated flags:
+//!
+//! ```
+//! use cliconf::{report::{self, Captured}, Flag, FlagValue, Flags};
+//!
+//! let mut flags = Flags::new();
+//! flags.help_args = vec!["--help".into()];
+//! flags.add(Flag::new("old", FlagValue::Bool(false)).deprecated("use --new instead"));
+//!
+//! let mut reporter = Captured::default();
+//! let result = flags.parse_args(vec!["--help".into()]);
+//! let parsed = report::outcome(result, &mut reporter, || "Usage: app\n".into(), String::new);
+//! assert_eq!(parsed, None);
+//! assert_eq!(reporter.stdout, "Usage: app\n");
+//!
+//! flags.parse_args(vec!["--old".into()]).unwrap();
+//! flags.check_with(&[], &mut reporter).unwrap();
+//! assert_eq!(reporter.stderr, "warning: --old: use --new instead [deprecated]\n");
+//! ```
+//!
+//! Output that isn't the result of a call, e.g. what a `from_command`
+//! command prints to stderr, goes to the reporter from [`set_reporter`].
+
+use std::{
+    io::{self, Write},
+    sync::Mutex,
+};
+
+use crate::Error;
+
+pub trait Reporter {
+    /// Help text the user asked for.
+    fn help(&mut self, text: &str);
+
+    /// Version text the user asked for.
+    fn version(&mut self, text: &str);
+
+    /// An error that stops the program from running as configured.
+    fn error(&mut self, err: &Error);
+
+    /// Something worth telling the user that doesn't stop the program, e.g. a
+    /// deprecated flag being used.
+    fn warning(&mut self, message: &str);
+}
+
+/// Reports how parsing ended: help or version text for
+/// [`Error::HelpRequested`] and [`Error::VersionRequested`], from `help` and
+/// `version`, the configuration for [`Error::PrintConfigRequested`], which is
+/// printed like help, or any other error. Returns the parsed value, or `None`
+/// if the program should stop there. [`Error::HelpAllRequested`] shows
+/// `help` too, so match it first to show help with nothing cut short.
+pub fn outcome<T>(
+    result: Result<T, Error>,
+    reporter: &mut impl Reporter,
+    help: impl FnOnce() -> String,
+    version: impl FnOnce() -> String,
+) -> Option<T> {
+    match result {
+        Ok(value) => return Some(value),
+        Err(Error::HelpRequested | Error::HelpAllRequested) => reporter.help(&help()),
+        Err(Error::VersionRequested) => reporter.version(&version()),
+        Err(Error::PrintConfigRequested(config)) => reporter.help(&config),
+        Err(err) => reporter.error(&err),
+    }
+    None
+}
+
+static REPORTER: Mutex<Option<Box<dyn Reporter + Send>>> = Mutex::new(None);
+
+/// Sets where output that isn't the result of a call goes, e.g. each line a
+/// `from_command` command prints to stderr, which is reported as a warning.
+/// Until this is called, it goes to [`Stdio`].
+pub fn set_reporter(reporter: impl Reporter + Send + 'static) {
+    *lock() = Some(Box::new(reporter));
+}
+
+/// Hands `message` to the reporter from [`set_reporter`].
+pub(crate) fn warning(message: &str) {
+    match &mut *lock() {
+        Some(reporter) => reporter.warning(message),
+        None => Stdio.warning(message),
+    }
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Option<Box<dyn Reporter + Send>>> {
+    // A reporter that panicked leaves nothing half-written that matters
+    REPORTER.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Prints help and version text to stdout, and errors and warnings to stderr.
+#[derive(Debug, Default)]
+pub struct Stdio;
+
+impl Reporter for Stdio {
+    fn help(&mut self, text: &str) {
+        let _ = io::stdout().write_all(text.as_bytes());
+    }
+
+    fn version(&mut self, text: &str) {
+        let _ = io::stdout().write_all(text.as_bytes());
+    }
+
+    fn error(&mut self, err: &Error) {
+        let _ = writeln!(io::stderr(), "error: {err}");
+    }
+
+    fn warning(&mut self, message: &str) {
+        let _ = writeln!(io::stderr(), "warning: {message}");
+    }
+}
+
+/// Like [`Stdio`], but errors and warnings are printed to stderr as JSON
+/// objects, one per line, e.g. `{"error":{"code":"...","message":"..."}}`.
+/// Help and version text is printed as given, so pair this with
+/// [`Flags::spec`](crate::Flags::spec) for machine-readable help.
+#[cfg(feature = "json")]
+#[derive(Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Reporter for Json {
+    fn help(&mut self, text: &str) {
+        Stdio.help(text);
+    }
+
+    fn version(&mut self, text: &str) {
+        Stdio.version(text);
+    }
+
+    fn error(&mut self, err: &Error) {
+        let json = serde_json::json!({ "error": err.to_json() });
+        let _ = writeln!(io::stderr(), "{json}");
+    }
+
+    fn warning(&mut self, message: &str) {
+        let json = serde_json::json!({ "warning": { "message": message } });
+        let _ = writeln!(io::stderr(), "{json}");
+    }
+}
+
+/// Collects everything reported, in the order it was reported.
+#[derive(Debug, Default)]
+pub struct Captured {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Reporter for Captured {
+    fn help(&mut self, text: &str) {
+        self.stdout += text;
+    }
+
+    fn version(&mut self, text: &str) {
+        self.stdout += text;
+    }
+
+    fn error(&mut self, err: &Error) {
+        self.stderr += &format!("error: {err}\n");
+    }
+
+    fn warning(&mut self, message: &str) {
+        self.stderr += &format!("warning: {message}\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outcome() {
+        let help = || "usage\n".to_string();
+        let version = || "app 1.0\n".to_string();
+        let mut r = Captured::default();
+        assert_eq!(outcome(Ok(1), &mut r, help, version), Some(1));
+        assert_eq!(
+            outcome::<()>(Err(Error::HelpRequested), &mut r, help, version),
+            None
+        );
+        outcome::<()>(Err(Error::VersionRequested), &mut r, help, version);
+        outcome::<()>(
+            Err(Error::PrintConfigRequested("port = 80\n".into())),
+            &mut r,
+            help,
+            version,
+        );
+        outcome::<()>(
+            Err(Error::UnknownFlag("--nope".into())),
+            &mut r,
+            help,
+            version,
+        );
+
+        assert_eq!(r.stdout, "usage\napp 1.0\nport = 80\n");
+        assert_eq!(r.stderr, "error: Unknown flag: --nope\n");
+    }
+
+    #[test]
+    fn test_captured() {
+        let mut r = Captured::default();
+        r.help("usage\n");
+        r.warning("--old is deprecated");
+        r.error(&Error::UnknownFlag("--nope".into()));
+
+        assert_eq!(r.stdout, "usage\n");
+        assert_eq!(
+            r.stderr,
+            "warning: --old is deprecated\nerror: Unknown flag: --nope\n"
+        );
+    }
+}
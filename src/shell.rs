@@ -0,0 +1,82 @@
+//! Splitting command lines the way a POSIX shell would.
+
+use crate::Error;
+
+/// Splits `line` into words at unquoted whitespace. Single quotes keep
+/// everything literally, double quotes keep everything but `\"` and `\\`, and
+/// a backslash outside quotes escapes the next character.
+///
+/// ```
+/// let words = cliconf::shell::split(r#"build --name "john doe" -x\ y"#).unwrap();
+/// assert_eq!(words, ["build", "--name", "john doe", "-x y"]);
+/// ```
+pub fn split(line: &str) -> Result<Vec<String>, Error> {
+    let mut words = vec![];
+    // `None` between words, so that `''` still makes an empty word
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(unterminated(line)),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(unterminated(line)),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(unterminated(line)),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err(unterminated(line)),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+fn unterminated(line: &str) -> Error {
+    Error::Format(format!("unterminated quote or escape in {line:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split("  a  b\tc\n").unwrap(), ["a", "b", "c"]);
+        assert_eq!(
+            split(r#"'it''s' "" 'a "b"'"#).unwrap(),
+            ["its", "", "a \"b\""]
+        );
+        assert_eq!(split(r#""a \"b\" \n""#).unwrap(), ["a \"b\" \\n"]);
+        assert_eq!(split(r"a\ b\\").unwrap(), ["a b\\"]);
+        assert!(split("'a").is_err());
+        assert!(split("a\\").is_err());
+        assert!(split("\"a\\").is_err());
+    }
+}
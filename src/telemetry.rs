@@ -0,0 +1,101 @@
+//! Snapshots of the configuration that are safe to attach to crash reports
+//! and metrics.
+//!
+//! Only flags marked for telemetry are included, and those can't be secrets
+//! or free-form strings, which is where personal data like paths, hostnames
+//! and names ends up.
+
+use crate::dump::Entry;
+
+/// The entries as a JSON object keyed by flag name. Array flags become
+/// arrays, and each value becomes a JSON boolean or number if it reads as
+/// one, and a string otherwise.
+pub fn snapshot(entries: &[Entry]) -> serde_json::Value {
+    let snapshot = entries
+        .iter()
+        .filter(|entry| !entry.secret)
+        .map(|entry| {
+            let mut values = entry.values.iter().map(|value| to_json(value));
+            let json = match entry.is_array {
+                true => values.collect(),
+                false => values.next().unwrap_or_default(),
+            };
+            (entry.name.clone(), json)
+        })
+        .collect();
+    serde_json::Value::Object(snapshot)
+}
+
+fn to_json(value: &str) -> serde_json::Value {
+    if let Ok(b) = value.parse::<bool>() {
+        return b.into();
+    }
+    if let Ok(n) = value.parse::<i64>() {
+        return n.into();
+    }
+    if let Ok(n) = value.parse::<u64>() {
+        return n.into();
+    }
+    match value
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+    {
+        Some(n) => n.into(),
+        None => value.into(),
+    }
+}
+
+/// Generates `sanitized_snapshot` for the derive macro, which can't tell
+/// whether the `json` feature is enabled itself.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __sanitized_snapshot {
+    ($vis:vis $name:ident) => {
+        /// The fields marked for telemetry as a JSON object, e.g. to attach
+        /// to crash reports. No other field is ever included.
+        $vis fn $name(&self) -> $crate::serde_json::Value {
+            $crate::telemetry::snapshot(&$crate::engine::Dispatch::telemetry_entries(self))
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let entry = |name: &str, values: &[&str], is_array| Entry {
+            name: name.into(),
+            env_var: None,
+            values: values.iter().map(|v| v.to_string()).collect(),
+            is_array,
+            secret: false,
+            source: None,
+            unit: None,
+        };
+        let mut token = entry("token", &["hunter2"], false);
+        token.secret = true;
+        let entries = [
+            entry("color", &["true"], false),
+            entry("jobs", &["8"], false),
+            entry("ratio", &["0.5"], false),
+            entry("level", &["info"], false),
+            entry("ports", &["80", "443"], true),
+            token,
+        ];
+        assert_eq!(
+            snapshot(&entries),
+            json!({
+                "color": true,
+                "jobs": 8,
+                "ratio": 0.5,
+                "level": "info",
+                "ports": [80, 443],
+            })
+        );
+    }
+}
@@ -0,0 +1,250 @@
+//! Numbers with units, so that `--timeout 2s` works for a flag measured in
+//! milliseconds.
+
+use std::time::Duration;
+
+/// Units of one kind, by how many of the smallest unit each one is.
+const FAMILIES: &[&[(&str, u128)]] = &[
+    &[
+        ("ns", 1),
+        ("us", 1_000),
+        ("µs", 1_000),
+        ("ms", 1_000_000),
+        ("s", 1_000_000_000),
+        ("m", 60_000_000_000),
+        ("min", 60_000_000_000),
+        ("h", 3_600_000_000_000),
+        ("d", 86_400_000_000_000),
+    ],
+    &[
+        ("B", 1),
+        ("KB", 1_000),
+        ("MB", 1_000_000),
+        ("GB", 1_000_000_000),
+        ("TB", 1_000_000_000_000),
+        ("KiB", 1 << 10),
+        ("MiB", 1 << 20),
+        ("GiB", 1 << 30),
+        ("TiB", 1 << 40),
+    ],
+];
+
+/// The index of the units of time in [`FAMILIES`], which are in nanoseconds.
+const TIME: usize = 0;
+
+fn scale(unit: &str) -> Option<(usize, u128)> {
+    FAMILIES.iter().enumerate().find_map(|(family, units)| {
+        let &(_, scale) = units.iter().find(|(name, _)| *name == unit)?;
+        Some((family, scale))
+    })
+}
+
+/// Parses a number written in decimal, like `-2.5` or `1_000`, exactly: as
+/// `mantissa / 10^exponent`, so that `2.3` isn't rounded like a float.
+fn parse_decimal(number: &str) -> Option<(i128, u32)> {
+    let number = number.replace('_', "");
+    let (negative, digits) = match number.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, number.strip_prefix('+').unwrap_or(&number)),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    let mut mantissa: i128 = 0;
+    for c in whole.chars().chain(fraction.chars()) {
+        let digit = c.to_digit(10)?;
+        mantissa = mantissa.checked_mul(10)?.checked_add(digit.into())?;
+    }
+    let exponent = u32::try_from(fraction.len()).ok()?;
+    Some((if negative { -mantissa } else { mantissa }, exponent))
+}
+
+/// Writes `numerator / denominator` as a plain number: exactly if it has a
+/// finite decimal expansion, like `0.5`, and rounded otherwise.
+fn format_ratio(numerator: i128, denominator: u128) -> Option<String> {
+    let divisor = gcd(numerator.unsigned_abs(), denominator);
+    let (numerator, denominator) = (numerator / divisor as i128, denominator / divisor);
+    if denominator == 1 {
+        return Some(numerator.to_string());
+    }
+    // Only powers of two and five end, e.g. a third doesn't
+    let (mut rest, mut twos, mut fives) = (denominator, 0, 0);
+    while rest % 2 == 0 {
+        rest /= 2;
+        twos += 1;
+    }
+    while rest % 5 == 0 {
+        rest /= 5;
+        fives += 1;
+    }
+    if rest != 1 {
+        return Some((numerator as f64 / denominator as f64).to_string());
+    }
+    let places: u32 = twos.max(fives);
+    let scale = i128::try_from(10u128.checked_pow(places)? / denominator).ok()?;
+    let scaled = numerator.checked_mul(scale)?;
+    let digits = format!(
+        "{:0>width$}",
+        scaled.unsigned_abs(),
+        width = places as usize + 1
+    );
+    let (whole, fraction) = digits.split_at(digits.len() - places as usize);
+    let sign = if scaled < 0 { "-" } else { "" };
+    Some(format!("{sign}{whole}.{}", fraction.trim_end_matches('0')))
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Converts `value` to a plain number in `unit`, ready to be parsed. A bare
+/// number is taken to already be in `unit`, and one with a suffix is
+/// converted if the suffix is a unit of the same kind, e.g. `2s` is `2000`
+/// in `ms`. The conversion is exact, so `2.3h` is a whole number of seconds.
+/// Returns `None` for a suffix that can't be converted.
+pub fn convert(value: &str, unit: &str) -> Option<String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | '_')))
+        .unwrap_or(value.len());
+    let (number, suffix) = value.split_at(split);
+    let suffix = suffix.trim_start();
+    if suffix.is_empty() || suffix == unit {
+        return Some(number.replace('_', ""));
+    }
+    let (to_family, to) = scale(unit)?;
+    match scale(suffix) {
+        Some((from_family, from)) if from_family == to_family => {
+            let (mantissa, exponent) = parse_decimal(number)?;
+            let numerator = mantissa.checked_mul(i128::try_from(from).ok()?)?;
+            format_ratio(numerator, 10u128.checked_pow(exponent)?.checked_mul(to)?)
+        }
+        Some(_) => None,
+        // A duration with several parts, like `1h30m`
+        None if to_family == TIME => {
+            format_ratio(i128::try_from(parse_duration(value)?.as_nanos()).ok()?, to)
+        }
+        None => None,
+    }
+}
+
+/// Parses a duration like `30s`, `1h30m` or `1.5 min`. A bare number is in
+/// seconds, as are JSON numbers, which reach here as strings. Negative
+/// durations can't be represented, so they return `None`, and anything
+/// finer than a nanosecond is dropped.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<f64>() {
+        return Duration::try_from_secs_f64(secs).ok();
+    }
+    let mut rest = value;
+    let mut nanos: u128 = 0;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '_')))
+            .unwrap_or(rest.len());
+        let (number, after) = rest.split_at(split);
+        let after = after.trim_start();
+        let end = after
+            .find(|c: char| !c.is_alphabetic())
+            .unwrap_or(after.len());
+        let (unit, after) = after.split_at(end);
+        let (mantissa, exponent) = parse_decimal(number)?;
+        let (family, scale) = scale(unit)?;
+        if family != TIME {
+            return None;
+        }
+        let part = mantissa.unsigned_abs().checked_mul(scale)? / 10u128.checked_pow(exponent)?;
+        nanos = nanos.checked_add(part)?;
+        rest = after.trim_start();
+    }
+    let secs = u64::try_from(nanos / 1_000_000_000).ok()?;
+    (!value.is_empty()).then(|| Duration::new(secs, (nanos % 1_000_000_000) as u32))
+}
+
+/// Writes a duration the way [`parse_duration`] reads it back, with each
+/// unit from days down to nanoseconds, e.g. `1h30m` or `1s500ms`.
+pub fn format_duration(duration: &Duration) -> String {
+    if duration.is_zero() {
+        return "0s".into();
+    }
+    const PARTS: &[(&str, u128)] = &[
+        ("d", 86_400_000_000_000),
+        ("h", 3_600_000_000_000),
+        ("m", 60_000_000_000),
+        ("s", 1_000_000_000),
+        ("ms", 1_000_000),
+        ("us", 1_000),
+        ("ns", 1),
+    ];
+    let mut nanos = duration.as_nanos();
+    let mut formatted = String::new();
+    for &(unit, scale) in PARTS {
+        if nanos >= scale {
+            formatted += &format!("{}{unit}", nanos / scale);
+            nanos %= scale;
+        }
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        assert_eq!(convert("2s", "ms").as_deref(), Some("2000"));
+        assert_eq!(convert("1.5 min", "s").as_deref(), Some("90"));
+        assert_eq!(convert("500ms", "s").as_deref(), Some("0.5"));
+        assert_eq!(convert("2000", "ms").as_deref(), Some("2000"));
+        assert_eq!(convert("2000ms", "ms").as_deref(), Some("2000"));
+        assert_eq!(convert("1GiB", "MiB").as_deref(), Some("1024"));
+        assert_eq!(convert("3 req", "req").as_deref(), Some("3"));
+        assert_eq!(convert("2MB", "ms"), None);
+        assert_eq!(convert("2parsecs", "ms"), None);
+        assert_eq!(convert("1h30m", "s").as_deref(), Some("5400"));
+        assert_eq!(convert("1m30", "s"), None);
+        assert_eq!(convert("1GiB2MiB", "MiB"), None);
+
+        // Exact, where floats would be a hair off a whole number
+        assert_eq!(convert("2.3h", "s").as_deref(), Some("8280"));
+        assert_eq!(convert("1.1GiB", "MiB").as_deref(), Some("1126.4"));
+        assert_eq!(convert("-1.5s", "ms").as_deref(), Some("-1500"));
+        assert_eq!(
+            convert("1s", "min").as_deref(),
+            Some(&*(1.0 / 60.0).to_string())
+        );
+        assert_eq!(convert("1_000", "ms").as_deref(), Some("1000"));
+        assert_eq!(convert("1_500ms", "s").as_deref(), Some("1.5"));
+        assert_eq!(convert("1.2.3s", "ms"), None);
+    }
+
+    #[test]
+    fn test_duration() {
+        let secs = Duration::from_secs;
+        assert_eq!(parse_duration("30s"), Some(secs(30)));
+        assert_eq!(parse_duration("5m"), Some(secs(300)));
+        assert_eq!(parse_duration("1h30m"), Some(secs(5400)));
+        assert_eq!(parse_duration("1h 30min 15s"), Some(secs(5415)));
+        assert_eq!(parse_duration("1.5 s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("90"), Some(secs(90)));
+        assert_eq!(parse_duration("0.25"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_duration("1_000ms"), Some(secs(1)));
+        assert_eq!(parse_duration("2.3h"), Some(secs(8280)));
+        for invalid in ["", "-5s", "1x", "5MB", "h", "1h-30m", "1e400"] {
+            assert_eq!(parse_duration(invalid), None, "{invalid}");
+        }
+
+        assert_eq!(format_duration(&secs(5400)), "1h30m");
+        assert_eq!(format_duration(&Duration::from_millis(1500)), "1s500ms");
+        assert_eq!(format_duration(&Duration::ZERO), "0s");
+        for duration in [secs(93_784), Duration::new(1, 2_003_004)] {
+            assert_eq!(parse_duration(&format_duration(&duration)), Some(duration));
+        }
+    }
+}
@@ -1,92 +1,471 @@
 use std::{
     cmp::min,
+    env,
     io::{self, Write},
 };
 
-use crate::{FlagValue, Flags};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    meta::{synopsis, About, FlagMeta, Positional, ValueHint},
+    units, FlagValue, Flags,
+};
+
+/// Defaults longer than this many characters are cut short with an ellipsis,
+/// unless the full usage is requested.
+const MAX_DEFAULT_WIDTH: usize = 40;
+
+/// In [`UsageStyle::Columns`], names wider than this many characters get a
+/// line of their own, so one long name doesn't squeeze every description.
+const MAX_NAME_WIDTH: usize = 30;
+
+/// How help lays out each flag.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UsageStyle {
+    /// Each flag's name on a line of its own, with its description indented
+    /// below it and a blank line before the next flag.
+    #[default]
+    Stacked,
+    /// Flag names in a left column and their descriptions wrapped in a right
+    /// column, without blank lines, which halves the length of help for
+    /// tools with many flags.
+    Columns,
+}
+
+/// Whether `c` is an invisible formatting character (Unicode category Cf),
+/// e.g. U+202E, which reverses the text after it. The zero width joiner is
+/// left out, since emoji sequences need it.
+fn is_format(c: char) -> bool {
+    matches!(
+        c,
+        '\u{ad}'
+            | '\u{600}'..='\u{605}'
+            | '\u{61c}'
+            | '\u{6dd}'
+            | '\u{70f}'
+            | '\u{890}'..='\u{891}'
+            | '\u{8e2}'
+            | '\u{180e}'
+            | '\u{200b}'..='\u{200c}'
+            | '\u{200e}'..='\u{200f}'
+            | '\u{202a}'..='\u{202e}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{206f}'
+            | '\u{feff}'
+            | '\u{fff9}'..='\u{fffb}'
+            | '\u{110bd}'
+            | '\u{110cd}'
+            | '\u{13430}'..='\u{1343f}'
+            | '\u{1bca0}'..='\u{1bca3}'
+            | '\u{1d173}'..='\u{1d17a}'
+            | '\u{e0001}'
+            | '\u{e0020}'..='\u{e007f}'
+    )
+}
+
+/// `text` with the characters `escape` picks escaped, e.g. `\u{202e}`.
+fn escape_chars(text: &str, escape: impl Fn(char) -> bool) -> String {
+    text.chars()
+        .map(|c| match escape(c) {
+            true => c.escape_default().to_string(),
+            false => c.to_string(),
+        })
+        .collect()
+}
+
+/// A description with its formatting characters escaped, so they can't
+/// disguise the rest of help. Newlines are kept, to break lines as written.
+fn render_description(description: &str) -> String {
+    escape_chars(description, is_format)
+}
+
+/// Makes a default value safe to print: control characters (newlines, ANSI
+/// escapes, ...) and formatting characters are escaped, and long values are
+/// truncated unless `full`.
+fn render_default(value: &str, full: bool) -> String {
+    let escaped = escape_chars(value, |c| c.is_control() || is_format(c));
+    if full || escaped.graphemes(true).count() <= MAX_DEFAULT_WIDTH {
+        return escaped;
+    }
+    let truncated: String = escaped
+        .graphemes(true)
+        .take(MAX_DEFAULT_WIDTH - 1)
+        .collect();
+    format!("{truncated}…")
+}
 
 pub fn generate<W: Write>(flags: &Flags, width: usize, w: &mut W) -> io::Result<()> {
+    generate_inner(flags, width, false, UsageStyle::Stacked, w)
+}
+
+/// Like [`generate`], but shows default values in full instead of truncating
+/// long ones, e.g. for a `--help-all` flag.
+pub fn generate_all<W: Write>(flags: &Flags, width: usize, w: &mut W) -> io::Result<()> {
+    generate_all_styled(flags, width, UsageStyle::Stacked, w)
+}
+
+/// Like [`generate_all`], but laid out in `style`.
+pub fn generate_all_styled<W: Write>(
+    flags: &Flags,
+    width: usize,
+    style: UsageStyle,
+    w: &mut W,
+) -> io::Result<()> {
+    generate_inner(flags, width, true, style, w)
+}
+
+/// Like [`generate`], but laid out in `style`.
+pub fn generate_styled<W: Write>(
+    flags: &Flags,
+    width: usize,
+    style: UsageStyle,
+    w: &mut W,
+) -> io::Result<()> {
+    generate_inner(flags, width, false, style, w)
+}
+
+fn generate_inner<W: Write>(
+    flags: &Flags,
+    width: usize,
+    full: bool,
+    style: UsageStyle,
+    w: &mut W,
+) -> io::Result<()> {
     let mut names: Vec<String> = flags.flags.keys().cloned().collect();
     names.sort();
-    let names = names;
 
-    for (n, name) in names.iter().enumerate() {
+    let mut entries = vec![];
+    for name in &names {
         let flag = flags.get(name);
-        if let None = flag.description {
+        if flag.description.is_none() {
             continue;
         }
         if flag.exclude_from_usage {
             continue;
         }
 
-        w.write(b"--")?;
-        w.write(flag.name.as_bytes())?;
-        if let Some(c) = flag.shorthand {
-            w.write(b" / -")?;
-            w.write(&[c as u8])?;
-        }
-        w.write(b"\n")?;
-
-        let mut desc = flag.description.as_ref().unwrap().to_string();
+        let mut desc = render_description(flag.description.as_ref().unwrap());
         let mut append_default_value = |value: String| {
-            desc += &format!(" (default: {value})");
+            let value = match &flag.auto {
+                Some(expr) => format!("{value} from {expr}"),
+                None => value,
+            };
+            desc += &format!(" (default: {})", render_default(&value, full));
         };
 
+        // Numbers show their unit, e.g. 500ms
+        let unit = flag.unit.as_deref().unwrap_or("");
         match flag.default_value.clone() {
             FlagValue::Bool(v) => append_default_value(v.to_string()),
             FlagValue::String(v) => append_default_value(v),
-            FlagValue::Int64(v) => append_default_value(v.to_string()),
-            FlagValue::Int128(v) => append_default_value(v.to_string()),
-            FlagValue::Float64(v) => append_default_value(v.to_string()),
+            FlagValue::Int64(v) => append_default_value(format!("{v}{unit}")),
+            FlagValue::Int128(v) => append_default_value(format!("{v}{unit}")),
+            FlagValue::Float64(v) => append_default_value(format!("{v}{unit}")),
+            FlagValue::Duration(v) => append_default_value(units::format_duration(&v)),
             FlagValue::StringArray(a) => append_default_value(format!("[{}]", a.join(", "))),
             FlagValue::Int64Array(a) => {
-                let strings: Vec<String> = a.iter().map(|v| v.to_string()).collect();
+                let strings: Vec<String> = a.iter().map(|v| format!("{v}{unit}")).collect();
                 append_default_value(format!("[{}]", strings.join(", ")))
             }
             FlagValue::Int128Array(a) => {
-                let strings: Vec<String> = a.iter().map(|v| v.to_string()).collect();
+                let strings: Vec<String> = a.iter().map(|v| format!("{v}{unit}")).collect();
                 append_default_value(format!("[{}]", strings.join(", ")))
             }
             FlagValue::Float64Array(a) => {
-                let strings: Vec<String> = a.iter().map(|v| v.to_string()).collect();
+                let strings: Vec<String> = a.iter().map(|v| format!("{v}{unit}")).collect();
                 append_default_value(format!("[{}]", strings.join(", ")))
             }
         }
 
-        let indentation = "    ";
-        let max_desc_width = width - indentation.len();
+        entries.push((name_cell(&flag.name, flag.shorthand), desc));
+    }
 
-        let mut l = 0;
-        while l < desc.len() {
-            let remaining = desc.len() - l;
-            let max_wrapped_width = min(max_desc_width, remaining);
-            let mut wrapped_width = max_wrapped_width;
-            let chars: Vec<char> = desc.chars().collect();
-            while remaining > max_desc_width && chars[l + wrapped_width - 1] != ' ' {
-                if wrapped_width == 0 {
-                    wrapped_width = max_wrapped_width;
-                    break;
+    write_flags(&entries, width, style, w)?;
+    w.flush()
+}
+
+/// A flag's name as help shows it, e.g. `--name / -n`.
+fn name_cell(name: &str, shorthand: Option<char>) -> String {
+    match shorthand {
+        Some(c) => format!("--{name} / -{c}"),
+        None => format!("--{name}"),
+    }
+}
+
+/// Writes each flag's name and description, laid out in `style`.
+fn write_flags<W: Write>(
+    entries: &[(String, String)],
+    width: usize,
+    style: UsageStyle,
+    w: &mut W,
+) -> io::Result<()> {
+    match style {
+        UsageStyle::Stacked => {
+            let indentation = "    ";
+            for (i, (name, desc)) in entries.iter().enumerate() {
+                if i > 0 {
+                    w.write_all(b"\n")?;
+                }
+                w.write_all(format!("{name}\n").as_bytes())?;
+                for line in wrap(desc, width.saturating_sub(indentation.len())) {
+                    w.write_all(format!("{indentation}{line}\n").as_bytes())?;
                 }
-                wrapped_width -= 1;
             }
-            w.write(format!("{indentation}{}\n", &desc[l..l + wrapped_width]).as_bytes())?;
-            l += wrapped_width;
         }
+        UsageStyle::Columns => {
+            let indentation = "  ";
+            let gap = "  ";
+            let name_width = |name: &str| name.graphemes(true).count();
+            let widest = entries
+                .iter()
+                .map(|(name, _)| name_width(name))
+                .filter(|&n| n <= MAX_NAME_WIDTH)
+                .max()
+                .unwrap_or(0);
+            let column = indentation.len() + widest + gap.len();
+            for (name, desc) in entries {
+                let mut lines = wrap(desc, width.saturating_sub(column)).into_iter();
+                w.write_all(format!("{indentation}{name}").as_bytes())?;
+                if name_width(name) <= widest {
+                    let padding = " ".repeat(widest - name_width(name));
+                    let first = lines.next().unwrap_or_default();
+                    w.write_all(format!("{padding}{gap}{first}").as_bytes())?;
+                }
+                w.write_all(b"\n")?;
+                for line in lines {
+                    w.write_all(format!("{}{line}\n", " ".repeat(column)).as_bytes())?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
 
-        if n != names.len() - 1 {
-            w.write(b"\n")?;
+/// Splits a description into lines of at most `width` characters.
+fn wrap(desc: &str, width: usize) -> Vec<String> {
+    // Always fit at least one character per line, however narrow the
+    // terminal is
+    let width = width.max(1);
+
+    let graphemes: Vec<&str> = desc.graphemes(true).collect();
+    let mut lines = vec![];
+    let mut l = 0;
+    while l < graphemes.len() {
+        let remaining = graphemes.len() - l;
+        let mut wrapped_width = min(width, remaining);
+        if remaining > width {
+            // Break after the last space that fits, or mid-word if a
+            // single word is wider than the line
+            if let Some(i) = graphemes[l..l + wrapped_width]
+                .iter()
+                .rposition(|&g| g == " ")
+            {
+                wrapped_width = i + 1;
+            }
         }
+        lines.push(graphemes[l..l + wrapped_width].concat());
+        l += wrapped_width;
     }
+    lines
+}
+
+/// Writes help for a struct deriving `cliconf::Parse`: the program's
+/// [`About::help_header`], then each documented flag with the environment
+/// variable that sets it. Flags are listed in the order the fields are
+/// declared.
+pub fn generate_meta<W: Write>(
+    about: &About,
+    flags: &[FlagMeta],
+    width: usize,
+    w: &mut W,
+) -> io::Result<()> {
+    let positionals = [Positional::any()];
+    generate_meta_styled(about, flags, &positionals, width, UsageStyle::Stacked, w)
+}
 
+/// Like [`generate_meta`], but laid out in `style`, and with the
+/// [`synopsis`] naming `positionals`.
+pub fn generate_meta_styled<W: Write>(
+    about: &About,
+    flags: &[FlagMeta],
+    positionals: &[Positional],
+    width: usize,
+    style: UsageStyle,
+    w: &mut W,
+) -> io::Result<()> {
+    generate_meta_inner(about, flags, positionals, width, false, style, w)
+}
+
+/// Like [`generate_meta_styled`], but shows default values in full, like
+/// [`generate_all`].
+pub fn generate_meta_all_styled<W: Write>(
+    about: &About,
+    flags: &[FlagMeta],
+    positionals: &[Positional],
+    width: usize,
+    style: UsageStyle,
+    w: &mut W,
+) -> io::Result<()> {
+    generate_meta_inner(about, flags, positionals, width, true, style, w)
+}
+
+fn generate_meta_inner<W: Write>(
+    about: &About,
+    flags: &[FlagMeta],
+    positionals: &[Positional],
+    width: usize,
+    full: bool,
+    style: UsageStyle,
+    w: &mut W,
+) -> io::Result<()> {
+    w.write_all(about.help_header().as_bytes())?;
+    w.write_all(b"\n")?;
+    w.write_all(synopsis(&about.name, flags, positionals).as_bytes())?;
+    let mut entries = vec![];
+    for flag in flags {
+        let Some(description) = &flag.description else {
+            continue;
+        };
+        let mut desc = render_description(description);
+        if let Some(ValueHint::Choices(choices)) = &flag.value_hint {
+            desc = format!("{desc} (one of: {})", choices.join(", "));
+        }
+        if let Some(unit) = &flag.unit {
+            desc = format!("{desc} (unit: {unit})");
+        }
+        if let Some(default) = &flag.default {
+            desc = format!("{desc} (default: {})", render_default(default, full));
+        }
+        if let Some(var) = &flag.env_var {
+            desc = format!("{desc} (env: {var})");
+        }
+        entries.push((name_cell(&flag.name, flag.shorthand), desc));
+    }
+    if !entries.is_empty() {
+        w.write_all(b"\n")?;
+    }
+    write_flags(&entries, width, style, w)?;
     w.flush()
 }
 
+pub fn generate_meta_string(about: &About, flags: &[FlagMeta], width: usize) -> String {
+    let positionals = [Positional::any()];
+    generate_meta_styled_string(about, flags, &positionals, width, UsageStyle::Stacked)
+}
+
+pub fn generate_meta_styled_string(
+    about: &About,
+    flags: &[FlagMeta],
+    positionals: &[Positional],
+    width: usize,
+    style: UsageStyle,
+) -> String {
+    let mut w = Vec::new();
+    generate_meta_styled(about, flags, positionals, width, style, &mut w)
+        .expect("Failed to generate usage");
+    String::from_utf8(w).expect("Failed to get usage string as utf-8")
+}
+
+pub fn generate_meta_all_styled_string(
+    about: &About,
+    flags: &[FlagMeta],
+    positionals: &[Positional],
+    width: usize,
+    style: UsageStyle,
+) -> String {
+    let mut w = Vec::new();
+    generate_meta_all_styled(about, flags, positionals, width, style, &mut w)
+        .expect("Failed to generate usage");
+    String::from_utf8(w).expect("Failed to get usage string as utf-8")
+}
+
+/// Generates `usage` for the derive macro, which can't tell whether the
+/// `usage` feature is enabled itself.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __usage {
+    (
+        $vis:vis $name:ident,
+        $styled:ident,
+        $all:ident,
+        $all_styled:ident,
+        $flags_metadata:ident,
+        $about:ident,
+        $positionals:ident
+    ) => {
+        /// Help for `--help`: the program's name, version and description,
+        /// how to call it, then each documented field's flag, wrapped to
+        /// `width` columns.
+        $vis fn $name(&self, width: usize) -> ::std::string::String {
+            self.$styled(width, $crate::usage::UsageStyle::Stacked)
+        }
+
+        /// Like `usage`, but laid out in `style`.
+        $vis fn $styled(
+            &self,
+            width: usize,
+            style: $crate::usage::UsageStyle,
+        ) -> ::std::string::String {
+            $crate::usage::generate_meta_styled_string(
+                &Self::$about(),
+                &Self::$flags_metadata(),
+                &Self::$positionals(),
+                width,
+                style,
+            )
+        }
+
+        /// Help for `--help-all`: like `usage`, but with long defaults shown
+        /// in full.
+        $vis fn $all(&self, width: usize) -> ::std::string::String {
+            self.$all_styled(width, $crate::usage::UsageStyle::Stacked)
+        }
+
+        /// Like `usage_all`, but laid out in `style`.
+        $vis fn $all_styled(
+            &self,
+            width: usize,
+            style: $crate::usage::UsageStyle,
+        ) -> ::std::string::String {
+            $crate::usage::generate_meta_all_styled_string(
+                &Self::$about(),
+                &Self::$flags_metadata(),
+                &Self::$positionals(),
+                width,
+                style,
+            )
+        }
+    };
+}
+
+/// The width to wrap usage at: the `COLUMNS` environment variable if it's set
+/// to a positive number, or 80 otherwise.
+pub fn columns() -> usize {
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.trim().parse().ok())
+        .filter(|&columns| columns > 0)
+        .unwrap_or(80)
+}
+
 pub fn generate_string(flags: &Flags, width: usize) -> String {
     let mut w = Vec::new();
     generate(flags, width, &mut w).expect("Failed to generate usage");
     String::from_utf8(w).expect("Failed to get usage string as utf-8")
 }
 
+pub fn generate_all_string(flags: &Flags, width: usize) -> String {
+    let mut w = Vec::new();
+    generate_all(flags, width, &mut w).expect("Failed to generate usage");
+    String::from_utf8(w).expect("Failed to get usage string as utf-8")
+}
+
+pub fn generate_styled_string(flags: &Flags, width: usize, style: UsageStyle) -> String {
+    let mut w = Vec::new();
+    generate_styled(flags, width, style, &mut w).expect("Failed to generate usage");
+    String::from_utf8(w).expect("Failed to get usage string as utf-8")
+}
+
 #[cfg(test)]
 mod tests {
     use crate::Flag;
@@ -131,4 +510,265 @@ mod tests {
         let result = generate_string(&flags, 80);
         assert_eq!(result, target);
     }
+
+    #[test]
+    fn test_generate_auto() {
+        let mut flags = Flags::new();
+        flags.add(
+            Flag::new("workers", FlagValue::Int64(16))
+                .auto("cpus * 2")
+                .description("How many threads to use"),
+        );
+
+        let result = generate_string(&flags, 80);
+        assert_eq!(
+            result,
+            "--workers\n    How many threads to use (default: 16 from cpus * 2)\n"
+        );
+    }
+
+    #[test]
+    fn test_generate_unit() {
+        let mut flags = Flags::new();
+        flags.add(
+            Flag::new("timeout", FlagValue::Int64(500))
+                .unit("ms")
+                .description("How long to wait"),
+        );
+        flags.add(
+            Flag::new("retries", FlagValue::Int64Array(vec![1, 2]))
+                .unit("s")
+                .description("When to retry"),
+        );
+
+        let result = generate_string(&flags, 80);
+        assert_eq!(
+            result,
+            "--retries\n    When to retry (default: [1s, 2s])\n\n--timeout\n    How long to wait (default: 500ms)\n"
+        );
+    }
+
+    #[test]
+    fn test_generate_meta_unit() {
+        let about = About {
+            name: "app".into(),
+            version: "1.0".into(),
+            about: None,
+            authors: None,
+        };
+        let flags = [FlagMeta {
+            name: "timeout".into(),
+            shorthand: None,
+            description: Some("How long to wait".into()),
+            env_var: None,
+            required: false,
+            value_hint: Some(ValueHint::Any),
+            kind: crate::meta::FlagKind::Integer,
+            is_array: false,
+            default: None,
+            unit: Some("ms".into()),
+            secret: false,
+        }];
+        let result = generate_meta_styled_string(&about, &flags, &[], 80, UsageStyle::Stacked);
+        assert!(
+            result.ends_with("--timeout\n    How long to wait (unit: ms)\n"),
+            "{result}"
+        );
+    }
+
+    #[test]
+    fn test_generate_columns() {
+        let mut flags = Flags::new();
+        flags.add(
+            Flag::new("name", FlagValue::String("john".into()))
+                .shorthand('n')
+                .description("The person we want to greet"),
+        );
+        flags.add(
+            Flag::new("timeout", FlagValue::Int64(500))
+                .unit("ms")
+                .description("How long to wait before giving up on the server"),
+        );
+        flags.add(
+            Flag::new("a-flag-with-a-really-long-name", FlagValue::Bool(false))
+                .description("Spills"),
+        );
+
+        let target = "  --a-flag-with-a-really-long-name
+               Spills (default: false)
+  --name / -n  The person we want to greet \n               (default: john)
+  --timeout    How long to wait before giving up \n               on the server (default: 500ms)
+";
+        let result = generate_styled_string(&flags, 50, UsageStyle::Columns);
+        assert_eq!(result, target);
+    }
+
+    #[test]
+    fn test_generate_columns_any_width() {
+        let mut flags = Flags::new();
+        flags.add(
+            Flag::new("name", FlagValue::String("jöhn".into()))
+                .shorthand('n')
+                .description(
+                    "Wraps ünicode text in the column, and a Superduperlongwordthatcantbewrapped",
+                ),
+        );
+        let column = "  --name / -n  ".len();
+
+        for width in 1..200 {
+            let result = generate_styled_string(&flags, width, UsageStyle::Columns);
+            let desc_width = width.saturating_sub(column).max(1);
+            let text: String = result
+                .lines()
+                .map(|line| {
+                    let line = line
+                        .strip_prefix("  --name / -n  ")
+                        .or_else(|| line.strip_prefix(&" ".repeat(column)))
+                        .unwrap();
+                    assert!(
+                        line.chars().count() <= desc_width,
+                        "width {width}: {line:?}"
+                    );
+                    line
+                })
+                .collect();
+            assert_eq!(
+                text,
+                "Wraps ünicode text in the column, and a Superduperlongwordthatcantbewrapped (default: jöhn)"
+            );
+        }
+
+        // Wide enough that nothing wraps
+        let result = generate_styled_string(&flags, 200, UsageStyle::Columns);
+        assert_eq!(result.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_columns() {
+        for (value, expected) in [("120", 120), (" 40 ", 40), ("0", 80), ("wide", 80)] {
+            env::set_var("COLUMNS", value);
+            assert_eq!(columns(), expected, "{value:?}");
+        }
+        env::remove_var("COLUMNS");
+        assert_eq!(columns(), 80);
+    }
+
+    #[test]
+    fn test_generate_any_width() {
+        let mut flags = Flags::new();
+        flags.add(
+            Flag::new("name", FlagValue::String("jöhn".into()))
+                .shorthand('ñ')
+                .description("Wraps ünicode text without splitting characters, and a Superduperlongwordthatcantbewrapped"),
+        );
+
+        for width in 1..200 {
+            let result = generate_string(&flags, width);
+            let desc_width = width.saturating_sub(4).max(1);
+            let text: String = result
+                .lines()
+                .skip(1)
+                .map(|line| {
+                    let line = line.strip_prefix("    ").unwrap();
+                    assert!(
+                        line.chars().count() <= desc_width,
+                        "width {width}: {line:?}"
+                    );
+                    line
+                })
+                .collect();
+            assert_eq!(
+                text,
+                "Wraps ünicode text without splitting characters, and a Superduperlongwordthatcantbewrapped (default: jöhn)"
+            );
+        }
+    }
+
+    #[test]
+    fn test_generate_default_sanitized() {
+        let mut flags = Flags::new();
+        flags
+            .add(Flag::new("sep", FlagValue::String("\n\x1b[31m".into())).description("Separator"));
+        flags.add(
+            Flag::new("motd", FlagValue::String("é".repeat(50))).description("Message of the day"),
+        );
+
+        let result = generate_string(&flags, 200);
+        assert_eq!(
+            result,
+            format!(
+                "--motd\n    Message of the day (default: {}…)\n\n--sep\n    Separator (default: \\n\\u{{1b}}[31m)\n",
+                "é".repeat(39)
+            )
+        );
+
+        let result = generate_all_string(&flags, 200);
+        assert!(result.contains(&format!("(default: {})", "é".repeat(50))));
+
+        let mut w = Vec::new();
+        generate_all_styled(&flags, 200, UsageStyle::Columns, &mut w).unwrap();
+        let result = String::from_utf8(w).unwrap();
+        assert!(result.starts_with(&format!(
+            "  --motd  Message of the day (default: {})\n",
+            "é".repeat(50)
+        )));
+    }
+
+    #[test]
+    fn test_generate_format_chars() {
+        let mut flags = Flags::new();
+        flags.add(
+            Flag::new("ext", FlagValue::String("\u{202e}gpj.exe".into()))
+                .description("Extension\u{202e} to use"),
+        );
+        flags.add(
+            Flag::new("emoji", FlagValue::String("👩\u{200d}💻".into())).description("Joined"),
+        );
+
+        let result = generate_string(&flags, 200);
+        assert_eq!(
+            result,
+            "--emoji\n    Joined (default: 👩\u{200d}💻)\n\n\
+             --ext\n    Extension\\u{202e} to use (default: \\u{202e}gpj.exe)\n"
+        );
+    }
+
+    #[test]
+    fn test_generate_meta_default() {
+        let about = About {
+            name: "app".into(),
+            version: "1.0".into(),
+            about: None,
+            authors: None,
+        };
+        let flags = [FlagMeta {
+            name: "motd".into(),
+            shorthand: None,
+            description: Some("Message\u{2066} of the day".into()),
+            env_var: Some("MOTD".into()),
+            required: false,
+            value_hint: Some(ValueHint::Any),
+            kind: crate::meta::FlagKind::String,
+            is_array: false,
+            default: Some(format!("\u{202e}{}", "é".repeat(50))),
+            unit: None,
+            secret: false,
+        }];
+        let result = generate_meta_styled_string(&about, &flags, &[], 200, UsageStyle::Stacked);
+        assert!(
+            result.ends_with(&format!(
+                "--motd\n    Message\\u{{2066}} of the day (default: \\u{{202e}}{}…) (env: MOTD)\n",
+                "é".repeat(31)
+            )),
+            "{result}"
+        );
+        let result = generate_meta_all_styled_string(&about, &flags, &[], 200, UsageStyle::Columns);
+        assert!(
+            result.ends_with(&format!(
+                "  --motd  Message\\u{{2066}} of the day (default: \\u{{202e}}{}) (env: MOTD)\n",
+                "é".repeat(50)
+            )),
+            "{result}"
+        );
+    }
 }
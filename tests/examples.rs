@@ -0,0 +1,182 @@
+//! Runs the example programs. Plain `cargo test` builds them first, but
+//! `cargo test --test examples` alone doesn't, and would run stale ones.
+
+use std::{env, path::PathBuf};
+
+use assert_cmd::Command;
+
+fn path(name: &str) -> PathBuf {
+    // The test binary is in target/<profile>/deps, and examples in
+    // target/<profile>/examples
+    let mut path = env::current_exe().unwrap();
+    path.pop();
+    path.pop();
+    path.push("examples");
+    path.push(format!("{name}{}", env::consts::EXE_SUFFIX));
+    path
+}
+
+/// The example, with none of the test's environment variables except the
+/// ones Windows needs to start a program.
+fn program(name: &str) -> std::process::Command {
+    let mut command = std::process::Command::new(path(name));
+    command.env_clear();
+    if let Some(root) = env::var_os("SYSTEMROOT") {
+        command.env("SYSTEMROOT", root);
+    }
+    command
+}
+
+fn example(name: &str) -> Command {
+    Command::from_std(program(name))
+}
+
+#[test]
+fn test_readme() {
+    example("readme")
+        .args(["-n", "Dane", "-r", "2", "-N", "Mia", "-N", "Kai"])
+        .assert()
+        .success()
+        .stdout(
+            "Hello, Dane!\n and Hello, Mia!\n and Hello, Kai!\n\
+             Hello, Dane!\n and Hello, Mia!\n and Hello, Kai!\n",
+        );
+    example("readme")
+        .env("SPANISH", "true")
+        .env("NAME", "Ana")
+        .args(["--name", "Luz"])
+        .assert()
+        .success()
+        .stdout("Hola, Luz!\n");
+    example("readme")
+        .arg("--nmae")
+        .assert()
+        .code(2)
+        .stderr("error: Unknown flag: --nmae\n");
+}
+
+#[test]
+fn test_wrapper() {
+    example("wrapper")
+        .env("WRAPPER_RETRIES", "3")
+        .args(["-n", "--", "echo", "--retries", "1"])
+        .assert()
+        .success()
+        .stdout("echo --retries 1 (retries: 3)\n");
+    example("wrapper")
+        .env("WRAPPER_RETRIES", "3")
+        .args(["-n", "-r", "1", "echo"])
+        .assert()
+        .success()
+        .stdout("echo (retries: 1)\n");
+    example("wrapper")
+        .arg("--help")
+        .assert()
+        .success()
+        .stdout("Usage: wrapper [OPTIONS] [--] <COMMAND> [ARGS]...\n");
+    example("wrapper")
+        .arg("-n")
+        .assert()
+        .code(2)
+        .stderr("error: Missing argument: <COMMAND>\n");
+
+    // The wrapper runs itself, which prints the command and succeeds
+    let wrapper = path("wrapper");
+    example("wrapper")
+        .arg("--")
+        .arg(&wrapper)
+        .args(["-n", "x"])
+        .assert()
+        .success()
+        .stdout("x (retries: 0)\n");
+    // ...or exits with 2 each time, which the wrapper passes on
+    example("wrapper")
+        .args(["-r", "2", "--"])
+        .arg(&wrapper)
+        .arg("--nope")
+        .assert()
+        .code(2)
+        .stderr("error: Unknown flag: --nope\n".repeat(3));
+}
+
+#[test]
+fn test_multi() {
+    example("multi")
+        .args(["-v", "b", "--release", "-j", "4"])
+        .assert()
+        .success()
+        .stdout("building release with 4 jobs\ndone\n");
+    example("multi")
+        .args(["run", "app", "--port", "80"])
+        .assert()
+        .success()
+        .stdout("running app on port 80\n");
+    example("multi").arg("-h").assert().success();
+    example("multi").arg("biuld").assert().code(2).stderr(
+        "error: Unknown command: biuld (did you mean build?); the commands are build, run\n",
+    );
+    example("multi")
+        .arg("run")
+        .assert()
+        .code(2)
+        .stderr("error: Missing argument: <APP>\n");
+    example("multi")
+        .args(["build", "extra"])
+        .assert()
+        .code(2)
+        .stderr("error: Unexpected argument: extra\n");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_daemon() {
+    use std::{
+        fs,
+        io::{BufRead, BufReader, Write},
+        process::Stdio,
+    };
+
+    let dir = env::temp_dir().join(format!("cliconf-test-daemon-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("daemon.json");
+    fs::write(
+        &config,
+        r#"{"port": 9000, "workers": 2, "log-level": "debug"}"#,
+    )
+    .unwrap();
+
+    // Lines are written one at a time, so the file can change in between
+    let mut daemon = program("daemon")
+        .env("DAEMON_CONFIG", &config)
+        .env("DAEMON_LOG_LEVEL", "warn")
+        .args(["--workers", "8"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = daemon.stdin.take().unwrap();
+    let mut stdout = BufReader::new(daemon.stdout.take().unwrap()).lines();
+    let mut next_line = || stdout.next().unwrap().unwrap();
+
+    writeln!(stdin, "show").unwrap();
+    assert_eq!(next_line(), "port=9000 workers=8 log-level=warn");
+
+    fs::write(&config, r#"{"port": 9001, "workers": 1}"#).unwrap();
+    writeln!(stdin, "reload").unwrap();
+    assert_eq!(next_line(), "restart needed for port");
+    assert_eq!(next_line(), "reloaded");
+    writeln!(stdin, "show").unwrap();
+    // The argument and variable still win over the file
+    assert_eq!(next_line(), "port=9000 workers=8 log-level=warn");
+
+    drop(stdin);
+    assert!(daemon.wait().unwrap().success());
+    fs::remove_dir_all(&dir).unwrap();
+
+    example("daemon")
+        .env("DAEMON_CONFIG", dir.join("missing.json"))
+        .env("DAEMON_PORT", "http")
+        .assert()
+        .code(2)
+        .stderr("error: Invalid value for DAEMON_PORT: \"http\"\n");
+}
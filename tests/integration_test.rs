@@ -1,4 +1,5 @@
 use cliconf::Parse;
+use proptest::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -17,6 +18,7 @@ struct Conf {
     my_string_vec: Vec<String>,
 }
 
+#[allow(clippy::bool_assert_comparison)]
 fn assertions(c: &Conf) {
     assert_eq!(c.my_bool, true);
     assert_eq!(c.my_string, "1");
@@ -38,12 +40,13 @@ fn test_env() {
         .map(|(key, val)| (key.to_string(), val.to_string()))
         .collect();
 
-    c.parse_env(vars);
+    c.parse_env(vars).unwrap();
 
     assertions(&c);
 }
 
 #[test]
+#[allow(clippy::useless_vec)]
 fn test_args() {
     let mut c = Conf::default();
 
@@ -62,12 +65,13 @@ fn test_args() {
     .map(|s| s.to_string())
     .collect();
 
-    c.parse_args(args);
+    c.parse_args(args).unwrap();
 
     assertions(&c);
 }
 
 #[test]
+#[allow(clippy::useless_vec)]
 fn test_args_shorthand() {
     let mut c = Conf::default();
 
@@ -76,7 +80,35 @@ fn test_args_shorthand() {
         .map(|s| s.to_string())
         .collect();
 
-    c.parse_args(args);
+    c.parse_args(args).unwrap();
+
+    assertions(&c);
+}
+
+#[test]
+fn test_args_inline_values() {
+    let mut c = Conf::default();
+
+    let args: Vec<String> = ["--my-bool=true", "-s=1", "--my-num=1", "-v=1", "-v=2"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+    c.parse_args(args).unwrap();
+
+    assertions(&c);
+}
+
+#[test]
+fn test_args_short_clusters() {
+    let mut c = Conf::default();
+
+    let args: Vec<String> = ["-bs", "1", "--my-num", "1", "-v1", "-v=2"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+    c.parse_args(args).unwrap();
 
     assertions(&c);
 }
@@ -96,3 +128,2527 @@ fn test_json() {
 
     assertions(&c);
 }
+
+mod hygiene {
+    #![no_implicit_prelude]
+    #![allow(dead_code, non_upper_case_globals)]
+
+    // Generated locals must not resolve to items in the user's scope
+    const value: i32 = 0;
+    const arg: i32 = 0;
+
+    #[derive(::cliconf::Parse, Default)]
+    pub struct Conf {
+        pub arg: ::std::string::String,
+        pub value: i32,
+        pub name: bool,
+        #[cliconf(delimiter = ",")]
+        pub vars: ::std::vec::Vec<::std::string::String>,
+    }
+
+    #[derive(::cliconf::Parse, Default)]
+    #[cliconf(matcher = "hashed", print_config)]
+    pub struct HashedConf {
+        pub arg: ::std::string::String,
+        pub value: i32,
+        pub list: ::std::vec::Vec<i32>,
+    }
+
+    #[derive(::cliconf::Parse)]
+    pub struct DefaultConf {
+        #[cliconf(default = "1")]
+        pub value: i32,
+        #[cliconf(default = "a")]
+        pub list: ::std::vec::Vec<::std::string::String>,
+    }
+
+    #[derive(::cliconf::Parse, Default)]
+    #[cliconf(unset_flag = "unset", print_config)]
+    pub struct FlattenedConf {
+        pub value: i32,
+        #[cliconf(flatten)]
+        pub inner: HashedConf,
+    }
+
+    #[derive(::cliconf::Parse, Default)]
+    #[cliconf(unset_flag = "unset", print_config)]
+    pub struct RequiredConf {
+        #[cliconf(required)]
+        pub value: i32,
+        #[cliconf(deprecated = "use --value instead")]
+        pub old_value: i32,
+        #[cliconf(immutable, env_prefix_map = "MAP_")]
+        pub map: ::std::collections::HashMap<::std::string::String, ::std::string::String>,
+        #[cliconf(sources)]
+        pub sources: ::cliconf::Sources,
+        #[cliconf(flatten)]
+        pub inner: FlattenedConf,
+    }
+}
+
+#[test]
+fn test_hygiene() {
+    let mut c = hygiene::Conf::default();
+
+    c.parse_env(HashMap::from([("VARS".into(), "a,b".into())]))
+        .unwrap();
+    let args: Vec<String> = ["--arg", "1", "--value", "2", "--name", "pos"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let positionals = c.parse_args(args).unwrap();
+
+    assert_eq!(c.arg, "1");
+    assert_eq!(c.value, 2);
+    assert!(c.name);
+    assert_eq!(c.vars, ["a", "b"]);
+    assert_eq!(positionals, ["pos"]);
+
+    let mut c = hygiene::FlattenedConf::default();
+    let args: Vec<String> = [
+        "--inner-arg",
+        "1",
+        "--inner-value",
+        "2",
+        "--unset",
+        "inner-value",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    c.parse_args(args).unwrap();
+    assert_eq!(c.inner.arg, "1");
+    assert_eq!(c.inner.value, 0);
+
+    let c = hygiene::DefaultConf::new();
+    assert_eq!(c.value, 1);
+    assert_eq!(c.list, ["a"]);
+}
+
+#[derive(Parse, Default)]
+#[cliconf(matcher = "bucketed")]
+struct BucketedConf {
+    #[cliconf(shorthand = 'a')]
+    aa: bool,
+    bb: String,
+    #[cliconf(shorthand = 'c')]
+    ccc: i32,
+    dd_dd: Vec<String>,
+}
+
+#[test]
+fn test_bucketed_matcher() {
+    let mut c = BucketedConf::default();
+
+    let args: Vec<String> = ["-a", "--bb", "1", "-c", "2", "--dd-dd", "3", "--dd-dd", "4"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    c.parse_args(args).unwrap();
+
+    assert!(c.aa);
+    assert_eq!(c.bb, "1");
+    assert_eq!(c.ccc, 2);
+    assert_eq!(c.dd_dd, ["3", "4"]);
+}
+
+#[derive(Parse, Default)]
+#[cliconf(matcher = "hashed")]
+struct HashedConf {
+    #[cliconf(shorthand = 'a')]
+    aa: bool,
+    bb: String,
+    #[cliconf(shorthand = 'c')]
+    ccc: i32,
+    dd_dd: Vec<String>,
+}
+
+#[test]
+fn test_hashed_matcher() {
+    let mut c = HashedConf::default();
+
+    let args: Vec<String> = ["-a", "--bb", "1", "-c", "2", "--dd-dd=3", "--dd-dd", "4"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    c.parse_args(args).unwrap();
+    c.parse_env(HashMap::from([("BB".into(), "5".into())]))
+        .unwrap();
+
+    assert!(c.aa);
+    assert_eq!(c.bb, "5");
+    assert_eq!(c.ccc, 2);
+    assert_eq!(c.dd_dd, ["3", "4"]);
+    assert!(c.parse_args(vec!["--ee".into()]).is_err());
+}
+
+#[derive(Parse, Default)]
+struct ProcessEnvConf {
+    cliconf_test_process_env_num: i32,
+    #[cliconf(delimiter = ":")]
+    cliconf_test_process_env_vec: Vec<String>,
+}
+
+#[test]
+fn test_process_env() {
+    let mut c = ProcessEnvConf::default();
+
+    std::env::set_var("CLICONF_TEST_PROCESS_ENV_NUM", "7");
+    std::env::set_var("CLICONF_TEST_PROCESS_ENV_VEC", "a:b");
+    c.parse_process_env().unwrap();
+
+    assert_eq!(c.cliconf_test_process_env_num, 7);
+    assert_eq!(c.cliconf_test_process_env_vec, ["a", "b"]);
+}
+
+#[derive(Parse, Default)]
+#[cliconf(env_case_insensitive = true)]
+struct CaseInsensitiveConf {
+    path: String,
+}
+
+#[test]
+fn test_env_case_insensitive() {
+    let mut c = CaseInsensitiveConf::default();
+
+    c.parse_env(HashMap::from([("Path".into(), "/bin".into())]))
+        .unwrap();
+
+    assert_eq!(c.path, "/bin");
+}
+
+#[derive(Parse, Default)]
+#[cliconf(env_case_insensitive = true)]
+struct ProcessCaseInsensitiveConf {
+    cliconf_test_folded: String,
+}
+
+#[test]
+fn test_process_env_case_insensitive() {
+    let mut c = ProcessCaseInsensitiveConf::default();
+
+    std::env::set_var("Cliconf_Test_Folded", "folded");
+    c.parse_process_env().unwrap();
+
+    assert_eq!(c.cliconf_test_folded, "folded");
+}
+
+#[derive(Parse, Default)]
+#[cliconf(vis = "pub(crate)", rename_methods(parse_args = "apply_cli"))]
+struct RenamedConf {
+    name: String,
+}
+
+impl RenamedConf {
+    fn parse_args(&self) -> &str {
+        "user-defined"
+    }
+}
+
+#[test]
+fn test_rename_methods() {
+    let mut c = RenamedConf::default();
+
+    c.apply_cli(vec!["--name".into(), "john".into()]).unwrap();
+
+    assert_eq!(c.name, "john");
+    assert_eq!(c.parse_args(), "user-defined");
+}
+
+#[derive(Parse, Default)]
+struct LabelsConf {
+    #[cliconf(env_prefix_map = "MYAPP_LABEL_")]
+    labels: std::collections::BTreeMap<String, String>,
+    #[cliconf(env_prefix_map = "MYAPP_LIMIT_")]
+    limits: HashMap<String, i32>,
+}
+
+#[test]
+fn test_env_prefix_map() {
+    let mut c = LabelsConf::default();
+    c.labels.insert("stale".into(), "1".into());
+
+    c.parse_env(HashMap::from([
+        ("MYAPP_LABEL_TEAM".into(), "infra".into()),
+        ("MYAPP_LABEL_TIER".into(), "gold".into()),
+        ("MYAPP_LIMIT_CPU".into(), "4".into()),
+        ("MYAPP_OTHER".into(), "x".into()),
+    ]))
+    .unwrap();
+
+    assert_eq!(
+        c.labels.into_iter().collect::<Vec<_>>(),
+        [
+            ("team".to_string(), "infra".to_string()),
+            ("tier".to_string(), "gold".to_string())
+        ]
+    );
+    assert_eq!(c.limits, HashMap::from([("cpu".to_string(), 4)]));
+}
+
+#[derive(Parse)]
+#[cliconf(unset_sentinel = "@unset", unset_flag = "unset")]
+struct UnsetConf {
+    verbose: bool,
+    name: String,
+}
+
+impl Default for UnsetConf {
+    fn default() -> Self {
+        Self {
+            verbose: false,
+            name: "world".into(),
+        }
+    }
+}
+
+#[test]
+fn test_unset() {
+    let mut c = UnsetConf::default();
+    c.parse_env(HashMap::from([
+        ("VERBOSE".into(), "true".into()),
+        ("NAME".into(), "john".into()),
+    ]))
+    .unwrap();
+
+    c.parse_args(vec![
+        "--unset".into(),
+        "verbose".into(),
+        "--name".into(),
+        "@unset".into(),
+    ])
+    .unwrap();
+
+    assert!(!c.verbose);
+    assert_eq!(c.name, "world");
+}
+
+#[derive(Parse, Default)]
+#[cliconf(set_flag = "set")]
+struct SetConf {
+    verbose: bool,
+    max_conns: i32,
+}
+
+#[test]
+fn test_set_flag() {
+    let mut c = SetConf::default();
+
+    c.parse_args(vec![
+        "--set".into(),
+        "verbose=true".into(),
+        "--set".into(),
+        "max_conns=8".into(),
+    ])
+    .unwrap();
+
+    assert!(c.verbose);
+    assert_eq!(c.max_conns, 8);
+}
+
+#[test]
+fn test_freeze() {
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let mut c = SetConf::default();
+    c.parse_args(vec!["--max-conns".into(), "8".into()])
+        .unwrap();
+    let c = c.freeze();
+
+    assert_send_sync(&c);
+    assert_eq!(c.max_conns, 8);
+}
+
+#[derive(Parse, Default)]
+struct ReloadConf {
+    #[cliconf(immutable)]
+    listen: String,
+    log_level: String,
+}
+
+#[test]
+fn test_reload() {
+    let mut c = ReloadConf {
+        listen: ":80".into(),
+        log_level: "info".into(),
+    };
+
+    let restart = c
+        .reload(ReloadConf {
+            listen: ":8080".into(),
+            log_level: "debug".into(),
+        })
+        .unwrap();
+
+    assert_eq!(restart, ["listen"]);
+    assert_eq!(c.listen, ":80");
+    assert_eq!(c.log_level, "debug");
+}
+
+#[derive(Parse, Default)]
+struct ReloadServerConf {
+    #[cliconf(immutable)]
+    port: u16,
+    #[cliconf(immutable)]
+    worker_threads: u32,
+    #[cliconf(required)]
+    host: String,
+    tls: bool,
+    #[cliconf(sources)]
+    sources: cliconf::Sources,
+}
+
+#[derive(Parse, Default)]
+struct ReloadAppConf {
+    log_level: String,
+    #[cliconf(flatten)]
+    server: ReloadServerConf,
+}
+
+#[test]
+fn test_reload_rollback() {
+    let load = |args: &[&str]| {
+        let mut c = ReloadAppConf::default();
+        c.parse_args(args.iter().map(|arg| arg.to_string()).collect())
+            .unwrap();
+        c
+    };
+    let mut c = load(&["--server-port=80", "--server-host=a", "--log-level=info"]);
+
+    // The new configuration is missing --server-host, so none of it applies
+    let err = c
+        .reload(load(&["--server-port=81", "--log-level=debug"]))
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Missing required flags: --server-host");
+    assert_eq!(c.log_level, "info");
+    assert_eq!(c.server.host, "a");
+    assert_eq!(c.server.port, 80);
+
+    let restart = c
+        .reload(load(&[
+            "--server-worker-threads=4",
+            "--server-port=81",
+            "--server-host=b",
+        ]))
+        .unwrap();
+    // Named by flag, like Flags::reload does
+    assert_eq!(restart, ["server-port", "server-worker-threads"]);
+    assert_eq!(c.log_level, "");
+    assert_eq!(c.server.host, "b");
+    assert_eq!(c.server.port, 80);
+    assert_eq!(c.server.sources.get("port"), &cliconf::Source::Arg(Some(1)));
+}
+
+#[derive(Parse, Default)]
+#[cliconf(print_config)]
+struct UnitConf {
+    #[cliconf(unit = "ms", delimiter = ",")]
+    timeouts: Vec<u64>,
+    #[cliconf(unit = "MiB")]
+    cache_size: f64,
+    #[cliconf(unit = "s")]
+    wait: u64,
+}
+
+#[test]
+fn test_unit() {
+    let mut c = UnitConf::default();
+
+    c.parse_env(HashMap::from([("TIMEOUTS".into(), "1s,250,2m".into())]))
+        .unwrap();
+    c.parse_args(vec!["--cache-size".into(), "1536KiB".into()])
+        .unwrap();
+
+    assert_eq!(c.timeouts, [1000, 250, 120000]);
+    assert_eq!(c.cache_size, 1.5);
+
+    c.parse_args(vec!["--wait=2.3h".into(), "--cache-size=1.1GiB".into()])
+        .unwrap();
+    assert_eq!(c.wait, 8280);
+    assert_eq!(c.cache_size, 1126.4);
+    c.parse_args(vec!["--wait".into(), "1_000".into()]).unwrap();
+    assert_eq!(c.wait, 1000);
+
+    let units: Vec<_> = UnitConf::flags_metadata()
+        .into_iter()
+        .map(|meta| meta.unit)
+        .collect();
+    assert_eq!(
+        units,
+        [Some("ms".into()), Some("MiB".into()), Some("s".into())]
+    );
+    let err = c.parse_args(vec!["--print-config=env".into()]).unwrap_err();
+    let cliconf::Error::PrintConfigRequested(out) = err else {
+        panic!("{err}");
+    };
+    assert_eq!(
+        out,
+        "TIMEOUTS=1000ms,250ms,120000ms\nCACHE_SIZE=1126.4MiB\nWAIT=1000s\n"
+    );
+}
+
+#[derive(Parse, Default)]
+struct AutoConf {
+    #[cliconf(auto = "cpus")]
+    workers: usize,
+    #[cliconf(auto = "free_port")]
+    port: u16,
+}
+
+#[test]
+fn test_auto() {
+    let mut c = AutoConf::default();
+
+    c.resolve_auto(&cliconf::auto::Resolvers::default())
+        .unwrap();
+    assert!(c.workers >= 1);
+    assert_ne!(c.port, 0);
+
+    c.parse_args(vec!["--port".into(), "80".into()]).unwrap();
+    assert_eq!(c.port, 80);
+}
+
+#[derive(Parse, Default)]
+struct TrackedAutoConf {
+    #[cliconf(auto = "two")]
+    workers: i64,
+    #[cliconf(auto = "two")]
+    port: i64,
+    #[cliconf(sources)]
+    sources: cliconf::Sources,
+}
+
+#[test]
+fn test_auto_parity() {
+    use cliconf::{auto::Resolvers, Flag, FlagValue, Flags, Source};
+
+    let mut resolvers = Resolvers::empty();
+    resolvers.register("two", || Ok("2".into()));
+    let args = || vec!["--port".to_string(), "80".to_string()];
+
+    let mut flags = Flags::new();
+    flags.add(Flag::new("workers", FlagValue::Int64(0)).auto("two"));
+    flags.add(Flag::new("port", FlagValue::Int64(0)).auto("two"));
+    flags.parse_args(args()).unwrap();
+    flags.resolve_auto(&resolvers).unwrap();
+
+    let mut c = TrackedAutoConf::default();
+    c.parse_args(args()).unwrap();
+    c.resolve_auto(&resolvers).unwrap();
+
+    // Values a source already set are kept on both paths
+    assert_eq!(flags.get("port").value, FlagValue::Int64(c.port));
+    assert_eq!(flags.get("workers").value, FlagValue::Int64(c.workers));
+    assert_eq!((c.port, c.workers), (80, 2));
+    assert_eq!(&flags.get("port").source, c.sources.get("port"));
+    assert_eq!(&flags.get("workers").source, c.sources.get("workers"));
+    assert_eq!(c.sources.get("workers"), &Source::Auto("two".into()));
+}
+
+#[derive(Parse, Default)]
+struct PointerConf {
+    name: Box<u64>,
+    verbose: std::rc::Rc<bool>,
+    #[cliconf(delimiter = ",")]
+    tags: std::sync::Arc<Vec<String>>,
+    #[cliconf(delimiter = ",")]
+    ids: std::rc::Rc<Vec<u32>>,
+}
+
+#[test]
+fn test_pointer_fields() {
+    let mut c = PointerConf::default();
+
+    c.parse_env(HashMap::from([("TAGS".into(), "a,b".into())]))
+        .unwrap();
+    c.parse_args(vec![
+        "--name".into(),
+        "7".into(),
+        "--verbose".into(),
+        "--ids".into(),
+        "1".into(),
+        "--ids".into(),
+        "2".into(),
+    ])
+    .unwrap();
+
+    assert_eq!(*c.name, 7);
+    assert!(*c.verbose);
+    assert_eq!(*c.tags, ["a", "b"]);
+    assert_eq!(*c.ids, [1, 2]);
+}
+
+#[derive(Parse)]
+struct StringTypesConf {
+    greeting: std::borrow::Cow<'static, str>,
+    #[cliconf(delimiter = ",")]
+    names: Vec<std::borrow::Cow<'static, str>>,
+    host: smol_str::SmolStr,
+    user: compact_str::CompactString,
+}
+
+#[test]
+fn test_string_types() {
+    let mut c = StringTypesConf {
+        greeting: "hello".into(),
+        names: vec![],
+        host: "localhost".into(),
+        user: "root".into(),
+    };
+    assert!(matches!(c.greeting, std::borrow::Cow::Borrowed(_)));
+
+    c.parse_env(HashMap::from([("NAMES".into(), "a,b".into())]))
+        .unwrap();
+    c.parse_args(vec![
+        "--greeting".into(),
+        "hi".into(),
+        "--host".into(),
+        "example.com".into(),
+        "--user".into(),
+        "john".into(),
+    ])
+    .unwrap();
+
+    assert_eq!(c.greeting, "hi");
+    assert_eq!(c.names, ["a", "b"]);
+    assert_eq!(c.host, "example.com");
+    assert_eq!(c.user, "john");
+}
+
+#[derive(Parse, Default)]
+struct DocConf {
+    /// The person we want
+    /// to greet.
+    ///
+    /// Defaults to nobody.
+    #[cliconf(shorthand = 'n')]
+    name: String,
+    /// Not shown
+    #[cliconf(description = "Overridden")]
+    count: i32,
+    undocumented: bool,
+}
+
+#[test]
+fn test_flags_metadata() {
+    let meta = DocConf::flags_metadata();
+
+    assert_eq!(
+        meta[0],
+        cliconf::meta::FlagMeta {
+            name: "name".into(),
+            shorthand: Some('n'),
+            description: Some("The person we want to greet.\n\nDefaults to nobody.".into()),
+            env_var: Some("NAME".into()),
+            required: false,
+            value_hint: Some(cliconf::meta::ValueHint::Any),
+            kind: cliconf::meta::FlagKind::String,
+            is_array: false,
+            default: None,
+            unit: None,
+            secret: false,
+        }
+    );
+    assert_eq!(meta[1].description.as_deref(), Some("Overridden"));
+    assert_eq!(meta[1].kind, cliconf::meta::FlagKind::Integer);
+    assert_eq!(meta[2].description, None);
+    assert_eq!(meta[2].kind, cliconf::meta::FlagKind::Bool);
+
+    let meta = DefaultsConf::flags_metadata();
+    let get = |name: &str| meta.iter().find(|meta| meta.name == name).unwrap();
+    assert_eq!(get("timeout-ms").default.as_deref(), Some("2s"));
+    assert_eq!(get("tags").kind, cliconf::meta::FlagKind::String);
+    assert!(get("tags").is_array);
+    assert_eq!(get("weights").default, None);
+    assert_eq!(get("retries").kind, cliconf::meta::FlagKind::Integer);
+    assert_eq!(get("retries").default.as_deref(), Some("3"));
+    assert_eq!(get("dir").kind, cliconf::meta::FlagKind::Path);
+    assert_eq!(get("name").default, None);
+}
+
+#[derive(Parse, Default)]
+#[cliconf(bin_name = "builder")]
+struct BuildConf {
+    /// Where to put the output
+    #[cliconf(shorthand = 'o')]
+    out_dir: std::path::PathBuf,
+    /// Build with optimizations
+    release: bool,
+    jobs: u8,
+}
+
+#[test]
+fn test_completions() {
+    use cliconf::{completions::Shell, meta::ValueHint};
+
+    let meta = BuildConf::flags_metadata();
+    assert_eq!(meta[0].value_hint, Some(ValueHint::Path));
+    assert_eq!(meta[1].value_hint, None);
+    assert_eq!(meta[2].value_hint, Some(ValueHint::Any));
+    assert_eq!(
+        BuildConf::completions(Shell::Fish),
+        "function __builder_values
+    set -l words (commandline -opc) (commandline -ct)
+    $words[1] __complete $words[2..-1] 2>/dev/null | string match -v -r '^:[0-9]+$'
+end
+complete -c builder -l out-dir -s o -r -F -d 'Where to put the output'
+complete -c builder -l release -d 'Build with optimizations'
+complete -c builder -l jobs -x -a '(__builder_values)'
+"
+    );
+    assert!(BuildConf::completions(Shell::Bash).ends_with("complete -F _builder builder\n"));
+    assert!(BuildConf::completions(Shell::Zsh).starts_with("#compdef builder\n"));
+}
+
+fn complete_cluster(_: &str) -> Vec<String> {
+    vec!["prod-eu".into(), "prod-us".into(), "staging".into()]
+}
+
+#[derive(Parse, Default)]
+struct TargetConf {
+    #[cliconf(completer = complete_cluster)]
+    cluster: String,
+}
+
+#[derive(Parse, Default)]
+struct DeployTargetConf {
+    /// Deploy without asking
+    yes: bool,
+    #[cliconf(flatten)]
+    target: TargetConf,
+}
+
+#[test]
+fn test_complete() {
+    let c = DeployTargetConf::default();
+    let complete = |args: &[&str]| {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        c.complete(&args).map(|completions| completions.to_string())
+    };
+    assert_eq!(complete(&["--yes"]), None);
+    assert_eq!(
+        complete(&["__complete", "--target-cluster", "prod"]).unwrap(),
+        "prod-eu\nprod-us\n:4\n"
+    );
+    assert_eq!(
+        complete(&["__complete", "--"]).unwrap(),
+        "--target-cluster\n--yes\tDeploy without asking\n:4\n"
+    );
+}
+
+#[derive(Parse, Default)]
+struct PathConf {
+    #[cliconf(expand)]
+    cache_dir: std::path::PathBuf,
+    #[cliconf(expand, delimiter = ":")]
+    search_path: Vec<std::path::PathBuf>,
+    raw: std::path::PathBuf,
+}
+
+#[test]
+fn test_expand() {
+    let home = std::path::PathBuf::from(std::env::var("HOME").unwrap());
+    let mut c = PathConf::default();
+    c.parse_env(HashMap::from([(
+        "SEARCH_PATH".into(),
+        "~/bin:/usr/bin".into(),
+    )]))
+    .unwrap();
+    c.parse_args(
+        ["--cache-dir", "$HOME/.cache", "--raw", "~/x"]
+            .map(String::from)
+            .into(),
+    )
+    .unwrap();
+    assert_eq!(c.cache_dir, home.join(".cache"));
+    assert_eq!(c.search_path, [home.join("bin"), "/usr/bin".into()]);
+    assert_eq!(c.raw, std::path::PathBuf::from("~/x"));
+
+    let err = c
+        .parse_args(
+            ["--cache-dir", "${CLICONF_TEST_UNSET}"]
+                .map(String::from)
+                .into(),
+        )
+        .unwrap_err();
+    assert!(matches!(err, cliconf::Error::InvalidValue { flag, .. } if flag == "--cache-dir"));
+}
+
+#[test]
+fn test_nixos_module() {
+    let module = BuildConf::nixos_module();
+    assert!(module.contains("  options.services.builder = {\n"));
+    assert!(module.contains(
+        "      out-dir = lib.mkOption {
+        type = lib.types.nullOr lib.types.path;
+        default = null;
+        description = \"Where to put the output\";
+      };
+"
+    ));
+    assert!(module.contains("environment.etc.\"builder/config.json\""));
+}
+
+#[derive(Parse)]
+#[cliconf(to_args, dump)]
+struct DurationConf {
+    #[cliconf(default = "30s")]
+    timeout: std::time::Duration,
+    #[cliconf(delimiter = ",")]
+    backoff: Vec<std::time::Duration>,
+    grace: Option<std::time::Duration>,
+}
+
+#[test]
+fn test_duration() {
+    use std::time::Duration;
+
+    let mut c = DurationConf::new();
+    assert_eq!(c.timeout, Duration::from_secs(30));
+    c.parse_env(HashMap::from([("BACKOFF".into(), "100ms,1.5s".into())]))
+        .unwrap();
+    c.parse_args(
+        ["--timeout", "1h30m", "--grace", "90"]
+            .map(String::from)
+            .into(),
+    )
+    .unwrap();
+    assert_eq!(c.timeout, Duration::from_secs(5400));
+    assert_eq!(
+        c.backoff,
+        [Duration::from_millis(100), Duration::from_millis(1500)]
+    );
+    assert_eq!(c.grace, Some(Duration::from_secs(90)));
+    assert_eq!(
+        c.to_args(),
+        [
+            "--timeout=1h30m",
+            "--backoff=100ms",
+            "--backoff=1s500ms",
+            "--grace=1m30s"
+        ]
+    );
+
+    let err = c
+        .parse_args(["--timeout", "soon"].map(String::from).into())
+        .unwrap_err();
+    assert!(matches!(err, cliconf::Error::InvalidValue { flag, .. } if flag == "--timeout"));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_duration_json() {
+    let mut c = DurationConf::new();
+    c.parse_str(
+        r#"{ "timeout": 2.5, "backoff": ["1s", 2] }"#,
+        cliconf::Format::Json,
+    )
+    .unwrap();
+    assert_eq!(c.timeout, std::time::Duration::from_millis(2500));
+    assert_eq!(
+        c.backoff,
+        [
+            std::time::Duration::from_secs(1),
+            std::time::Duration::from_secs(2)
+        ]
+    );
+}
+
+#[derive(Parse, Default)]
+#[cliconf(to_args)]
+struct AddrConf {
+    listen: Option<std::net::SocketAddr>,
+    #[cliconf(delimiter = ",")]
+    allow: Vec<std::net::IpAddr>,
+    bind: Option<std::net::Ipv6Addr>,
+}
+
+#[test]
+fn test_addresses() {
+    let mut c = AddrConf::default();
+    c.parse_env(HashMap::from([("ALLOW".into(), "10.0.0.1,::1".into())]))
+        .unwrap();
+    c.parse_args(["--listen", "0.0.0.0:8080"].map(String::from).into())
+        .unwrap();
+    assert_eq!(c.listen, Some("0.0.0.0:8080".parse().unwrap()));
+    assert_eq!(
+        c.allow,
+        [
+            std::net::IpAddr::from([10, 0, 0, 1]),
+            std::net::Ipv6Addr::LOCALHOST.into()
+        ]
+    );
+    assert_eq!(
+        c.to_args(),
+        ["--listen=0.0.0.0:8080", "--allow=10.0.0.1", "--allow=::1"]
+    );
+
+    // Rejected while parsing, rather than when the program binds
+    for args in [
+        ["--listen", "0.0.0.0"],
+        ["--listen", "localhost:8080"],
+        ["--bind", "127.0.0.1"],
+    ] {
+        let err = c.parse_args(args.map(String::from).into()).unwrap_err();
+        assert!(
+            matches!(err, cliconf::Error::InvalidValue { ref flag, .. } if flag == args[0]),
+            "{err}"
+        );
+    }
+}
+
+#[derive(cliconf::FlagEnum, Debug, Clone, Copy, PartialEq)]
+enum LogLevel {
+    Debug,
+    Info,
+    #[cliconf(rename = "warning")]
+    Warn,
+    OnlyErrors,
+}
+
+#[derive(Parse)]
+#[cliconf(bin_name = "logger", to_args)]
+struct LogConf {
+    /// How much to log
+    #[cliconf(default = "info")]
+    level: LogLevel,
+    quiet_for: Vec<LogLevel>,
+}
+
+#[test]
+fn test_flag_enum() {
+    use cliconf::{completions::Shell, meta::ValueHint};
+
+    let mut c = LogConf::default();
+    assert_eq!(c.level, LogLevel::Info);
+    c.parse_env(HashMap::from([("QUIET_FOR".into(), "debug".into())]))
+        .unwrap();
+    c.parse_args(
+        ["--level", "WARNING", "--quiet-for", "only-errors"]
+            .map(String::from)
+            .into(),
+    )
+    .unwrap();
+    assert_eq!(c.level, LogLevel::Warn);
+    assert_eq!(c.quiet_for, [LogLevel::OnlyErrors]);
+    assert_eq!(c.to_args(), ["--level=warning", "--quiet-for=only-errors"]);
+
+    let err = c
+        .parse_args(["--level", "warn"].map(String::from).into())
+        .unwrap_err();
+    assert_eq!(err.code(), "CLICONF_E_INVALID_CHOICE");
+    assert_eq!(
+        err.to_string(),
+        r#"Invalid value for --level: "warn" (expected one of: debug, info, warning, only-errors)"#
+    );
+    let err = c
+        .parse_env(HashMap::from([("LEVEL".into(), "loud".into())]))
+        .unwrap_err();
+    assert!(matches!(err, cliconf::Error::InvalidChoice { ref flag, .. } if flag == "LEVEL"));
+
+    let choices: Vec<String> = ["debug", "info", "warning", "only-errors"]
+        .map(String::from)
+        .into();
+    let meta = LogConf::flags_metadata();
+    assert_eq!(
+        meta[0].value_hint,
+        Some(ValueHint::Choices(choices.clone()))
+    );
+    assert_eq!(meta[1].value_hint, Some(ValueHint::Choices(choices)));
+    assert!(LogConf::completions(Shell::Fish).starts_with(
+        "complete -c logger -l level -x -a 'debug info warning only-errors' -d 'How much to log'\n"
+    ));
+    #[cfg(feature = "usage")]
+    assert!(c.usage(100).ends_with(
+        "--level\n    How much to log (one of: debug, info, warning, only-errors) (default: info) (env: LEVEL)\n"
+    ));
+}
+
+fn parse_size(s: &str) -> Result<u64, std::num::ParseIntError> {
+    match s.strip_suffix("KiB") {
+        Some(n) => n.parse::<u64>().map(|n| n * 1024),
+        None => s.parse(),
+    }
+}
+
+fn parse_pair(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s.split_once('=').ok_or("expected KEY=VALUE")?;
+    Ok((key.into(), value.into()))
+}
+
+#[derive(Debug, PartialEq)]
+struct Date(u16, u8, u8);
+
+mod dates {
+    pub fn parse(s: &str) -> Result<super::Date, Box<dyn std::error::Error>> {
+        let mut parts = s.splitn(3, '-');
+        let mut next = || parts.next().ok_or("expected YYYY-MM-DD");
+        Ok(super::Date(
+            next()?.parse()?,
+            next()?.parse()?,
+            next()?.parse()?,
+        ))
+    }
+}
+
+#[derive(Parse)]
+struct ParseWithConf {
+    #[cliconf(default = "1KiB", parse_with = parse_size)]
+    limit: u64,
+    #[cliconf(parse_with = parse_pair, delimiter = ",")]
+    labels: Vec<(String, String)>,
+    #[cliconf(parse_with = dates::parse)]
+    since: Option<Date>,
+}
+
+#[test]
+fn test_parse_with() {
+    let mut c = ParseWithConf::default();
+    assert_eq!(c.limit, 1024);
+    c.parse_env(HashMap::from([("LABELS".into(), "a=1,b=2".into())]))
+        .unwrap();
+    c.parse_args(
+        ["--limit", "4KiB", "--since", "2024-02-29"]
+            .map(String::from)
+            .into(),
+    )
+    .unwrap();
+    assert_eq!(c.limit, 4096);
+    assert_eq!(
+        c.labels,
+        [("a".into(), "1".into()), ("b".into(), "2".into())]
+    );
+    assert_eq!(c.since, Some(Date(2024, 2, 29)));
+
+    for args in [
+        ["--limit", "4MiB"],
+        ["--labels", "a"],
+        ["--since", "2024-02"],
+    ] {
+        let err = c.parse_args(args.map(String::from).into()).unwrap_err();
+        assert!(
+            matches!(err, cliconf::Error::InvalidValue { ref flag, ref value, ref source } if flag == args[0] && value == args[1] && *source == Some(cliconf::Source::Arg(Some(2)))),
+            "{err}"
+        );
+    }
+}
+
+#[test]
+fn test_docker() {
+    assert!(BuildConf::dockerfile().starts_with(
+        "# Where to put the output
+ARG OUT_DIR
+# ENV OUT_DIR=${OUT_DIR}
+"
+    ));
+    assert_eq!(
+        DurationConf::compose_environment(),
+        "environment:
+  TIMEOUT: \"30s\"
+  # BACKOFF:
+  # GRACE:
+"
+    );
+}
+
+#[test]
+fn test_markdown() {
+    assert_eq!(
+        BuildConf::markdown(),
+        "# builder
+
+Dead-simple configuration for Rust CLI tools
+
+| Flag | Default | Environment variable | Description |
+| --- | --- | --- | --- |
+| `--out-dir`, `-o` |  | `OUT_DIR` | Where to put the output |
+| `--release` |  | `RELEASE` | Build with optimizations |
+| `--jobs` |  | `JOBS` |  |
+"
+    );
+}
+
+#[test]
+fn test_apply_arg() {
+    let mut c = Conf::default();
+    c.apply_arg("my-bool", None).unwrap();
+    c.apply_arg("my-num", Some("3")).unwrap();
+    assert!(c.apply_env_var("MY_STRING_VEC", "1,2").unwrap());
+    assert!(!c.apply_env_var("MY_OTHER", "1").unwrap());
+    assert!(c.my_bool);
+    assert_eq!(c.my_num, 3);
+    assert_eq!(c.my_string_vec, ["1", "2"]);
+    assert!(c.apply_arg("nope", None).is_err());
+
+    let mut c = LabelsConf::default();
+    c.labels.insert("team".into(), "infra".into());
+    assert!(c.apply_env_var("MYAPP_LABEL_TIER", "gold").unwrap());
+    assert_eq!(c.labels.len(), 2);
+    assert_eq!(c.labels["tier"], "gold");
+}
+
+#[derive(Parse, Default)]
+#[cliconf(double_dash = "keep_and_end", empty_arg = "skip")]
+struct WrapperConf {
+    verbose: bool,
+}
+
+#[test]
+fn test_arg_syntax() {
+    let mut c = WrapperConf::default();
+    let args: Vec<String> = ["", "--verbose", "-", "--", "cmd", "--verbose"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let positionals = c.parse_args(args).unwrap();
+    assert!(c.verbose);
+    assert_eq!(positionals, ["-", "--", "cmd", "--verbose"]);
+}
+
+#[derive(Parse, Default)]
+#[cliconf(set_flag = "set", to_args)]
+struct RenameConf {
+    #[cliconf(rename = "type")]
+    type_: String,
+    r#loop: bool,
+    #[cliconf(rename = "label", shorthand = 'l')]
+    display_name_override: String,
+}
+
+#[test]
+fn test_rename() {
+    let mut c = RenameConf::default();
+    c.parse_env(HashMap::from([
+        ("TYPE".into(), "a".into()),
+        ("LABEL".into(), "b".into()),
+    ]))
+    .unwrap();
+    assert_eq!(c.type_, "a");
+    assert_eq!(c.display_name_override, "b");
+
+    let args: Vec<String> = ["--type", "c", "--loop", "--set", "label=d"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    c.parse_args(args).unwrap();
+    assert_eq!(c.type_, "c");
+    assert!(c.r#loop);
+    assert_eq!(c.display_name_override, "d");
+    assert_eq!(c.to_args(), ["--type=c", "--loop", "--label=d"]);
+    assert!(c.parse_args(vec!["--type-".into(), "e".into()]).is_err());
+    assert_eq!(
+        RenameConf::flags_metadata()
+            .iter()
+            .map(|meta| meta.name.as_str())
+            .collect::<Vec<_>>(),
+        ["type", "loop", "label"]
+    );
+}
+
+#[derive(Parse, Default)]
+#[cliconf(env_prefix = "APP", print_config)]
+struct PrintConfigConf {
+    host: String,
+    port: Box<u16>,
+    tags: Vec<String>,
+    #[cliconf(secret)]
+    token: Option<String>,
+    proxy: Option<String>,
+}
+
+#[test]
+fn test_print_config() {
+    let mut c = PrintConfigConf::default();
+    let args: Vec<String> = [
+        "--print-config=env",
+        "--host",
+        "example.com",
+        "--tags=a b",
+        "--token",
+        "hunter2",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    let err = c.parse_args(args).unwrap_err();
+    assert!(err.is_request());
+    let cliconf::Error::PrintConfigRequested(out) = err else {
+        panic!("{err}");
+    };
+    assert_eq!(
+        out,
+        "APP_HOST=example.com\nAPP_PORT=0\nAPP_TAGS='a b'\nAPP_TOKEN='<redacted>'\n"
+    );
+
+    let err = c
+        .parse_args(vec!["--print-config=json".into()])
+        .unwrap_err();
+    let cliconf::Error::PrintConfigRequested(out) = err else {
+        panic!("{err}");
+    };
+    assert_eq!(
+        out,
+        r#"{
+  "host": { "value": "example.com" },
+  "port": { "value": "0" },
+  "tags": { "value": ["a b"] },
+  "token": { "value": "<redacted>" }
+}
+"#
+    );
+    let err = c.parse_args(vec!["--print-config=yml".into()]).unwrap_err();
+    assert!(matches!(err, cliconf::Error::InvalidValue { flag, .. } if flag == "--print-config"));
+}
+
+#[derive(Parse, Default, Debug, PartialEq)]
+#[cliconf(to_args, print_config)]
+struct DatabaseConf {
+    host: String,
+    port: u16,
+    #[cliconf(delimiter = ",")]
+    replicas: Vec<String>,
+}
+
+#[derive(Parse, Default)]
+#[cliconf(env_prefix = "APP", unset_flag = "unset", to_args, print_config)]
+struct ModularConf {
+    name: String,
+    #[cliconf(flatten)]
+    db: DatabaseConf,
+    #[cliconf(flatten, rename = "cache")]
+    cache_db: DatabaseConf,
+}
+
+#[test]
+fn test_flatten() {
+    let mut c = ModularConf::default();
+    c.parse_env(HashMap::from([
+        ("APP_DB_HOST".into(), "db.internal".into()),
+        ("APP_DB_REPLICAS".into(), "a,b".into()),
+        ("APP_CACHE_PORT".into(), "6379".into()),
+    ]))
+    .unwrap();
+    let args: Vec<String> = ["--name", "app", "--db-port", "5432", "--cache-host=cache"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    c.parse_args(args).unwrap();
+    assert_eq!(c.name, "app");
+    assert_eq!(
+        c.db,
+        DatabaseConf {
+            host: "db.internal".into(),
+            port: 5432,
+            replicas: vec!["a".into(), "b".into()],
+        }
+    );
+    assert_eq!(c.cache_db.host, "cache");
+    assert_eq!(c.cache_db.port, 6379);
+    assert_eq!(
+        c.to_args(),
+        [
+            "--name=app",
+            "--db-host=db.internal",
+            "--db-port=5432",
+            "--db-replicas=a",
+            "--db-replicas=b",
+            "--cache-host=cache",
+            "--cache-port=6379",
+        ]
+    );
+
+    c.parse_args(vec!["--unset".into(), "db-host".into()])
+        .unwrap();
+    assert_eq!(c.db.host, "");
+    assert_eq!(c.db.port, 5432);
+
+    let err = c.parse_args(vec!["--db-port=x".into()]).unwrap_err();
+    assert!(matches!(err, cliconf::Error::InvalidValue { flag, .. } if flag == "--db-port"));
+    assert!(c.parse_args(vec!["--port=1".into()]).is_err());
+
+    let err = c.parse_args(vec!["--print-config=env".into()]).unwrap_err();
+    let cliconf::Error::PrintConfigRequested(out) = err else {
+        panic!("{err}");
+    };
+    assert!(out.contains("APP_CACHE_PORT=6379\n"), "{out}");
+    assert_eq!(
+        ModularConf::flags_metadata()
+            .iter()
+            .map(|meta| meta.env_var.as_deref().unwrap())
+            .collect::<Vec<_>>(),
+        [
+            "APP_NAME",
+            "APP_DB_HOST",
+            "APP_DB_PORT",
+            "APP_DB_REPLICAS",
+            "APP_CACHE_HOST",
+            "APP_CACHE_PORT",
+            "APP_CACHE_REPLICAS",
+        ]
+    );
+}
+
+#[derive(Parse, Default)]
+#[cliconf(env_prefix = "APP_")]
+struct DbPoolConf {
+    port: u16,
+    #[cliconf(env = "POOL_TOKEN")]
+    token: String,
+}
+
+#[derive(Parse, Default)]
+#[cliconf(env_prefix = "APP")]
+struct DbPooledConf {
+    #[cliconf(flatten)]
+    db: DbPoolConf,
+}
+
+#[derive(Parse, Default)]
+struct ServicesConf {
+    #[cliconf(flatten)]
+    main: DbPooledConf,
+}
+
+#[test]
+fn test_flatten_env() {
+    let mut c = DbPooledConf::default();
+    c.parse_env(HashMap::from([
+        ("APP_DB_PORT".into(), "5432".into()),
+        ("POOL_TOKEN".into(), "t".into()),
+    ]))
+    .unwrap();
+    assert_eq!(c.db.port, 5432);
+    assert_eq!(c.db.token, "t");
+
+    let mut c = ServicesConf::default();
+    c.parse_env(HashMap::from([
+        ("MAIN_DB_PORT".into(), "6432".into()),
+        ("POOL_TOKEN".into(), "u".into()),
+    ]))
+    .unwrap();
+    assert_eq!(c.main.db.port, 6432);
+    assert_eq!(c.main.db.token, "u");
+    assert_eq!(
+        ServicesConf::flags_metadata()
+            .iter()
+            .map(|meta| meta.env_var.as_deref().unwrap())
+            .collect::<Vec<_>>(),
+        ["MAIN_DB_PORT", "POOL_TOKEN"]
+    );
+}
+
+#[derive(Parse, Default)]
+struct AliasConf {
+    #[cliconf(alias = "colour", alias = "colours")]
+    color: bool,
+    #[cliconf(rename = "jobs", alias = "threads")]
+    workers: u32,
+}
+
+#[test]
+fn test_aliases() {
+    let mut c = AliasConf::default();
+    c.parse_args(vec!["--colour".into(), "--threads=4".into()])
+        .unwrap();
+    assert!(c.color);
+    assert_eq!(c.workers, 4);
+    c.parse_args(vec!["--colours=false".into(), "--jobs".into(), "2".into()])
+        .unwrap();
+    assert!(!c.color);
+    assert_eq!(c.workers, 2);
+    assert!(c.parse_args(vec!["--workers=1".into()]).is_err());
+    assert_eq!(AliasConf::flags_metadata().len(), 2);
+}
+
+#[derive(Parse, Default)]
+struct SplitAttrsConf {
+    #[cliconf(alias = "colour")]
+    #[cliconf(alias = "colours", shorthand = 'c')]
+    #[cliconf(env = "PAINT")]
+    color: bool,
+}
+
+#[test]
+fn test_split_attrs() {
+    let mut c = SplitAttrsConf::default();
+    c.parse_env(HashMap::from([("PAINT".into(), "true".into())]))
+        .unwrap();
+    assert!(c.color);
+    c.parse_args(vec!["--colours=false".into()]).unwrap();
+    assert!(!c.color);
+    c.parse_args(vec!["--colour".into()]).unwrap();
+    assert!(c.color);
+    c.parse_args(vec!["--color=false".into(), "-c".into()])
+        .unwrap();
+    assert!(c.color);
+}
+
+#[derive(Parse, Default)]
+#[cliconf(to_args)]
+struct CountConf {
+    #[cliconf(count, shorthand = 'v')]
+    verbose: u8,
+    #[cliconf(count, shorthand = 'q')]
+    quiet: Option<u32>,
+    #[cliconf(shorthand = 'f')]
+    force: bool,
+}
+
+#[test]
+fn test_count() {
+    let mut c = CountConf::default();
+    c.parse_env(HashMap::from([("VERBOSE".into(), "1".into())]))
+        .unwrap();
+    let args: Vec<String> = ["-vv", "--verbose", "-fv", "pos"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let positionals = c.parse_args(args).unwrap();
+    assert_eq!(positionals, ["pos"]);
+    assert_eq!(c.verbose, 5);
+    assert_eq!(c.quiet, None);
+    assert!(c.force);
+    assert_eq!(c.to_args(), ["--verbose=5", "--force"]);
+
+    c.parse_args(vec!["-qq".into(), "--verbose=0".into()])
+        .unwrap();
+    assert_eq!(c.quiet, Some(2));
+    assert_eq!(c.verbose, 0);
+    c.verbose = u8::MAX;
+    c.parse_args(vec!["-v".into()]).unwrap();
+    assert_eq!(c.verbose, u8::MAX);
+}
+
+#[derive(Parse, Debug, PartialEq)]
+#[cliconf(unset_flag = "unset")]
+struct DefaultsConf {
+    #[cliconf(default = "localhost")]
+    host: String,
+    #[cliconf(default = 8080)]
+    port: u16,
+    #[cliconf(unit = "ms", default = "2s")]
+    timeout_ms: u64,
+    #[cliconf(default = "a")]
+    tags: Vec<String>,
+    #[cliconf(default = vec![1, 2])]
+    weights: Vec<u8>,
+    #[cliconf(default = 3)]
+    retries: Option<Box<u8>>,
+    #[cliconf(default = "/tmp")]
+    dir: std::path::PathBuf,
+    name: String,
+    #[cliconf(flatten)]
+    db: DatabaseConf,
+}
+
+#[test]
+fn test_default_attribute() {
+    let mut c = DefaultsConf::new();
+    assert_eq!(c, DefaultsConf::default());
+    assert_eq!(c.host, "localhost");
+    assert_eq!(c.port, 8080);
+    assert_eq!(c.timeout_ms, 2000);
+    assert_eq!(c.tags, ["a"]);
+    assert_eq!(c.weights, [1, 2]);
+    assert_eq!(c.retries, Some(Box::new(3)));
+    assert_eq!(c.dir, std::path::Path::new("/tmp"));
+    assert_eq!(c.name, "");
+    assert_eq!(c.db, DatabaseConf::default());
+
+    let args: Vec<String> = ["--port=1", "--unset", "port", "--host=h", "--unset", "host"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    c.parse_args(args).unwrap();
+    assert_eq!(c.port, 8080);
+    assert_eq!(c.host, "localhost");
+}
+
+#[derive(Parse, Default)]
+#[cliconf(dump)]
+struct ServerConf {
+    #[cliconf(required)]
+    host: String,
+    #[cliconf(required)]
+    port: u16,
+    verbose: bool,
+    #[cliconf(sources)]
+    sources: cliconf::Sources,
+}
+
+#[derive(Parse, Default)]
+#[cliconf(unset_flag = "unset")]
+struct DeployConf {
+    #[cliconf(required)]
+    target: String,
+    #[cliconf(sources)]
+    sources: cliconf::Sources,
+    #[cliconf(flatten)]
+    server: ServerConf,
+}
+
+#[test]
+fn test_required() {
+    let mut c = ServerConf::default();
+    let err = c.validate().unwrap_err();
+    assert!(matches!(err, cliconf::Error::MissingRequired(names) if names == ["host", "port"]));
+
+    c.parse_env(HashMap::from([("HOST".into(), "h".into())]))
+        .unwrap();
+    c.parse_args(vec!["--port=80".into()]).unwrap();
+    c.validate().unwrap();
+    assert_eq!(
+        ServerConf::synopsis(),
+        "Usage: cliconf --host <HOST> --port <PORT> [OPTIONS] [--] [ARGS]...\n"
+    );
+    assert_eq!(
+        c.dump(cliconf::dump::DumpFormat::Env),
+        "# from $HOST\nHOST=h\n# from argument 1 on the command line\nPORT=80\n# from the default\nVERBOSE=false\n"
+    );
+    assert_eq!(c.sources.get("host"), &cliconf::Source::Env("HOST".into()));
+    assert_eq!(c.sources.get("port"), &cliconf::Source::Arg(Some(1)));
+    assert_eq!(c.sources.get("verbose"), &cliconf::Source::Default);
+
+    let mut c = DeployConf::default();
+    assert_eq!(
+        c.validate().unwrap_err().to_string(),
+        "Missing required flags: --server-host, --server-port, --target"
+    );
+    let args: Vec<String> = ["--target=prod", "--server-host=h", "--server-port=1"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    c.parse_args(args).unwrap();
+    c.validate().unwrap();
+    assert_eq!(c.server.sources.get("port"), &cliconf::Source::Arg(Some(3)));
+
+    c.parse_args(vec!["--unset".into(), "server-port".into()])
+        .unwrap();
+    let err = c.validate().unwrap_err();
+    assert!(matches!(err, cliconf::Error::MissingRequired(names) if names == ["server-port"]));
+}
+
+#[derive(Parse, Default)]
+#[cliconf(env_prefix = "APP_")]
+struct LegacyConf {
+    #[cliconf(deprecated = "use --workers instead")]
+    threads: u32,
+    workers: u32,
+    #[cliconf(sources)]
+    sources: cliconf::Sources,
+}
+
+#[derive(Parse, Default)]
+struct LegacyAppConf {
+    #[cliconf(flatten)]
+    pool: LegacyConf,
+}
+
+#[test]
+fn test_deprecated() {
+    let mut c = LegacyConf::default();
+    assert_eq!(c.check().unwrap(), []);
+
+    c.parse_env(HashMap::from([("APP_THREADS".into(), "4".into())]))
+        .unwrap();
+    c.parse_args(vec!["--threads=8".into(), "--workers=2".into()])
+        .unwrap();
+    let warnings: Vec<String> = c
+        .check()
+        .unwrap()
+        .iter()
+        .map(|warning| warning.to_string())
+        .collect();
+    // The environment is named even though the arguments overrode it
+    assert_eq!(
+        warnings,
+        [
+            "--threads: use --workers instead (set by $APP_THREADS) [deprecated]",
+            "--threads: use --workers instead [deprecated]",
+        ]
+    );
+
+    let mut c = LegacyAppConf::default();
+    c.parse_env(HashMap::from([("POOL_THREADS".into(), "4".into())]))
+        .unwrap();
+    let warnings = c.check().unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].flag.as_deref(), Some("pool-threads"));
+    assert_eq!(
+        warnings[0].message,
+        "use --workers instead (set by $POOL_THREADS)"
+    );
+}
+
+#[derive(Parse, Default)]
+#[cliconf(dump)]
+struct LintConf {
+    #[cliconf(secret)]
+    password: String,
+    quiet: bool,
+    verbose: bool,
+    #[cliconf(sources)]
+    sources: cliconf::Sources,
+}
+
+#[test]
+fn test_lints() {
+    use cliconf::lint::Conflicts;
+
+    let mut c = LintConf::default();
+    c.parse_args(
+        ["--password=changeme", "--quiet", "--verbose"]
+            .map(String::from)
+            .into(),
+    )
+    .unwrap();
+    let warnings: Vec<String> = c
+        .check()
+        .unwrap()
+        .iter()
+        .map(|warning| warning.to_string())
+        .collect();
+    assert_eq!(
+        warnings,
+        [
+            "--password: secrets on the command line are visible to other users; use $PASSWORD or a config file instead [secret-in-args]",
+            "--password: the secret is a well-known default [default-secret]",
+        ]
+    );
+
+    let conflicts = Conflicts(vec![("quiet".into(), "verbose".into())]);
+    let warnings = cliconf::engine::check(&c, &[&conflicts]).unwrap();
+    assert_eq!(
+        warnings.last().unwrap().to_string(),
+        "--quiet and --verbose conflict [conflicts]"
+    );
+}
+
+#[derive(Parse, Default, Clone)]
+struct ListenConf {
+    host: String,
+    port: u16,
+}
+
+#[derive(Parse, Default, Clone)]
+#[cliconf(unset_flag = "unset")]
+struct ChangeConf {
+    #[cliconf(secret)]
+    token: String,
+    #[cliconf(flatten)]
+    listen: ListenConf,
+    #[cliconf(sources)]
+    sources: cliconf::Sources,
+}
+
+#[test]
+fn test_change_set() {
+    use cliconf::{changes::Recorder, engine, Source};
+
+    let mut c = ChangeConf::default();
+    let mut recorder = Recorder::new(&c);
+    engine::parse_env(
+        &mut recorder,
+        HashMap::from([("LISTEN_PORT".into(), "80".into())]),
+    )
+    .unwrap();
+    let args = [
+        "--listen-host=::",
+        "--token=hunter2",
+        "--unset",
+        "listen-port",
+    ];
+    engine::parse_args(&mut recorder, args.map(String::from).into()).unwrap();
+    assert_eq!(recorder.preview().listen.host, "::");
+    let mut changes = recorder.finish();
+    let flags: Vec<_> = changes
+        .iter()
+        .map(|change| (change.flag.as_deref().unwrap(), &change.source))
+        .collect();
+    assert_eq!(
+        flags,
+        [
+            ("listen-port", &Source::Env("LISTEN_PORT".into())),
+            ("listen-host", &Source::Arg(Some(1))),
+            ("token", &Source::Arg(Some(2))),
+            ("listen-port", &Source::Unset),
+        ]
+    );
+    assert_eq!(c.listen.host, "");
+
+    // Secrets don't belong in the command line, where other users can see them
+    changes.retain(|change| !(change.is("token") && matches!(change.source, Source::Arg(_))));
+    changes.apply(&mut c).unwrap();
+    assert_eq!(c.listen.host, "::");
+    assert_eq!(c.listen.port, 0);
+    assert_eq!(c.token, "");
+    assert_eq!(c.sources.get("token"), &Source::Default);
+
+    let mut recorder = Recorder::new(&c);
+    let err = engine::parse_args(&mut recorder, vec!["--listen-port=x".into()]).unwrap_err();
+    assert!(
+        matches!(err, cliconf::Error::InvalidValue { ref flag, .. } if flag == "--listen-port")
+    );
+}
+
+#[derive(Parse, Default)]
+#[cliconf(print_config)]
+struct LayeredConf {
+    name: String,
+    port: u16,
+    #[cliconf(sources)]
+    sources: cliconf::Sources,
+    #[cliconf(flatten)]
+    server: ServerConf,
+}
+
+#[test]
+fn test_sources() {
+    let mut c = LayeredConf::default();
+    c.parse_env(HashMap::from([("NAME".into(), "env".into())]))
+        .unwrap();
+    c.parse_args(vec!["--port=1".into(), "--server-host=h".into()])
+        .unwrap();
+    assert_eq!(
+        c.sources(),
+        [
+            ("name".into(), cliconf::Source::Env("NAME".into())),
+            ("port".into(), cliconf::Source::Arg(Some(1))),
+            ("server-host".into(), cliconf::Source::Arg(Some(2))),
+            ("server-port".into(), cliconf::Source::Default),
+            ("server-verbose".into(), cliconf::Source::Default),
+        ]
+    );
+
+    let err = c
+        .parse_args(vec!["--print-config=json".into()])
+        .unwrap_err();
+    let cliconf::Error::PrintConfigRequested(out) = err else {
+        panic!("{err}");
+    };
+    assert_eq!(
+        out,
+        r#"{
+  "name": { "value": "env", "source": "$NAME" },
+  "port": { "value": "1", "source": "argument 1 on the command line" },
+  "server-host": { "value": "h", "source": "argument 2 on the command line" },
+  "server-port": { "value": "0", "source": "the default" },
+  "server-verbose": { "value": "false", "source": "the default" }
+}
+"#
+    );
+}
+
+#[cfg(feature = "json")]
+#[derive(Parse, Default)]
+#[cliconf(coerce)]
+struct CoerceConf {
+    verbose: bool,
+    ports: Vec<u16>,
+    name: String,
+    port: u16,
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_coerce() {
+    let mut c = CoerceConf::default();
+    c.parse_str(
+        r#"{ "verbose": 1, "ports": [80, "443"], "name": 42, "port": "8080" }"#,
+        cliconf::Format::Json,
+    )
+    .unwrap();
+    assert!(c.verbose);
+    assert_eq!(c.ports, [80, 443]);
+    assert_eq!(c.name, "42");
+    assert_eq!(c.port, 8080);
+
+    let err = c
+        .parse_str(r#"{ "verbose": 2 }"#, cliconf::Format::Json)
+        .unwrap_err();
+    assert!(matches!(err, cliconf::Error::InvalidValue { flag, .. } if flag == "verbose"));
+}
+
+#[derive(Parse, Default)]
+struct CommandConf {
+    #[cliconf(from_command)]
+    token: String,
+    #[cliconf(from_command = "echo", command_timeout = "5s")]
+    port: u16,
+    name: String,
+}
+
+#[cfg(unix)]
+#[test]
+fn test_from_command() {
+    let mut c = CommandConf::default();
+    c.parse_env(HashMap::from([
+        ("TOKEN".into(), "cmd://printf hunter2".into()),
+        ("NAME".into(), "cmd://printf x".into()),
+    ]))
+    .unwrap();
+    assert_eq!(c.token, "hunter2");
+    assert_eq!(c.name, "cmd://printf x");
+
+    c.parse_args(vec!["--port".into(), "8080".into(), "--token=plain".into()])
+        .unwrap();
+    assert_eq!(c.port, 8080);
+    assert_eq!(c.token, "plain");
+
+    let err = c
+        .parse_args(vec!["--token=cmd://false".into()])
+        .unwrap_err();
+    assert!(matches!(err, cliconf::Error::Command { flag, .. } if flag == "--token"));
+}
+
+#[cfg(feature = "gsettings")]
+#[derive(Parse, Default)]
+struct DesktopConf {
+    #[cliconf(gsettings = "org.example.cliconf.missing proxy-host")]
+    proxy_host: String,
+    #[cliconf(gsettings = "/org/example/cliconf/missing/theme")]
+    theme: String,
+    port: u16,
+}
+
+#[cfg(feature = "gsettings")]
+#[test]
+fn test_gsettings() {
+    let mut c = DesktopConf {
+        theme: "light".into(),
+        ..Default::default()
+    };
+    // Settings that can't be read leave the fields alone
+    c.parse_gsettings().unwrap();
+    assert_eq!(c.proxy_host, "");
+    assert_eq!(c.theme, "light");
+
+    c.parse_args(vec!["--proxy-host=proxy.example.com".into()])
+        .unwrap();
+    assert_eq!(c.proxy_host, "proxy.example.com");
+}
+
+#[cfg(feature = "plist")]
+#[derive(Parse, Default)]
+struct PreferencesConf {
+    #[cliconf(defaults = "com.example.cliconf.missing ShowSidebar")]
+    sidebar: bool,
+    #[cliconf(defaults = "com.example.cliconf.missing FontSize")]
+    font_size: u16,
+}
+
+#[cfg(feature = "plist")]
+#[test]
+fn test_defaults() {
+    let mut c = PreferencesConf {
+        font_size: 12,
+        ..Default::default()
+    };
+    // Domains that can't be read leave the fields alone
+    c.parse_defaults().unwrap();
+    assert!(!c.sidebar);
+    assert_eq!(c.font_size, 12);
+
+    c.parse_args(vec!["--font-size=14".into()]).unwrap();
+    assert_eq!(c.font_size, 14);
+}
+
+#[derive(Parse, Default)]
+#[cliconf(bin_name = "greet", about = "Greets people")]
+struct AboutConf {
+    name: String,
+}
+
+#[test]
+fn test_about() {
+    let about = AboutConf::about();
+    assert_eq!(about.name, "greet");
+    assert_eq!(about.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(about.about.as_deref(), Some("Greets people"));
+    assert_eq!(about.version_text(), format!("greet {}\n", about.version));
+
+    assert_eq!(DocConf::about().name, env!("CARGO_PKG_NAME"));
+}
+
+#[test]
+fn test_errors() {
+    let mut c = Conf::default();
+
+    let err = c.parse_args(vec!["--nope".into()]).unwrap_err();
+    assert!(matches!(err, cliconf::Error::UnknownFlag(arg) if arg == "--nope"));
+    let err = c.parse_args(vec!["--my-num".into()]).unwrap_err();
+    assert!(matches!(err, cliconf::Error::MissingValue(arg) if arg == "--my-num"));
+    let err = c
+        .parse_env(HashMap::from([("MY_NUM".into(), "x".into())]))
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        cliconf::Error::InvalidValue { flag, value, .. } if flag == "MY_NUM" && value == "x"
+    ));
+}
+
+#[derive(Parse, Default)]
+#[cliconf(
+    bin_name = "greet",
+    version = "1.0.0",
+    about = "Greets people",
+    positionals = "GREETING [NAMES]..."
+)]
+struct HelpConf {
+    /// The person to greet
+    #[cliconf(shorthand = 'n')]
+    name: String,
+    /// Include hidden people, who are usually
+    /// left out
+    #[cliconf(shorthand = 'h')]
+    hidden: bool,
+    count: i32,
+}
+
+#[test]
+fn test_help() {
+    let mut c = HelpConf::default();
+
+    let err = c
+        .parse_args(vec!["--count".into(), "2".into(), "--help".into()])
+        .unwrap_err();
+    assert!(matches!(err, cliconf::Error::HelpRequested));
+    // -h belongs to a field
+    let positionals = c.parse_args(vec!["-h".into(), "hello".into()]).unwrap();
+    assert!(c.hidden);
+    assert_eq!(positionals, ["hello"]);
+    let err = c.parse_args(vec!["-h".into()]).unwrap_err();
+    assert_eq!(err.to_string(), "Missing argument: <GREETING>");
+    let err = Conf::default().parse_args(vec!["-h".into()]).unwrap_err();
+    assert!(matches!(err, cliconf::Error::HelpRequested));
+    let err = c.parse_args(vec!["--help-all".into()]).unwrap_err();
+    assert!(matches!(err, cliconf::Error::HelpAllRequested));
+    #[cfg(feature = "usage")]
+    assert_eq!(c.usage_all(40), c.usage(40));
+    let err = c.parse_args(vec!["--version".into()]).unwrap_err();
+    assert!(matches!(err, cliconf::Error::VersionRequested));
+    let err = c.parse_args(vec!["-V".into()]).unwrap_err();
+    assert!(matches!(err, cliconf::Error::VersionRequested));
+
+    #[cfg(feature = "usage")]
+    assert_eq!(
+        c.usage(40),
+        "greet 1.0.0
+Greets people
+
+Usage: greet [OPTIONS] [--] <GREETING> [NAMES]...
+
+--name / -n
+    The person to greet (env: NAME)
+
+--hidden / -h
+    Include hidden people, who are 
+    usually left out (env: HIDDEN)
+"
+    );
+    #[cfg(feature = "usage")]
+    assert_eq!(
+        c.usage_styled(50, cliconf::usage::UsageStyle::Columns),
+        "greet 1.0.0
+Greets people
+
+Usage: greet [OPTIONS] [--] <GREETING> [NAMES]...
+
+  --name / -n    The person to greet (env: NAME)
+  --hidden / -h  Include hidden people, who are 
+                 usually left out (env: HIDDEN)
+"
+    );
+}
+
+#[derive(Parse, Default)]
+#[cliconf(unset_flag = "unset")]
+struct OptionConf {
+    timeout: Option<u64>,
+    verbose: Option<bool>,
+    #[cliconf(delimiter = ",")]
+    tags: Option<Vec<String>>,
+    port: Option<std::sync::Arc<u16>>,
+}
+
+#[test]
+fn test_option_fields() {
+    let mut c = OptionConf::default();
+    c.parse_env(HashMap::from([("TAGS".into(), "a,b".into())]))
+        .unwrap();
+    c.parse_args(vec!["--timeout".into(), "0".into(), "--verbose".into()])
+        .unwrap();
+    assert_eq!(c.timeout, Some(0));
+    assert_eq!(c.verbose, Some(true));
+    assert_eq!(c.tags, Some(vec!["a".into(), "b".into()]));
+    assert_eq!(c.port, None);
+
+    c.parse_args(vec!["--unset".into(), "timeout".into(), "--port=80".into()])
+        .unwrap();
+    assert_eq!(c.timeout, None);
+    assert_eq!(c.port.as_deref(), Some(&80));
+}
+
+#[derive(Parse, Default)]
+#[cliconf(env_prefix = "MYAPP_")]
+struct EnvNameConf {
+    name: String,
+    #[cliconf(env = "MYAPP_LISTEN")]
+    listen_addr: String,
+    #[cliconf(env = "PORT")]
+    port: u16,
+}
+
+#[test]
+fn test_env_names() {
+    let mut c = EnvNameConf::default();
+    c.parse_env(HashMap::from([
+        ("NAME".into(), "unprefixed".into()),
+        ("MYAPP_NAME".into(), "john".into()),
+        ("MYAPP_LISTEN".into(), "0.0.0.0:80".into()),
+        ("MYAPP_LISTEN_ADDR".into(), "ignored".into()),
+        ("PORT".into(), "80".into()),
+    ]))
+    .unwrap();
+    assert_eq!(c.name, "john");
+    assert_eq!(c.listen_addr, "0.0.0.0:80");
+    assert_eq!(c.port, 80);
+
+    let env_vars: Vec<_> = EnvNameConf::flags_metadata()
+        .into_iter()
+        .map(|meta| meta.env_var.unwrap())
+        .collect();
+    assert_eq!(env_vars, ["MYAPP_NAME", "MYAPP_LISTEN", "PORT"]);
+}
+
+#[cfg(feature = "json")]
+#[derive(Parse, Default)]
+struct FileConf {
+    name: String,
+    db_host: String,
+    db_port: u16,
+    tags: Vec<String>,
+    ports: Vec<u16>,
+    verbose: bool,
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_parse_file() {
+    let dir = std::env::temp_dir().join(format!("cliconf-parse-file-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("app.json");
+    std::fs::write(
+        &path,
+        r#"{
+            "name": "file",
+            "db": { "host": "localhost", "port": 5432 },
+            "tags": ["a", "b"],
+            "ports": [],
+            "verbose": true
+        }"#,
+    )
+    .unwrap();
+
+    let mut c = FileConf {
+        tags: vec!["default".into()],
+        ports: vec![80],
+        ..FileConf::default()
+    };
+    c.parse_file(&path).unwrap();
+    assert_eq!(c.name, "file");
+    assert_eq!(c.db_host, "localhost");
+    assert_eq!(c.db_port, 5432);
+    assert_eq!(c.tags, ["a", "b"]);
+    assert!(c.ports.is_empty());
+    assert!(c.verbose);
+
+    // Later sources override the file, replacing arrays as a whole
+    c.parse_env(HashMap::from([("DB_PORT".into(), "1".into())]))
+        .unwrap();
+    c.parse_args(vec!["--tags=c".into()]).unwrap();
+    assert_eq!(c.tags, ["c"]);
+    assert_eq!(c.db_port, 1);
+
+    std::fs::write(&path, r#"{ "nope": 1 }"#).unwrap();
+    let err = c.parse_file(&path).unwrap_err();
+    assert!(matches!(err, cliconf::Error::UnknownKey(key) if key == "nope"));
+    std::fs::write(&path, r#"{ "db_port": "x", "name": [] }"#).unwrap();
+    let err = c.parse_file(&path).unwrap_err();
+    assert!(matches!(err, cliconf::Error::InvalidValue { flag, .. } if flag == "db_port"));
+    std::fs::write(&path, r#"{ "name": [] }"#).unwrap();
+    assert!(c.parse_file(&path).is_err());
+
+    std::fs::write(&path, r#"{ "name": "limited" }"#).unwrap();
+    let limits = cliconf::Limits {
+        max_size: Some(10),
+        ..Default::default()
+    };
+    let err = c.parse_file_with_limits(&path, &limits).unwrap_err();
+    assert!(matches!(err, cliconf::Error::Limit(..)));
+    let limits = cliconf::Limits {
+        timeout: Some(std::time::Duration::from_secs(10)),
+        ..Default::default()
+    };
+    c.parse_file_with_limits(&path, &limits).unwrap();
+    assert_eq!(c.name, "limited");
+    // A timeout alone still caps the read
+    #[cfg(unix)]
+    {
+        let err = c
+            .parse_file_with_limits(std::path::Path::new("/dev/zero"), &limits)
+            .unwrap_err();
+        assert!(matches!(err, cliconf::Error::Limit(..)));
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "json5")]
+#[test]
+fn test_parse_json5() {
+    let dir = std::env::temp_dir().join(format!("cliconf-parse-json5-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("app.jsonc");
+    std::fs::write(
+        &path,
+        r#"{
+    // Comments are fine
+    "name": "jsonc",
+    db: { host: 'localhost', port: 5432 },
+    /* and so are trailing commas */
+    "tags": ["a", "b",],
+}
+"#,
+    )
+    .unwrap();
+
+    let mut c = FileConf::default();
+    c.parse_file(&path).unwrap();
+    assert_eq!(c.name, "jsonc");
+    assert_eq!(c.db_host, "localhost");
+    assert_eq!(c.db_port, 5432);
+    assert_eq!(c.tags, ["a", "b"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    c.parse_str("{ db_port: 0x10 }", cliconf::Format::Json5)
+        .unwrap();
+    assert_eq!(c.db_port, 16);
+    let err = c.parse_str("{ name: ", cliconf::Format::Json5).unwrap_err();
+    assert_eq!(err.code(), "CLICONF_E_FORMAT");
+}
+
+#[cfg(feature = "hcl")]
+#[derive(Parse, Default)]
+struct HclConf {
+    name: String,
+    db_host: String,
+    db_port: u16,
+    server_api_port: u16,
+    tags: Vec<String>,
+}
+
+#[cfg(feature = "hcl")]
+#[test]
+fn test_parse_hcl() {
+    let dir = std::env::temp_dir().join(format!("cliconf-parse-hcl-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("app.hcl");
+    std::fs::write(
+        &path,
+        r#"# Comments are fine
+name = "hcl"
+tags = ["a", "b"]
+
+db {
+  host = "localhost"
+  port = 5432
+}
+
+server "api" {
+  port = 8080
+}
+"#,
+    )
+    .unwrap();
+
+    let mut c = HclConf::default();
+    c.parse_file(&path).unwrap();
+    assert_eq!(c.name, "hcl");
+    assert_eq!(c.db_host, "localhost");
+    assert_eq!(c.db_port, 5432);
+    assert_eq!(c.server_api_port, 8080);
+    assert_eq!(c.tags, ["a", "b"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    c.parse_str("db_port = 1", cliconf::Format::Hcl).unwrap();
+    assert_eq!(c.db_port, 1);
+    let err = c.parse_str("db {", cliconf::Format::Hcl).unwrap_err();
+    assert_eq!(err.code(), "CLICONF_E_FORMAT");
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn test_parse_yaml() {
+    let dir = std::env::temp_dir().join(format!("cliconf-parse-yaml-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("app.yml");
+    std::fs::write(
+        &path,
+        "---
+name: yaml
+db:
+  host: localhost
+  port: 5432
+tags:
+  - a
+  - b
+ports: []
+verbose: true
+",
+    )
+    .unwrap();
+
+    let mut c = FileConf {
+        ports: vec![80],
+        ..FileConf::default()
+    };
+    c.parse_file(&path).unwrap();
+    assert_eq!(c.name, "yaml");
+    assert_eq!(c.db_host, "localhost");
+    assert_eq!(c.db_port, 5432);
+    assert_eq!(c.tags, ["a", "b"]);
+    assert!(c.ports.is_empty());
+    assert!(c.verbose);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    c.parse_str("db_port: 1", cliconf::Format::Yaml).unwrap();
+    assert_eq!(c.db_port, 1);
+    // YAML 1.2 has no `yes`, so it's the string "yes", which isn't a bool
+    let err = c
+        .parse_str("verbose: yes", cliconf::Format::Yaml)
+        .unwrap_err();
+    assert!(matches!(err, cliconf::Error::InvalidValue { flag, .. } if flag == "verbose"));
+    let err = c.parse_str("db: [1", cliconf::Format::Yaml).unwrap_err();
+    assert_eq!(err.code(), "CLICONF_E_FORMAT");
+}
+
+#[cfg(feature = "json")]
+#[derive(Parse, Default)]
+#[cliconf(embedded_defaults = "tests/fixtures/defaults.json")]
+struct EmbeddedConf {
+    name: String,
+    db_host: String,
+    db_port: u16,
+    tags: Vec<String>,
+    verbose: bool,
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_embedded_defaults() {
+    let mut c = EmbeddedConf::default();
+    c.parse_embedded_defaults().unwrap();
+    assert_eq!(c.name, "embedded");
+    assert_eq!(c.db_host, "db.internal");
+    assert_eq!(c.db_port, 5432);
+    assert_eq!(c.tags, ["default"]);
+    assert!(!c.verbose);
+
+    c.parse_env(HashMap::from([("DB_PORT".into(), "6543".into())]))
+        .unwrap();
+    c.parse_args(vec!["--tags=cli".into()]).unwrap();
+    assert_eq!(c.db_port, 6543);
+    assert_eq!(c.tags, ["cli"]);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_load() {
+    let dir = std::env::temp_dir().join(format!("cliconf-load-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let system = dir.join("system.json");
+    std::fs::write(&system, r#"{ "name": "system", "db": { "port": 1 } }"#).unwrap();
+
+    let loader = cliconf::Loader::new()
+        .system_file(&system)
+        .user_file(dir.join("user.json"))
+        .env(HashMap::from([("DB_PORT".into(), "2".into())]))
+        .args(vec!["--verbose".into(), "pos".into()]);
+    let mut c = EmbeddedConf::default();
+    let positionals = c.load_with(&loader).unwrap();
+    assert_eq!(positionals, ["pos"]);
+    // The embedded defaults come first, then the system file, the
+    // environment and the arguments
+    assert_eq!(c.db_host, "db.internal");
+    assert_eq!(c.name, "system");
+    assert_eq!(c.db_port, 2);
+    assert!(c.verbose);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_parse_bytes() {
+    // As if from include_bytes! or a mobile app's asset bundle
+    let asset: &[u8] = br#"{ "name": "asset", "db": { "port": 5432 }, "tags": ["a"] }"#;
+    let mut c = FileConf::default();
+    c.parse_bytes(asset, cliconf::Format::Json).unwrap();
+    assert_eq!(c.name, "asset");
+    assert_eq!(c.db_port, 5432);
+    assert_eq!(c.tags, ["a"]);
+
+    let err = c
+        .parse_bytes(b"{ \"name\": 1 ", cliconf::Format::Json)
+        .unwrap_err();
+    assert_eq!(err.code(), "CLICONF_E_FORMAT");
+    let err = c
+        .parse_bytes(br#"{ "tags": [["a"]] }"#, cliconf::Format::Json)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Invalid value for tags: expected a string, number or boolean, found a list"
+    );
+}
+
+#[cfg(feature = "json")]
+#[derive(Parse, Default)]
+struct CounterConf {
+    bytes: u64,
+    id: i128,
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_large_integers() {
+    let mut c = CounterConf::default();
+    c.parse_str(
+        r#"{ "bytes": 18446744073709551615 }"#,
+        cliconf::Format::Json,
+    )
+    .unwrap();
+    assert_eq!(c.bytes, u64::MAX);
+    let err = c
+        .parse_str(r#"{ "bytes": -1 }"#, cliconf::Format::Json)
+        .unwrap_err();
+    assert!(matches!(err, cliconf::Error::InvalidValue { flag, .. } if flag == "bytes"));
+
+    #[cfg(feature = "arbitrary_precision")]
+    {
+        let data = format!(r#"{{ "id": {} }}"#, i128::MIN);
+        c.parse_str(&data, cliconf::Format::Json).unwrap();
+        assert_eq!(c.id, i128::MIN);
+    }
+}
+
+#[cfg(feature = "json")]
+#[derive(Parse, Default)]
+struct PoolConf {
+    #[cliconf(telemetry)]
+    max_conns: u32,
+    url: String,
+}
+
+#[cfg(feature = "json")]
+#[derive(Parse, Default)]
+struct TelemetryConf {
+    #[cliconf(telemetry)]
+    color: bool,
+    #[cliconf(telemetry)]
+    ratio: f64,
+    #[cliconf(telemetry)]
+    ports: Vec<u16>,
+    #[cliconf(telemetry)]
+    retries: Option<u8>,
+    name: String,
+    #[cliconf(flatten, telemetry)]
+    pool: PoolConf,
+    #[cliconf(flatten)]
+    cache: PoolConf,
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_sanitized_snapshot() {
+    let mut c = TelemetryConf::default();
+    let args: Vec<String> = [
+        "--color",
+        "--ratio=0.5",
+        "--ports=80",
+        "--ports=443",
+        "--name=Jane Doe",
+        "--pool-max-conns=10",
+        "--pool-url=postgres://jane@db",
+        "--cache-max-conns=5",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    c.parse_args(args).unwrap();
+    assert_eq!(
+        c.sanitized_snapshot(),
+        serde_json::json!({
+            "color": true,
+            "ratio": 0.5,
+            "ports": [80, 443],
+            "pool-max-conns": 10,
+        })
+    );
+}
+
+#[cfg(feature = "toml")]
+#[derive(Parse, Default)]
+struct TomlConf {
+    name: String,
+    db_host: String,
+    db_replica_hosts: Vec<String>,
+    db_port: u16,
+    started: String,
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn test_parse_toml() {
+    let mut c = TomlConf::default();
+    c.parse_str(
+        r#"
+        name = "toml"
+        started = 1979-05-27T07:32:00Z
+
+        [db]
+        host = "localhost"
+        port = 5432
+
+        [db.replica]
+        hosts = ["a", "b"]
+        "#,
+        cliconf::Format::Toml,
+    )
+    .unwrap();
+    assert_eq!(c.name, "toml");
+    assert_eq!(c.started, "1979-05-27T07:32:00Z");
+    assert_eq!(c.db_host, "localhost");
+    assert_eq!(c.db_port, 5432);
+    assert_eq!(c.db_replica_hosts, ["a", "b"]);
+
+    let err = c
+        .parse_str("[db]\nname = 1", cliconf::Format::Toml)
+        .unwrap_err();
+    assert!(matches!(err, cliconf::Error::UnknownKey(key) if key == "db.name"));
+    let err = c.parse_str("name = ", cliconf::Format::Toml).unwrap_err();
+    assert!(matches!(err, cliconf::Error::Format(_)));
+
+    let dir = std::env::temp_dir().join(format!("cliconf-parse-toml-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("app.toml");
+    std::fs::write(&path, "db.port = 1").unwrap();
+    c.parse_file(&path).unwrap();
+    assert_eq!(c.db_port, 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_replay_manifest() {
+    use cliconf::{Flag, FlagValue, Flags};
+
+    let dir = std::env::temp_dir().join(format!("cliconf-replay-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("manifest.json");
+
+    let mut flags = Flags::new();
+    flags.add(Flag::new("my-string", FlagValue::String("".into())));
+    flags.add(Flag::new("my-num", FlagValue::Int64(0)));
+    flags.add(Flag::new("my-string-vec", FlagValue::StringArray(vec![])));
+    flags.add(Flag::new("removed", FlagValue::Bool(false)));
+    flags
+        .parse_args(
+            [
+                "--my-string=1",
+                "--my-num=1",
+                "--my-string-vec=1",
+                "--my-string-vec=2",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+        )
+        .unwrap();
+    flags
+        .write_manifest(&path, env!("CARGO_PKG_VERSION"))
+        .unwrap();
+
+    let mut c = Conf {
+        my_bool: true,
+        ..Conf::default()
+    };
+    let warnings = c.replay_manifest(&path).unwrap();
+    assertions(&c);
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].flag.as_deref(), Some("removed"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[derive(Parse, Default)]
+#[cliconf(env_prefix = "APP")]
+struct EnvPrefixConf {
+    my_bool: bool,
+    port: u16,
+}
+
+#[test]
+fn test_env_prefix() {
+    let mut c = EnvPrefixConf::default();
+    c.parse_env(HashMap::from([
+        ("APP_MY_BOOL".into(), "true".into()),
+        ("PORT".into(), "80".into()),
+    ]))
+    .unwrap();
+    assert!(c.my_bool);
+    assert_eq!(c.port, 0);
+}
+
+#[derive(Parse, Default, Debug, Clone, PartialEq)]
+#[cliconf(to_args)]
+struct ArgsConf {
+    verbose: bool,
+    #[cliconf(shorthand = 'n')]
+    name: String,
+    count: i64,
+    ratio: f64,
+    tags: Vec<String>,
+    limit: Option<u32>,
+    label: std::sync::Arc<String>,
+}
+
+proptest! {
+    #[test]
+    fn test_to_args_round_trip(
+        verbose: bool,
+        name: String,
+        count: i64,
+        ratio in any::<f64>().prop_filter("NaN never equals itself", |x| !x.is_nan()),
+        tags: Vec<String>,
+        limit: Option<u32>,
+        label: String,
+    ) {
+        let c = ArgsConf {
+            verbose,
+            name,
+            count,
+            ratio,
+            tags,
+            limit,
+            label: label.into(),
+        };
+        let mut parsed = ArgsConf::default();
+        let positionals = parsed.parse_args(c.to_args()).unwrap();
+        prop_assert!(positionals.is_empty());
+        prop_assert_eq!(parsed, c);
+    }
+}
@@ -0,0 +1,9 @@
+use cliconf::Parse;
+
+#[derive(Parse)]
+struct Conf {
+    #[cliconf(deprecated = "use --name instead")]
+    user: String,
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use cliconf::Parse;
+
+#[derive(Parse)]
+#[cliconf(env_prefix = "APP_")]
+struct Conf {
+    name: String,
+
+    #[cliconf(env = "APP_NAME")]
+    full_name: String,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use cliconf::Parse;
+
+#[derive(Parse)]
+struct Conf {
+    name: String,
+
+    #[cliconf(rename = "name")]
+    full_name: String,
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use cliconf::Parse;
+
+#[derive(Parse)]
+struct Conf {
+    #[cliconf(shorthand = 'n')]
+    name: String,
+
+    #[cliconf(shorthand = 'n')]
+    number: i32,
+}
+
+fn main() {}
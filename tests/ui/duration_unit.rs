@@ -0,0 +1,11 @@
+use std::time::Duration;
+
+use cliconf::Parse;
+
+#[derive(Parse, Default)]
+struct Conf {
+    #[cliconf(unit = "ms")]
+    timeout: Duration,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use cliconf::Parse;
+
+#[derive(Parse, Default)]
+#[cliconf(embedded_defaults = "defaults.ini")]
+struct Conf {
+    name: String,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use cliconf::Parse;
+
+#[derive(Parse)]
+enum Conf {
+    A,
+    B,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use cliconf::FlagEnum;
+
+#[derive(FlagEnum)]
+enum Level {
+    Debug,
+    Custom(u8),
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use cliconf::Parse;
+use std::collections::HashMap;
+
+#[derive(Parse)]
+struct Conf {
+    labels: HashMap<String, String>,
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+use cliconf::Parse;
+
+fn parse_timeout(s: &str) -> Result<u64, std::num::ParseIntError> {
+    s.parse()
+}
+
+#[derive(Parse, Default)]
+struct Conf {
+    #[cliconf(unit = "ms", parse_with = parse_timeout)]
+    timeout: u64,
+}
+
+fn main() {}
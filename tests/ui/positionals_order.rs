@@ -0,0 +1,9 @@
+use cliconf::Parse;
+
+#[derive(Parse)]
+#[cliconf(positionals = "[SRC] DEST")]
+struct Conf {
+    name: String,
+}
+
+fn main() {}
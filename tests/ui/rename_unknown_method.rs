@@ -0,0 +1,9 @@
+use cliconf::Parse;
+
+#[derive(Parse)]
+#[cliconf(rename_methods(parse_argz = "apply_cli"))]
+struct Conf {
+    name: String,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use cliconf::Parse;
+
+#[derive(Parse)]
+struct Conf {
+    #[cliconf(shorthand = "n")]
+    name: String,
+}
+
+fn main() {}
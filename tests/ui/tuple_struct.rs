@@ -0,0 +1,6 @@
+use cliconf::Parse;
+
+#[derive(Parse)]
+struct Conf(String);
+
+fn main() {}
@@ -0,0 +1,8 @@
+use cliconf::Parse;
+
+#[derive(Parse)]
+struct Conf {
+    flags: Vec<bool>,
+}
+
+fn main() {}